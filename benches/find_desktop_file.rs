@@ -0,0 +1,94 @@
+//! Benchmarks of the fuzzy desktop file search against the desktop files of the fedora testset.
+//!
+//! Run with `cargo bench --bench find_desktop_file`, the `normalized_levenshtein` variant is the search
+//! without the bounds of [`bounded_normalized_levenshtein`] to compare against.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use gnome_session_restore::find_command::{
+    methods::{
+        bounded_levenshtein::bounded_normalized_levenshtein, partial_match_similarity::partial_match_similarity,
+        try_find_desktop_file_fuzzy,
+    },
+    testsets, DesktopFileIndex,
+};
+
+const TESTSET: &str = include_str!("../tests/testsets/fedora.list");
+
+const MIN_WM_CLASS_SIMILARITY: f64 = 0.8;
+
+/// Window classes of common applications, not all of them have a desktop file of that name
+const SEARCH_TERMS: [&str; 6] = ["firefox", "gnome-terminal-server", "steam", "jetbrains-clion", "code", "nautilus"];
+
+fn fuzzy_search(c: &mut Criterion) {
+    let index = DesktopFileIndex::new(testsets::paths(TESTSET));
+    let mut group = c.benchmark_group("try_find_desktop_file_fuzzy");
+
+    group.bench_function("bounded_normalized_levenshtein", |b| {
+        b.iter(|| {
+            for search_term in SEARCH_TERMS {
+                let found = try_find_desktop_file_fuzzy(
+                    black_box(search_term),
+                    bounded_normalized_levenshtein,
+                    MIN_WM_CLASS_SIMILARITY,
+                    &index,
+                    false,
+                );
+                black_box(found.ok());
+            }
+        })
+    });
+
+    group.bench_function("normalized_levenshtein", |b| {
+        b.iter(|| {
+            for search_term in SEARCH_TERMS {
+                let found = try_find_desktop_file_fuzzy(
+                    black_box(search_term),
+                    |a, b, min_similarity| {
+                        Some(strsim::normalized_levenshtein(a, b)).filter(|sim| *sim >= min_similarity)
+                    },
+                    MIN_WM_CLASS_SIMILARITY,
+                    &index,
+                    false,
+                );
+                black_box(found.ok());
+            }
+        })
+    });
+
+    group.bench_function("partial_match_similarity", |b| {
+        b.iter(|| {
+            for search_term in SEARCH_TERMS {
+                let found = try_find_desktop_file_fuzzy(
+                    black_box(search_term),
+                    |a, b, _| Some(partial_match_similarity(a, b)),
+                    f64::NEG_INFINITY,
+                    &index,
+                    false,
+                );
+                black_box(found.ok());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn partial_match(c: &mut Criterion) {
+    let stems: Vec<String> = testsets::paths(TESTSET)
+        .filter_map(|path| path.file_stem())
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .collect();
+
+    c.bench_function("partial_match_similarity", |b| {
+        b.iter(|| {
+            for search_term in SEARCH_TERMS {
+                for stem in &stems {
+                    black_box(partial_match_similarity(black_box(search_term), stem));
+                }
+            }
+        })
+    });
+}
+
+criterion_group!(benches, fuzzy_search, partial_match);
+criterion_main!(benches);
@@ -0,0 +1,269 @@
+//! Defaults for command line options from `config.toml` in the config directory
+//! (`$XDG_CONFIG_HOME/gnome-session-restore` or one of `$XDG_CONFIG_DIRS`).
+//!
+//! Top level keys set global options, tables named after a subcommand set the options of that subcommand,
+//! keys are the long option names (`min-wm-class-similarity` or `min_wm_class_similarity`):
+//!
+//! ```toml
+//! force-wayland = true
+//!
+//! [save]
+//! min_wm_class_similarity = 0.7
+//! procfs-cwd-policy = "allow"
+//! redact-pattern = ["token=(\\w+)"]
+//! ```
+//!
+//! Options given on the command line take precedence over the config file,
+//! which takes precedence over the built-in defaults.
+//! The config is applied by adding the options to the command line in front of the ones that were given.
+
+use clap::{Arg, ArgMatches, Command, ErrorKind};
+use std::{ffi::OsString, io, path::Path};
+use thiserror::Error;
+use toml::{value::Table, Value};
+
+/// The name of the config file in the config directory
+pub const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("unable to read the config file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("unknown subcommand `{0}`")]
+    UnknownSubcommand(String),
+
+    #[error("unknown option `{0}`")]
+    UnknownOption(String),
+
+    #[error("invalid value for `{0}`, expected {1}")]
+    InvalidValue(String, &'static str),
+}
+
+/// Reads the config file at `path`, a missing file is an empty config
+pub fn read(path: &Path) -> Result<Table, ConfigError> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(toml::from_str(&s)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Table::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Adds the options from `config` to `argv`, except for the ones that are already given in `argv`.
+///
+/// `argv` is returned as it is if it cannot be parsed by `cmd`, so that parsing it again reports the error.
+pub fn apply(config: &Table, cmd: &Command, argv: Vec<OsString>) -> Result<Vec<OsString>, ConfigError> {
+    let mut global = Vec::new();
+    let mut subcommands = Vec::new();
+
+    for (key, value) in config {
+        match value {
+            Value::Table(table) => {
+                let sub = cmd
+                    .find_subcommand(key)
+                    .ok_or_else(|| ConfigError::UnknownSubcommand(key.clone()))?;
+
+                subcommands.push((sub, table));
+            },
+            value => global.push((find_option(cmd, key)?, key, value)),
+        }
+    }
+
+    // validate all tables, not only the one of the invoked subcommand
+    let subcommands = subcommands
+        .into_iter()
+        .map(|(sub, table)| {
+            table
+                .iter()
+                .map(|(key, value)| Ok((find_option(sub, key)?, key, value)))
+                .collect::<Result<Vec<_>, ConfigError>>()
+                .map(|options| (sub.get_name(), options))
+        })
+        .collect::<Result<Vec<_>, ConfigError>>()?;
+
+    let Ok(matches) = cmd.clone().try_get_matches_from(&argv) else {
+        return Ok(argv);
+    };
+
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(argv);
+    };
+
+    let Some(pos) = subcommand_position(cmd, &argv, name) else {
+        return Ok(argv);
+    };
+
+    let global_args = to_args(&global, &matches)?;
+
+    let sub_args = match subcommands.iter().find(|(sub, _)| *sub == name) {
+        Some((_, options)) => to_args(options, sub_matches)?,
+        None => Vec::new(),
+    };
+
+    let mut args = argv;
+    args.splice(pos + 1..pos + 1, sub_args);
+    args.splice(1..1, global_args);
+
+    Ok(args)
+}
+
+/// Finds the option (i.e. not a positional argument) named `key`
+fn find_option<'c, 'help>(cmd: &'c Command<'help>, key: &str) -> Result<&'c Arg<'help>, ConfigError> {
+    let long = key.replace('_', "-");
+
+    cmd.get_arguments()
+        .find(|arg| arg.get_long() == Some(long.as_str()))
+        .ok_or_else(|| ConfigError::UnknownOption(key.to_owned()))
+}
+
+/// The index of the subcommand `name` in `argv`, which is the first occurrence that is not the value of an option,
+/// i.e. where everything in front of it parses as global options
+fn subcommand_position(cmd: &Command, argv: &[OsString], name: &str) -> Option<usize> {
+    (1..argv.len())
+        .filter(|&ix| argv[ix] == name)
+        .find(|&ix| match cmd.clone().try_get_matches_from(&argv[..ix]) {
+            Ok(matches) => matches.subcommand().is_none(),
+            Err(e) => matches!(
+                e.kind(),
+                ErrorKind::MissingSubcommand | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+            ),
+        })
+}
+
+/// Turns the options into command line arguments, skipping the ones that were given in `matches`
+fn to_args(options: &[(&Arg, &String, &Value)], matches: &ArgMatches) -> Result<Vec<OsString>, ConfigError> {
+    let mut args = Vec::new();
+
+    for &(arg, key, value) in options {
+        if matches.occurrences_of(arg.get_id()) > 0 {
+            continue;
+        }
+
+        // only options with a long name are found
+        let long = arg.get_long().unwrap_or_default();
+
+        if !arg.is_takes_value_set() {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{long}").into()),
+                Value::Boolean(false) => (),
+                _ => return Err(ConfigError::InvalidValue(key.clone(), "true or false")),
+            }
+
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+
+        for value in values {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Integer(i) => i.to_string(),
+                Value::Float(f) => f.to_string(),
+                _ => return Err(ConfigError::InvalidValue(key.clone(), "a string or a number")),
+            };
+
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, ConfigError};
+    use clap::{Arg, Command};
+    use std::ffi::OsString;
+
+    fn cmd() -> Command<'static> {
+        Command::new("prog")
+            .subcommand_required(true)
+            .arg(Arg::new("file").short('f').long("file").takes_value(true))
+            .arg(Arg::new("system").long("system"))
+            .subcommand(
+                Command::new("save")
+                    .arg(Arg::new("similarity").long("similarity").takes_value(true))
+                    .arg(
+                        Arg::new("pattern")
+                            .long("pattern")
+                            .takes_value(true)
+                            .multiple_occurrences(true),
+                    )
+                    .arg(Arg::new("no-redact").long("no-redact")),
+            )
+            .subcommand(Command::new("restore").arg(Arg::new("rm").long("rm")))
+    }
+
+    fn apply_to(config: &str, argv: &[&str]) -> Result<Vec<String>, ConfigError> {
+        let config = toml::from_str(config).unwrap();
+        let argv = argv.iter().map(OsString::from).collect();
+
+        Ok(apply(&config, &cmd(), argv)?
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    const CONFIG: &str = r#"
+        system = true
+
+        [save]
+        similarity = 0.5
+        pattern = ["a", "b"]
+        no_redact = false
+
+        [restore]
+        rm = true
+    "#;
+
+    #[test]
+    fn config_below_command_line() {
+        assert_eq!(
+            apply_to(CONFIG, &["prog", "-f", "save", "save", "--similarity", "0.9"]).unwrap(),
+            vec![
+                "prog",
+                "--system",
+                "-f",
+                "save",
+                "save",
+                "--pattern=a",
+                "--pattern=b",
+                "--similarity",
+                "0.9"
+            ]
+        );
+
+        assert_eq!(
+            apply_to(CONFIG, &["prog", "--system", "restore"]).unwrap(),
+            vec!["prog", "--system", "restore", "--rm"]
+        );
+
+        // errors are left to the final parse
+        assert_eq!(
+            apply_to(CONFIG, &["prog", "--unknown"]).unwrap(),
+            vec!["prog", "--unknown"]
+        );
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(matches!(
+            apply_to("[load]\nrm = true", &["prog", "restore"]),
+            Err(ConfigError::UnknownSubcommand(s)) if s == "load"
+        ));
+        assert!(matches!(
+            apply_to("[restore]\nsimilarity = 0.5", &["prog", "save"]),
+            Err(ConfigError::UnknownOption(s)) if s == "similarity"
+        ));
+        assert!(matches!(
+            apply_to("[restore]\nrm = 1", &["prog", "restore"]),
+            Err(ConfigError::InvalidValue(s, _)) if s == "rm"
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+//! Saving the session when the user logs out.
+//!
+//! The daemon registers as a client of gnome-session, which asks its clients whether the session may end
+//! (`QueryEndSession`) before it tells them that it ends (`EndSession`) and stops them (`Stop`).
+//! The session is saved on the query, while the shell is still running, and the query is only answered after
+//! saving, as gnome-session waits for the answer before it tears down the shell. A save on `EndSession` is only
+//! attempted if the query was missed (e.g. on a forced logout); it may find the shell already gone,
+//! which never replaces the saved session, see [`SaveOptions::allow_empty`](crate::session::SaveOptions).
+
+use crate::dbus::{ClientPrivateProxy, SessionManagerProxy};
+use std::{env, sync::mpsc};
+
+/// The app id the client is registered under
+const APP_ID: &str = "gnome-session-restore";
+
+/// What gnome-session tells its clients
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ClientSignal {
+    QueryEndSession,
+    EndSession,
+    CancelEndSession,
+    Stop,
+}
+
+/// What to do after a [`ClientSignal`] was handled
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Reply {
+    /// Answer that the session may end
+    EndSessionResponse,
+    Nothing,
+    Stop,
+}
+
+/// Saves the session at most once per end of the session
+struct EndSession<F> {
+    save: F,
+    saved: bool,
+}
+
+impl<F: FnMut() -> bool> EndSession<F> {
+    fn handle(&mut self, signal: ClientSignal) -> Reply {
+        match signal {
+            ClientSignal::QueryEndSession | ClientSignal::EndSession => {
+                if !self.saved {
+                    self.saved = (self.save)();
+                }
+
+                // the logout is never held up by a failed save
+                Reply::EndSessionResponse
+            },
+            ClientSignal::CancelEndSession => {
+                // the session goes on, so the next end has to be saved again
+                self.saved = false;
+                Reply::Nothing
+            },
+            ClientSignal::Stop => Reply::Stop,
+        }
+    }
+}
+
+/// Registers with gnome-session and calls `save` when the session ends, returns once gnome-session stops the
+/// client. `save` returns whether the session was saved.
+pub fn run<F: FnMut() -> bool>(conn: &zbus::Connection, save: F) -> zbus::Result<()> {
+    let session_manager = SessionManagerProxy::new(conn)?;
+
+    // set if the daemon was started by gnome-session from an autostart file
+    let startup_id = env::var("DESKTOP_AUTOSTART_ID").unwrap_or_default();
+    let client_id = session_manager.register_client(APP_ID, &startup_id)?;
+
+    let client = ClientPrivateProxy::new_for_path(conn, client_id.as_str())?;
+    let (tx, rx) = mpsc::channel();
+
+    // the handlers only forward the signals, they are handled after `next_signal` returned
+    let forward = |signal| {
+        let tx = tx.clone();
+        move || {
+            tx.send(signal).ok();
+            Ok(())
+        }
+    };
+
+    let on_query_end_session = forward(ClientSignal::QueryEndSession);
+    let on_end_session = forward(ClientSignal::EndSession);
+
+    client.connect_query_end_session(move |_flags| on_query_end_session())?;
+    client.connect_end_session(move |_flags| on_end_session())?;
+    client.connect_cancel_end_session(forward(ClientSignal::CancelEndSession))?;
+    client.connect_stop(forward(ClientSignal::Stop))?;
+
+    let mut end_session = EndSession { save, saved: false };
+
+    loop {
+        client.next_signal()?;
+
+        for signal in rx.try_iter() {
+            match end_session.handle(signal) {
+                Reply::EndSessionResponse => client.end_session_response(true, "")?,
+                Reply::Nothing => {},
+                Reply::Stop => return session_manager.unregister_client(&client_id),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientSignal, EndSession, Reply};
+    use std::cell::Cell;
+
+    #[test]
+    fn saves_once_per_end_of_session() {
+        let saves = Cell::new(0);
+        let mut end_session = EndSession {
+            save: || {
+                saves.set(saves.get() + 1);
+                true
+            },
+            saved: false,
+        };
+
+        assert_eq!(
+            end_session.handle(ClientSignal::QueryEndSession),
+            Reply::EndSessionResponse
+        );
+        assert_eq!(end_session.handle(ClientSignal::EndSession), Reply::EndSessionResponse);
+
+        // a cancelled logout is saved again the next time
+        assert_eq!(end_session.handle(ClientSignal::CancelEndSession), Reply::Nothing);
+        assert_eq!(end_session.handle(ClientSignal::EndSession), Reply::EndSessionResponse);
+        assert_eq!(end_session.handle(ClientSignal::Stop), Reply::Stop);
+        assert_eq!(saves.get(), 2);
+    }
+
+    #[test]
+    fn retries_failed_save_on_end_session() {
+        let saves = Cell::new(0);
+        let mut end_session = EndSession {
+            save: || {
+                saves.set(saves.get() + 1);
+                false
+            },
+            saved: false,
+        };
+
+        end_session.handle(ClientSignal::QueryEndSession);
+        end_session.handle(ClientSignal::EndSession);
+        assert_eq!(saves.get(), 2);
+    }
+}
@@ -0,0 +1,494 @@
+//! Lenient decoding of the `ListWindows` reply.
+//!
+//! Instead of deserializing the whole reply into `Vec<MetaWindow>` in one go, where a single
+//! malformed record fails the entire call, the reply is first decoded into dynamic values
+//! and every window record is then converted on its own.
+//!
+//! Supported record shapes are
+//! - the positional struct `((iiiib)iusss)` the extension has always sent
+//! - a dict `a{sv}` keyed by the field names of [`MetaWindow`], where missing optional
+//!   fields (e.g. from older extension versions) fall back to their defaults.
+//!   Fields added after the positional struct (e.g. `startup_id`, `title`, `group_id`, `monitor` or `window_type`)
+//!   are only available in this shape.
+//! - either of the above wrapped in a variant (e.g. for a reply of signature `av`)
+
+use super::{MetaWindow, Rect, WindowGeom, WindowType, WINDOW_STATE_KEYS};
+use byteorder::{BigEndian, LittleEndian};
+use std::{collections::BTreeMap, convert::TryFrom};
+use thiserror::Error;
+use zbus::{EndianSig, Message};
+use zvariant::{Array, ArraySeed, Dict, EncodingContext, Signature, Structure, Value};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DecodeError {
+    #[error("missing field `{0}`")]
+    MissingField(&'static str),
+
+    #[error("field `{0}` has an incorrect type")]
+    IncorrectType(&'static str),
+
+    #[error("unexpected record of signature `{0}`")]
+    UnexpectedRecord(String),
+}
+
+pub type WindowRecord = Result<MetaWindow, DecodeError>;
+
+/// Decodes the reply of `ListWindows` into one result per window record
+pub fn decode_window_list(reply: &Message) -> zbus::Result<Vec<WindowRecord>> {
+    let signature = reply.body_signature()?;
+
+    let element_signature = match signature.as_str().strip_prefix('a') {
+        Some(elem) if !elem.is_empty() => Signature::try_from(elem)?,
+        _ => return Err(zbus::Error::Message(zbus::MessageError::UnmatchedBodySignature)),
+    };
+
+    let bytes = reply.as_bytes();
+    let body_len = reply.primary_header()?.body_len() as usize;
+    let body = &bytes[bytes.len() - body_len..];
+
+    decode_body(body, reply.primary_header()?.endian_sig(), element_signature)
+}
+
+/// Decodes a body of the given endianness, which is that of the sender and need not be the native one
+fn decode_body(body: &[u8], endian: EndianSig, element_signature: Signature) -> zbus::Result<Vec<WindowRecord>> {
+    let seed = ArraySeed::new(element_signature);
+
+    // the body always starts 8-aligned
+    let array: Array = match endian {
+        EndianSig::Big => zvariant::from_slice_with_seed(body, EncodingContext::<BigEndian>::new_dbus(0), seed)?,
+        EndianSig::Little => {
+            zvariant::from_slice_with_seed(body, EncodingContext::<LittleEndian>::new_dbus(0), seed)?
+        },
+    };
+
+    Ok(array.get().iter().map(decode_window).collect())
+}
+
+/// Decodes a single window record
+pub fn decode_window(value: &Value) -> WindowRecord {
+    match value {
+        Value::Value(inner) => decode_window(inner),
+        Value::Structure(s) => decode_window_struct(s),
+        Value::Dict(d) => decode_window_dict(d),
+        other => Err(DecodeError::UnexpectedRecord(other.value_signature().to_string())),
+    }
+}
+
+fn decode_window_struct(s: &Structure) -> WindowRecord {
+    match s.fields() {
+        [geom, pid, stable_seq, window_class, gtk_app_id, sandboxed_app_id] => Ok(MetaWindow {
+            geom: decode_geom(geom)?,
+            pid: field(pid, "pid")?,
+            stable_seq: field(stable_seq, "stable_seq")?,
+            window_class: string_field(window_class, "window_class")?,
+            gtk_app_id: string_field(gtk_app_id, "gtk_app_id")?,
+            sandboxed_app_id: string_field(sandboxed_app_id, "sandboxed_app_id")?,
+            ..Default::default()
+        }),
+        _ => Err(DecodeError::UnexpectedRecord(s.signature().to_string())),
+    }
+}
+
+fn decode_window_dict(d: &Dict) -> WindowRecord {
+    let get = |key: &'static str| -> Result<Option<&Value>, DecodeError> {
+        d.get::<str, Value>(key).map_err(|_| DecodeError::IncorrectType(key))
+    };
+
+    let required = |key: &'static str| get(key)?.ok_or(DecodeError::MissingField(key));
+
+    let optional_string = |key: &'static str| -> Result<String, DecodeError> {
+        get(key)?.map_or(Ok(String::new()), |v| string_field(v, key))
+    };
+
+    let mut state = BTreeMap::new();
+    for &key in WINDOW_STATE_KEYS {
+        if let Some(v) = get(key)? {
+            state.insert(key.to_owned(), json_field(v, key)?);
+        }
+    }
+
+    Ok(MetaWindow {
+        geom: decode_geom(required("geom")?)?,
+        pid: field(required("pid")?, "pid")?,
+        stable_seq: get("stable_seq")?.map_or(Ok(0), |v| field(v, "stable_seq"))?,
+        window_class: string_field(required("window_class")?, "window_class")?,
+        gtk_app_id: optional_string("gtk_app_id")?,
+        sandboxed_app_id: optional_string("sandboxed_app_id")?,
+        startup_id: optional_string("startup_id")?,
+        title: optional_string("title")?,
+        group_id: get("group_id")?.map(group_id_field).transpose()?,
+        // windows that are not on any monitor are reported as -1
+        monitor: get("monitor")?
+            .map(|v| field::<i32>(v, "monitor"))
+            .transpose()?
+            .and_then(|m| u32::try_from(m).ok()),
+        flatpak_scope: None,
+        state,
+        // sent as the value of `Meta.WindowType`
+        window_type: get("window_type")?
+            .map(|v| field::<u32>(v, "window_type"))
+            .transpose()?
+            .map_or(WindowType::Normal, WindowType::from_meta),
+        skip_taskbar: get("skip_taskbar")?.map_or(Ok(false), |v| field(v, "skip_taskbar"))?,
+        // windows the user never interacted with are reported as 0
+        user_time: get("user_time")?
+            .map(|v| field::<u64>(v, "user_time"))
+            .transpose()?
+            .filter(|&t| t > 0),
+        local_geom: get("local_geom")?.map(decode_local_geom).transpose()?,
+    })
+}
+
+fn decode_geom(value: &Value) -> Result<WindowGeom, DecodeError> {
+    const NAME: &str = "geom";
+
+    match value {
+        Value::Value(inner) => decode_geom(inner),
+        Value::Structure(s) => match s.fields() {
+            [x, y, width, height, minimized] => Ok(WindowGeom {
+                x: field(x, NAME)?,
+                y: field(y, NAME)?,
+                width: field(width, NAME)?,
+                height: field(height, NAME)?,
+                minimized: field(minimized, NAME)?,
+            }),
+            _ => Err(DecodeError::IncorrectType(NAME)),
+        },
+        _ => Err(DecodeError::IncorrectType(NAME)),
+    }
+}
+
+fn decode_local_geom(value: &Value) -> Result<Rect, DecodeError> {
+    const NAME: &str = "local_geom";
+
+    match value {
+        Value::Value(inner) => decode_local_geom(inner),
+        Value::Structure(s) => match s.fields() {
+            [x, y, width, height] => Ok(Rect {
+                x: field(x, NAME)?,
+                y: field(y, NAME)?,
+                width: field(width, NAME)?,
+                height: field(height, NAME)?,
+            }),
+            _ => Err(DecodeError::IncorrectType(NAME)),
+        },
+        _ => Err(DecodeError::IncorrectType(NAME)),
+    }
+}
+
+fn field<'a, T>(value: &'a Value<'a>, name: &'static str) -> Result<T, DecodeError>
+where
+    T: TryFrom<&'a Value<'a>>,
+{
+    match value {
+        Value::Value(inner) => field(inner, name),
+        v => T::try_from(v).map_err(|_| DecodeError::IncorrectType(name)),
+    }
+}
+
+/// Converts a basic value to json, for the window state which is interpreted when saving instead
+fn json_field(value: &Value, name: &'static str) -> Result<serde_json::Value, DecodeError> {
+    Ok(match value {
+        Value::Value(inner) => return json_field(inner, name),
+        Value::Bool(b) => (*b).into(),
+        Value::U8(n) => (*n).into(),
+        Value::I16(n) => (*n).into(),
+        Value::U16(n) => (*n).into(),
+        Value::I32(n) => (*n).into(),
+        Value::U32(n) => (*n).into(),
+        Value::I64(n) => (*n).into(),
+        Value::U64(n) => (*n).into(),
+        Value::F64(x) => (*x).into(),
+        Value::Str(s) => s.as_str().into(),
+        _ => return Err(DecodeError::IncorrectType(name)),
+    })
+}
+
+/// Group ids are window ids on X11, which are sent as `u` or `t` depending on the extension version
+fn group_id_field(value: &Value) -> Result<u64, DecodeError> {
+    const NAME: &str = "group_id";
+
+    field::<u64>(value, NAME).or_else(|_| field::<u32>(value, NAME).map(u64::from))
+}
+
+fn string_field(value: &Value, name: &'static str) -> Result<String, DecodeError> {
+    match value {
+        Value::Value(inner) => string_field(inner, name),
+        Value::Str(s) => Ok(s.as_str().to_owned()),
+        _ => Err(DecodeError::IncorrectType(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_body, decode_window_list, DecodeError, Rect, WindowType};
+    use byteorder::{BigEndian, LittleEndian};
+    use serde::Serialize;
+    use std::{collections::HashMap, convert::TryFrom};
+    use zbus::{EndianSig, Message};
+    use zvariant::{EncodingContext, Signature, Type, Value};
+
+    fn reply_with<B: Serialize + Type>(body: &B) -> Message {
+        let call = Message::method(
+            None,
+            Some("org.gnome.Shell"),
+            "/com/github/clueliss/WindowCtl",
+            Some("com.github.clueliss.WindowCtl"),
+            "ListWindows",
+            &(),
+        )
+        .unwrap();
+
+        Message::method_reply(None, &call, body).unwrap()
+    }
+
+    type LegacyWindow = ((i32, i32, i32, i32, bool), i32, u32, String, String, String);
+
+    fn window(window_class: &str) -> LegacyWindow {
+        (
+            (1, 2, 3, 4, false),
+            42,
+            7,
+            window_class.to_string(),
+            String::new(),
+            String::new(),
+        )
+    }
+
+    fn window_dict(window_class: &str) -> HashMap<&'static str, Value<'static>> {
+        let mut d = HashMap::new();
+        d.insert("geom", Value::from((1i32, 2i32, 3i32, 4i32, false)));
+        d.insert("pid", Value::from(42i32));
+        d.insert("stable_seq", Value::from(7u32));
+        d.insert("window_class", Value::from(window_class.to_string()));
+        d
+    }
+
+    fn classes(records: &[super::WindowRecord]) -> Vec<Result<&str, &DecodeError>> {
+        records
+            .iter()
+            .map(|r| r.as_ref().map(|w| w.window_class.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn decode_legacy_struct_reply() {
+        let reply = reply_with(&vec![window("firefox"), window("gnome-terminal-server")]);
+        let records = decode_window_list(&reply).unwrap();
+
+        assert_eq!(classes(&records), vec![Ok("firefox"), Ok("gnome-terminal-server")]);
+    }
+
+    #[test]
+    fn decode_body_in_sender_endianness() {
+        let windows = vec![window("firefox"), window("code")];
+        let signature = || Signature::try_from("((iiiib)iusss)").unwrap();
+
+        let big = zvariant::to_bytes(EncodingContext::<BigEndian>::new_dbus(0), &windows).unwrap();
+        let records = decode_body(&big, EndianSig::Big, signature()).unwrap();
+        assert_eq!(classes(&records), vec![Ok("firefox"), Ok("code")]);
+        assert_eq!(records[0].as_ref().unwrap().pid, 42);
+
+        let little = zvariant::to_bytes(EncodingContext::<LittleEndian>::new_dbus(0), &windows).unwrap();
+        let records = decode_body(&little, EndianSig::Little, signature()).unwrap();
+        assert_eq!(classes(&records), vec![Ok("firefox"), Ok("code")]);
+        assert_eq!(records[0].as_ref().unwrap().pid, 42);
+    }
+
+    #[test]
+    fn decode_dict_reply_with_missing_optional_fields() {
+        let reply = reply_with(&vec![window_dict("firefox")]);
+        let records = decode_window_list(&reply).unwrap();
+        let w = records[0].as_ref().unwrap();
+
+        assert_eq!(w.window_class, "firefox");
+        assert_eq!(w.pid, 42);
+        assert_eq!(w.geom.width, 3);
+        assert!(w.gtk_app_id.is_empty());
+        assert!(w.sandboxed_app_id.is_empty());
+        assert!(w.startup_id.is_empty());
+    }
+
+    #[test]
+    fn decode_dict_reply_with_startup_id() {
+        let mut d = window_dict("firefox");
+        d.insert("startup_id", Value::from("gnome-session-restore-1-0_TIME0".to_string()));
+
+        let reply = reply_with(&vec![d]);
+        let records = decode_window_list(&reply).unwrap();
+
+        assert_eq!(
+            records[0].as_ref().unwrap().startup_id,
+            "gnome-session-restore-1-0_TIME0"
+        );
+    }
+
+    #[test]
+    fn decode_dict_reply_with_group_id() {
+        let mut small = window_dict("firefox");
+        small.insert("group_id", Value::from(42u32));
+
+        let mut large = window_dict("firefox");
+        large.insert("group_id", Value::from(42u64));
+
+        let reply = reply_with(&vec![small, large, window_dict("code")]);
+        let group_ids: Vec<_> = decode_window_list(&reply)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap().group_id)
+            .collect();
+
+        assert_eq!(group_ids, vec![Some(42), Some(42), None]);
+    }
+
+    #[test]
+    fn decode_dict_reply_with_monitor() {
+        let mut on_monitor = window_dict("firefox");
+        on_monitor.insert("monitor", Value::from(1i32));
+
+        let mut off_monitor = window_dict("firefox");
+        off_monitor.insert("monitor", Value::from(-1i32));
+
+        let reply = reply_with(&vec![on_monitor, off_monitor, window_dict("code")]);
+        let monitors: Vec<_> = decode_window_list(&reply)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap().monitor)
+            .collect();
+
+        assert_eq!(monitors, vec![Some(1), None, None]);
+    }
+
+    #[test]
+    fn decode_dict_reply_with_window_type() {
+        let mut dialog = window_dict("firefox");
+        dialog.insert("window_type", Value::from(4u32));
+        dialog.insert("skip_taskbar", Value::from(true));
+
+        let reply = reply_with(&vec![dialog, window_dict("firefox")]);
+        let types: Vec<_> = decode_window_list(&reply)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.map(|w| (w.window_type, w.skip_taskbar)).unwrap())
+            .collect();
+
+        assert_eq!(
+            types,
+            vec![(WindowType::ModalDialog, true), (WindowType::Normal, false)]
+        );
+    }
+
+    #[test]
+    fn decode_dict_reply_with_local_geom() {
+        let mut on_monitor = window_dict("firefox");
+        on_monitor.insert("monitor", Value::from(1i32));
+        on_monitor.insert("local_geom", Value::from((10i32, 20i32, 3i32, 4i32)));
+
+        let mut malformed = window_dict("code");
+        malformed.insert("local_geom", Value::from((10i32, 20i32)));
+
+        let reply = reply_with(&vec![on_monitor, malformed, window_dict("kitty")]);
+        let windows = decode_window_list(&reply).unwrap();
+
+        assert_eq!(
+            windows[0].as_ref().unwrap().local_geom,
+            Some(Rect { x: 10, y: 20, width: 3, height: 4 })
+        );
+        assert!(matches!(windows[1], Err(DecodeError::IncorrectType("local_geom"))));
+        assert_eq!(windows[2].as_ref().unwrap().local_geom, None);
+    }
+
+    #[test]
+    fn decode_dict_reply_with_user_time() {
+        let mut used = window_dict("firefox");
+        used.insert("user_time", Value::from(1_700_000_000u64));
+
+        let mut unused = window_dict("firefox");
+        unused.insert("user_time", Value::from(0u64));
+
+        let reply = reply_with(&vec![used, unused, window_dict("code")]);
+        let user_times: Vec<_> = decode_window_list(&reply)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap().user_time)
+            .collect();
+
+        assert_eq!(user_times, vec![Some(1_700_000_000), None, None]);
+    }
+
+    #[test]
+    fn decode_dict_reply_with_window_state() {
+        let mut d = window_dict("firefox");
+        d.insert("above", Value::from(true));
+        d.insert("opacity", Value::from(200u32));
+        d.insert("unknown", Value::from(1u32));
+
+        let reply = reply_with(&vec![d, window_dict("code")]);
+        let states: Vec<_> = decode_window_list(&reply)
+            .unwrap()
+            .into_iter()
+            .map(|r| serde_json::to_string(&r.unwrap().state).unwrap())
+            .collect();
+
+        assert_eq!(states, vec![r#"{"above":true,"opacity":200}"#, "{}"]);
+    }
+
+    #[test]
+    fn decode_dict_reply_skips_corrupted_records() {
+        let mut missing_pid = window_dict("missing-pid");
+        missing_pid.remove("pid");
+
+        let mut bad_class = window_dict("bad-class");
+        bad_class.insert("window_class", Value::from(1u32));
+
+        let mut bad_geom = window_dict("bad-geom");
+        bad_geom.insert("geom", Value::from((1i32, 2i32)));
+
+        let reply = reply_with(&vec![
+            window_dict("firefox"),
+            missing_pid,
+            bad_class,
+            bad_geom,
+            window_dict("code"),
+        ]);
+        let records = decode_window_list(&reply).unwrap();
+
+        assert_eq!(
+            classes(&records),
+            vec![
+                Ok("firefox"),
+                Err(&DecodeError::MissingField("pid")),
+                Err(&DecodeError::IncorrectType("window_class")),
+                Err(&DecodeError::IncorrectType("geom")),
+                Ok("code"),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_variant_reply() {
+        let body: Vec<Value> = vec![
+            Value::from(window_dict("firefox")),
+            Value::from(42u32),
+            Value::from(window_dict("code")),
+        ];
+
+        let reply = reply_with(&body);
+        let records = decode_window_list(&reply).unwrap();
+
+        assert_eq!(
+            classes(&records),
+            vec![
+                Ok("firefox"),
+                Err(&DecodeError::UnexpectedRecord("u".to_string())),
+                Ok("code")
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_non_array_reply() {
+        let reply = reply_with(&42u32);
+        assert!(decode_window_list(&reply).is_err());
+    }
+}
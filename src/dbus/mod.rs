@@ -0,0 +1,277 @@
+pub mod decode;
+
+use crate::find_command::flatpak::FlatpakScope;
+use decode::WindowRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zbus::dbus_proxy;
+use zvariant::derive::Type;
+
+#[dbus_proxy(
+    interface = "com.github.clueliss.WindowCtl",
+    default_service = "org.gnome.Shell",
+    default_path = "/com/github/clueliss/WindowCtl"
+)]
+pub trait WindowCtl {
+    fn get_num_monitors(&self) -> zbus::Result<u32>;
+    fn set_window_geom_by_class(&self, window_class: &str, window_geom: WindowGeom) -> zbus::Result<bool>;
+
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn set_window_geom_by_stable_seq(&self, stable_seq: u32, window_geom: WindowGeom) -> zbus::Result<bool>;
+
+    /// Moves several windows at once, returning whether each one was moved.
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn set_window_geoms_by_stable_seq(&self, window_geoms: &[(u32, WindowGeom)]) -> zbus::Result<Vec<bool>>;
+
+    /// The work area (i.e. without panels) of the primary monitor.
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn get_primary_work_area(&self) -> zbus::Result<Rect>;
+
+    /// The work areas of all monitors, indexed like [`MetaWindow::monitor`].
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn get_monitor_work_areas(&self) -> zbus::Result<Vec<Rect>>;
+
+    /// The scale factors of all monitors (e.g. 2.0 for HiDPI), indexed like [`MetaWindow::monitor`].
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn get_monitor_scales(&self) -> zbus::Result<Vec<f64>>;
+
+    /// Sets one of the [`WINDOW_STATE_KEYS`] of a window, returning whether the window was found.
+    /// Only available in newer versions of the extension, see [`is_unknown_method`]
+    fn set_window_state_by_stable_seq(&self, stable_seq: u32, key: &str, value: &zvariant::Value)
+        -> zbus::Result<bool>;
+}
+
+/// The fields of dict window records that describe the state of a window beyond its geometry,
+/// they are only reported by newer versions of the extension and collected in [`MetaWindow::state`]
+pub const WINDOW_STATE_KEYS: &[&str] = &[
+    "above",
+    "sticky",
+    "opacity",
+    "tile",
+    "fullscreen_monitor",
+    "theme_variant",
+];
+
+#[dbus_proxy(
+    interface = "org.gnome.SessionManager",
+    default_service = "org.gnome.SessionManager",
+    default_path = "/org/gnome/SessionManager"
+)]
+pub trait SessionManager {
+    /// Returns a cookie identifying the inhibitor, which is passed to [`SessionManagerProxy::uninhibit`]
+    fn inhibit(&self, app_id: &str, toplevel_xid: u32, reason: &str, flags: u32) -> zbus::Result<u32>;
+    fn uninhibit(&self, inhibit_cookie: u32) -> zbus::Result<()>;
+
+    /// Registers a client that is told about the end of the session, returns the path of its [`ClientPrivateProxy`]
+    fn register_client(&self, app_id: &str, client_startup_id: &str) -> zbus::Result<zvariant::OwnedObjectPath>;
+    fn unregister_client(&self, client_id: &zvariant::ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+/// The interface through which gnome-session talks to a registered client, its path is returned by
+/// [`SessionManagerProxy::register_client`]
+#[dbus_proxy(
+    interface = "org.gnome.SessionManager.ClientPrivate",
+    default_service = "org.gnome.SessionManager"
+)]
+pub trait ClientPrivate {
+    /// Answers [`query_end_session`](Self::connect_query_end_session) and
+    /// [`end_session`](Self::connect_end_session), the session waits for it before it ends
+    fn end_session_response(&self, is_ok: bool, reason: &str) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn query_end_session(&self, flags: u32) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn end_session(&self, flags: u32) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn cancel_end_session(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn stop(&self) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.gnome.Shell",
+    default_service = "org.gnome.Shell",
+    default_path = "/org/gnome/Shell"
+)]
+pub trait Shell {
+    #[dbus_proxy(property)]
+    fn shell_version(&self) -> zbus::Result<String>;
+}
+
+/// The version of the running gnome-shell, see [`session::compat`](crate::session::compat)
+pub fn shell_version(conn: &zbus::Connection) -> zbus::Result<String> {
+    ShellProxy::new(conn)?.shell_version()
+}
+
+/// Checks whether the error was caused by calling a method the other side does not know about,
+/// e.g. because an older version of the extension is installed
+pub fn is_unknown_method(e: &zbus::Error) -> bool {
+    matches!(e, zbus::Error::MethodError(name, ..) if name == "org.freedesktop.DBus.Error.UnknownMethod")
+}
+
+/// The names of D-Bus errors that may go away by themselves, e.g. because the other side was too busy to reply
+pub const TRANSIENT_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Timeout",
+    "org.freedesktop.DBus.Error.TimedOut",
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.UnknownObject",
+    "org.freedesktop.DBus.Error.LimitsExceeded",
+];
+
+/// The names of D-Bus errors with which a call was refused before it was handled, unlike a missing reply they
+/// guarantee that nothing was done. Only these are retried for calls that must not be done twice, like activating
+/// an application.
+pub const REFUSED_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.LimitsExceeded",
+    "org.freedesktop.DBus.Error.NoMemory",
+    "org.freedesktop.DBus.Error.Spawn.ForkFailed",
+];
+
+/// Checks whether the error may go away by itself, e.g. because the shell was too busy to reply in time
+/// or the extension was being reloaded
+pub fn is_transient(e: &zbus::Error) -> bool {
+    match e {
+        zbus::Error::Io(_) => true,
+        zbus::Error::MethodError(name, ..) => TRANSIENT_ERRORS.contains(&name.as_str()),
+        _ => false,
+    }
+}
+
+impl WindowCtlProxy<'_> {
+    /// Lists all windows, every window record is decoded independently
+    /// so that a single malformed record does not fail the whole call
+    pub fn list_windows(&self) -> zbus::Result<Vec<WindowRecord>> {
+        let reply = self.call_method("ListWindows", &())?;
+        decode::decode_window_list(&reply)
+    }
+}
+
+/// The geometry of a window, always its frame rect, i.e. the visible window without the invisible shadow margins
+/// that applications with client side decorations (e.g. most GTK4 applications) draw around it.
+/// The extension reports and sets the same rect, so that restoring a saved geometry does not move the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, Type)]
+pub struct WindowGeom {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub minimized: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, Type)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MetaWindow {
+    pub geom: WindowGeom,
+    pub pid: i32,
+    pub stable_seq: u32,
+    pub window_class: String,
+    pub gtk_app_id: String,
+    pub sandboxed_app_id: String,
+
+    /// The startup notification id the window was opened with, this is only meaningful while
+    /// the window is open and thus never saved
+    #[serde(skip)]
+    pub startup_id: String,
+
+    /// The title of the window, only used to recognize windows while they are open and never saved
+    #[serde(skip)]
+    pub title: String,
+
+    /// Identifies the group (e.g. of tabbed windows) the window belongs to, if any.
+    /// Only used to warn about groups, which cannot be restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<u64>,
+
+    /// The index of the monitor the window is on, only reported by newer versions of the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<u32>,
+
+    /// The flatpak installation the application runs from, not reported by the extension but read from /proc when saving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flatpak_scope: Option<FlatpakScope>,
+
+    /// The [`WINDOW_STATE_KEYS`] the extension reported for the window, it is saved via
+    /// [`state_hooks`](crate::session::state_hooks) and thus never serialized with the window
+    #[serde(skip)]
+    pub state: BTreeMap<String, serde_json::Value>,
+
+    /// The type of the window, only reported by newer versions of the extension.
+    /// Windows of other types than [`WindowType::Normal`] are not saved unless dialogs are included.
+    #[serde(default, skip_serializing_if = "WindowType::is_normal")]
+    pub window_type: WindowType,
+
+    /// Whether the window is left out of the taskbar (and alt-tab), like most transient windows.
+    /// Only reported by newer versions of the extension, and only used to leave out such windows when saving.
+    #[serde(skip)]
+    pub skip_taskbar: bool,
+
+    /// When the user last interacted with the window, in seconds since the unix epoch. Only reported by newer
+    /// versions of the extension, which convert mutter's user time to wall-clock time, see [`age`](crate::session::age).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_time: Option<u64>,
+
+    /// The frame rect relative to the top left corner of the work area of [`monitor`](Self::monitor), only reported
+    /// by newer versions of the extension. `geom` stays the absolute rect that windows are placed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_geom: Option<Rect>,
+}
+
+#[cfg(test)]
+impl MetaWindow {
+    /// A window of the given class, with everything else left at its default
+    pub fn with_class(window_class: &str) -> Self {
+        MetaWindow { window_class: window_class.to_owned(), ..Default::default() }
+    }
+}
+
+/// The type of a window as mutter reports it (`Meta.WindowType`)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowType {
+    #[default]
+    Normal,
+    Desktop,
+    Dock,
+    Dialog,
+    ModalDialog,
+    Toolbar,
+    Menu,
+    Utility,
+    Splashscreen,
+
+    /// Override redirect windows like popup menus and tooltips, which are never listed anyway
+    Other,
+}
+
+impl WindowType {
+    /// The window type for a value of `Meta.WindowType`
+    pub fn from_meta(value: u32) -> Self {
+        match value {
+            0 => WindowType::Normal,
+            1 => WindowType::Desktop,
+            2 => WindowType::Dock,
+            3 => WindowType::Dialog,
+            4 => WindowType::ModalDialog,
+            5 => WindowType::Toolbar,
+            6 => WindowType::Menu,
+            7 => WindowType::Utility,
+            8 => WindowType::Splashscreen,
+            _ => WindowType::Other,
+        }
+    }
+
+    pub fn is_normal(&self) -> bool {
+        *self == WindowType::Normal
+    }
+}
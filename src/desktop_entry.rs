@@ -0,0 +1,392 @@
+//! Reading of desktop entries, independent of gio's `DesktopAppInfo`.
+//!
+//! gio refuses to load entries for a number of reasons without saying which one,
+//! so the entry is read here again to find out why and to get at its `Exec` line.
+
+use gio::glib::{KeyFile, KeyFileFlags};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+use thiserror::Error;
+
+const GROUP: &str = "Desktop Entry";
+
+/// The locales localized keys are looked up with, see [`locale_chain`], for the locale of messages, which is the first
+/// of `$LC_ALL`, `$LC_MESSAGES` and `$LANG` that is set
+static MESSAGES_LOCALES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .map_or_else(Vec::new, |locale| locale_chain(&locale))
+});
+
+/// The locales a localized key like `Name[sr_YU@Latn]` is looked up with for the locale
+/// `lang_COUNTRY.ENCODING@MODIFIER`, best first, as in the desktop entry specification:
+/// `lang_COUNTRY@MODIFIER`, `lang_COUNTRY`, `lang@MODIFIER` and `lang`, as far as the locale has these parts.
+/// The encoding is ignored, and the `C` and `POSIX` locales have no translations.
+pub fn locale_chain(locale: &str) -> Vec<String> {
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    let rest = rest.split_once('.').map_or(rest, |(rest, _encoding)| rest);
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+
+    if matches!(lang, "" | "C" | "POSIX") {
+        return Vec::new();
+    }
+
+    let mut chain = Vec::new();
+
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        chain.push(format!("{lang}_{country}@{modifier}"));
+    }
+    if let Some(country) = country {
+        chain.push(format!("{lang}_{country}"));
+    }
+    if let Some(modifier) = modifier {
+        chain.push(format!("{lang}@{modifier}"));
+    }
+    chain.push(lang.to_owned());
+
+    chain
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ExecParseError {
+    #[error("empty Exec line")]
+    Empty,
+
+    #[error("unterminated quote in Exec line")]
+    UnterminatedQuote,
+}
+
+/// The values the field codes of an `Exec` line expand to
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FieldCodes<'a> {
+    /// The translated name of the application, for `%c`
+    pub name: Option<&'a str>,
+
+    /// The icon of the application, for `%i`
+    pub icon: Option<&'a str>,
+
+    /// The location of the desktop file, for `%k`
+    pub location: Option<&'a Path>,
+}
+
+/// Splits an `Exec` line into argv as described by the desktop entry specification.
+///
+/// Arguments are separated by spaces and may be quoted with double quotes, inside of which
+/// `"`, `` ` ``, `$` and `\` are escaped with a backslash. File and URL field codes are removed
+/// since nothing is passed to the application, the others are expanded from `codes`.
+pub fn parse_exec(exec: &str, codes: FieldCodes) -> Result<Vec<String>, ExecParseError> {
+    let mut argv = Vec::new();
+
+    for arg in split_exec(exec)? {
+        match arg.as_str() {
+            "%f" | "%F" | "%u" | "%U" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => (),
+            "%i" => {
+                if let Some(icon) = codes.icon {
+                    argv.extend(["--icon".to_owned(), icon.to_owned()]);
+                }
+            },
+            _ => argv.push(expand_field_codes(&arg, codes)),
+        }
+    }
+
+    if argv.is_empty() {
+        Err(ExecParseError::Empty)
+    } else {
+        Ok(argv)
+    }
+}
+
+fn split_exec(exec: &str) -> Result<Vec<String>, ExecParseError> {
+    let mut args = Vec::new();
+    let mut cur: Option<String> = None;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => args.extend(cur.take()),
+            '"' => {
+                let arg = cur.get_or_insert_with(String::new);
+
+                loop {
+                    match chars.next().ok_or(ExecParseError::UnterminatedQuote)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or(ExecParseError::UnterminatedQuote)? {
+                            escaped @ ('"' | '`' | '$' | '\\') => arg.push(escaped),
+                            other => arg.extend(['\\', other]),
+                        },
+                        other => arg.push(other),
+                    }
+                }
+            },
+            other => cur.get_or_insert_with(String::new).push(other),
+        }
+    }
+
+    args.extend(cur);
+    Ok(args)
+}
+
+fn expand_field_codes(arg: &str, codes: FieldCodes) -> String {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('c') => expanded.push_str(codes.name.unwrap_or_default()),
+            Some('k') => expanded.push_str(&codes.location.map(|p| p.to_string_lossy()).unwrap_or_default()),
+            // unknown and deprecated field codes are dropped
+            _ => (),
+        }
+    }
+
+    expanded
+}
+
+/// The parts of a desktop entry needed to diagnose and work around launch failures
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopEntry {
+    pub path: PathBuf,
+
+    /// The name as glib translates it, for display
+    pub name: Option<String>,
+
+    /// The untranslated `Name`
+    pub untranslated_name: Option<String>,
+
+    /// The `Name[..]` of the best locale of the user's [`locale_chain`] that has one
+    pub localized_name: Option<String>,
+
+    pub icon: Option<String>,
+    pub exec: Option<String>,
+    pub try_exec: Option<String>,
+    pub startup_wm_class: Option<String>,
+
+    /// `SingleMainWindow`: launching the application again only focuses its window instead of opening another one
+    pub single_main_window: bool,
+
+    /// `StartupNotify`, `None` if the entry does not say whether the application sends startup notifications
+    pub startup_notify: Option<bool>,
+}
+
+impl DesktopEntry {
+    pub fn load(path: &Path) -> Result<Self, gio::glib::Error> {
+        Self::load_with_locales(path, &MESSAGES_LOCALES)
+    }
+
+    /// Loads the entry with the localized keys looked up in `locales`, best first
+    pub fn load_with_locales(path: &Path, locales: &[String]) -> Result<Self, gio::glib::Error> {
+        // without it, glib drops the translations for other locales than its own
+        let key_file = KeyFile::new();
+        key_file.load_from_file(path, KeyFileFlags::KEEP_TRANSLATIONS)?;
+
+        // fails if the file is not a desktop entry at all
+        key_file.string(GROUP, "Type")?;
+
+        let string = |key: &str| key_file.string(GROUP, key).ok().map(String::from);
+
+        Ok(DesktopEntry {
+            path: path.to_owned(),
+            name: key_file.locale_string(GROUP, "Name", None).ok().map(String::from),
+            untranslated_name: string("Name"),
+            localized_name: locales.iter().find_map(|locale| string(&format!("Name[{locale}]"))),
+            icon: string("Icon"),
+            exec: string("Exec"),
+            try_exec: string("TryExec"),
+            startup_wm_class: string("StartupWMClass"),
+            single_main_window: key_file.boolean(GROUP, "SingleMainWindow").unwrap_or(false),
+            startup_notify: key_file.boolean(GROUP, "StartupNotify").ok(),
+        })
+    }
+
+    /// The binary of the entry if it is not installed (anymore), which is the `TryExec` binary or,
+    /// lacking that, the program of the `Exec` line. Entries without a valid `Exec` line have no binary to miss.
+    pub fn missing_binary(&self) -> Option<String> {
+        let bin = match &self.try_exec {
+            Some(bin) => bin.clone(),
+            None => self.argv()?.ok()?.into_iter().next()?,
+        };
+
+        find_executable(&bin).is_none().then_some(bin)
+    }
+
+    pub fn argv(&self) -> Option<Result<Vec<String>, ExecParseError>> {
+        let codes = FieldCodes {
+            name: self.name.as_deref(),
+            icon: self.icon.as_deref(),
+            location: Some(&self.path),
+        };
+
+        self.exec.as_deref().map(|exec| parse_exec(exec, codes))
+    }
+}
+
+/// Finds an executable by path or by name in `PATH`
+pub fn find_executable(bin: &str) -> Option<PathBuf> {
+    let is_executable = |p: &Path| {
+        p.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+
+    if bin.contains('/') {
+        let p = PathBuf::from(bin);
+        return is_executable(&p).then_some(p);
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(bin))
+        .find(|p| is_executable(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{locale_chain, parse_exec, DesktopEntry, ExecParseError, FieldCodes};
+    use std::path::{Path, PathBuf};
+
+    fn parse(exec: &str) -> Result<Vec<String>, ExecParseError> {
+        let codes = FieldCodes {
+            name: Some("Firefox"),
+            icon: Some("firefox"),
+            location: Some(Path::new("/usr/share/applications/firefox.desktop")),
+        };
+
+        parse_exec(exec, codes)
+    }
+
+    #[test]
+    fn removes_file_and_url_field_codes() {
+        assert_eq!(parse("firefox %u").unwrap(), vec!["firefox"]);
+        assert_eq!(parse("code --new-window %F").unwrap(), vec!["code", "--new-window"]);
+    }
+
+    #[test]
+    fn expands_field_codes() {
+        assert_eq!(
+            parse("app %i --name=%c --desktop-file %k 100%%").unwrap(),
+            vec![
+                "app",
+                "--icon",
+                "firefox",
+                "--name=Firefox",
+                "--desktop-file",
+                "/usr/share/applications/firefox.desktop",
+                "100%"
+            ]
+        );
+
+        assert_eq!(parse_exec("app %i", FieldCodes::default()).unwrap(), vec!["app"]);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            parse(r#""/opt/My App/app" --title "a \"b\" \$c \\d" x"y"z"#).unwrap(),
+            vec!["/opt/My App/app", "--title", r#"a "b" $c \d"#, "xyz"]
+        );
+
+        assert_eq!(
+            parse(r#"sh -c "echo \"%%\"""#).unwrap(),
+            vec!["sh", "-c", r#"echo "%""#]
+        );
+    }
+
+    #[test]
+    fn missing_binaries() {
+        let entry = |exec: Option<&str>, try_exec: Option<&str>| DesktopEntry {
+            path: PathBuf::from("/usr/share/applications/app.desktop"),
+            name: None,
+            untranslated_name: None,
+            localized_name: None,
+            icon: None,
+            exec: exec.map(String::from),
+            try_exec: try_exec.map(String::from),
+            startup_wm_class: None,
+            single_main_window: false,
+            startup_notify: None,
+        };
+
+        assert_eq!(entry(Some("sh -c true"), None).missing_binary(), None);
+        assert_eq!(entry(Some("/bin/sh %U"), None).missing_binary(), None);
+        assert_eq!(
+            entry(Some("uninstalled-app %U"), None).missing_binary().as_deref(),
+            Some("uninstalled-app")
+        );
+        assert_eq!(
+            entry(Some("sh"), Some("/nonexistent/app")).missing_binary().as_deref(),
+            Some("/nonexistent/app")
+        );
+        assert_eq!(entry(Some("uninstalled-app"), Some("sh")).missing_binary(), None);
+        assert_eq!(entry(None, None).missing_binary(), None);
+    }
+
+    #[test]
+    fn locale_chains() {
+        // the examples of the desktop entry specification
+        assert_eq!(locale_chain("sr_YU@Latn"), ["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]);
+        assert_eq!(locale_chain("sr_YU"), ["sr_YU", "sr"]);
+        assert_eq!(locale_chain("sr@Latn"), ["sr@Latn", "sr"]);
+        assert_eq!(locale_chain("sr"), ["sr"]);
+
+        // the encoding is not part of the lookup
+        assert_eq!(locale_chain("de_DE.UTF-8"), ["de_DE", "de"]);
+        assert_eq!(
+            locale_chain("sr_RS.UTF-8@latin"),
+            ["sr_RS@latin", "sr_RS", "sr@latin", "sr"]
+        );
+
+        assert!(locale_chain("C").is_empty());
+        assert!(locale_chain("C.UTF-8").is_empty());
+        assert!(locale_chain("POSIX").is_empty());
+        assert!(locale_chain("").is_empty());
+    }
+
+    #[test]
+    fn localized_names() {
+        let path = std::env::temp_dir().join(format!("gnome-session-restore-locale-{}.desktop", std::process::id()));
+        std::fs::write(
+            &path,
+            "[Desktop Entry]\nType=Application\nExec=app\nName=Files\nName[sr]=Датотеке\nName[sr@Latn]=Datoteke\nName[de]=Dateien\n",
+        )
+        .unwrap();
+
+        let localized = |locale: &str| {
+            DesktopEntry::load_with_locales(&path, &locale_chain(locale))
+                .unwrap()
+                .localized_name
+        };
+
+        assert_eq!(localized("sr_YU@Latn").as_deref(), Some("Datoteke"));
+        assert_eq!(localized("sr_YU").as_deref(), Some("Датотеке"));
+        assert_eq!(localized("de_AT.UTF-8").as_deref(), Some("Dateien"));
+        assert_eq!(localized("fr_FR"), None);
+        assert_eq!(localized("C"), None);
+
+        let entry = DesktopEntry::load_with_locales(&path, &[]).unwrap();
+        assert_eq!(entry.untranslated_name.as_deref(), Some("Files"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(parse(r#"app "unterminated"#), Err(ExecParseError::UnterminatedQuote));
+        assert_eq!(parse("  "), Err(ExecParseError::Empty));
+        assert_eq!(parse("%U"), Err(ExecParseError::Empty));
+    }
+}
@@ -0,0 +1,107 @@
+//! Well-known window classes that do not resemble the name of their desktop file.
+//!
+//! Fuzzy matching handles these inconsistently (e.g. firefox' main window has the class `Navigator`),
+//! so they are looked up here first. The built-in table can be extended with `--alias`.
+
+use std::{collections::BTreeMap, str::FromStr};
+use thiserror::Error;
+
+/// The built-in aliases, the window classes are lowercase
+pub const BUILTIN_ALIASES: &[(&str, &str)] = &[
+    ("navigator", "firefox"),
+    ("gimp-2.10", "gimp"),
+    ("soffice", "libreoffice-startcenter"),
+    ("libreoffice", "libreoffice-startcenter"),
+    ("code - insiders", "code-insiders"),
+    ("gnome-terminal-server", "org.gnome.Terminal"),
+    ("telegramdesktop", "org.telegram.desktop"),
+    ("chromium-browser", "chromium"),
+    ("signal", "signal-desktop"),
+];
+
+/// A window class and the name of the desktop file its windows belong to, parsed from `CLASS=NAME`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    pub window_class: String,
+    pub desktop_file: String,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("expected CLASS=NAME, e.g. `Navigator=firefox`")]
+pub struct AliasParseError;
+
+impl FromStr for Alias {
+    type Err = AliasParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // window classes may contain anything, desktop file names do not contain `=`
+        match s.rsplit_once('=') {
+            Some((window_class, desktop_file)) if !window_class.is_empty() && !desktop_file.is_empty() => Ok(Alias {
+                window_class: window_class.to_owned(),
+                desktop_file: desktop_file.to_owned(),
+            }),
+            _ => Err(AliasParseError),
+        }
+    }
+}
+
+/// The desktop file names of window classes, which are matched case-insensitively
+#[derive(Debug, Clone)]
+pub struct Aliases {
+    map: BTreeMap<String, String>,
+}
+
+impl Default for Aliases {
+    fn default() -> Self {
+        Aliases::builtin()
+    }
+}
+
+impl Aliases {
+    pub fn builtin() -> Self {
+        Aliases {
+            map: BUILTIN_ALIASES
+                .iter()
+                .map(|&(window_class, desktop_file)| (window_class.to_owned(), desktop_file.to_owned()))
+                .collect(),
+        }
+    }
+
+    /// Adds the given aliases, replacing built-in ones for the same window class
+    pub fn extend<I: IntoIterator<Item = Alias>>(&mut self, aliases: I) {
+        self.map.extend(
+            aliases
+                .into_iter()
+                .map(|alias| (alias.window_class.to_lowercase(), alias.desktop_file)),
+        );
+    }
+
+    /// The desktop file name of `window_class`, if it has an alias
+    pub fn get(&self, window_class: &str) -> Option<&str> {
+        self.map.get(&window_class.to_lowercase()).map(String::as_str)
+    }
+
+    /// The aliases ordered by window class
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.map.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alias, AliasParseError, Aliases};
+
+    #[test]
+    fn extend() {
+        let mut aliases = Aliases::builtin();
+        aliases.extend(["Navigator=firefox-esr", "My=App=my-app"].map(|s| s.parse::<Alias>().unwrap()));
+
+        assert_eq!(aliases.get("Navigator"), Some("firefox-esr"));
+        assert_eq!(aliases.get("my=app"), Some("my-app"));
+        assert_eq!(aliases.get("Gimp-2.10"), Some("gimp"));
+        assert_eq!(aliases.get("firefox"), None);
+
+        assert_eq!("Navigator".parse::<Alias>(), Err(AliasParseError));
+        assert_eq!("=firefox".parse::<Alias>(), Err(AliasParseError));
+    }
+}
@@ -0,0 +1,167 @@
+//! Sweeping the confidence thresholds of [`FindOptions`] over labeled testsets (see [`testsets`](super::testsets)),
+//! so that the defaults can be changed based on how many windows are found correctly instead of guesswork.
+
+use super::{
+    testsets::Expectation, try_find_command_any, Aliases, Capabilities, Confidence, DesktopFileIndex, FindOptions,
+    DEFAULT_FIND_ORDER,
+};
+use crate::{dbus::MetaWindow, session::Exec};
+
+/// The thresholds that are tried for both confidences
+pub fn default_thresholds() -> Vec<Confidence> {
+    (0..=20).map(|i| i as Confidence / 20.0).collect()
+}
+
+/// The desktop files of a testset and the windows that should be found in them
+pub struct Corpus<'t> {
+    pub index: DesktopFileIndex,
+    pub expectations: Vec<Expectation<'t>>,
+}
+
+/// How well the finder did with one pair of thresholds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sweep {
+    pub min_wm_class_similarity: Confidence,
+    pub min_partial_match_confidence: Confidence,
+
+    /// Windows for which the expected desktop file was found
+    pub correct: usize,
+
+    /// Windows for which a different desktop file was found, or one although none was expected
+    pub wrong: usize,
+
+    /// Windows for which nothing was found although a desktop file was expected
+    pub missed: usize,
+}
+
+impl Sweep {
+    /// The share of found desktop files that were the expected ones
+    pub fn precision(&self) -> f32 {
+        ratio(self.correct, self.correct + self.wrong)
+    }
+
+    /// The share of expected desktop files that were found
+    pub fn recall(&self) -> f32 {
+        ratio(self.correct, self.correct + self.missed)
+    }
+
+    /// The harmonic mean of precision and recall
+    pub fn f1(&self) -> f32 {
+        let (p, r) = (self.precision(), self.recall());
+
+        if p + r == 0.0 {
+            0.0
+        } else {
+            2.0 * p * r / (p + r)
+        }
+    }
+}
+
+fn ratio(n: usize, total: usize) -> f32 {
+    if total == 0 {
+        1.0
+    } else {
+        n as f32 / total as f32
+    }
+}
+
+fn window(e: &Expectation) -> MetaWindow {
+    MetaWindow {
+        window_class: e.window_class.to_owned(),
+        gtk_app_id: e.gtk_app_id.to_owned(),
+        sandboxed_app_id: e.sandboxed_app_id.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Runs the finder over all `corpora` for every pair of `thresholds`.
+/// /proc is not used, as the processes of the labeled windows are not running.
+pub fn sweep(corpora: &[Corpus], aliases: &Aliases, thresholds: &[Confidence]) -> Vec<Sweep> {
+    let mut sweeps = Vec::with_capacity(thresholds.len() * thresholds.len());
+
+    for &min_wm_class_similarity in thresholds {
+        for &min_partial_match_confidence in thresholds {
+            let options = FindOptions {
+                min_wm_class_similarity,
+                min_partial_match_confidence,
+                capabilities: Capabilities::none(),
+                order: &DEFAULT_FIND_ORDER,
+                allow_missing_binaries: false,
+                aliases,
+                gio_fallback: false,
+            };
+
+            let mut sweep = Sweep {
+                min_wm_class_similarity,
+                min_partial_match_confidence,
+                correct: 0,
+                wrong: 0,
+                missed: 0,
+            };
+
+            for corpus in corpora {
+                for e in &corpus.expectations {
+                    let found = match try_find_command_any(options, &window(e), &corpus.index) {
+                        Ok((Exec::DesktopFile(path), _)) => Some(path),
+                        _ => None,
+                    };
+
+                    if !e.is_expected(found.as_deref()) {
+                        if found.is_some() {
+                            sweep.wrong += 1;
+                        } else {
+                            sweep.missed += 1;
+                        }
+                    } else if found.is_some() {
+                        sweep.correct += 1;
+                    }
+                }
+            }
+
+            sweeps.push(sweep);
+        }
+    }
+
+    sweeps
+}
+
+/// The sweep with the best [`Sweep::f1`], preferring stricter thresholds among equally good ones
+/// as they are less likely to launch the wrong application for windows that are not in the corpus
+pub fn recommend(sweeps: &[Sweep]) -> Option<&Sweep> {
+    sweeps.iter().max_by(|a, b| {
+        a.f1()
+            .total_cmp(&b.f1())
+            .then(a.min_wm_class_similarity.total_cmp(&b.min_wm_class_similarity))
+            .then(
+                a.min_partial_match_confidence
+                    .total_cmp(&b.min_partial_match_confidence),
+            )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recommend, sweep, Corpus};
+    use crate::find_command::{testsets, Aliases, DesktopFileIndex};
+
+    #[test]
+    fn calibrate_default_testset() {
+        let corpus = Corpus {
+            index: DesktopFileIndex::new(testsets::paths(testsets::DEFAULT)),
+            expectations: testsets::expectations(testsets::DEFAULT).unwrap(),
+        };
+
+        let sweeps = sweep(&[corpus], &Aliases::builtin(), &[0.0, 0.6, 0.8, 1.0]);
+        assert_eq!(sweeps.len(), 16);
+
+        // the expectations were written against the defaults
+        let defaults = sweeps
+            .iter()
+            .find(|s| s.min_wm_class_similarity == 0.8 && s.min_partial_match_confidence == 0.6)
+            .unwrap();
+        assert_eq!((defaults.wrong, defaults.missed), (0, 0));
+
+        let best = recommend(&sweeps).unwrap();
+        assert_eq!(best.f1(), 1.0);
+    }
+}
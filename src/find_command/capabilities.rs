@@ -0,0 +1,150 @@
+//! What gnome-session-restore is allowed to look at when finding commands and saving sessions.
+//!
+//! The granted [`Capabilities`] are given as a comma separated list of [`Capability`] names
+//! (e.g. `procfs-search,environ`) and recorded in that form in saved sessions.
+
+use clap::ArgEnum;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+#[derive(ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Search `/proc/{pid}/cmdline` for hints about the application
+    #[clap(name = "procfs-search")]
+    ProcFsSearch,
+
+    /// Launch the command found in `/proc/{pid}/cmdline` if no desktop file is found
+    #[clap(name = "procfs-command")]
+    UseProcFsCommand,
+
+    /// Save the working directory from `/proc/{pid}/cwd`
+    #[clap(name = "cwd")]
+    ReadProcCwd,
+
+    /// Save the environment from `/proc/{pid}/environ`
+    #[clap(name = "environ")]
+    ReadProcEnviron,
+
+    /// Search the process name from `/proc/{pid}/comm`
+    #[clap(name = "comm")]
+    ReadProcComm,
+
+    /// Find the default application for the type of a file in `/proc/{pid}/cmdline`
+    #[clap(name = "mime")]
+    MimeType,
+}
+
+impl Capability {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn name(self) -> &'static str {
+        self.to_possible_value().expect("no capability is skipped").get_name()
+    }
+}
+
+/// A set of [`Capability`]s
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    /// No capabilities at all, e.g. for sessions whose processes are not running anymore
+    pub const fn none() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    pub fn insert(&mut self, capability: Capability) {
+        self.0 |= capability.bit();
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Capability> {
+        Capability::value_variants()
+            .iter()
+            .copied()
+            .filter(move |&capability| self.contains(capability))
+    }
+}
+
+impl FromIterator<Capability> for Capabilities {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut capabilities = Capabilities::none();
+        iter.into_iter().for_each(|capability| capabilities.insert(capability));
+        capabilities
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("unknown capability {0:?}, expected one of {}", Capabilities(u8::MAX))]
+pub struct CapabilityParseError(String);
+
+/// Parses a comma separated list of capability names, the empty string grants none
+impl FromStr for Capabilities {
+    type Err = CapabilityParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Capability::from_str(name, false).map_err(|_| CapabilityParseError(name.to_owned())))
+            .collect()
+    }
+}
+
+/// Formats the capabilities as a comma separated list of their names
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, capability) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+
+            f.write_str(capability.name())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for Capabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Capabilities {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, Capability, CapabilityParseError};
+
+    #[test]
+    fn parse_and_format() {
+        let caps: Capabilities = "environ, procfs-search".parse().unwrap();
+        assert!(caps.contains(Capability::ProcFsSearch));
+        assert!(caps.contains(Capability::ReadProcEnviron));
+        assert!(!caps.contains(Capability::UseProcFsCommand));
+        assert_eq!(caps.to_string(), "procfs-search,environ");
+
+        assert_eq!("".parse(), Ok(Capabilities::none()));
+        assert_eq!(Capabilities::none().to_string(), "");
+
+        assert_eq!(
+            "procfs-search,proc".parse::<Capabilities>(),
+            Err(CapabilityParseError("proc".to_owned()))
+        );
+
+        let json = serde_json::to_string(&caps).unwrap();
+        assert_eq!(json, r#""procfs-search,environ""#);
+        assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), caps);
+    }
+}
@@ -0,0 +1,103 @@
+//! The installation (user or system) flatpak applications run from.
+//!
+//! The same application can be installed both per user and system wide, e.g. in different branches.
+//! Both export a desktop file with the same id, of which the user one shadows the system one, so the sandboxed
+//! app id alone would always resolve to the user installation.
+
+use super::FindError;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Where a flatpak application is installed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlatpakScope {
+    /// In the user's data home, i.e. `flatpak install --user`
+    User,
+
+    /// In a system wide installation, e.g. `/var/lib/flatpak`
+    System,
+}
+
+/// The user installation, every other installation is a system one
+static USER_INSTALLATION: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
+    xdg::BaseDirectories::new()
+        .ok()
+        .map(|bd| bd.get_data_home().join("flatpak"))
+});
+
+fn scope_of(path: &Path, user_installation: Option<&Path>) -> FlatpakScope {
+    match user_installation {
+        Some(user) if path.starts_with(user) => FlatpakScope::User,
+        _ => FlatpakScope::System,
+    }
+}
+
+/// The installation the flatpak instance described by the `.flatpak-info` file `info` runs from,
+/// `None` if it does not name the path of the application
+fn parse_flatpak_info(info: &str, user_installation: Option<&Path>) -> Option<FlatpakScope> {
+    let mut in_instance = false;
+
+    for line in info.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_instance = line == "[Instance]";
+        } else if let Some(app_path) = line.strip_prefix("app-path=").filter(|_| in_instance) {
+            return Some(scope_of(Path::new(app_path), user_installation));
+        }
+    }
+
+    None
+}
+
+/// Reads the installation of the flatpak instance the process with the given pid belongs to from the
+/// `.flatpak-info` file at the root of its sandbox
+pub fn try_read_flatpak_scope(pid: i32) -> Result<FlatpakScope, FindError> {
+    let info = std::fs::read_to_string(format!("/proc/{pid}/root/.flatpak-info"))?;
+    parse_flatpak_info(&info, USER_INSTALLATION.as_deref()).ok_or(FindError::NoSuitableEntryFound)
+}
+
+/// The installation that exported the desktop file at `path`, `None` if it was not exported by flatpak
+pub fn exported_by(path: &Path) -> Option<FlatpakScope> {
+    path.ancestors()
+        .any(|dir| dir.ends_with("exports/share/applications"))
+        .then(|| scope_of(path, USER_INSTALLATION.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_flatpak_info, FlatpakScope};
+    use std::path::Path;
+
+    #[test]
+    fn flatpak_info() {
+        let info = |app_path: &str| {
+            format!(
+                "[Application]\nname=org.mozilla.firefox\nruntime=runtime/org.freedesktop.Platform/x86_64/23.08\n\n\
+                 [Instance]\ninstance-id=123\napp-path={app_path}\nbranch=stable\narch=x86_64\n"
+            )
+        };
+        let user = Some(Path::new("/home/user/.local/share/flatpak"));
+
+        assert_eq!(
+            parse_flatpak_info(
+                &info("/home/user/.local/share/flatpak/app/org.mozilla.firefox/x86_64/stable/abc/files"),
+                user
+            ),
+            Some(FlatpakScope::User)
+        );
+        assert_eq!(
+            parse_flatpak_info(
+                &info("/var/lib/flatpak/app/org.mozilla.firefox/x86_64/stable/abc/files"),
+                user
+            ),
+            Some(FlatpakScope::System)
+        );
+        assert_eq!(
+            parse_flatpak_info("[Application]\napp-path=/home/user/.local/share/flatpak/app", user),
+            None
+        );
+    }
+}
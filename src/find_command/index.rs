@@ -0,0 +1,566 @@
+//! The desktop files that are searched for the command of a window.
+//!
+//! Every window is matched against every desktop file, often more than once (e.g. with the window class and
+//! with the binary name), so everything a desktop file is matched by is computed once up front.
+//! Long running processes keep the index up to date with [`DesktopFileIndex::refresh`].
+//!
+//! Desktop files that cannot be read or are malformed are still indexed, they are only matched by their name.
+
+use crate::desktop_entry::DesktopEntry;
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// How deep desktop files are searched for in the subdirectories of an application directory,
+/// e.g. wine puts them in `applications/wine/Programs/<Vendor>/<App>.desktop`
+pub const MAX_SUBDIR_DEPTH: usize = 4;
+
+/// Whether `path` is named like a desktop file
+pub fn is_desktop_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "desktop")
+}
+
+/// The desktop file id of `path` without the `.desktop` extension.
+///
+/// Following the desktop entry spec, the id is the path relative to the `applications` directory it is in,
+/// with the directory separators replaced by `-`, e.g. `wine-Programs-Notepad` for
+/// `applications/wine/Programs/Notepad.desktop`. Files that are not in an `applications` directory
+/// (within [`MAX_SUBDIR_DEPTH`]) are identified by their name only.
+pub fn desktop_file_id(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let mut subdirs = Vec::new();
+
+    for dir in path.parent()?.components().rev().take(MAX_SUBDIR_DEPTH + 1) {
+        match dir {
+            Component::Normal(name) if name == "applications" => {
+                let components: Vec<&str> = subdirs.iter().rev().map(AsRef::as_ref).chain([stem.as_ref()]).collect();
+                return Some(components.join("-"));
+            },
+            Component::Normal(name) => subdirs.push(name.to_string_lossy()),
+            _ => break,
+        }
+    }
+
+    Some(stem.into_owned())
+}
+
+/// A desktop file and the names it is matched by
+#[derive(Debug, Clone)]
+pub struct IndexedDesktopFile {
+    pub path: PathBuf,
+
+    /// The lowercase file name without the `.desktop` extension
+    pub stem: String,
+
+    /// See [`desktop_file_id`]
+    pub id: String,
+
+    /// The lowercase untranslated `Name` of the desktop entry, `None` if it has none or the file cannot be read
+    pub name: Option<String>,
+
+    /// The lowercase `Name` in the user's locale, see [`DesktopEntry::localized_name`]
+    pub localized_name: Option<String>,
+
+    /// The `StartupWMClass` of the desktop entry, `None` if it has none or the file cannot be read
+    pub startup_wm_class: Option<String>,
+
+    /// Whether the binary of the desktop entry is not installed, see [`DesktopEntry::missing_binary`].
+    /// Uninstalled applications often leave their desktop files behind.
+    pub binary_missing: bool,
+
+    /// See [`DesktopEntry::single_main_window`]
+    pub single_main_window: bool,
+
+    /// See [`DesktopEntry::startup_notify`]
+    pub startup_notify: Option<bool>,
+
+    /// The position of the directory the file is in, files in earlier directories shadow the ones with
+    /// the same id in later directories
+    priority: usize,
+
+    shadowed: bool,
+}
+
+/// The desktop files, of which only the effective ones are matched and launched. As in XDG, a desktop file
+/// shadows the ones with the same id in directories of lower priority, e.g. `~/.local/share/applications`
+/// the ones in `/usr/share/applications`.
+#[derive(Debug, Default, Clone)]
+pub struct DesktopFileIndex {
+    /// Ordered by priority
+    files: Vec<IndexedDesktopFile>,
+
+    /// The directories in order of priority, empty if the index was not scanned
+    dirs: Vec<PathBuf>,
+}
+
+impl IndexedDesktopFile {
+    fn new(path: &Path, priority: usize) -> Self {
+        let entry = DesktopEntry::load(path).ok();
+
+        IndexedDesktopFile {
+            path: path.to_owned(),
+            stem: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            id: desktop_file_id(path).unwrap_or_default(),
+            name: entry
+                .as_ref()
+                .and_then(|entry| entry.untranslated_name.as_deref())
+                .map(str::to_lowercase),
+            localized_name: entry
+                .as_ref()
+                .and_then(|entry| entry.localized_name.as_deref())
+                .map(str::to_lowercase),
+            binary_missing: entry.as_ref().is_some_and(|entry| entry.missing_binary().is_some()),
+            single_main_window: entry.as_ref().is_some_and(|entry| entry.single_main_window),
+            startup_notify: entry.as_ref().and_then(|entry| entry.startup_notify),
+            startup_wm_class: entry.and_then(|entry| entry.startup_wm_class),
+            priority,
+            shadowed: false,
+        }
+    }
+}
+
+impl DesktopFileIndex {
+    /// Indexes the given desktop files, earlier ones shadow later ones with the same id
+    pub fn new<I, P>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut index = DesktopFileIndex {
+            files: paths
+                .into_iter()
+                .map(|path| IndexedDesktopFile::new(path.as_ref(), 0))
+                .collect(),
+            dirs: Vec::new(),
+        };
+
+        index.update_shadowing();
+        index
+    }
+
+    /// Indexes the desktop files in the given directories (in order of priority) and their subdirectories
+    /// up to [`MAX_SUBDIR_DEPTH`], directories that cannot be read are skipped
+    pub fn scan<I, P>(dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let dirs: Vec<PathBuf> = dirs.into_iter().map(|dir| dir.as_ref().to_owned()).collect();
+        let mut files = Vec::new();
+
+        for (priority, dir) in dirs.iter().enumerate() {
+            let mut paths = Vec::new();
+            collect_desktop_files(dir, MAX_SUBDIR_DEPTH, &mut paths);
+
+            files.extend(paths.iter().map(|path| IndexedDesktopFile::new(path, priority)));
+        }
+
+        let mut index = DesktopFileIndex { files, dirs };
+        index.update_shadowing();
+        index
+    }
+
+    /// Updates the entries of the given desktop files, adding the ones that are new and removing the ones
+    /// that do not exist anymore
+    pub fn refresh<I, P>(&mut self, paths: I)
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            let path = path.as_ref();
+            let exists = is_desktop_file(path) && path.is_file();
+
+            match self.files.iter().position(|file| file.path == path) {
+                Some(ix) if exists => self.files[ix] = IndexedDesktopFile::new(path, self.files[ix].priority),
+                Some(ix) => {
+                    self.files.remove(ix);
+                },
+                None if exists => {
+                    let priority = self
+                        .dirs
+                        .iter()
+                        .position(|dir| path.starts_with(dir))
+                        .unwrap_or(self.dirs.len());
+
+                    let ix = self.files.partition_point(|file| file.priority <= priority);
+                    self.files.insert(ix, IndexedDesktopFile::new(path, priority));
+                },
+                None => (),
+            }
+        }
+
+        self.update_shadowing();
+    }
+
+    fn update_shadowing(&mut self) {
+        let mut seen = HashSet::new();
+
+        for file in &mut self.files {
+            file.shadowed = !seen.insert(file.id.clone());
+        }
+    }
+
+    /// The effective desktop files, i.e. the ones that are not shadowed
+    pub fn iter(&self) -> impl Iterator<Item = &IndexedDesktopFile> {
+        self.files.iter().filter(|file| !file.shadowed)
+    }
+
+    /// The desktop files that are shadowed by an effective one with the same id
+    pub fn shadowed(&self) -> impl Iterator<Item = &IndexedDesktopFile> {
+        self.files.iter().filter(|file| file.shadowed)
+    }
+
+    /// The effective desktop files that can be matched, which are the ones whose binary is installed
+    /// unless `allow_missing_binaries` is given
+    pub fn candidates(&self, allow_missing_binaries: bool) -> impl Iterator<Item = &IndexedDesktopFile> {
+        self.iter()
+            .filter(move |file| allow_missing_binaries || !file.binary_missing)
+    }
+
+    /// Writes the paths of the effective desktop files one per line, in the format of `tests/testsets`
+    pub fn write_testset<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for file in self.iter() {
+            writeln!(writer, "{}", file.path.display())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the effective desktop files with the fields they are matched and launched by as a JSON array,
+    /// to see why a testset behaves differently than the machine it was recorded on
+    pub fn write_json<W: Write>(&self, mut writer: W) -> serde_json::Result<()> {
+        let entries: Vec<DumpedDesktopFile> = self
+            .iter()
+            .map(|file| {
+                let entry = DesktopEntry::load(&file.path).ok();
+
+                DumpedDesktopFile {
+                    path: &file.path,
+                    id: &file.id,
+                    name: entry.as_ref().and_then(|entry| entry.name.clone()),
+                    startup_wm_class: file.startup_wm_class.as_deref(),
+                    exec: entry.and_then(|entry| entry.exec),
+                    binary_missing: file.binary_missing,
+                    single_main_window: file.single_main_window,
+                    startup_notify: file.startup_notify,
+                }
+            })
+            .collect();
+
+        serde_json::to_writer_pretty(&mut writer, &entries)?;
+        writeln!(writer).map_err(serde_json::Error::io)
+    }
+}
+
+/// A desktop file as written by [`DesktopFileIndex::write_json`]
+#[derive(Serialize)]
+struct DumpedDesktopFile<'i> {
+    path: &'i Path,
+    id: &'i str,
+    name: Option<String>,
+    startup_wm_class: Option<&'i str>,
+    exec: Option<String>,
+    binary_missing: bool,
+    single_main_window: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    startup_notify: Option<bool>,
+}
+
+/// Adds the desktop files in `dir` to `paths`, descending `depth` levels into subdirectories.
+/// Symlinks to directories are not followed, so that links back up the tree are not visited repeatedly.
+fn collect_desktop_files(dir: &Path, depth: usize, paths: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+            if depth > 0 {
+                collect_desktop_files(&path, depth - 1, paths);
+            }
+        } else if is_desktop_file(&path) {
+            paths.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{desktop_file_id, DesktopFileIndex};
+    use crate::{
+        find_command::{
+            methods::{try_find_command_by_sandboxed_app_id, try_find_command_by_wm_class},
+            testsets,
+        },
+        session::Exec,
+    };
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        fs,
+        path::Path,
+    };
+
+    /// Counts the allocations of the current thread, so that tests running in parallel do not interfere
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let res = f();
+        (res, ALLOCATIONS.with(Cell::get) - before)
+    }
+
+    #[test]
+    fn scoring_does_not_allocate_per_desktop_file() {
+        let index = DesktopFileIndex::new(testsets::paths(testsets::DEFAULT));
+        assert!(index.iter().count() > 100);
+
+        let (found, n) = allocations(|| try_find_command_by_wm_class("Org.Gnome.Nautilus", 0.8, &index, false));
+        assert!(found.is_ok());
+
+        // lowercasing the search term and cloning the path of the match
+        assert!(n <= 2, "{n} allocations");
+    }
+
+    #[test]
+    fn nested_desktop_files() {
+        let root = std::env::temp_dir().join(format!("gnome-session-restore-index-{}", std::process::id()));
+        let apps = root.join("share/applications");
+
+        for file in [
+            "org.example.App.desktop",
+            "kde4/konsole.desktop",
+            "wine/Programs/Notepad++/Notepad++.desktop",
+            "wine/Programs/Notepad++/Uninstall Notepad++.desktop",
+            "wine/Programs/Notepad++/readme.txt",
+            "wine/wine-Programs.directory",
+            "a/b/c/d/e/too-deep.desktop",
+        ] {
+            let path = apps.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "[Desktop Entry]\nType=Application\nExec=sh\n").unwrap();
+        }
+
+        let index = DesktopFileIndex::scan([&apps]);
+        let mut ids: Vec<_> = index.iter().map(|file| file.id.as_str()).collect();
+        ids.sort_unstable();
+
+        assert_eq!(
+            ids,
+            vec![
+                "kde4-konsole",
+                "org.example.App",
+                "wine-Programs-Notepad++-Notepad++",
+                "wine-Programs-Notepad++-Uninstall Notepad++",
+            ]
+        );
+
+        assert_eq!(
+            try_find_command_by_sandboxed_app_id("kde4-konsole", None, &index, false).ok(),
+            Some(Exec::DesktopFile(apps.join("kde4/konsole.desktop")))
+        );
+
+        // the window class of wine applications is the name of the executable
+        assert_eq!(
+            try_find_command_by_wm_class("notepad++.exe", 0.6, &index, false).ok(),
+            Some((
+                Exec::DesktopFile(apps.join("wine/Programs/Notepad++/Notepad++.desktop")),
+                0.6923076923076923
+            ))
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn malformed_desktop_files() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-malformed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let valid = dir.join("org.example.Editor.desktop");
+        fs::write(
+            &valid,
+            "[Desktop Entry]\nType=Application\nExec=sh\nStartupWMClass=ExampleEdit\n",
+        )
+        .unwrap();
+        fs::write(dir.join("broken.desktop"), b"[Desktop Entry\nType=\xff\x00\n=\n").unwrap();
+        fs::write(dir.join("empty.desktop"), "").unwrap();
+        fs::create_dir_all(dir.join("not-a-file.desktop")).unwrap();
+
+        let index = DesktopFileIndex::scan([&dir]);
+        let mut stems: Vec<_> = index.iter().map(|file| file.stem.as_str()).collect();
+        stems.sort_unstable();
+
+        assert_eq!(stems, vec!["broken", "empty", "org.example.editor"]);
+        assert_eq!(
+            try_find_command_by_wm_class("ExampleEdit", 0.8, &index, false).ok(),
+            Some((Exec::DesktopFile(valid), 1.0))
+        );
+        assert_eq!(
+            try_find_command_by_wm_class("broken", 0.8, &index, false).ok(),
+            Some((Exec::DesktopFile(dir.join("broken.desktop")), 1.0))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-names-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let files = dir.join("org.example.FileManager.desktop");
+        fs::write(&files, "[Desktop Entry]\nType=Application\nExec=sh\nName=Files\n").unwrap();
+
+        let mut index = DesktopFileIndex::scan([&dir]);
+        assert_eq!(index.files[0].name.as_deref(), Some("files"));
+
+        // the untranslated name counts a little less than the file name
+        assert_eq!(
+            try_find_command_by_wm_class("Files", 0.8, &index, false).ok(),
+            Some((Exec::DesktopFile(files.clone()), 0.9))
+        );
+
+        // the window shows the localized name, which counts a little more
+        index.files[0].localized_name = Some("dateien".to_owned());
+        assert_eq!(
+            try_find_command_by_wm_class("Dateien", 0.8, &index, false).ok(),
+            Some((Exec::DesktopFile(files), 0.95))
+        );
+        assert!(try_find_command_by_wm_class("Dateien", 0.96, &index, false).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_binaries() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-binaries-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let uninstalled = dir.join("org.example.Game.desktop");
+        fs::write(
+            &uninstalled,
+            "[Desktop Entry]\nType=Application\nExec=example-game %U\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("org.example.GameLauncher.desktop"),
+            "[Desktop Entry]\nType=Application\nExec=sh\nTryExec=/nonexistent/launcher\n",
+        )
+        .unwrap();
+
+        let index = DesktopFileIndex::scan([&dir]);
+
+        assert!(index.iter().all(|file| file.binary_missing));
+        assert!(try_find_command_by_wm_class("org.example.Game", 0.8, &index, false).is_err());
+        assert_eq!(
+            try_find_command_by_wm_class("org.example.Game", 0.8, &index, true).ok(),
+            Some((Exec::DesktopFile(uninstalled), 1.0))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shadowing() {
+        let root = std::env::temp_dir().join(format!("gnome-session-restore-shadowing-{}", std::process::id()));
+        let user = root.join("home/.local/share/applications");
+        let flatpak = root.join("var/lib/flatpak/exports/share/applications");
+        let system = root.join("usr/share/applications");
+
+        for file in [
+            user.join("firefox.desktop"),
+            system.join("firefox.desktop"),
+            flatpak.join("org.mozilla.firefox.desktop"),
+            system.join("org.mozilla.firefox.desktop"),
+            system.join("org.gnome.Terminal.desktop"),
+        ] {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, "[Desktop Entry]\nType=Application\nExec=sh\n").unwrap();
+        }
+
+        let mut index = DesktopFileIndex::scan([&user, &flatpak, &system]);
+        let paths = |index: &DesktopFileIndex| {
+            let mut paths: Vec<_> = index.iter().map(|file| file.path.clone()).collect();
+            paths.sort_unstable();
+            paths
+        };
+
+        // the user's firefox overrides the system one, the flatpak export the one installed by the system
+        assert_eq!(
+            paths(&index),
+            vec![
+                user.join("firefox.desktop"),
+                system.join("org.gnome.Terminal.desktop"),
+                flatpak.join("org.mozilla.firefox.desktop"),
+            ]
+        );
+        assert_eq!(index.shadowed().count(), 2);
+        assert_eq!(
+            try_find_command_by_wm_class("firefox", 0.8, &index, false).ok(),
+            Some((Exec::DesktopFile(user.join("firefox.desktop")), 1.0))
+        );
+
+        // removing the override makes the system one effective again, a new override shadows it
+        fs::remove_file(user.join("firefox.desktop")).unwrap();
+        fs::write(user.join("org.gnome.Terminal.desktop"), "").unwrap();
+        index.refresh([user.join("firefox.desktop"), user.join("org.gnome.Terminal.desktop")]);
+
+        assert_eq!(
+            paths(&index),
+            vec![
+                user.join("org.gnome.Terminal.desktop"),
+                system.join("firefox.desktop"),
+                flatpak.join("org.mozilla.firefox.desktop"),
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn desktop_file_ids() {
+        let id = |path: &str| desktop_file_id(Path::new(path)).unwrap();
+
+        assert_eq!(
+            id("/usr/share/applications/org.gnome.Terminal.desktop"),
+            "org.gnome.Terminal"
+        );
+        assert_eq!(id("/usr/share//applications/kde4/konsole.desktop"), "kde4-konsole");
+        assert_eq!(
+            id("/home/user/.local/share/applications/wine/Programs/Steam/Steam.desktop"),
+            "wine-Programs-Steam-Steam"
+        );
+        assert_eq!(id("/tmp/org.example.App.desktop"), "org.example.App");
+        assert_eq!(id("relative/app.desktop"), "app");
+    }
+}
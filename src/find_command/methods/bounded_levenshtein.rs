@@ -0,0 +1,133 @@
+//! Levenshtein similarity that gives up on strings that cannot be similar enough.
+//!
+//! Most desktop files are nowhere near the window class that is searched for, so calculating their
+//! full edit distance is wasted when all that matters is whether they beat the best match so far.
+
+/// Up to which length of the second string the rows of the edit distance matrix are kept on the stack,
+/// which covers practically all desktop file names
+const STACK_ROW_LEN: usize = 64;
+
+/// The same as `strsim::normalized_levenshtein`, except that `None` is returned as soon as the similarity
+/// is known to be below `min_similarity`. Otherwise the result is exactly the one of `strsim`.
+pub fn bounded_normalized_levenshtein(a: &str, b: &str, min_similarity: f64) -> Option<f64> {
+    let (a_len, b_len) = (a.chars().count(), b.chars().count());
+    let max_len = a_len.max(b_len);
+
+    if max_len == 0 {
+        return (1.0 >= min_similarity).then_some(1.0);
+    }
+
+    // calculated like strsim does, so that the results are identical
+    let similarity = |distance: usize| 1.0 - (distance as f64) / (max_len as f64);
+
+    // the distance is at least the difference in length
+    let max_distance = (a_len.abs_diff(b_len)..=max_len)
+        .take_while(|&distance| similarity(distance) >= min_similarity)
+        .last()?;
+
+    let mut stack_rows = [[0; STACK_ROW_LEN]; 2];
+    let mut heap_rows = Vec::new();
+
+    let (mut prev, mut cur) = if b_len < STACK_ROW_LEN {
+        let [prev, cur] = &mut stack_rows;
+        (&mut prev[..=b_len], &mut cur[..=b_len])
+    } else {
+        heap_rows.resize(2 * (b_len + 1), 0);
+        heap_rows.split_at_mut(b_len + 1)
+    };
+
+    for (j, d) in prev.iter_mut().enumerate() {
+        *d = j;
+    }
+
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+
+        for (j, cb) in b.chars().enumerate() {
+            let substitution = prev[j] + usize::from(ca != cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+
+        // the smallest distance of a row never decreases in the following rows
+        if cur.iter().min().is_some_and(|&min| min > max_distance) {
+            return None;
+        }
+
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    let distance = prev[b_len];
+    (distance <= max_distance).then(|| similarity(distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::try_find_command_by_wm_class, bounded_normalized_levenshtein};
+    use crate::{
+        find_command::{testsets, DesktopFileIndex},
+        session::Exec,
+    };
+    use std::path::Path;
+
+    fn testset() -> impl Iterator<Item = &'static Path> + Clone {
+        testsets::paths(testsets::DEFAULT)
+    }
+
+    fn stems() -> Vec<String> {
+        testset()
+            .map(|p| p.file_stem().unwrap().to_string_lossy().to_lowercase())
+            .collect()
+    }
+
+    #[test]
+    fn same_as_strsim() {
+        let stems = stems();
+
+        for a in &stems {
+            for b in &stems {
+                let naive = strsim::normalized_levenshtein(a, b);
+
+                for min_similarity in [0.0, 0.5, 0.8, 1.0] {
+                    let expected = (naive >= min_similarity).then_some(naive);
+                    assert_eq!(
+                        bounded_normalized_levenshtein(a, b, min_similarity),
+                        expected,
+                        "{a} {b}"
+                    );
+                }
+            }
+        }
+
+        assert_eq!(bounded_normalized_levenshtein("", "", 1.0), Some(1.0));
+        assert_eq!(bounded_normalized_levenshtein("", "abc", 0.0), Some(0.0));
+    }
+
+    #[test]
+    fn same_winners_as_strsim() {
+        const MIN_SIMILARITY: f64 = 0.8;
+
+        let search_terms = stems()
+            .into_iter()
+            .flat_map(|stem| [stem.rsplit('.').next().unwrap().to_owned(), stem])
+            .chain(["firefox", "gnome-terminal-server", "Steam", "jetbrains-clion"].map(String::from));
+
+        let index = DesktopFileIndex::new(testset());
+
+        for search_term in search_terms {
+            let naive = testset()
+                .map(|p| {
+                    let stem = p.file_stem().unwrap().to_string_lossy().to_lowercase();
+                    (p, strsim::normalized_levenshtein(&search_term.to_lowercase(), &stem))
+                })
+                .reduce(|acc, x| if x.1 > acc.1 { x } else { acc })
+                .filter(|(_, sim)| *sim >= MIN_SIMILARITY)
+                .map(|(p, sim)| (Exec::DesktopFile(p.to_owned()), sim));
+
+            assert_eq!(
+                try_find_command_by_wm_class(&search_term, MIN_SIMILARITY, &index, false).ok(),
+                naive,
+                "{search_term}"
+            );
+        }
+    }
+}
@@ -1,91 +1,307 @@
+pub mod bounded_levenshtein;
 pub mod partial_match_similarity;
 
-use super::FindError;
+use super::{
+    flatpak::{self, FlatpakScope},
+    DesktopFileIndex, FindError,
+};
 use crate::session::Exec;
+use bounded_levenshtein::bounded_normalized_levenshtein;
+use gio::prelude::{AppInfoExt, Cast};
 use partial_match_similarity::partial_match_similarity;
 use std::{
     ffi::{OsStr, OsString},
     os::unix::ffi::{OsStrExt, OsStringExt},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 pub type Error = FindError;
 pub type Result<T> = std::result::Result<T, Error>;
 pub type Confidence = f64;
 
-pub fn try_find_command_by_gtk_app_id(gtk_app_id: &str) -> Result<Exec> {
-    let desktop_file_name = format!("{gtk_app_id}.desktop");
-    let p = Path::new("/usr/share/applications").join(&desktop_file_name);
+/// Finds the desktop file whose [desktop file id](super::index::desktop_file_id) is the gtk app id
+pub fn try_find_command_by_gtk_app_id(
+    gtk_app_id: &str,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<Exec> {
+    try_find_desktop_file_by_id(gtk_app_id, desktop_files, allow_missing_binaries)
+}
 
-    if p.exists() {
-        Ok(Exec::DesktopFile(p))
-    } else {
-        Err(FindError::NoSuitableEntryFound)
+/// Finds the desktop file whose [desktop file id](super::index::desktop_file_id) is the sandboxed app id.
+/// If the flatpak installation the window came from is known, the desktop file exported by it is preferred,
+/// even if it is shadowed by the one of another installation.
+pub fn try_find_command_by_sandboxed_app_id(
+    sandboxed_app_id: &str,
+    scope: Option<FlatpakScope>,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<Exec> {
+    let exported = scope.and_then(|scope| {
+        desktop_files
+            .candidates(allow_missing_binaries)
+            .chain(
+                desktop_files
+                    .shadowed()
+                    .filter(|file| allow_missing_binaries || !file.binary_missing),
+            )
+            .find(|file| file.id == sandboxed_app_id && flatpak::exported_by(&file.path) == Some(scope))
+    });
+
+    match exported {
+        Some(file) => Ok(Exec::DesktopFile(file.path.clone())),
+        None => try_find_desktop_file_by_id(sandboxed_app_id, desktop_files, allow_missing_binaries),
     }
 }
 
-pub fn try_find_command_by_sandboxed_app_id<L, P>(
-    sandboxed_app_id: &str,
-    mut desktop_entry_locations: L,
-) -> Result<Exec>
-where
-    L: Iterator<Item = P>,
-    P: AsRef<Path>,
-{
-    let desktop_file_name = format!("{sandboxed_app_id}.desktop");
+/// How much the default application for the type of a file counts, the application may as well have been opened
+/// with another one than the default
+pub const MIME_TYPE_CONFIDENCE: Confidence = 0.5;
+
+/// The files a process was opened with, i.e. the absolute paths and `file://` URIs among its arguments
+pub fn file_arguments(cmdline: &[OsString]) -> impl Iterator<Item = &Path> {
+    cmdline.iter().skip(1).filter_map(|arg| {
+        let arg = arg.as_bytes();
+
+        // URIs with escapes are left out rather than decoded
+        let path = match arg.strip_prefix(b"file://") {
+            Some(path) if !path.contains(&b'%') => path,
+            Some(_) => return None,
+            None => arg,
+        };
+
+        path.starts_with(b"/").then(|| Path::new(OsStr::from_bytes(path)))
+    })
+}
+
+/// Finds the desktop file of the default application for the type of the first file in `cmdline`
+/// (see [`file_arguments`]) whose type is known
+pub fn try_find_command_by_mime_type(
+    cmdline: &[OsString],
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<(Exec, Confidence)> {
+    for path in file_arguments(cmdline).filter(|path| path.is_file()) {
+        let Some(file_name) = path.to_str() else {
+            continue;
+        };
+
+        let (content_type, uncertain) = gio::content_type_guess(Some(file_name), &[]);
+
+        let default_app = match gio::AppInfo::default_for_type(&content_type, false) {
+            Some(app) if !uncertain => app,
+            _ => continue,
+        };
+
+        let id = default_app.id();
+        let id = id.as_deref().map(|id| id.strip_suffix(".desktop").unwrap_or(id));
+
+        if let Some(Ok(exec)) = id.map(|id| try_find_desktop_file_by_id(id, desktop_files, allow_missing_binaries)) {
+            return Ok((exec, MIME_TYPE_CONFIDENCE));
+        }
+    }
+
+    Err(FindError::NoSuitableEntryFound)
+}
+
+/// An application GIO knows about, see [`try_find_command_by_gio_app_info`]
+#[derive(Debug, Clone)]
+pub struct AppInfoCandidate {
+    pub path: PathBuf,
+
+    /// The desktop file id without the `.desktop` suffix
+    pub id: String,
+    pub name: String,
+
+    /// The file name of the executable
+    pub executable: String,
+}
+
+/// The candidate whose id, name or executable is most similar to `wm_class`, if it is at least `min_similarity`
+pub fn best_app_info_match(
+    wm_class: &str,
+    min_similarity: Confidence,
+    candidates: impl IntoIterator<Item = AppInfoCandidate>,
+) -> Option<(PathBuf, Confidence)> {
+    let wm_class = wm_class.to_lowercase();
+    let mut best: Option<(PathBuf, Confidence)> = None;
+
+    for candidate in candidates {
+        let min_similarity = best
+            .as_ref()
+            .map_or(min_similarity, |(_, best)| best.max(min_similarity));
+
+        let sim = [&candidate.id, &candidate.name, &candidate.executable]
+            .into_iter()
+            .filter_map(|haystack| bounded_normalized_levenshtein(&wm_class, &haystack.to_lowercase(), min_similarity))
+            .reduce(f64::max);
+
+        match (sim, &best) {
+            (Some(sim), Some((_, best_sim))) if sim <= *best_sim => {},
+            (Some(sim), _) => best = Some((candidate.path, sim)),
+            (None, _) => {},
+        }
+    }
 
-    let p = desktop_entry_locations.find_map(|p| {
-        let p = p.as_ref().join(&desktop_file_name);
-        p.exists().then_some(p)
+    best
+}
+
+/// Finds the desktop file of the application GIO knows about that best matches `wm_class`, see
+/// [`best_app_info_match`]. GIO also sees desktop files outside of the indexed directories (e.g. of
+/// `$XDG_DATA_DIRS` entries added after the index was built), but listing them reads every desktop file again.
+pub fn try_find_command_by_gio_app_info(wm_class: &str, min_similarity: Confidence) -> Result<(Exec, Confidence)> {
+    let candidates = gio::AppInfo::all().into_iter().filter_map(|app| {
+        let path = app.downcast_ref::<gio::DesktopAppInfo>()?.filename()?;
+        let id = app.id()?;
+
+        Some(AppInfoCandidate {
+            path,
+            id: id.strip_suffix(".desktop").unwrap_or(&id).to_owned(),
+            name: app.name().to_string(),
+            executable: app
+                .executable()
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        })
     });
 
-    match p {
-        Some(p) => Ok(Exec::DesktopFile(p)),
+    match best_app_info_match(wm_class, min_similarity, candidates) {
+        Some((path, confidence)) => Ok((Exec::DesktopFile(path), confidence)),
+        None => Err(FindError::NoSuitableEntryFound),
+    }
+}
+
+fn try_find_desktop_file_by_id(
+    id: &str,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<Exec> {
+    match desktop_files
+        .candidates(allow_missing_binaries)
+        .find(|file| file.id == id)
+    {
+        Some(file) => Ok(Exec::DesktopFile(file.path.clone())),
         None => Err(FindError::NoSuitableEntryFound),
     }
 }
 
-fn try_find_desktop_file_fuzzy<S, D, P>(
+/// How much a match of the `Name` of a desktop file counts compared to a match of its file name, which is what
+/// window classes are usually derived from. The name in the user's locale is what the window shows and thus
+/// a slightly better hint than the untranslated one.
+const LOCALIZED_NAME_WEIGHT: Confidence = 0.95;
+const NAME_WEIGHT: Confidence = 0.9;
+
+/// Finds the desktop file whose file name, localized or untranslated `Name` (weighted by [`LOCALIZED_NAME_WEIGHT`]
+/// and [`NAME_WEIGHT`]) is most similar to `search_term`, the first one wins on ties.
+///
+/// `similarity_measure` is given the similarity a desktop file has to reach to be of interest, which is
+/// `min_similarity` or the one of the best match so far, and may return `None` for ones below it.
+pub fn try_find_desktop_file_fuzzy<S>(
     search_term: &str,
     similarity_measure: S,
-    desktop_files: D,
+    min_similarity: f64,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
 ) -> Result<(Exec, Confidence)>
 where
-    S: Fn(&str, &str) -> f64,
-    D: Iterator<Item = P>,
-    P: AsRef<Path>,
+    S: Fn(&str, &str, f64) -> Option<f64>,
 {
     let search_term = search_term.to_lowercase();
+    let mut desktop_file: Option<(&Path, f64)> = None;
 
-    let desktop_file = desktop_files
-        .map(|path| {
-            let filename = path.as_ref().file_stem().unwrap().to_string_lossy().to_lowercase();
-            let sim = similarity_measure(&search_term, &filename);
+    for file in desktop_files.candidates(allow_missing_binaries) {
+        let min_similarity = desktop_file
+            .as_ref()
+            .map_or(min_similarity, |(_, best)| best.max(min_similarity));
 
-            (path, sim)
-        })
-        .reduce(max_by_sim);
+        let targets = [
+            (Some(&file.stem), 1.0),
+            (file.localized_name.as_ref(), LOCALIZED_NAME_WEIGHT),
+            (file.name.as_ref(), NAME_WEIGHT),
+        ];
+
+        let sim = targets
+            .into_iter()
+            .filter_map(|(target, weight)| Some((target?, weight)))
+            .filter_map(|(target, weight)| {
+                // penalties of bad matches are not lessened by the weight
+                let sim = similarity_measure(&search_term, target, min_similarity / weight)?;
+                Some(if sim > 0.0 { sim * weight } else { sim })
+            })
+            .reduce(f64::max);
+
+        if let Some(sim) = sim {
+            desktop_file = Some(match desktop_file {
+                Some(best) => max_by_sim(best, (&file.path, sim)),
+                None => (&file.path, sim),
+            });
+        }
+    }
 
     match desktop_file {
-        Some((path, confidence)) => Ok((Exec::DesktopFile(path.as_ref().to_owned()), confidence)),
+        Some((path, confidence)) => Ok((Exec::DesktopFile(path.to_owned()), confidence)),
         None => Err(FindError::NoSuitableEntryFound),
     }
 }
 
-pub fn try_find_command_by_wm_class<D, P>(wm_class: &str, desktop_files: D) -> Result<(Exec, Confidence)>
-where
-    D: Iterator<Item = P>,
-    P: AsRef<Path>,
-{
-    try_find_desktop_file_fuzzy(wm_class, strsim::normalized_levenshtein, desktop_files)
+/// Finds the desktop file whose `StartupWMClass` is `wm_class`, otherwise the one whose name is most similar
+/// to `wm_class`, if it is at least `min_similarity`
+pub fn try_find_command_by_wm_class(
+    wm_class: &str,
+    min_similarity: Confidence,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<(Exec, Confidence)> {
+    let declared = desktop_files
+        .candidates(allow_missing_binaries)
+        .find(|file| file.startup_wm_class.as_deref() == Some(wm_class));
+
+    if let Some(file) = declared {
+        return Ok((Exec::DesktopFile(file.path.clone()), 1.0));
+    }
+
+    try_find_desktop_file_fuzzy(
+        wm_class,
+        bounded_normalized_levenshtein,
+        min_similarity,
+        desktop_files,
+        allow_missing_binaries,
+    )
 }
 
-pub fn try_find_command_by_search_term<D, P>(search_term: &str, desktop_files: D) -> Result<(Exec, Confidence)>
-where
-    D: Iterator<Item = P>,
-    P: AsRef<Path>,
-{
-    try_find_desktop_file_fuzzy(search_term, partial_match_similarity, desktop_files)
+pub fn try_find_command_by_search_term(
+    search_term: &str,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<(Exec, Confidence)> {
+    // penalties of bad matches add up, so every similarity has to be calculated exactly
+    try_find_desktop_file_fuzzy(
+        search_term,
+        |search_term, haystack, _| Some(partial_match_similarity(search_term, haystack)),
+        f64::NEG_INFINITY,
+        desktop_files,
+        allow_missing_binaries,
+    )
+}
+
+/// Finds the desktop file an [alias](super::aliases) refers to, which is the one with that id or name or otherwise
+/// the best partial match. The confidence of partial matches is raised, as the alias is known to be right.
+pub fn try_find_command_by_alias(
+    desktop_file: &str,
+    desktop_files: &DesktopFileIndex,
+    allow_missing_binaries: bool,
+) -> Result<(Exec, Confidence)> {
+    let stem = desktop_file.to_lowercase();
+    let exact = desktop_files
+        .candidates(allow_missing_binaries)
+        .find(|file| file.id == desktop_file || file.stem == stem);
+
+    if let Some(file) = exact {
+        return Ok((Exec::DesktopFile(file.path.clone()), 1.0));
+    }
+
+    let (exec, confidence) = try_find_command_by_search_term(desktop_file, desktop_files, allow_missing_binaries)?;
+    Ok((exec, (1.0 + confidence.max(0.0)) / 2.0))
 }
 
 /// Tries to get the commandline for a given pid from the `/proc` filesystem.
@@ -111,7 +327,7 @@ where
 /// 2. The symlink might not be available if the main thread exited early e.g. via `pthread_exit()`.
 /// 3. It might also point to a deleted file, if the executable got deleted.
 pub fn try_find_command_in_proc(pid: i32) -> Result<Vec<OsString>> {
-    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline"))?;
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).map_err(|e| proc_read_error(pid, e))?;
 
     if cmdline.is_empty() {
         Err(FindError::ProcessIsZombie)
@@ -143,6 +359,66 @@ pub fn try_find_command_in_proc(pid: i32) -> Result<Vec<OsString>> {
     }
 }
 
+/// Reads the name of the process with the given pid from `/proc/{pid}/comm`, which is set independently of `argv`
+/// but truncated to [`PROC_COMM_MAX_LEN`] bytes
+pub fn try_read_proc_comm(pid: i32) -> Result<String> {
+    let comm = std::fs::read(format!("/proc/{pid}/comm"))?;
+    let comm = String::from_utf8_lossy(&comm).trim_end_matches('\n').to_owned();
+
+    if comm.is_empty() {
+        Err(FindError::NoSuitableEntryFound)
+    } else {
+        Ok(comm)
+    }
+}
+
+/// The length `/proc/{pid}/comm` is truncated to, names of this length are likely truncated
+pub const PROC_COMM_MAX_LEN: usize = 15;
+
+/// Reads the current working directory of the process with the given pid from `/proc/{pid}/cwd`
+pub fn try_read_proc_cwd(pid: i32) -> Result<PathBuf> {
+    Ok(std::fs::read_link(format!("/proc/{pid}/cwd"))?)
+}
+
+/// Reads the pid of the parent of the process with the given pid from `/proc/{pid}/stat`
+pub fn try_read_proc_ppid(pid: i32) -> Result<i32> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+
+    parse_ppid(&stat).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed stat").into())
+}
+
+/// The parent pid from the contents of `/proc/{pid}/stat`, which is the second field after the
+/// process name. The name is in parentheses and may itself contain spaces and parentheses.
+fn parse_ppid(stat: &str) -> Option<i32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Reads the environment of the process with the given pid from `/proc/{pid}/environ`.
+///
+/// Note that this is the environment the process was started with,
+/// changes the process made to its own environment afterwards are not visible.
+pub fn try_read_proc_environ(pid: i32) -> Result<Vec<(OsString, OsString)>> {
+    let environ = std::fs::read(format!("/proc/{pid}/environ"))?;
+
+    if environ.is_empty() {
+        return Err(FindError::ProcessIsZombie);
+    }
+
+    let vars = environ
+        .split(|&b| b == b'\0')
+        .filter(|b| !b.is_empty())
+        .filter_map(|var| {
+            let eq = var.iter().position(|&b| b == b'=')?;
+            let (key, value) = (&var[..eq], &var[eq + 1..]);
+
+            Some((OsStr::from_bytes(key).to_owned(), OsStr::from_bytes(value).to_owned()))
+        })
+        .collect();
+
+    Ok(vars)
+}
+
 fn max_by_sim<T>(acc @ (_, acc_sim): (T, f64), x @ (_, x_sim): (T, f64)) -> (T, f64) {
     if x_sim > acc_sim {
         x
@@ -150,3 +426,101 @@ fn max_by_sim<T>(acc @ (_, acc_sim): (T, f64), x @ (_, x_sim): (T, f64)) -> (T,
         acc
     }
 }
+
+/// Tells a denied read of a file in `/proc/{pid}` apart from other io errors
+fn proc_read_error(pid: i32, e: std::io::Error) -> FindError {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        FindError::ProcAccessDenied(pid)
+    } else {
+        FindError::IOError(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{best_app_info_match, file_arguments, parse_ppid, proc_read_error, AppInfoCandidate};
+    use crate::find_command::FindError;
+    use std::{
+        ffi::OsString,
+        io,
+        path::{Path, PathBuf},
+    };
+
+    #[test]
+    fn matches_gio_app_infos() {
+        let app = |id: &str, name: &str, executable: &str| AppInfoCandidate {
+            path: PathBuf::from(format!("/opt/apps/{id}.desktop")),
+            id: id.to_owned(),
+            name: name.to_owned(),
+            executable: executable.to_owned(),
+        };
+
+        let apps = || {
+            [
+                app("org.gnome.Nautilus", "Files", "nautilus"),
+                app("com.example.Tool", "Example Tool", "extool"),
+                app("obsidian", "Obsidian", "obsidian"),
+            ]
+        };
+
+        let best = |wm_class: &str| {
+            best_app_info_match(wm_class, 0.8, apps()).map(|(path, confidence)| (path, (confidence * 100.0).round()))
+        };
+
+        // by executable, name and id
+        assert_eq!(
+            best("nautilus"),
+            Some((PathBuf::from("/opt/apps/org.gnome.Nautilus.desktop"), 100.0))
+        );
+        assert_eq!(
+            best("Example Tool"),
+            Some((PathBuf::from("/opt/apps/com.example.Tool.desktop"), 100.0))
+        );
+        assert_eq!(
+            best("Obsidian"),
+            Some((PathBuf::from("/opt/apps/obsidian.desktop"), 100.0))
+        );
+        assert_eq!(
+            best("extoo"),
+            Some((PathBuf::from("/opt/apps/com.example.Tool.desktop"), 83.0))
+        );
+
+        assert_eq!(best("steam"), None);
+        assert_eq!(best_app_info_match("nautilus", 0.8, []), None);
+    }
+
+    #[test]
+    fn ppid_from_stat() {
+        assert_eq!(parse_ppid("4242 (bash) S 4200 4242 4242 34817 4242"), Some(4200));
+        assert_eq!(parse_ppid("17 (evil) S 1 (name)) R 99 17 17 0"), Some(99));
+        assert_eq!(parse_ppid("17 (bash"), None);
+    }
+
+    #[test]
+    fn files_on_the_command_line() {
+        let cmdline: Vec<_> = [
+            "/usr/bin/evince",
+            "--page-label=3",
+            "/home/user/a b.pdf",
+            "file:///tmp/c.pdf",
+        ]
+        .into_iter()
+        .chain(["file:///tmp/d%20e.pdf", "relative.pdf", "https://example.org/f.pdf"])
+        .map(OsString::from)
+        .collect();
+
+        assert_eq!(
+            file_arguments(&cmdline).collect::<Vec<_>>(),
+            [Path::new("/home/user/a b.pdf"), Path::new("/tmp/c.pdf")]
+        );
+    }
+
+    #[test]
+    fn denied_proc_read() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(matches!(proc_read_error(42, denied), FindError::ProcAccessDenied(42)));
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert!(matches!(proc_read_error(42, missing), FindError::IOError(_)));
+    }
+}
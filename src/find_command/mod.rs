@@ -1,4 +1,11 @@
+pub mod aliases;
+pub mod calibrate;
+pub mod capabilities;
+pub mod flatpak;
+pub mod index;
 pub mod methods;
+pub mod testsets;
+pub mod watch;
 
 use crate::dbus::MetaWindow;
 use clap::ArgEnum;
@@ -7,42 +14,122 @@ use std::{
     collections::HashSet,
     ffi::OsStr,
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::{LazyLock, PoisonError, RwLock},
 };
 use thiserror::Error;
 
 use crate::session;
+pub use aliases::Aliases;
+pub use capabilities::{Capabilities, Capability};
+pub use index::DesktopFileIndex;
 pub use methods::Confidence;
 
-static DESKTOP_ENTRY_LOCATIONS: LazyLock<HashSet<PathBuf>> = LazyLock::new(|| {
+/// The application directories in order of priority, i.e. the data home first, then the data dirs in the order
+/// they are given in `$XDG_DATA_DIRS`
+static DESKTOP_ENTRY_LOCATIONS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
     let bd = xdg::BaseDirectories::new().unwrap();
+    let mut locations = Vec::new();
 
-    std::iter::once(bd.get_data_home())
-        .chain(bd.get_data_dirs())
-        .filter_map(|mut p| {
-            p.push("applications");
+    for mut p in std::iter::once(bd.get_data_home()).chain(bd.get_data_dirs()) {
+        p.push("applications");
 
-            if p.exists() {
-                Some(p)
-            } else {
-                eprintln!("Ignoring {p:?} reason: directory does not exist");
-                None
-            }
-        })
-        .collect()
+        if !p.exists() {
+            eprintln!("Ignoring {p:?} reason: directory does not exist");
+        } else if !locations.contains(&p) {
+            locations.push(p);
+        }
+    }
+
+    locations
 });
 
+/// Checks whether `path` is in one of the application directories, where launchers like `gtk-launch` find it by its id
+pub fn is_in_application_dirs(path: &Path) -> bool {
+    DESKTOP_ENTRY_LOCATIONS.iter().any(|dir| path.starts_with(dir))
+}
+
+static DESKTOP_FILES: LazyLock<RwLock<DesktopFileIndex>> =
+    LazyLock::new(|| RwLock::new(DesktopFileIndex::scan(DESKTOP_ENTRY_LOCATIONS.iter())));
+
+/// The methods used to find the command of a window
 #[derive(ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Hash)]
-pub enum Capability {
-    ProcFsSearch,
-    UseProcFsCommand,
+pub enum FindMethod {
+    /// The desktop file named after the gtk app id
+    GtkAppId,
+
+    /// The desktop file named after the sandboxed (e.g. flatpak) app id
+    SandboxedAppId,
+
+    /// The desktop file whose name is most similar to the window class
+    WmClass,
+
+    /// The desktop file that best matches the window class, parts of it or the binary found in `/proc/{pid}/cmdline`
+    SearchTerm,
+
+    /// The command line found in `/proc/{pid}/cmdline`
+    ProcCmdline,
+
+    /// The desktop file of the default application for the type of a file the process was given on its command line,
+    /// for applications opened via a file association whose window class says nothing.
+    /// It is only used with [`Capability::MimeType`] and tried last unless it is in the find order.
+    MimeType,
+
+    /// The application GIO knows about whose id, name or executable is most similar to the window class, including
+    /// ones whose desktop files are not in the indexed directories.
+    /// It is only used with [`FindOptions::gio_fallback`] and tried after all others unless it is in the find order.
+    GioAppInfo,
+}
+
+/// The order in which [`FindMethod`]s are tried by default
+pub const DEFAULT_FIND_ORDER: [FindMethod; 5] = [
+    FindMethod::GtkAppId,
+    FindMethod::SandboxedAppId,
+    FindMethod::WmClass,
+    FindMethod::SearchTerm,
+    FindMethod::ProcCmdline,
+];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FindOrderError {
+    #[error("`{}` is missing from the find order", .0.to_possible_value().unwrap().get_name())]
+    Missing(FindMethod),
+
+    #[error("`{}` is given more than once in the find order", .0.to_possible_value().unwrap().get_name())]
+    Duplicate(FindMethod),
+}
+
+/// Checks that every [`FindMethod`] is given exactly once, [`FindMethod::MimeType`] and [`FindMethod::GioAppInfo`]
+/// may be left out
+pub fn validate_find_order(order: &[FindMethod]) -> Result<(), FindOrderError> {
+    let mut seen = HashSet::new();
+
+    if let Some(&dup) = order.iter().find(|method| !seen.insert(**method)) {
+        return Err(FindOrderError::Duplicate(dup));
+    }
+
+    match DEFAULT_FIND_ORDER.iter().find(|method| !seen.contains(method)) {
+        Some(&missing) => Err(FindOrderError::Missing(missing)),
+        None => Ok(()),
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct FindOptions<'r> {
     pub min_wm_class_similarity: Confidence,
     pub min_partial_match_confidence: Confidence,
-    pub capabilities: &'r HashSet<Capability>,
+    pub capabilities: Capabilities,
+
+    /// The order in which the methods are tried, see [`validate_find_order`]
+    pub order: &'r [FindMethod],
+
+    /// Also match desktop files whose binary is not installed, see [`DesktopFileIndex::candidates`]
+    pub allow_missing_binaries: bool,
+
+    /// Consulted before the window class is matched against the names of desktop files
+    pub aliases: &'r Aliases,
+
+    /// Try [`FindMethod::GioAppInfo`], which asks GIO for all installed applications
+    pub gio_fallback: bool,
 }
 
 #[derive(Error, Debug)]
@@ -56,6 +143,11 @@ pub enum FindError {
     #[error("process is zombie")]
     ProcessIsZombie,
 
+    /// The cmdline of processes of other users (or under `hidepid`) cannot be read,
+    /// the window is still matched by every method that does not need it
+    #[error("permission denied reading /proc/{0}/cmdline and could not find alternative")]
+    ProcAccessDenied(i32),
+
     #[error("proc search disabled but could not find alternative")]
     ProcSearchDisabledNoOtherOptionFound,
 
@@ -63,55 +155,201 @@ pub enum FindError {
     NotAllowedToUseProcCmdNoOtherOptionFound,
 }
 
-pub fn find_command(options: FindOptions, meta: &MetaWindow) -> Result<session::Exec, FindError> {
-    static DESKTOP_FILES: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
-        DESKTOP_ENTRY_LOCATIONS
-            .iter()
-            .filter_map(|location| std::fs::read_dir(location).ok())
-            .flatten()
-            .flatten()
-            .map(|direntry| direntry.path())
-            .filter(|path| path.extension().map_or(false, |ext| ext == "desktop"))
-            .collect()
-    });
-
-    try_find_command_any(options, meta, &DESKTOP_FILES.iter())
+impl FindError {
+    /// A short stable name of the variant, for scripts
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FindError::IOError(_) => "io",
+            FindError::NoSuitableEntryFound => "no-suitable-entry",
+            FindError::ProcessIsZombie => "zombie",
+            FindError::ProcAccessDenied(_) => "proc-access-denied",
+            FindError::ProcSearchDisabledNoOtherOptionFound => "proc-search-disabled",
+            FindError::NotAllowedToUseProcCmdNoOtherOptionFound => "proc-command-not-allowed",
+        }
+    }
 }
 
-pub fn try_find_command_any<D, P>(
-    options: FindOptions,
-    meta: &MetaWindow,
-    desktop_files: &D,
-) -> Result<session::Exec, FindError>
-where
-    D: Iterator<Item = P> + Clone,
-    P: AsRef<Path>,
-{
+/// Finds the command of a window, along with how confident the match is, which is 1.0 for exact matches
+pub fn find_command(options: FindOptions, meta: &MetaWindow) -> Result<(session::Exec, Confidence), FindError> {
+    let desktop_files = DESKTOP_FILES.read().unwrap_or_else(PoisonError::into_inner);
+    try_find_command_any(options, meta, &desktop_files)
+}
+
+/// Finds the desktop file of a window by its gtk or sandboxed app id alone,
+/// e.g. when the desktop file it was saved with does not exist on this machine
+pub fn find_command_by_app_id(meta: &MetaWindow) -> Result<session::Exec, FindError> {
+    let desktop_files = DESKTOP_FILES.read().unwrap_or_else(PoisonError::into_inner);
+
     if !meta.gtk_app_id.is_empty() {
-        if let Ok(exec) = methods::try_find_command_by_gtk_app_id(&meta.gtk_app_id) {
+        if let Ok(exec) = methods::try_find_command_by_gtk_app_id(&meta.gtk_app_id, &desktop_files, false) {
             return Ok(exec);
         }
     }
 
     if !meta.sandboxed_app_id.is_empty() {
-        if let Ok(exec) =
-            methods::try_find_command_by_sandboxed_app_id(&meta.sandboxed_app_id, DESKTOP_ENTRY_LOCATIONS.iter())
-        {
-            return Ok(exec);
-        }
+        return methods::try_find_command_by_sandboxed_app_id(
+            &meta.sandboxed_app_id,
+            meta.flatpak_scope,
+            &desktop_files,
+            false,
+        );
     }
 
-    match methods::try_find_command_by_wm_class(&meta.window_class, desktop_files.clone()) {
-        Ok((exec, confidence)) if confidence >= options.min_wm_class_similarity => return Ok(exec),
-        _ => (),
-    }
+    Err(FindError::NoSuitableEntryFound)
+}
+
+/// The directories desktop files are searched in, e.g. to watch them for changes
+pub fn desktop_entry_locations() -> impl Iterator<Item = &'static Path> {
+    DESKTOP_ENTRY_LOCATIONS.iter().map(PathBuf::as_path)
+}
+
+/// Applies changes to the desktop files to the index used by [`find_command`],
+/// commands that are being found meanwhile still see the old index
+pub fn refresh_desktop_files(changes: watch::Changes) {
+    let refreshed = match changes {
+        watch::Changes::Files(paths) => {
+            let mut index = DESKTOP_FILES.read().unwrap_or_else(PoisonError::into_inner).clone();
+            index.refresh(paths);
+            index
+        },
+        watch::Changes::Rescan => DesktopFileIndex::scan(DESKTOP_ENTRY_LOCATIONS.iter()),
+    };
+
+    *DESKTOP_FILES.write().unwrap_or_else(PoisonError::into_inner) = refreshed;
+}
+
+/// A command found for a window and how it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found {
+    pub exec: session::Exec,
+    pub confidence: Confidence,
+    pub method: FindMethod,
+}
 
-    let maybe_proc_cmdline = if options.capabilities.contains(&Capability::ProcFsSearch) {
+/// Like [`find_command`], but also tells which method found the command
+pub fn find_command_detailed(options: FindOptions, meta: &MetaWindow) -> Result<Found, FindError> {
+    let desktop_files = DESKTOP_FILES.read().unwrap_or_else(PoisonError::into_inner);
+    try_find_command_detailed(options, meta, &desktop_files)
+}
+
+pub fn try_find_command_any(
+    options: FindOptions,
+    meta: &MetaWindow,
+    desktop_files: &DesktopFileIndex,
+) -> Result<(session::Exec, Confidence), FindError> {
+    try_find_command_detailed(options, meta, desktop_files).map(|found| (found.exec, found.confidence))
+}
+
+pub fn try_find_command_detailed(
+    options: FindOptions,
+    meta: &MetaWindow,
+    desktop_files: &DesktopFileIndex,
+) -> Result<Found, FindError> {
+    let maybe_proc_cmdline = if options.capabilities.contains(Capability::ProcFsSearch) {
+        // a denied cmdline is not fatal, the methods below just go without it
         methods::try_find_command_in_proc(meta.pid)
     } else {
         Err(FindError::ProcSearchDisabledNoOtherOptionFound)
     };
 
+    let proc_comm = if options.capabilities.contains(Capability::ReadProcComm) {
+        methods::try_read_proc_comm(meta.pid).ok()
+    } else {
+        None
+    };
+
+    // the weakest hint, it is only tried before others if it was put into the order explicitly
+    let mime_type_last = (!options.order.contains(&FindMethod::MimeType)).then_some(&FindMethod::MimeType);
+    let gio_app_info_last = (!options.order.contains(&FindMethod::GioAppInfo)).then_some(&FindMethod::GioAppInfo);
+
+    for method in options.order.iter().chain(mime_type_last).chain(gio_app_info_last) {
+        let exec = match method {
+            FindMethod::GtkAppId if !meta.gtk_app_id.is_empty() => {
+                methods::try_find_command_by_gtk_app_id(&meta.gtk_app_id, desktop_files, options.allow_missing_binaries)
+                    .ok()
+                    .map(|exec| (exec, 1.0))
+            },
+            FindMethod::SandboxedAppId if !meta.sandboxed_app_id.is_empty() => {
+                methods::try_find_command_by_sandboxed_app_id(
+                    &meta.sandboxed_app_id,
+                    meta.flatpak_scope,
+                    desktop_files,
+                    options.allow_missing_binaries,
+                )
+                .ok()
+                .map(|exec| (exec, 1.0))
+            },
+            FindMethod::WmClass => {
+                let aliased = options.aliases.get(&meta.window_class).and_then(|desktop_file| {
+                    methods::try_find_command_by_alias(desktop_file, desktop_files, options.allow_missing_binaries).ok()
+                });
+
+                let found = match aliased {
+                    Some(found @ (_, confidence)) if confidence >= options.min_wm_class_similarity => Ok(found),
+                    _ => methods::try_find_command_by_wm_class(
+                        &meta.window_class,
+                        options.min_wm_class_similarity,
+                        desktop_files,
+                        options.allow_missing_binaries,
+                    ),
+                };
+
+                match found {
+                    Ok(found @ (_, confidence)) if confidence >= options.min_wm_class_similarity => Some(found),
+                    _ => None,
+                }
+            },
+            FindMethod::SearchTerm => try_find_command_by_search_terms(
+                options,
+                meta,
+                &maybe_proc_cmdline,
+                proc_comm.as_deref(),
+                desktop_files,
+            ),
+            FindMethod::MimeType if options.capabilities.contains(Capability::MimeType) => {
+                maybe_proc_cmdline.as_ref().ok().and_then(|cmdline| {
+                    methods::try_find_command_by_mime_type(cmdline, desktop_files, options.allow_missing_binaries).ok()
+                })
+            },
+            FindMethod::GioAppInfo if options.gio_fallback && !meta.window_class.is_empty() => {
+                methods::try_find_command_by_gio_app_info(&meta.window_class, options.min_wm_class_similarity).ok()
+            },
+            FindMethod::ProcCmdline if options.capabilities.contains(Capability::UseProcFsCommand) => {
+                maybe_proc_cmdline
+                    .as_ref()
+                    .ok()
+                    .map(|cmdline| (session::Exec::CmdLine(cmdline.clone()), 1.0))
+            },
+            _ => None,
+        };
+
+        if let Some((exec, confidence)) = exec {
+            return Ok(Found { exec, confidence, method: *method });
+        }
+    }
+
+    if options.capabilities.contains(Capability::UseProcFsCommand) {
+        Err(maybe_proc_cmdline.err().unwrap_or(FindError::NoSuitableEntryFound))
+    } else {
+        Err(FindError::NotAllowedToUseProcCmdNoOtherOptionFound)
+    }
+}
+
+/// How much a match of the process name in `/proc/{pid}/comm` counts, it is a weaker hint than the binary
+/// as it may be any name the process chose, and even less so if it was truncated
+const PROC_COMM_WEIGHT: Confidence = 0.9;
+const TRUNCATED_PROC_COMM_WEIGHT: Confidence = 0.8;
+
+/// Searches desktop files for the window class, parts of it and the binary name found in `/proc/{pid}/cmdline`,
+/// or the process name from `/proc/{pid}/comm` if the command line is of no use, returns the best match if it is good enough
+fn try_find_command_by_search_terms(
+    options: FindOptions,
+    meta: &MetaWindow,
+    maybe_proc_cmdline: &Result<Vec<std::ffi::OsString>, FindError>,
+    proc_comm: Option<&str>,
+    desktop_files: &DesktopFileIndex,
+) -> Option<(session::Exec, Confidence)> {
+    // the search terms with the weight of their matches
     let alt_search_terms = {
         static CHROME_APP_RE: LazyLock<Regex> =
             LazyLock::new(|| Regex::new("chrome-(?P<website>.+?)__.*?-(?P<profile>.+)").unwrap());
@@ -119,36 +357,58 @@ where
         let mut buf = Vec::new();
 
         if !meta.window_class.is_empty() {
-            buf.push((&meta.window_class).into());
+            buf.push(((&meta.window_class).into(), 1.0));
         }
 
         if let Some(cap) = CHROME_APP_RE.captures(&meta.window_class) {
-            buf.extend([cap.name("website"), cap.name("profile")].map(|m| m.unwrap().as_str().into()));
+            buf.extend([cap.name("website"), cap.name("profile")].map(|m| (m.unwrap().as_str().into(), 1.0)));
         }
 
+        let mut found_binary = false;
+
         {
             let proc_binary = maybe_proc_cmdline
                 .as_ref()
                 .ok()
-                .and_then(|cmdline| cmdline.get(0))
+                .and_then(|cmdline| cmdline.first())
                 .and_then(|binary| Path::new(binary).file_name())
                 .map(OsStr::to_string_lossy);
 
             if let Some(proc_binary) = proc_binary {
                 if meta.window_class.is_empty()
-                    || strsim::normalized_levenshtein(&proc_binary, &meta.window_class) > 0.5
+                    || methods::bounded_levenshtein::bounded_normalized_levenshtein(
+                        &proc_binary,
+                        &meta.window_class,
+                        0.5,
+                    )
+                    .is_some_and(|sim| sim > 0.5)
                 {
-                    buf.push(proc_binary);
+                    buf.push((proc_binary, 1.0));
+                    found_binary = true;
                 }
             }
         }
 
+        if let Some(comm) = proc_comm.filter(|_| !found_binary) {
+            let weight = if comm.len() >= methods::PROC_COMM_MAX_LEN {
+                TRUNCATED_PROC_COMM_WEIGHT
+            } else {
+                PROC_COMM_WEIGHT
+            };
+
+            buf.push((comm.into(), weight));
+        }
+
         buf
     };
 
     let search_term_result = alt_search_terms
         .into_iter()
-        .filter_map(|search_term| methods::try_find_command_by_search_term(&search_term, desktop_files.clone()).ok())
+        .filter_map(|(search_term, weight)| {
+            methods::try_find_command_by_search_term(&search_term, desktop_files, options.allow_missing_binaries)
+                .ok()
+                .map(|(exec, confidence)| (exec, confidence.min(confidence * weight)))
+        })
         .reduce(
             |acc @ (_, acc_sim), x @ (_, x_sim)| {
                 if x_sim > acc_sim {
@@ -160,115 +420,139 @@ where
         );
 
     match search_term_result {
-        Some((exec, confidence)) if confidence >= options.min_partial_match_confidence => return Ok(exec),
-        _ => (),
-    }
-
-    if options.capabilities.contains(&Capability::UseProcFsCommand) {
-        Ok(session::Exec::CmdLine(maybe_proc_cmdline?))
-    } else {
-        Err(FindError::NotAllowedToUseProcCmdNoOtherOptionFound)
+        Some(found @ (_, confidence)) if confidence >= options.min_partial_match_confidence => Some(found),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        dbus::{MetaWindow, WindowGeom},
-        find_command::{FindError, FindOptions},
+        dbus::MetaWindow,
+        find_command::{
+            testsets, validate_find_order, Aliases, Capabilities, DesktopFileIndex, FindError, FindMethod, FindOptions,
+            FindOrderError, DEFAULT_FIND_ORDER,
+        },
         session::Exec,
     };
-    use std::{collections::HashSet, path::Path, sync::LazyLock};
-
-    const TESTSET: &str = include_str!("../../testset.list");
+    use std::sync::LazyLock;
 
-    fn get_testset() -> impl Iterator<Item = &'static Path> + Clone {
-        static TS: LazyLock<Vec<&'static Path>> = LazyLock::new(|| {
-            TESTSET
-                .split("\n")
-                .filter(|s| !s.is_empty())
-                .map(|s| Path::new(s))
-                .collect()
-        });
-
-        TS.iter().map(|&p| p)
-    }
+    static TESTSET_INDEX: LazyLock<DesktopFileIndex> =
+        LazyLock::new(|| DesktopFileIndex::new(testsets::paths(testsets::DEFAULT)));
 
-    fn find_dummy(window_class: &str, gtk_app_id: &str, sandboxed_app_id: &str) -> Result<Exec, FindError> {
+    fn find_in(
+        desktop_files: &DesktopFileIndex,
+        window_class: &str,
+        gtk_app_id: &str,
+        sandboxed_app_id: &str,
+    ) -> Result<Exec, FindError> {
         super::try_find_command_any(
             FindOptions {
                 min_wm_class_similarity: 0.8,
                 min_partial_match_confidence: 0.6,
-                capabilities: &HashSet::new(),
+                capabilities: Capabilities::none(),
+                order: &DEFAULT_FIND_ORDER,
+                allow_missing_binaries: false,
+                aliases: &Aliases::builtin(),
+                gio_fallback: false,
             },
             &MetaWindow {
-                geom: WindowGeom { x: 0, y: 0, width: 0, height: 0, minimized: false },
-                pid: 0,
-                stable_seq: 0,
                 window_class: window_class.to_string(),
                 gtk_app_id: gtk_app_id.to_string(),
                 sandboxed_app_id: sandboxed_app_id.to_string(),
+                ..Default::default()
             },
-            &get_testset(),
+            desktop_files,
         )
+        .map(|(exec, _)| exec)
     }
 
     #[test]
-    fn find_chrome_custom_app() {
-        let s = find_dummy("chrome-listen.tidal.com__-Spotify", "", "").expect("finding any");
-
-        assert_eq!(
-            s,
-            Exec::DesktopFile("/home/liss/.local/share/applications/tidal.desktop".into())
-        );
+    fn find_testset_expectations() {
+        for (name, testset) in testsets::all() {
+            let index = DesktopFileIndex::new(testsets::paths(&testset));
+            let expectations = testsets::expectations(&testset).unwrap();
+            assert!(!expectations.is_empty(), "{name:?} has no expectations");
+
+            for e in expectations {
+                let found = find_in(&index, e.window_class, e.gtk_app_id, e.sandboxed_app_id).ok();
+                let path = match &found {
+                    Some(Exec::DesktopFile(path)) => Some(path.as_path()),
+                    _ => None,
+                };
+
+                assert!(e.is_expected(path), "{name:?}: {e:?}, found {found:?}");
+            }
+        }
     }
 
     #[test]
-    fn find_flatpak_app() {
-        let s = find_dummy("jetbrains-clion", "", "com.jetbrains.CLion").expect("finding clion");
+    fn find_by_proc_comm() {
+        let options = FindOptions {
+            min_wm_class_similarity: 0.8,
+            min_partial_match_confidence: 0.6,
+            capabilities: Capabilities::none(),
+            order: &DEFAULT_FIND_ORDER,
+            allow_missing_binaries: false,
+            aliases: &Aliases::builtin(),
+            gio_fallback: false,
+        };
+
+        let meta = MetaWindow::default();
+
+        // the process wrote over argv
+        let cmdline = Ok(vec!["".into()]);
+        let find = |comm| {
+            super::try_find_command_by_search_terms(options, &meta, &cmdline, Some(comm), &TESTSET_INDEX)
+                .map(|(exec, _)| exec)
+        };
 
         assert_eq!(
-            s,
-            Exec::DesktopFile("/var/lib/flatpak/exports/share/applications/com.jetbrains.CLion.desktop".into())
+            find("steam"),
+            Some(Exec::DesktopFile(
+                "/var/lib/flatpak/exports/share/applications/com.valvesoftware.Steam.desktop".into()
+            ))
         );
-
-        let s = find_dummy("firefox", "", "org.mozilla.firefox").expect("finding firefox");
-
+        // truncated to 15 characters
         assert_eq!(
-            s,
-            Exec::DesktopFile("/var/lib/flatpak/exports/share/applications/org.mozilla.firefox.desktop".into())
+            find("libreoffice-wri"),
+            Some(Exec::DesktopFile(
+                "/usr/share/applications/libreoffice-writer.desktop".into()
+            ))
         );
-    }
-
-    #[test]
-    fn find_gnome_app() {
-        let s = find_dummy("gnome-terminal-server", "org.gnome.Terminal", "").expect("finding gnome terminal");
-
-        assert_eq!(
-            s,
-            Exec::DesktopFile("/usr/share/applications/org.gnome.Terminal.desktop".into())
+        assert_eq!(find("xyzzy"), None);
+
+        // the binary found in the command line takes precedence
+        let cmdline = Ok(vec!["/usr/bin/firefox".into()]);
+        let found = super::try_find_command_by_search_terms(options, &meta, &cmdline, Some("steam"), &TESTSET_INDEX);
+        assert_ne!(
+            found.map(|(exec, _)| exec),
+            Some(Exec::DesktopFile(
+                "/var/lib/flatpak/exports/share/applications/com.valvesoftware.Steam.desktop".into()
+            ))
         );
     }
 
     #[test]
-    fn find_lutris_app() {
-        let s = find_dummy("org.multimc.MultiMC", "", "").expect("finding multimc");
-
-        dbg!(
-            strsim::normalized_levenshtein("org.multimc.MultiMC", "net.lutris.multimc-2"),
-            strsim::normalized_levenshtein("org.multimc.MultiMC", "org.gimp.GIMP")
+    fn find_order() {
+        assert_eq!(validate_find_order(&DEFAULT_FIND_ORDER), Ok(()));
+        assert_eq!(
+            validate_find_order(&[
+                FindMethod::ProcCmdline,
+                FindMethod::SearchTerm,
+                FindMethod::WmClass,
+                FindMethod::SandboxedAppId,
+                FindMethod::GtkAppId,
+            ]),
+            Ok(())
         );
-
         assert_eq!(
-            s,
-            Exec::DesktopFile("/home/liss/.local/share/applications/net.lutris.multimc-2.desktop".into())
+            validate_find_order(&[FindMethod::GtkAppId, FindMethod::WmClass]),
+            Err(FindOrderError::Missing(FindMethod::SandboxedAppId))
         );
-
-        let s = find_dummy("battle.net.exe", "", "").expect("finding battlenet");
-
         assert_eq!(
-            s,
-            Exec::DesktopFile("/home/liss/.local/share/applications/net.lutris.battlenet-7.desktop".into())
+            validate_find_order(&[FindMethod::WmClass, FindMethod::GtkAppId, FindMethod::WmClass]),
+            Err(FindOrderError::Duplicate(FindMethod::WmClass))
         );
     }
 
@@ -0,0 +1,105 @@
+//! Lists of desktop files labeled with the windows that should be found in them, as in `tests/testsets`,
+//! which are recorded with `dev dump-desktop-files` on real machines.
+//!
+//! Every line is the path of a desktop file, except for empty lines, comments starting with `#` and
+//! expectations of the form `? <window class> | <gtk app id> | <sandboxed app id> => <desktop file>`,
+//! where the desktop file is given by its path or its desktop file id, or is `none` if nothing should be found.
+
+use super::index::desktop_file_id;
+use std::path::Path;
+use thiserror::Error;
+
+/// The testset most tests are written against
+#[cfg(test)]
+pub const DEFAULT: &str = include_str!("../../tests/testsets/fedora.list");
+
+/// A window and the desktop file that should be found for it
+#[derive(Debug)]
+pub struct Expectation<'t> {
+    pub window_class: &'t str,
+    pub gtk_app_id: &'t str,
+    pub sandboxed_app_id: &'t str,
+
+    /// The path or desktop file id of the desktop file, `None` if nothing should be found
+    pub expected: Option<&'t str>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("line {line}: {reason}")]
+pub struct TestsetParseError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl Expectation<'_> {
+    /// Whether `found` is the expected desktop file
+    pub fn is_expected(&self, found: Option<&Path>) -> bool {
+        match (self.expected, found) {
+            (Some(expected), Some(found)) if expected.contains('/') => Path::new(expected) == found,
+            (Some(expected), Some(found)) => desktop_file_id(found).is_some_and(|id| id == expected),
+            (expected, found) => expected.is_none() && found.is_none(),
+        }
+    }
+}
+
+/// The desktop files of a testset
+pub fn paths(testset: &str) -> impl Iterator<Item = &Path> + Clone {
+    testset
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('?'))
+        .map(Path::new)
+}
+
+/// The expectations of a testset
+pub fn expectations(testset: &str) -> Result<Vec<Expectation<'_>>, TestsetParseError> {
+    let mut expectations = Vec::new();
+
+    for (ix, line) in testset.lines().enumerate() {
+        let Some(line) = line.trim().strip_prefix('?') else {
+            continue;
+        };
+
+        let error = |reason| TestsetParseError { line: ix + 1, reason };
+
+        let (window, expected) = line.split_once("=>").ok_or_else(|| error("expected `=>`"))?;
+        let fields: Vec<_> = window.split('|').map(str::trim).collect();
+
+        let [window_class, gtk_app_id, sandboxed_app_id] = fields[..] else {
+            return Err(error("expected `<window class> | <gtk app id> | <sandboxed app id>`"));
+        };
+
+        let expected = match expected.trim() {
+            "" => return Err(error("expected a desktop file or `none` after `=>`")),
+            "none" => None,
+            expected => Some(expected),
+        };
+
+        if window_class.is_empty() && gtk_app_id.is_empty() && sandboxed_app_id.is_empty() {
+            return Err(error("expected a window class or app id"));
+        }
+
+        expectations.push(Expectation { window_class, gtk_app_id, sandboxed_app_id, expected });
+    }
+
+    Ok(expectations)
+}
+
+/// The names and contents of the testsets in `tests/testsets`
+#[cfg(test)]
+pub fn all() -> Vec<(std::path::PathBuf, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/testsets");
+
+    let mut testsets: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "list"))
+        .map(|path| {
+            let contents = std::fs::read_to_string(&path).unwrap();
+            (path, contents)
+        })
+        .collect();
+
+    testsets.sort();
+    testsets
+}
@@ -0,0 +1,161 @@
+//! Watching the desktop file directories for changes, so that long running processes notice
+//! applications that were installed or removed after they started.
+
+use super::index::is_desktop_file;
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// What changed in the watched directories
+#[derive(Debug, Clone, PartialEq)]
+pub enum Changes {
+    /// The desktop files that were created, changed or removed
+    Files(BTreeSet<PathBuf>),
+
+    /// Too many changes happened to keep track of them, all directories have to be scanned again
+    Rescan,
+}
+
+/// Watches directories for changes to the desktop files in them, changes in subdirectories
+/// are not noticed and need a [`Changes::Rescan`]
+#[derive(Debug)]
+pub struct DesktopFileWatcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl DesktopFileWatcher {
+    /// Watches the given directories, directories that cannot be watched (e.g. because they do not exist) are skipped
+    pub fn new<I, P>(dirs: I) -> nix::Result<Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+        let mask = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO;
+
+        let dirs = dirs
+            .into_iter()
+            .filter_map(|dir| {
+                let dir = dir.as_ref();
+
+                match inotify.add_watch(dir, mask) {
+                    Ok(wd) => Some((wd, dir.to_owned())),
+                    Err(e) => {
+                        eprintln!("not watching {dir:?} for new applications: {e}");
+                        None
+                    },
+                }
+            })
+            .collect();
+
+        Ok(DesktopFileWatcher { inotify, dirs })
+    }
+
+    /// Waits up to `timeout` (or forever) for desktop files to change, then keeps collecting changes until
+    /// there were none for `debounce`, so that e.g. a package installing many files causes a single refresh.
+    /// Returns `None` if nothing changed within `timeout`.
+    pub fn wait_for_changes(&self, timeout: Option<Duration>, debounce: Duration) -> nix::Result<Option<Changes>> {
+        let mut changed = BTreeSet::new();
+        let mut rescan = false;
+        let mut wait = timeout;
+
+        loop {
+            let mut fds = [PollFd::new(self.inotify.as_raw_fd(), PollFlags::POLLIN)];
+            let millis = wait.map_or(-1, |wait| i32::try_from(wait.as_millis()).unwrap_or(i32::MAX));
+
+            if poll(&mut fds, millis)? == 0 {
+                break;
+            }
+
+            for event in self.inotify.read_events()? {
+                if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                    rescan = true;
+                }
+
+                let path = self
+                    .dirs
+                    .get(&event.wd)
+                    .zip(event.name)
+                    .map(|(dir, name)| dir.join(name));
+
+                if let Some(path) = path.filter(|path| is_desktop_file(path)) {
+                    changed.insert(path);
+                }
+            }
+
+            if rescan || !changed.is_empty() {
+                wait = Some(debounce);
+            }
+        }
+
+        Ok(if rescan {
+            Some(Changes::Rescan)
+        } else {
+            (!changed.is_empty()).then_some(Changes::Files(changed))
+        })
+    }
+}
+
+impl Drop for DesktopFileWatcher {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.inotify.as_raw_fd());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Changes, DesktopFileWatcher};
+    use crate::find_command::DesktopFileIndex;
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn index_converges() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut index = DesktopFileIndex::scan([&dir]);
+        let watcher = DesktopFileWatcher::new([&dir]).unwrap();
+        let wait = || {
+            watcher
+                .wait_for_changes(Some(Duration::from_secs(5)), Duration::from_millis(50))
+                .unwrap()
+        };
+
+        let app = dir.join("org.example.App.desktop");
+        fs::write(&app, "[Desktop Entry]\nType=Application\nExec=sh\n").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let Some(Changes::Files(changed)) = wait() else {
+            panic!("expected changed files")
+        };
+        assert_eq!(changed.iter().collect::<Vec<_>>(), vec![&app]);
+
+        index.refresh(&changed);
+        assert_eq!(
+            index.iter().map(|f| f.stem.as_str()).collect::<Vec<_>>(),
+            vec!["org.example.app"]
+        );
+
+        fs::remove_file(&app).unwrap();
+
+        let Some(Changes::Files(changed)) = wait() else {
+            panic!("expected changed files")
+        };
+        index.refresh(&changed);
+        assert_eq!(index.iter().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
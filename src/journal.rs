@@ -0,0 +1,261 @@
+//! The journal of saves and restores in the state directory, to look at what happened when something went wrong
+//! (e.g. at login).
+//!
+//! Every invocation appends one json [`Record`] as a line to `journal.jsonl`. Once the file grows beyond
+//! [`MAX_JOURNAL_SIZE`] it is rotated to `journal.jsonl.1`, replacing the previous rotation.
+//! Writing the journal is best-effort: failing to do so is printed and never fails the save or restore.
+
+use crate::session::{RestoreReport, SaveReport};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The name of the journal in the state directory
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// The size in bytes beyond which the journal is rotated
+pub const MAX_JOURNAL_SIZE: u64 = 1024 * 1024;
+
+/// What happened in an invocation
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Saved(SaveReport),
+    Restored(RestoreReport),
+    Failed { error: String },
+}
+
+impl Outcome {
+    /// The outcome of a save or restore that returned `result`
+    pub fn of<R: Clone + Into<Outcome>, E: fmt::Display>(result: &Result<R, E>) -> Self {
+        match result {
+            Ok(report) => report.clone().into(),
+            Err(e) => Outcome::Failed { error: e.to_string() },
+        }
+    }
+}
+
+impl From<SaveReport> for Outcome {
+    fn from(report: SaveReport) -> Self {
+        Outcome::Saved(report)
+    }
+}
+
+impl From<RestoreReport> for Outcome {
+    fn from(report: RestoreReport) -> Self {
+        Outcome::Restored(report)
+    }
+}
+
+/// One save or restore
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// When the invocation finished, in seconds since the unix epoch
+    pub timestamp: u64,
+
+    /// The subcommand, e.g. `restore`
+    pub subcommand: String,
+
+    /// The session file that was saved or restored
+    pub file: PathBuf,
+
+    /// The version of gnome-session-restore
+    pub version: String,
+
+    /// The version of the running gnome-shell, if it could be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_version: Option<String>,
+
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+impl Record {
+    pub fn new(subcommand: &str, file: &Path, shell_version: Option<String>, outcome: Outcome) -> Self {
+        Record {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            subcommand: subcommand.to_owned(),
+            file: file.to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            shell_version,
+            outcome,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal {
+    path: PathBuf,
+    max_size: u64,
+}
+
+impl Journal {
+    /// The journal in the state directory `state_dir`
+    pub fn new(state_dir: &Path) -> Self {
+        Journal {
+            path: state_dir.join(JOURNAL_FILE),
+            max_size: MAX_JOURNAL_SIZE,
+        }
+    }
+
+    /// Where the journal is rotated to
+    fn rotated_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".1");
+        PathBuf::from(path)
+    }
+
+    /// Appends `record`, rotating the journal first if it is too large
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() + line.len() as u64 > self.max_size => {
+                fs::rename(&self.path, self.rotated_path())?
+            },
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+
+        // a single write, so that concurrent invocations do not interleave their records
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)
+    }
+
+    /// Appends `record`, printing instead of failing if it cannot be written
+    pub fn record(&self, record: &Record) {
+        if let Err(e) = self.append(record) {
+            eprintln!("unable to write to the journal {:?}: {e}", self.path);
+        }
+    }
+
+    /// The last `n` records, oldest first. Lines that cannot be read (e.g. cut off by a crash) are skipped.
+    pub fn last(&self, n: usize) -> io::Result<Vec<Record>> {
+        let mut records = Vec::new();
+
+        for path in [self.rotated_path(), self.path.clone()] {
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            records.extend(contents.lines().filter_map(|line| serde_json::from_str(line).ok()));
+        }
+
+        let skip = records.len().saturating_sub(n);
+        Ok(records.split_off(skip))
+    }
+
+    /// Removes the journal and its rotation
+    pub fn clear(&self) -> io::Result<()> {
+        for path in [self.path.clone(), self.rotated_path()] {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Journal, Outcome, Record};
+    use crate::session::{RestoreReport, SaveReport};
+    use std::path::Path;
+
+    fn failed(error: &str) -> Record {
+        Record::new(
+            "restore",
+            Path::new("/tmp/session.json"),
+            None,
+            Outcome::Failed { error: error.to_owned() },
+        )
+    }
+
+    #[test]
+    fn appends_rotates_and_clears() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-journal-{}", std::process::id()));
+        let journal = Journal { max_size: 400, ..Journal::new(&dir) };
+
+        let saved = SaveReport {
+            saved: vec!["Firefox".to_owned()],
+            unresolved: vec!["steam".to_owned()],
+        };
+        journal
+            .append(&Record::new(
+                "save",
+                Path::new("/tmp/session.json"),
+                Some("45.2".to_owned()),
+                Outcome::Saved(saved),
+            ))
+            .unwrap();
+
+        let restored = RestoreReport { launched: vec!["Firefox".to_owned()], ..Default::default() };
+        journal
+            .append(&Record::new(
+                "restore",
+                Path::new("/tmp/session.json"),
+                None,
+                Outcome::Restored(restored),
+            ))
+            .unwrap();
+
+        let records = journal.last(10).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(&records[0].outcome, Outcome::Saved(report) if report.unresolved == ["steam"]));
+        assert_eq!(records[0].shell_version.as_deref(), Some("45.2"));
+        assert!(matches!(&records[1].outcome, Outcome::Restored(report) if report.launched == ["Firefox"]));
+
+        // garbage is skipped
+        std::fs::write(
+            &journal.path,
+            [std::fs::read(&journal.path).unwrap(), b"{\"time\n".to_vec()].concat(),
+        )
+        .unwrap();
+        assert_eq!(journal.last(10).unwrap().len(), 2);
+
+        // every record is kept in the journal or its rotation, until it is rotated out
+        for i in 0..10 {
+            journal.append(&failed(&i.to_string())).unwrap();
+            assert!(std::fs::metadata(&journal.path).unwrap().len() <= 400);
+        }
+
+        let errors: Vec<_> = journal
+            .last(2)
+            .unwrap()
+            .into_iter()
+            .map(|record| match record.outcome {
+                Outcome::Failed { error } => error,
+                outcome => panic!("unexpected {outcome:?}"),
+            })
+            .collect();
+        assert_eq!(errors, ["8", "9"]);
+        assert!(journal.rotated_path().exists());
+
+        journal.clear().unwrap();
+        assert!(journal.last(10).unwrap().is_empty());
+        journal.clear().unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
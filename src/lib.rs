@@ -0,0 +1,15 @@
+//! The modules of gnome-session-restore, the command line interface is in `main.rs`.
+//! They are a library so that the benchmarks in `benches` can use them.
+
+pub mod config;
+pub mod daemon;
+pub mod dbus;
+pub mod desktop_entry;
+pub mod find_command;
+pub mod journal;
+pub mod marker;
+pub mod output;
+pub mod paths;
+pub mod prune;
+pub mod resolve;
+pub mod session;
@@ -1,23 +1,31 @@
-#![feature(once_cell)]
-
-mod dbus;
-pub mod find_command;
-mod session;
-
 use crate::dbus::MetaWindow;
-use clap::{ArgEnum, Parser, Subcommand, ValueHint};
-use dbus::WindowCtlProxy;
-use session::{Capability, Confidence};
+use clap::{ArgEnum, Args, CommandFactory, ErrorKind, FromArgMatches, Parser, Subcommand, ValueHint};
+use dbus::{SessionManagerProxy, WindowCtlProxy};
+use find_command::{aliases::Alias, Aliases, FindMethod, DEFAULT_FIND_ORDER};
+use gnome_session_restore::{
+    config, daemon, dbus, find_command, journal, marker, output, paths, prune, resolve, session,
+};
+use journal::{Journal, Outcome};
+use marker::RestoreMarker;
+use output::OutputFormat;
+use regex::Regex;
+use session::{
+    filter::{self, ExcludePattern},
+    Capabilities, Capability, Confidence, DisplayServer, WindowFilter,
+};
 use std::{
     collections::HashSet,
-    ffi::{OsStr, OsString},
+    ffi::OsString,
     fmt::Debug,
-    fs::File,
-    io::{BufReader, BufWriter, Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 use zbus::Connection;
 
+const DEFAULT_MIN_WM_CLASS_SIMILARITY: Confidence = 0.8;
+const DEFAULT_MIN_PARTIAL_MATCH_CONFIDENCE: Confidence = 0.6;
+
 fn valid_confidence_value(s: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     let x = s.parse::<f32>()?;
 
@@ -28,11 +36,34 @@ fn valid_confidence_value(s: &str) -> Result<(), Box<dyn std::error::Error + Sen
     }
 }
 
-fn default_session_file_path() -> PathBuf {
-    xdg::BaseDirectories::with_prefix("gnome-session-restore")
-        .unwrap()
-        .place_state_file("last-session.json")
-        .unwrap()
+fn valid_format_version(s: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    let v = s.parse::<u32>()?;
+
+    if session::format::is_supported(v) {
+        Ok(())
+    } else {
+        Err(format!(
+            "unsupported format version, expected a version in {}..={}",
+            session::format::OLDEST_FORMAT_VERSION,
+            session::CURRENT_FORMAT_VERSION
+        )
+        .into())
+    }
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum SessionFormat {
+    Json,
+    Toml,
+}
+
+impl SessionFormat {
+    fn stream_format(self) -> session::io::StreamFormat {
+        match self {
+            SessionFormat::Json => session::io::StreamFormat::Json,
+            SessionFormat::Toml => session::io::StreamFormat::Toml,
+        }
+    }
 }
 
 #[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
@@ -41,50 +72,776 @@ enum Policy {
     Deny,
 }
 
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum ExcludeLayer {
+    /// The shell's own windows and dialogs
+    Default,
+
+    /// `--exclude`, `--exclude-pid` and `--exclude-current`
+    CommandLine,
+
+    /// The patterns of `--exclude-file`
+    ExcludeFile,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum MissingApp {
+    /// Do not launch the application
+    Skip,
+
+    /// Find a new command for the application's saved window, like `save` does
+    Cmdline,
+
+    /// Abort the restore
+    Error,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum Fallback {
+    /// Do not place windows
+    Skip,
+
+    /// Fit all windows into the primary monitor, keeping their relative positions
+    Primary,
+
+    /// Cascade all windows on the primary monitor
+    Cascade,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum LaunchBackend {
+    /// Spawn the Exec line of the desktop file
+    Direct,
+
+    /// Launch via gio, falling back to the Exec line if that fails
+    Gio,
+
+    /// Launch via `gtk-launch`, only works for desktop files in the application directories
+    GtkLaunch,
+
+    /// Spawn the Exec line in its own scope via `systemd-run --user --scope`
+    SystemdRun,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum CrossSessionType {
+    /// Do not move windows
+    Skip,
+
+    /// Add or remove the title bar (see `--title-bar-height`) to the saved geometry
+    Correct,
+
+    /// Move windows to the saved geometry as it is
+    Force,
+}
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+enum SettleStrategy {
+    /// Sleep for `--settle-time`, then place the windows that are open
+    Fixed,
+
+    /// Place windows as they appear
+    Poll,
+
+    /// Launch applications one by one and wait for the windows of each
+    PerApp,
+}
+
+/// How the commands of windows are found, shared by `save` and `resolve-session`
+#[derive(Debug, Args)]
+struct FindArgs {
+    /// Set the minimum required (levenshtein) similarity between the WM_CLASS
+    /// and the binary name to allow it to be considered
+    /// as an alternative application name.
+    #[clap(long, default_value_t = DEFAULT_MIN_WM_CLASS_SIMILARITY, validator = valid_confidence_value)]
+    min_wm_class_similarity: Confidence,
+
+    #[clap(long, default_value_t = DEFAULT_MIN_PARTIAL_MATCH_CONFIDENCE, validator = valid_confidence_value)]
+    min_partial_match_confidence: Confidence,
+
+    /// The comma separated capabilities to grant, e.g. `procfs-search,environ`, instead of those allowed by the
+    /// --procfs-*-policy flags [possible values: procfs-search, procfs-command, cwd, environ, comm, mime]
+    #[clap(long, value_name = "CAPABILITIES")]
+    capabilities: Option<Capabilities>,
+
+    /// Determine whether gnome-session-restore is allowed to search in /proc/{pid}/cmdline
+    /// to obtain information that may be helpful. [hint: specifying deny will also implicitly add --procfs-use-comand-policy deny]
+    #[clap(long, arg_enum, default_value_t = Policy::Allow)]
+    procfs_search_policy: Policy,
+
+    /// Determine whether gnome-session-restore is allowed to use the command it finds
+    /// in /proc/{pid}/commandline as a way to start an application if not desktop file is found.
+    #[clap(long, arg_enum, default_value_t = Policy::Deny)]
+    procfs_use_command_policy: Policy,
+
+    /// Determine whether gnome-session-restore is allowed to read the process name from /proc/{pid}/comm,
+    /// which is searched for when the command line of an application is of no use (e.g. it wrote over it)
+    #[clap(long, arg_enum, default_value_t = Policy::Allow)]
+    procfs_comm_policy: Policy,
+
+    /// The order in which the ways of finding the command of a window are tried, every one has to be given
+    /// exactly once, except mime-type and gio-app-info which are tried last if they are left out
+    /// [default: gtk-app-id,sandboxed-app-id,wm-class,search-term,proc-cmdline]
+    #[clap(long, arg_enum, use_value_delimiter = true, require_value_delimiter = true)]
+    find_order: Vec<FindMethod>,
+
+    /// Leave out the windows of the process with the given pid [hint: can be given multiple times]
+    #[clap(long, value_name = "PID", multiple_occurrences = true)]
+    exclude_pid: Vec<i32>,
+
+    /// Leave out the windows of the terminal gnome-session-restore is run from, i.e. the windows of
+    /// the processes it was started by [hint: this excludes all windows of single process terminals like gnome-terminal]
+    #[clap(long)]
+    exclude_current: bool,
+
+    /// Leave out the windows whose window class, gtk app id or sandboxed app id matches the regex as a whole
+    /// [hint: can be given multiple times]
+    #[clap(long, value_name = "PATTERN", multiple_occurrences = true)]
+    exclude: Vec<ExcludePattern>,
+
+    /// Leave out the windows matching any of the patterns in the file, one per line like for `--exclude`;
+    /// empty lines and lines starting with # are skipped
+    #[clap(long, value_name = "PATH", parse(try_from_os_str = paths::expand), value_hint = ValueHint::FilePath)]
+    exclude_file: Option<PathBuf>,
+
+    /// Also save the windows of the shell itself and dialogs, same as `--disable-excludes default`
+    #[clap(long)]
+    no_default_excludes: bool,
+
+    /// Ignore all excludes of the given layers [hint: can be given multiple times]
+    #[clap(long, arg_enum, value_name = "LAYER", multiple_occurrences = true)]
+    disable_excludes: Vec<ExcludeLayer>,
+
+    /// Print every window that is left out, and the exclude that left it out
+    #[clap(long)]
+    explain: bool,
+
+    /// Also save dialogs (e.g. file choosers or about boxes) and other windows that are not normal windows or are
+    /// left out of the taskbar [hint: needs an extension version that reports window types]
+    #[clap(long)]
+    include_dialogs: bool,
+
+    /// Also consider desktop files whose binary (`TryExec` or the program of `Exec`) is not installed,
+    /// which are often left behind by uninstalled applications
+    #[clap(long)]
+    allow_missing_binaries: bool,
+
+    /// As a last resort, match the window class against all applications GIO knows about, including ones whose
+    /// desktop files are outside of the searched directories
+    #[clap(long)]
+    enable_gio_fallback: bool,
+}
+
+impl FindArgs {
+    /// The find order, or exits with a usage error if it is not valid
+    fn find_order(&self) -> Vec<FindMethod> {
+        let find_order = if self.find_order.is_empty() {
+            DEFAULT_FIND_ORDER.to_vec()
+        } else {
+            self.find_order.clone()
+        };
+
+        if let Err(e) = find_command::validate_find_order(&find_order) {
+            Opts::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+
+        find_order
+    }
+
+    /// The granted capabilities, those given with --capabilities or else those allowed by the policy flags,
+    /// including the `policies` of flags that only some subcommands have
+    fn capabilities(&self, policies: &[(Policy, Capability)]) -> Capabilities {
+        if let Some(capabilities) = self.capabilities {
+            return capabilities;
+        }
+
+        [
+            (self.procfs_search_policy, Capability::ProcFsSearch),
+            (self.procfs_use_command_policy, Capability::UseProcFsCommand),
+            (self.procfs_comm_policy, Capability::ReadProcComm),
+        ]
+        .iter()
+        .chain(policies)
+        .filter(|(policy, _)| matches!(policy, Policy::Allow))
+        .map(|&(_, capability)| capability)
+        .collect()
+    }
+
+    /// The windows to leave out, or exits with a usage error if the exclude file cannot be read
+    fn window_filter(&self) -> WindowFilter {
+        let mut filter = WindowFilter::new();
+
+        let ancestors = if self.exclude_current {
+            session::process_ancestors(std::process::id() as i32)
+        } else {
+            Vec::new()
+        };
+
+        filter.add(
+            filter::Layer::CommandLine,
+            self.exclude
+                .iter()
+                .cloned()
+                .map(filter::Rule::Pattern)
+                .chain(self.exclude_pid.iter().copied().map(filter::Rule::Pid))
+                .chain(ancestors.into_iter().map(filter::Rule::Ancestor)),
+        );
+
+        if let Some(path) = &self.exclude_file {
+            match filter::read_exclude_file(path) {
+                Ok(patterns) => filter.add(
+                    filter::Layer::ExcludeFile,
+                    patterns.into_iter().map(filter::Rule::Pattern),
+                ),
+                Err(e) => Opts::command().error(ErrorKind::Io, e).exit(),
+            }
+        }
+
+        let no_default_excludes = self.no_default_excludes.then_some(ExcludeLayer::Default);
+
+        for layer in self.disable_excludes.iter().copied().chain(no_default_excludes) {
+            filter.disable(match layer {
+                ExcludeLayer::Default => filter::Layer::Default,
+                ExcludeLayer::CommandLine => filter::Layer::CommandLine,
+                ExcludeLayer::ExcludeFile => filter::Layer::ExcludeFile,
+            });
+        }
+
+        if self.include_dialogs {
+            filter.include_dialogs();
+        }
+
+        filter.explain = self.explain;
+        filter
+    }
+
+    fn find_options<'a>(
+        &self,
+        capabilities: Capabilities,
+        order: &'a [FindMethod],
+        aliases: &'a Aliases,
+    ) -> session::FindOptions<'a> {
+        session::FindOptions {
+            min_wm_class_similarity: self.min_wm_class_similarity,
+            min_partial_match_confidence: self.min_partial_match_confidence,
+            capabilities,
+            order,
+            allow_missing_binaries: self.allow_missing_binaries,
+            aliases,
+            gio_fallback: self.enable_gio_fallback,
+        }
+    }
+}
+
+/// The options of `save`, which `daemon` saves with
+#[derive(Debug, Args)]
+struct SaveArgs {
+    #[clap(flatten)]
+    find: FindArgs,
+
+    /// Determine whether gnome-session-restore is allowed to read the working directory of an application
+    /// from /proc/{pid}/cwd. It is used as the working directory when the application is started via its command.
+    #[clap(long, arg_enum, default_value_t = Policy::Deny)]
+    procfs_cwd_policy: Policy,
+
+    /// Determine whether gnome-session-restore is allowed to read the environment of an application
+    /// from /proc/{pid}/environ. When the application is started via its command, variables missing from
+    /// the environment of the restore are added. [hint: the environment may contain secrets, which will be saved]
+    #[clap(long, arg_enum, default_value_t = Policy::Deny)]
+    procfs_environ_policy: Policy,
+
+    /// Write the session file in the given (older) format version, so that it can be read
+    /// by older versions of gnome-session-restore. Fields unknown to that version are dropped.
+    #[clap(long, default_value_t = session::CURRENT_FORMAT_VERSION, validator = valid_format_version)]
+    format_version: u32,
+
+    /// Do not redact secrets (e.g. `--password=...` or tokens in URLs) from saved command lines
+    #[clap(long)]
+    no_redact: bool,
+
+    /// Additionally redact matches of the given regex from saved command lines,
+    /// if it has a capture group only the first group is redacted [hint: can be given multiple times]
+    #[clap(long, multiple_occurrences = true, conflicts_with = "no-redact")]
+    redact_pattern: Vec<Regex>,
+
+    /// Also save windows for which no command was found. They are not launched when restoring,
+    /// but placed if they are already open. [hint: requires format version 8 or later]
+    #[clap(long)]
+    include_unmatched: bool,
+
+    /// Write the windows for which no command was found to the given file as json, with everything they can be
+    /// identified by and an `--alias` to complete [hint: use `-` for stdout]
+    #[clap(long, value_name = "PATH", parse(try_from_os_str = paths::expand), value_hint = ValueHint::FilePath)]
+    unresolved_out: Option<PathBuf>,
+
+    /// The format to write the session to stdout in with `--file -`, files are always written as json
+    #[clap(long, arg_enum, default_value_t = SessionFormat::Json)]
+    output_format: SessionFormat,
+
+    /// Replace invalid utf-8 in saved command lines with U+FFFD instead of failing to save.
+    /// [hint: the replaced arguments are corrupted, such commands may not launch correctly]
+    #[clap(long)]
+    lossy_cmdline: bool,
+
+    /// Save the session even if no applications or no monitors (e.g. over SSH with a headless shell) were found,
+    /// replacing a session file that contains applications
+    #[clap(long)]
+    allow_empty: bool,
+
+    /// Also save whether windows use the dark or light theme variant, if the extension reports it.
+    /// [hint: this is only noted, it cannot be restored]
+    #[clap(long)]
+    capture_theme_variant: bool,
+
+    /// Also save the geometry of windows as fractions of their monitor's work area, which is restored on
+    /// monitors with a different resolution instead of the pixels [hint: needs an extension version that
+    /// reports monitors]
+    #[clap(long)]
+    relative_geometry: bool,
+
+    /// Pipe the session as json through the given program, which prints the transformed session on stdout
+    /// (e.g. to drop applications or rewrite their commands) before it is written
+    #[clap(long, value_name = "PROG", parse(try_from_os_str = paths::expand))]
+    transform_cmd: Option<PathBuf>,
+
+    /// How often to retry listing the windows if the shell is briefly unavailable or lists none
+    /// although the replaced session file contains applications, waiting twice as long each time
+    #[clap(long, value_name = "N", default_value_t = 3)]
+    dbus_retries: u32,
+}
+
+/// The options shared by `restore` and `sync`
+#[derive(Debug, Args)]
+struct RestoreArgs {
+    /// The format of the session read from stdin with `--file -`, files are always read as json
+    #[clap(long, arg_enum, default_value_t = SessionFormat::Json)]
+    input_format: SessionFormat,
+
+    /// How to wait for launched applications to open their windows before moving them to their saved positions
+    #[clap(long, arg_enum, default_value_t = SettleStrategy::Poll)]
+    settle_strategy: SettleStrategy,
+
+    /// How long (in seconds) to sleep before moving windows with `--settle-strategy fixed`
+    #[clap(long, default_value_t = 1.0)]
+    settle_time: f64,
+
+    /// How long (in seconds) to keep looking for the windows of launched applications
+    /// in order to move them to their saved positions [hint: applies to every application with `--settle-strategy per-app`]
+    #[clap(long, default_value_t = 5.0)]
+    wait_timeout: f64,
+
+    /// Give up on the window of an application after this many seconds, even if `--wait-timeout` has not passed,
+    /// so that a single application whose window never appears does not hold up the others
+    #[clap(long, value_name = "SECS")]
+    timeout_per_app: Option<f64>,
+
+    /// Inhibit logout and suspend while restoring [default]
+    #[clap(long, overrides_with = "no-inhibit")]
+    inhibit: bool,
+
+    /// Do not inhibit logout and suspend while restoring
+    #[clap(long, overrides_with = "inhibit")]
+    no_inhibit: bool,
+
+    /// What to do with applications whose saved desktop file does not exist anymore
+    #[clap(long, arg_enum, default_value_t = MissingApp::Skip)]
+    on_missing_app: MissingApp,
+
+    /// Launch every saved window instead of one per window class, starting new instances
+    /// (e.g. via a "new window" action) of applications that are already running
+    #[clap(long)]
+    new_instance: bool,
+
+    /// How often to retry launching an application that failed with an error that may go away by itself
+    /// (e.g. D-Bus activation refused while many applications are launched), waiting twice as long each time
+    #[clap(long, value_name = "N", default_value_t = 2)]
+    launch_retries: u32,
+
+    /// Only accept windows of the given class that fulfill a condition as the restored window, to skip
+    /// splash screens and updaters. One of `<CLASS>:title=<REGEX>`, `<CLASS>:min-size=<W>x<H>` or
+    /// `<CLASS>:settle-ms=<MS>` [hint: can be given multiple times, all conditions have to be fulfilled]
+    #[clap(long, multiple_occurrences = true)]
+    wait_for: Vec<session::WaitRule>,
+
+    /// What to do with the windows if the number of monitors differs from when the session was saved
+    /// [hint: primary and cascade need an extension version that reports the primary monitor's work area]
+    #[clap(long, arg_enum, default_value_t = Fallback::Skip)]
+    fallback: Fallback,
+
+    /// If the number of monitors differs from when the session was saved, move the windows of the saved
+    /// monitor onto the given current monitor, e.g. `2:0`. Monitors are counted from 0, unmapped monitors
+    /// that do not exist anymore are clamped into the primary monitor. [hint: can be given multiple times,
+    /// needs a session saved with an extension version that reports monitors]
+    #[clap(long, multiple_occurrences = true)]
+    monitor_map: Vec<session::MonitorMapping>,
+
+    /// Move all windows onto the current monitor with the given index (counted from 0), keeping their position
+    /// relative to their saved monitor, even if the number of monitors did not change. Windows whose saved
+    /// monitor is unknown are cascaded. [hint: needs an extension version that reports monitors]
+    #[clap(long, value_name = "INDEX", conflicts_with = "monitor-map")]
+    target_monitor: Option<u32>,
+
+    /// Scale windows by the ratio of the current to the saved scale factor of their monitor if it changed,
+    /// e.g. when restoring a session saved on a standard display on a HiDPI one [hint: needs a session saved
+    /// with an extension version that reports monitor scales]
+    #[clap(long)]
+    dpi_aware: bool,
+
+    /// Do not move windows whose edges are all at most this many pixels away from their saved position,
+    /// to not fight applications that position their windows themselves
+    #[clap(long, default_value_t = 0)]
+    geometry_tolerance: u32,
+
+    /// After the restore, keep watching the placed windows for this many seconds and move them back to their saved
+    /// position if they moved away by more than `--geometry-tolerance`, for applications that restore their own last
+    /// position a while after launch [hint: the first Ctrl-C ends the watch early]
+    #[clap(long, value_name = "SECS", default_value_t = 0.0)]
+    reassert_geometry: f64,
+
+    /// Do not watch the windows of the given window class with `--reassert-geometry`
+    /// [hint: can be given multiple times]
+    #[clap(long, value_name = "CLASS", multiple_occurrences = true)]
+    no_reassert: Vec<String>,
+
+    /// Never move the windows of applications that are known to position them themselves (e.g. GIMP)
+    #[clap(long)]
+    no_reposition_self_placing: bool,
+
+    /// Treat the applications with the given window class as positioning their windows themselves
+    /// [hint: can be given multiple times]
+    #[clap(long, multiple_occurrences = true, requires = "no-reposition-self-placing")]
+    self_placing: Vec<String>,
+
+    /// Never move or resize the windows of the given window class, e.g. shaped desktop widgets like conky
+    /// [hint: can be given multiple times]
+    #[clap(long, value_name = "CLASS", multiple_occurrences = true)]
+    skip_classes_geometry: Vec<String>,
+
+    /// Leave out applications whose window had not been used for longer than this when the session was saved,
+    /// e.g. `7d` or `12h` (units: s, m, h, d, w) [hint: counted up to the most recently used window of the session,
+    /// windows saved without a user time by an older extension version are always restored]
+    #[clap(long, value_name = "AGE", parse(try_from_str = session::age::parse_age))]
+    max_age: Option<Duration>,
+
+    /// Only restore the applications whose window class, gtk app id or sandboxed app id matches the regex as a whole,
+    /// e.g. `code|org\.gnome\.TextEditor` [hint: can be given multiple times]
+    #[clap(long, value_name = "PATTERN", multiple_occurrences = true)]
+    restore_only: Vec<ExcludePattern>,
+
+    /// Do not restore the applications whose window class, gtk app id or sandboxed app id matches the regex as a
+    /// whole, neither launching nor moving their windows [hint: can be given multiple times]
+    #[clap(long, value_name = "PATTERN", multiple_occurrences = true)]
+    restore_skip: Vec<ExcludePattern>,
+
+    /// Minimize applications that were saved minimized (e.g. tray apps) as soon as their window appears,
+    /// so that they do not keep the focus
+    #[clap(long)]
+    no_activate_minimized: bool,
+
+    /// Move windows that were saved at the same position and size as earlier ones this many pixels further
+    /// right and down per earlier window, so that they do not hide each other
+    #[clap(long, value_name = "PX", default_value_t = 0)]
+    stagger: u32,
+
+    /// How to launch applications that were saved with a desktop file
+    #[clap(long, arg_enum, default_value_t = LaunchBackend::Gio)]
+    launcher: LaunchBackend,
+
+    /// Launch applications that were saved with a command line through your login shell (`$SHELL -lc 'exec ...'`),
+    /// so that the PATH set up in your profile applies (e.g. for binaries installed by nvm)
+    #[clap(long)]
+    spawn_via_shell: bool,
+
+    /// Stop restoring and exit with an error as soon as a call to the extension fails,
+    /// instead of printing the error and going on with the other windows
+    #[clap(long)]
+    strict_dbus: bool,
+
+    /// Pipe the session as json through the given program, which prints the transformed session on stdout
+    /// (e.g. to drop applications or rewrite their commands) before it is restored
+    #[clap(long, value_name = "PROG", parse(try_from_os_str = paths::expand))]
+    transform_cmd: Option<PathBuf>,
+
+    /// Replace the prefix FROM of saved desktop file paths with TO, e.g. when the session was saved on a machine
+    /// that installs flatpaks elsewhere. If the remapped desktop file does not exist either, it is looked up
+    /// by the app id of the window [hint: can be given multiple times, the first matching prefix is used]
+    #[clap(long, value_name = "FROM=TO", multiple_occurrences = true)]
+    path_prefix_map: Vec<session::PathPrefixMap>,
+
+    /// Refuse to restore a session that was saved by another user or on another host,
+    /// instead of warning about it
+    #[clap(long)]
+    strict_host: bool,
+
+    /// Rewrite the home directory the session was saved with to the current one in the command lines
+    /// (only arguments that are paths, `--option=` values or file URIs), desktop files and working directories
+    /// of the applications
+    #[clap(long)]
+    rewrite_home: bool,
+
+    /// What to do with the window geometry if the session was saved under a different display server
+    /// (X11 or Wayland), whose geometry does (X11) or does not (Wayland) leave out the title bar
+    #[clap(long, arg_enum, default_value_t = CrossSessionType::Correct)]
+    cross_session_type: CrossSessionType,
+
+    /// The height of title bars in pixels, which `--cross-session-type correct` adds or removes
+    #[clap(long, value_name = "PX", default_value_t = 37)]
+    title_bar_height: i32,
+}
+
 #[derive(Debug, Subcommand)]
 enum SessionAction {
     /// Saves the current gnome session
     Save {
-        /// Set the minimum required (levenshtein) similarity between the WM_CLASS
-        /// and the binary name to allow it to be considered
-        /// as an alternative application name.
-        #[clap(long, default_value_t = 0.8, validator = valid_confidence_value)]
-        min_wm_class_similarity: Confidence,
+        #[clap(flatten)]
+        save: SaveArgs,
+    },
+
+    /// Saves the current gnome session whenever the user logs out, until gnome-session stops it.
+    /// It registers with gnome-session, so it is meant to be started on login (e.g. from an autostart file).
+    Daemon {
+        #[clap(flatten)]
+        save: SaveArgs,
+    },
+
+    /// Shows the command that would be saved for every open window, how it was found and how confident that is,
+    /// without saving anything
+    ResolveSession {
+        #[clap(flatten)]
+        find: FindArgs,
+    },
 
-        #[clap(long, default_value_t = 0.6, validator = valid_confidence_value)]
-        min_partial_match_confidence: Confidence,
+    /// Shows the command that would be saved for a window with the given ids, how it was found and how confident
+    /// that is, without the window having to be open. Exits with 1 if no command is found.
+    Resolve {
+        /// The window class (`WM_CLASS`) of the window
+        #[clap(long, value_name = "CLASS", default_value = "")]
+        wm_class: String,
 
-        /// Determine whether gnome-session-restore is allowed to search in /proc/{pid}/cmdline
-        /// to obtain information that may be helpful. [hint: specifying deny will also implicitly add --procfs-use-comand-policy deny]
-        #[clap(long, arg_enum, default_value_t = Policy::Allow)]
-        procfs_search_policy: Policy,
+        /// The gtk app id of the window
+        #[clap(long, value_name = "ID", default_value = "")]
+        gtk_app_id: String,
 
-        /// Determine whether gnome-session-restore is allowed to use the command it finds
-        /// in /proc/{pid}/commandline as a way to start an application if not desktop file is found.
-        #[clap(long, arg_enum, default_value_t = Policy::Deny)]
-        procfs_use_command_policy: Policy,
+        /// The sandboxed (e.g. flatpak) app id of the window
+        #[clap(long, value_name = "ID", default_value = "")]
+        sandboxed_app_id: String,
+
+        /// The process of the window, to look at in /proc as far as the capabilities allow
+        /// [hint: without it, nothing is read from /proc]
+        #[clap(long)]
+        pid: Option<i32>,
+
+        /// Resolve against the desktop files of a testset (see `tests/testsets`) instead of the installed ones
+        #[clap(long, hide = true, parse(try_from_os_str = paths::expand), value_hint = ValueHint::FilePath)]
+        testset: Option<PathBuf>,
+
+        #[clap(flatten)]
+        find: FindArgs,
     },
 
     /// Restores a gnome session from disk
     Restore {
         /// Remove the session file after restoring
-        /// [hint: ignored when reading from stdin]
+        /// [hint: ignored when not reading from a regular file]
         #[clap(long)]
         rm: bool,
 
         /// Rename the file to the given name after restoring
-        /// [hint: ignored when reading from stdin]
+        /// [hint: ignored when not reading from a regular file]
         #[clap(long)]
         rename: Option<OsString>,
+
+        /// Restore the session file even if it was already restored in this login
+        /// [hint: without it, a restore that is run again (e.g. by a restarted service) is skipped]
+        #[clap(long)]
+        force: bool,
+
+        #[clap(flatten)]
+        restore: RestoreArgs,
+
+        /// Only launch the applications, without moving their windows to the saved positions
+        #[clap(long, conflicts_with = "only-geometry")]
+        only_launch: bool,
+
+        /// Only move the windows that are already open to their saved positions, without launching anything
+        #[clap(long)]
+        only_geometry: bool,
+
+        /// Kill applications saved with a command line whose window does not appear within the wait timeout
+        /// (`--settle-time` with `--settle-strategy fixed`) [hint: desktop files are launched by gio or
+        /// another launcher, their processes are never killed]
+        #[clap(long, conflicts_with_all = &["only-launch", "only-geometry"])]
+        kill_on_timeout: bool,
+
+        /// After restoring, save the windows that are open over the session file, with the options of `save`
+        /// from the config file, so that stale commands and desktop files are replaced by freshly found ones
+        /// [hint: skipped if the window of any application was not found, which would drop it from the session,
+        /// and not possible with a partial restore]
+        #[clap(long, conflicts_with_all = &["only-launch", "rm", "restore-only", "restore-skip", "max-age"])]
+        refresh: bool,
+    },
+
+    /// Makes the screen match a saved session: moves the windows that are already open to their saved positions
+    /// and launches only the applications that are not open, then prints what it did
+    Sync {
+        #[clap(flatten)]
+        restore: RestoreArgs,
+    },
+
+    /// Stops restoring the applications with the given window class, without removing them from the session file
+    Disable { window_class: String },
+
+    /// Restores the applications with the given window class again
+    Enable { window_class: String },
+
+    /// Checks whether the shell extension is reachable via D-Bus, prints OK or FAIL and exits accordingly
+    Probe,
+
+    /// Deletes files gnome-session-restore left in the state directory, nothing else is ever touched.
+    /// Lists what would be deleted and asks before deleting it.
+    Prune {
+        /// Delete session files (e.g. ones kept with `restore --rename`) that were not modified for this many days,
+        /// the default session file is never deleted
+        #[clap(long, value_name = "DAYS")]
+        sessions_older_than: Option<u64>,
+
+        /// Delete the temporary files of saves and edits (e.g. `disable` or `order`) that were interrupted
+        #[clap(long)]
+        orphaned_tmp: bool,
+
+        /// Only list what would be deleted
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Delete without asking
+        #[clap(long, conflicts_with = "dry-run")]
+        yes: bool,
+    },
+
+    /// Writes a bash script to stdout that launches the applications of the session file
+    /// without gnome-session-restore, e.g. to inspect what a restore would do
+    ExportScript {
+        /// Also move the windows to their saved positions via the extension, after waiting this many seconds
+        #[clap(long, value_name = "SECONDS")]
+        geometry: Option<f64>,
+
+        /// The format of the session read from stdin with `--file -`, files are always read as json
+        #[clap(long, arg_enum, default_value_t = SessionFormat::Json)]
+        input_format: SessionFormat,
+    },
+
+    /// Changes when the applications with the given window class are launched
+    Order {
+        window_class: String,
+
+        /// Launch before other applications of the same stage if higher, the default is 0
+        #[clap(long, allow_hyphen_values = true)]
+        priority: Option<i32>,
+
+        /// Launch only after the windows of the applications with the given window class or app id
+        /// have appeared (or the wait for them timed out) [hint: can be given multiple times]
+        #[clap(long, multiple_occurrences = true, conflicts_with = "no-after")]
+        after: Vec<String>,
+
+        /// Do not wait for any other applications before launching
+        #[clap(long)]
+        no_after: bool,
+    },
+
+    /// Shows the sessions of the profile directory (`--file <DIR>` or the state directory)
+    Sessions {
+        #[clap(subcommand)]
+        action: SessionsAction,
+    },
+
+    /// Shows the window classes that are known to belong to desktop files of a different name
+    Aliases {
+        #[clap(subcommand)]
+        action: AliasesAction,
+    },
+
+    /// Shows the journal of saves and restores in the state directory
+    Log {
+        #[clap(subcommand)]
+        action: LogAction,
+    },
+
+    /// Tools for developing gnome-session-restore
+    #[clap(hide = true)]
+    Dev {
+        #[clap(subcommand)]
+        action: DevAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum SessionsAction {
+    /// Prints the names of the sessions, newest first, and how long ago they were saved
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum AliasesAction {
+    /// Prints the window class aliases, the built-in ones and the ones given with `--alias`
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum LogAction {
+    /// Prints the most recent saves and restores, oldest first, with what they did for every application
+    Show {
+        /// How many of the most recent records to print
+        #[clap(long, value_name = "N", default_value_t = 10)]
+        last: usize,
+    },
+
+    /// Removes the journal
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+enum DevAction {
+    /// Prints the paths of the effective desktop files of this machine, to be added to `tests/testsets`
+    DumpDesktopFiles {
+        /// Print the parsed fields the desktop files are matched and launched by as JSON instead
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Finds the windows of labeled testsets (see `tests/testsets`) with every combination of confidence thresholds,
+    /// printing how precise and complete the results are and which thresholds do best
+    Calibrate {
+        /// The testsets, the desktop files listed in them do not need to exist
+        #[clap(required = true, value_hint = ValueHint::FilePath)]
+        testsets: Vec<PathBuf>,
     },
 }
 
 #[derive(Debug, Parser)]
-#[clap(version, author, about, subcommand_required = true)]
+#[clap(
+    version,
+    author,
+    about,
+    subcommand_required = true,
+    after_help = "Defaults for options can be set in $XDG_CONFIG_HOME/gnome-session-restore/config.toml, \
+                  options given on the command line take precedence over them."
+)]
 struct Opts {
-    /// Manually specify a session file [hint: use `-` for std(in|out) redirection]
-    #[clap(short, long, default_value_os_t = default_session_file_path(), forbid_empty_values = true, value_hint = ValueHint::FilePath)]
-    file: PathBuf,
+    /// Manually specify a session file, or a profile directory of session files (e.g. a synced folder), in which
+    /// the newest session is restored unless `--name` is given [default: last-session.json in the state directory]
+    /// [hint: use `-` for std(in|out) redirection, `~` and `$VAR` are expanded]
+    #[clap(short, long, forbid_empty_values = true, parse(try_from_os_str = paths::expand), value_hint = ValueHint::FilePath)]
+    file: Option<PathBuf>,
+
+    /// Keep all state (e.g. the default session file) in the given directory
+    /// instead of `$XDG_STATE_HOME/gnome-session-restore`
+    #[clap(long, forbid_empty_values = true, parse(try_from_os_str = paths::expand), value_hint = ValueHint::DirPath)]
+    state_dir: Option<PathBuf>,
+
+    /// Use the session with the given name in the profile directory, i.e. `<NAME>.json` in the directory
+    /// given with `--file` or the state directory
+    #[clap(long, forbid_empty_values = true)]
+    name: Option<String>,
 
     /// Connect to the specified D-Bus address
     #[clap(long, conflicts_with_all = &["session", "system"])]
@@ -98,88 +855,847 @@ struct Opts {
     #[clap(long, conflicts_with = "session")]
     system: bool,
 
+    /// Assume X11 geometry semantics when moving windows, regardless of the detected display server
+    #[clap(long, conflicts_with = "force-wayland")]
+    force_x11: bool,
+
+    /// Assume Wayland geometry semantics when moving windows, regardless of the detected display server
+    #[clap(long, conflicts_with = "force-x11")]
+    force_wayland: bool,
+
+    /// Treat windows of the given class as belonging to the desktop file with the given name (e.g. `Navigator=firefox`),
+    /// in addition to the built-in aliases listed by `aliases list` [hint: can be given multiple times]
+    #[clap(long, value_name = "CLASS=NAME", multiple_occurrences = true)]
+    alias: Vec<Alias>,
+
+    /// The format of the output of `resolve-session`, `resolve`, `sessions list`, `aliases list`, `log show` and `probe`
+    #[clap(long, arg_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
     #[clap(subcommand)]
     subcommand: SessionAction,
 }
 
-fn main() {
-    let opts = Opts::parse();
-    let redirected_to_std_stream = opts.file == OsStr::new("-");
+/// Parses the command line, taking defaults for options that are not given from the config file
+fn parse_opts() -> Opts {
+    parse_opts_from(std::env::args_os().collect())
+}
+
+/// Parses `argv` with the options from the config file added
+fn parse_opts_from(argv: Vec<OsString>) -> Opts {
+    let mut cmd = Opts::command();
+
+    let argv = match paths::Paths::config_file().unwrap() {
+        Some(path) => config::read(&path)
+            .and_then(|config| config::apply(&config, &cmd, argv))
+            .unwrap_or_else(|e| {
+                cmd.error(ErrorKind::InvalidValue, format!("{}: {e}", path.display()))
+                    .exit()
+            }),
+        None => argv,
+    };
+
+    let matches = cmd.get_matches_from(argv);
+    Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+fn session_file<T>(res: Result<T, paths::ProfileError>) -> T {
+    res.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    })
+}
+
+/// Saves the current session to `file` with the given options
+fn save(
+    conn: &Connection,
+    file: &Path,
+    args: &SaveArgs,
+    display_server: Option<DisplayServer>,
+    aliases: &Aliases,
+) -> Result<session::SaveReport, session::SaveError> {
+    let SaveArgs {
+        find,
+        procfs_cwd_policy,
+        procfs_environ_policy,
+        format_version,
+        no_redact,
+        redact_pattern,
+        include_unmatched,
+        unresolved_out,
+        output_format,
+        lossy_cmdline,
+        allow_empty,
+        capture_theme_variant,
+        relative_geometry,
+        transform_cmd,
+        dbus_retries,
+    } = args;
+
+    let find_order = find.find_order();
+    let shellbus = WindowCtlProxy::new(conn).expect("service at destination");
+
+    let caps = find.capabilities(&[
+        (*procfs_cwd_policy, Capability::ReadProcCwd),
+        (*procfs_environ_policy, Capability::ReadProcEnviron),
+    ]);
+
+    let options = find.find_options(caps, &find_order, aliases);
+    let finder = move |mw: &MetaWindow| find_command::find_command(options, mw);
+
+    let redactor = session::Redactor::new(redact_pattern.clone());
+
+    let filter = find.window_filter();
+    let shell_version = shell_version(conn);
+
+    let save_options = session::SaveOptions {
+        format_version: *format_version,
+        capabilities: caps,
+        redactor: (!no_redact).then_some(&redactor),
+        include_unmatched: *include_unmatched,
+        filter: &filter,
+        lossy_cmdline: *lossy_cmdline,
+        replaces_non_empty: session::io::is_regular_file(file) && session::has_applications(file),
+        allow_empty: *allow_empty,
+        retry: session::RetryPolicy {
+            retries: *dbus_retries,
+            initial_backoff: Duration::from_millis(250),
+        },
+        display_server,
+        shell_version: shell_version.as_deref(),
+        capture_theme_variant: *capture_theme_variant,
+        relative_geometry: *relative_geometry,
+        transform_cmd: transform_cmd.as_deref(),
+    };
+
+    let unresolved_out = unresolved_out.as_deref().map(session::io::open_writer).transpose()?;
+
+    let writer = || session::io::open_writer_as(file, output_format.stream_format());
+
+    session::save(&shellbus, writer, unresolved_out, save_options, finder)
+}
+
+/// The options `restore --refresh` saves with, those of `save` from the config file
+fn refresh_save_args() -> SaveArgs {
+    let argv = [env!("CARGO_PKG_NAME"), "save"].map(OsString::from).to_vec();
+
+    match parse_opts_from(argv).subcommand {
+        SessionAction::Save { save } => save,
+        _ => unreachable!("parsed the save subcommand"),
+    }
+}
+
+/// Saves the windows that are open after restoring `file` over it, returns whether it was saved.
+/// Nothing is saved if the window of any application was not found, as it would be dropped from the session.
+fn refresh(
+    conn: &Connection,
+    file: &Path,
+    report: &session::RestoreReport,
+    display_server: Option<DisplayServer>,
+    aliases: &Aliases,
+) -> bool {
+    if !session::io::is_regular_file(file) {
+        eprintln!("ignoring `--refresh` because input file is not a regular file");
+        return false;
+    }
+
+    if !report.missing.is_empty() {
+        eprintln!(
+            "not refreshing {file:?}: the windows of {} were not found",
+            report.missing.join(", ")
+        );
+        return false;
+    }
+
+    match save(conn, file, &refresh_save_args(), display_server, aliases) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("unable to refresh {file:?}: {e}");
+            false
+        },
+    }
+}
+
+/// Restores the session in `file` with the given options
+fn restore(
+    conn: &Connection,
+    file: &Path,
+    args: RestoreArgs,
+    mode: session::RestoreMode,
+    kill_on_timeout: bool,
+    display_server: Option<DisplayServer>,
+    aliases: &Aliases,
+) -> Result<session::RestoreReport, session::RestoreError> {
+    let RestoreArgs {
+        input_format,
+        settle_strategy,
+        settle_time,
+        wait_timeout,
+        timeout_per_app,
+        inhibit: _,
+        no_inhibit,
+        on_missing_app,
+        new_instance,
+        launch_retries,
+        wait_for,
+        fallback,
+        monitor_map,
+        target_monitor,
+        dpi_aware,
+        geometry_tolerance,
+        reassert_geometry,
+        no_reassert,
+        no_reposition_self_placing,
+        self_placing,
+        skip_classes_geometry,
+        max_age,
+        restore_only,
+        restore_skip,
+        no_activate_minimized,
+        stagger,
+        launcher,
+        spawn_via_shell,
+        strict_dbus,
+        transform_cmd,
+        path_prefix_map,
+        strict_host,
+        rewrite_home,
+        cross_session_type,
+        title_bar_height,
+    } = args;
+
+    let shellbus = WindowCtlProxy::new(conn).expect("service at destination");
+    let reader = session::io::open_reader_as(file, input_format.stream_format()).unwrap_or_else(|e| {
+        eprintln!("unable to read {file:?}: {e}");
+        std::process::exit(1);
+    });
+
+    let settle_strategy = match settle_strategy {
+        SettleStrategy::Fixed => session::SettleStrategy::Fixed(Duration::from_secs_f64(settle_time)),
+        SettleStrategy::Poll => session::SettleStrategy::Poll,
+        SettleStrategy::PerApp => session::SettleStrategy::PerApp,
+    };
+
+    let display_server = display_server.unwrap_or_else(|| {
+        eprintln!("unable to detect the display server, assuming X11 (use `--force-wayland` to override)");
+        DisplayServer::X11
+    });
+
+    let cross_session_type = match cross_session_type {
+        CrossSessionType::Skip => session::CrossSessionType::Skip,
+        CrossSessionType::Correct => session::CrossSessionType::Correct { title_bar_height },
+        CrossSessionType::Force => session::CrossSessionType::Force,
+    };
 
-    let conn = if opts.system {
-        Connection::new_system().expect("system dbus")
-    } else if let Some(addr) = &opts.dbus_address {
-        Connection::new_for_address(addr, true).expect("dbus at address")
+    let self_placing: HashSet<String> = if no_reposition_self_placing {
+        session::SELF_PLACING_CLASSES
+            .iter()
+            .map(|class| class.to_string())
+            .chain(self_placing)
+            .collect()
     } else {
-        Connection::new_session().expect("session dbus")
+        HashSet::new()
     };
 
-    let shellbus = WindowCtlProxy::new(&conn).expect("service at destination");
+    let skip_geometry: HashSet<String> = skip_classes_geometry.into_iter().collect();
+
+    let shell_version = shell_version(conn);
+
+    let restore_options = session::RestoreOptions {
+        settle_strategy,
+        wait_timeout: Duration::from_secs_f64(wait_timeout),
+        timeout_per_app: timeout_per_app.map(Duration::from_secs_f64),
+        display_server,
+        shell_version: shell_version.as_deref(),
+        cross_session_type,
+        on_missing_app: match on_missing_app {
+            MissingApp::Skip => session::MissingAppPolicy::Skip,
+            MissingApp::Cmdline => session::MissingAppPolicy::Resolve,
+            MissingApp::Error => session::MissingAppPolicy::Error,
+        },
+        new_instance,
+        launch_retry: session::RetryPolicy {
+            retries: launch_retries,
+            initial_backoff: Duration::from_millis(250),
+        },
+        wait_rules: &session::WaitRules::new(wait_for),
+        monitor_fallback: match fallback {
+            Fallback::Skip => session::MonitorFallback::Skip,
+            Fallback::Primary => session::MonitorFallback::Primary,
+            Fallback::Cascade => session::MonitorFallback::Cascade,
+        },
+        monitor_map: &monitor_map,
+        target_monitor,
+        dpi_aware,
+        geometry_tolerance,
+        self_placing: &self_placing,
+        skip_geometry: &skip_geometry,
+        max_age,
+        restore_only: &restore_only,
+        restore_skip: &restore_skip,
+        no_activate_minimized,
+        stagger,
+        mode,
+        kill_on_timeout,
+        path_prefix_map: &path_prefix_map,
+        strict_dbus,
+        transform_cmd: transform_cmd.as_deref(),
+        strict_host,
+        rewrite_home,
+        reassert_geometry: Duration::from_secs_f64(reassert_geometry),
+        no_reassert: &no_reassert.into_iter().collect(),
+        cancel: (reassert_geometry > 0.0).then(cancel_on_signal),
+    };
+
+    // the saved processes are gone, so /proc is of no use here
+    let find_options = session::FindOptions {
+        min_wm_class_similarity: DEFAULT_MIN_WM_CLASS_SIMILARITY,
+        min_partial_match_confidence: DEFAULT_MIN_PARTIAL_MATCH_CONFIDENCE,
+        capabilities: Capabilities::none(),
+        order: &DEFAULT_FIND_ORDER,
+        allow_missing_binaries: false,
+        aliases,
+        gio_fallback: false,
+    };
+
+    let finder = move |mw: &MetaWindow| find_command::find_command(find_options, mw).map(|(exec, _)| exec);
+
+    {
+        let session_manager = (!no_inhibit)
+            .then(|| SessionManagerProxy::new(conn))
+            .transpose()
+            .unwrap_or_else(|e| {
+                eprintln!("unable to connect to the session manager: {e}");
+                None
+            });
+
+        let _inhibit_guard = session_manager
+            .as_ref()
+            .and_then(|sm| session::inhibit::inhibit(sm, "Restoring the previous session"));
+
+        let mut launcher = session::SystemLauncher::new(match launcher {
+            LaunchBackend::Direct => session::DesktopFileBackend::Direct,
+            LaunchBackend::Gio => session::DesktopFileBackend::Gio,
+            LaunchBackend::GtkLaunch => session::DesktopFileBackend::GtkLaunch,
+            LaunchBackend::SystemdRun => session::DesktopFileBackend::SystemdRun,
+        });
+        launcher.spawn_via_shell = spawn_via_shell;
+
+        session::restore(&shellbus, reader, &launcher, restore_options, finder)
+    }
+}
+
+/// Records the outcome of `subcommand` on `file` in the journal, see [`Journal::record`]
+fn record(journal: &Journal, conn: &Connection, subcommand: &str, file: &Path, outcome: Outcome) {
+    let shell_version = dbus::shell_version(conn).ok();
+    journal.record(&journal::Record::new(subcommand, file, shell_version, outcome));
+}
+
+/// Set by the first SIGINT or SIGTERM once [`cancel_on_signal`] was called
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn cancel(_signal: nix::libc::c_int) {
+    CANCELLED.store(true, Ordering::Relaxed);
+}
+
+/// Sets the returned flag on the first SIGINT or SIGTERM instead of terminating, a second one terminates as usual
+fn cancel_on_signal() -> &'static AtomicBool {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+    let action = SigAction::new(SigHandler::Handler(cancel), SaFlags::SA_RESETHAND, SigSet::empty());
+
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        // the handler only stores to an atomic, which is async-signal-safe
+        if let Err(e) = unsafe { sigaction(signal, &action) } {
+            eprintln!("unable to handle {signal}: {e}");
+        }
+    }
+
+    &CANCELLED
+}
+
+/// The version of the running gnome-shell, if it can be read
+fn shell_version(conn: &Connection) -> Option<String> {
+    dbus::shell_version(conn)
+        .map_err(|e| eprintln!("unable to get the version of gnome-shell: {e}"))
+        .ok()
+}
+
+fn main() {
+    let opts = parse_opts();
+
+    let paths = paths::Paths::resolve(opts.state_dir.as_deref()).unwrap_or_else(|e| {
+        eprintln!("unable to find the state directory: {e}");
+        std::process::exit(1);
+    });
+    let journal = Journal::new(&paths.state_dir);
+    let location = paths.session_location(opts.file);
+    let name = opts.name.as_deref();
+
+    // editing the session file does not need D-Bus
+    let try_connect = || {
+        if opts.system {
+            Connection::new_system()
+        } else if let Some(addr) = &opts.dbus_address {
+            Connection::new_for_address(addr, true)
+        } else {
+            Connection::new_session()
+        }
+    };
+
+    let connect = || try_connect().expect("dbus connection");
+
+    let mut aliases = Aliases::builtin();
+    aliases.extend(opts.alias);
+
+    let display_server = if opts.force_x11 {
+        Some(DisplayServer::X11)
+    } else if opts.force_wayland {
+        Some(DisplayServer::Wayland)
+    } else {
+        DisplayServer::detect()
+    };
 
     match opts.subcommand {
-        SessionAction::Save {
-            min_wm_class_similarity,
-            min_partial_match_confidence,
-            procfs_search_policy,
-            procfs_use_command_policy,
-        } => {
-            let writer: Box<dyn Write> = if redirected_to_std_stream {
-                Box::new(std::io::stdout())
-            } else {
-                let f = File::create(&opts.file).unwrap();
-                let bw = BufWriter::new(f);
+        SessionAction::Save { save: args } => {
+            let file = session_file(location.save_file(name));
+            let conn = connect();
 
-                Box::new(bw)
-            };
+            let saved = save(&conn, &file, &args, display_server, &aliases);
+            record(&journal, &conn, "save", &file, Outcome::of(&saved));
 
-            let caps = {
-                let mut hs = HashSet::new();
+            if let Err(e) = saved {
+                eprintln!("unable to save the session: {e}");
+                std::process::exit(1);
+            }
+        },
+        SessionAction::Daemon { save: args } => {
+            let file = session_file(location.save_file(name));
 
-                if let Policy::Allow = procfs_search_policy {
-                    hs.insert(Capability::ProcFsSearch);
+            // the session is only saved when it ends, which is too late to find out that this cannot be written
+            if let Some(path) = &args.unresolved_out {
+                if let Err(e) = session::io::open_writer(path) {
+                    eprintln!("unable to open {path:?} for the unresolved windows: {e}");
+                    std::process::exit(1);
                 }
+            }
 
-                if let Policy::Allow = procfs_use_command_policy {
-                    hs.insert(Capability::UseProcFsCommand);
+            let conn = connect();
+
+            let saved = daemon::run(&conn, || {
+                let saved = save(&conn, &file, &args, display_server, &aliases);
+                record(&journal, &conn, "daemon", &file, Outcome::of(&saved));
+
+                match saved {
+                    Ok(_) => true,
+                    Err(e) => {
+                        eprintln!("unable to save the session: {e}");
+                        false
+                    },
                 }
+            });
+
+            if let Err(e) = saved {
+                eprintln!("unable to listen for the end of the session: {e}");
+                std::process::exit(1);
+            }
+        },
+        SessionAction::ResolveSession { find } => {
+            let find_order = find.find_order();
+            let caps = find.capabilities(&[]);
+            let filter = find.window_filter();
+
+            let conn = connect();
+            let shellbus = WindowCtlProxy::new(&conn).expect("service at destination");
+
+            let options = find.find_options(caps, &find_order, &aliases);
+            let finder = move |mw: &MetaWindow| find_command::find_command_detailed(options, mw);
+
+            let retry = session::RetryPolicy { retries: 3, initial_backoff: Duration::from_millis(250) };
+
+            let windows = session::resolve_windows(&shellbus, caps, &filter, retry, false, &finder).unwrap_or_else(|e| {
+                eprintln!("unable to get the open windows: {e}");
+                std::process::exit(1);
+            });
 
-                hs
+            let resolutions: Vec<_> = windows
+                .into_iter()
+                .map(|(w, found)| resolve::Resolution::new(&w, found))
+                .collect();
+
+            output::print(&resolutions, opts.output);
+        },
+        SessionAction::Resolve { wm_class, gtk_app_id, sandboxed_app_id, pid, testset, find } => {
+            let find_order = find.find_order();
+
+            // all of them read the process, which a made up window does not have
+            let needs_process = [
+                Capability::ProcFsSearch,
+                Capability::UseProcFsCommand,
+                Capability::ReadProcComm,
+                Capability::MimeType,
+            ];
+            let caps = match pid {
+                Some(_) => find.capabilities(&[]),
+                None => find
+                    .capabilities(&[])
+                    .iter()
+                    .filter(|capability| !needs_process.contains(capability))
+                    .collect(),
             };
 
-            let options = session::FindOptions {
-                min_wm_class_similarity,
-                min_partial_match_confidence,
-                capabilities: &caps,
+            let options = find.find_options(caps, &find_order, &aliases);
+            let window = resolve::window(&wm_class, &gtk_app_id, &sandboxed_app_id, pid.unwrap_or_default());
+
+            let found = match testset {
+                Some(path) => {
+                    let testset = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                        eprintln!("unable to read the testset {path:?}: {e}");
+                        std::process::exit(1);
+                    });
+                    let index = find_command::DesktopFileIndex::new(find_command::testsets::paths(&testset));
+
+                    find_command::try_find_command_detailed(options, &window, &index)
+                },
+                None => find_command::find_command_detailed(options, &window),
             };
 
-            let finder = move |mw: &MetaWindow| find_command::find_command(options, mw);
+            let resolution = resolve::Resolution::new(&window, found);
+            output::print(&resolution, opts.output);
 
-            session::save(&shellbus, writer, finder).unwrap();
+            if resolution.error.is_some() {
+                std::process::exit(1);
+            }
         },
-        SessionAction::Restore { rm, rename } => {
-            let reader: Box<dyn Read> = if redirected_to_std_stream {
-                Box::new(std::io::stdin())
+        SessionAction::Restore {
+            rm,
+            rename,
+            force,
+            restore: args,
+            only_launch,
+            only_geometry,
+            kill_on_timeout,
+            refresh: refresh_file,
+        } => {
+            let file = session_file(location.restore_file(name));
+
+            let mode = if only_launch {
+                session::RestoreMode::OnlyLaunch
+            } else if only_geometry {
+                session::RestoreMode::OnlyGeometry
+            } else {
+                session::RestoreMode::Full
+            };
+
+            // only launching applications twice does harm, moving windows again does not
+            let marker = if mode != session::RestoreMode::OnlyGeometry && session::io::is_regular_file(&file) {
+                RestoreMarker::current(&file).unwrap_or_else(|e| {
+                    eprintln!("unable to read {file:?} to check whether it was already restored: {e}");
+                    None
+                })
             } else {
-                let f = File::open(&opts.file).unwrap();
-                let br = BufReader::new(f);
+                None
+            };
 
-                Box::new(br)
+            if let Some(marker) = marker.as_ref().filter(|marker| marker.skips_restore(force)) {
+                eprintln!(
+                    "not restoring {file:?}: it was already restored in this login (use `--force` to restore it again, \
+                     or remove {:?})",
+                    marker.path()
+                );
+                return;
+            }
+
+            let conn = connect();
+            let report = restore(&conn, &file, args, mode, kill_on_timeout, display_server, &aliases);
+            record(&journal, &conn, "restore", &file, Outcome::of(&report));
+
+            let report = report.unwrap_or_else(|e| {
+                eprintln!("unable to restore the session: {e}");
+                std::process::exit(1);
+            });
+            let refreshed = refresh_file && refresh(&conn, &file, &report, display_server, &aliases);
+
+            // the refreshed file must not be restored again in this login either
+            let marker = match marker {
+                Some(_) if refreshed => RestoreMarker::current(&file).unwrap_or_else(|e| {
+                    eprintln!("unable to read the refreshed {file:?} to record the restore: {e}");
+                    None
+                }),
+                marker => marker,
             };
 
-            session::restore(&shellbus, reader).unwrap();
+            if let Some(marker) = marker {
+                if let Err(e) = marker.create() {
+                    eprintln!("unable to record the restore in {:?}: {e}", marker.path());
+                }
+            }
 
-            if redirected_to_std_stream {
-                eprintln!("ignoring `--rm` and `--rename` because input file was stdin");
+            if !session::io::is_regular_file(&file) {
+                if rm || rename.is_some() {
+                    eprintln!("ignoring `--rm` and `--rename` because input file is not a regular file");
+                }
             } else if let Some(new_name) = rename {
-                let new_file = opts.file.with_file_name(new_name);
-                std::fs::rename(&opts.file, &new_file).unwrap();
+                let new_file = file.with_file_name(new_name);
+
+                if let Err(e) = std::fs::rename(&file, &new_file) {
+                    eprintln!("unable to rename {file:?} to {new_file:?}: {e}");
+                    std::process::exit(1);
+                }
 
                 if rm {
-                    std::fs::remove_file(new_file).unwrap();
+                    if let Err(e) = std::fs::remove_file(&new_file) {
+                        eprintln!("unable to remove {new_file:?}: {e}");
+                        std::process::exit(1);
+                    }
                 }
             } else if rm {
-                std::fs::remove_file(&opts.file).unwrap();
+                if let Err(e) = std::fs::remove_file(&file) {
+                    eprintln!("unable to remove {file:?}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        },
+        SessionAction::Sync { restore: args } => {
+            let conn = connect();
+            let file = session_file(location.restore_file(name));
+
+            let report = restore(
+                &conn,
+                &file,
+                args,
+                session::RestoreMode::Sync,
+                false,
+                display_server,
+                &aliases,
+            );
+            record(&journal, &conn, "sync", &file, Outcome::of(&report));
+
+            let report = report.unwrap_or_else(|e| {
+                eprintln!("unable to restore the session: {e}");
+                std::process::exit(1);
+            });
+            output::print(&report, opts.output);
+        },
+        SessionAction::Disable { window_class } => {
+            let file = session_file(location.restore_file(name));
+            let n = session::edit::set_disabled(&file, &window_class, true).unwrap_or_else(|e| {
+                eprintln!("unable to disable '{window_class}' in {file:?}: {e}");
+                std::process::exit(1);
+            });
+            eprintln!("disabled {n} application(s)");
+        },
+        SessionAction::Enable { window_class } => {
+            let file = session_file(location.restore_file(name));
+            let n = session::edit::set_disabled(&file, &window_class, false).unwrap_or_else(|e| {
+                eprintln!("unable to enable '{window_class}' in {file:?}: {e}");
+                std::process::exit(1);
+            });
+            eprintln!("enabled {n} application(s)");
+        },
+        SessionAction::Probe => {
+            let (res, shell_version) = match try_connect() {
+                Ok(conn) => (
+                    WindowCtlProxy::new(&conn).and_then(|proxy| proxy.get_num_monitors()),
+                    dbus::shell_version(&conn).ok(),
+                ),
+                Err(e) => (Err(e), None),
+            };
+            let report = output::ProbeReport {
+                ok: res.is_ok(),
+                error: res.err().map(|e| e.to_string()),
+                shell_version,
+            };
+
+            output::print(&report, opts.output);
+
+            if !report.ok {
+                std::process::exit(1);
+            }
+        },
+        SessionAction::ExportScript { geometry, input_format } => {
+            let file = session_file(location.restore_file(name));
+            let reader = session::io::open_reader_as(&file, input_format.stream_format()).unwrap_or_else(|e| {
+                eprintln!("unable to read {file:?}: {e}");
+                std::process::exit(1);
+            });
+            let geometry = geometry.map(Duration::from_secs_f64);
+
+            if let Err(e) = session::script::export_script(reader, std::io::stdout().lock(), geometry) {
+                eprintln!("unable to export {file:?} as a script: {e}");
+                std::process::exit(1);
+            }
+        },
+        SessionAction::Sessions { action: SessionsAction::List } => {
+            let sessions: Vec<_> = session_file(location.sessions())
+                .into_iter()
+                .map(|session| output::SessionEntry {
+                    name: session
+                        .path
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    modified: session
+                        .modified
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                    path: session.path,
+                })
+                .collect();
+
+            output::print(&sessions, opts.output);
+        },
+        SessionAction::Log { action: LogAction::Show { last } } => {
+            let records = journal.last(last).unwrap_or_else(|e| {
+                eprintln!("unable to read the journal: {e}");
+                std::process::exit(1);
+            });
+
+            output::print(&records, opts.output);
+        },
+        SessionAction::Log { action: LogAction::Clear } => {
+            if let Err(e) = journal.clear() {
+                eprintln!("unable to remove the journal: {e}");
+                std::process::exit(1);
+            }
+        },
+        SessionAction::Aliases { action: AliasesAction::List } => {
+            let aliases: Vec<_> = aliases
+                .iter()
+                .map(|(window_class, desktop_file)| output::AliasEntry {
+                    window_class: window_class.to_owned(),
+                    desktop_file: desktop_file.to_owned(),
+                })
+                .collect();
+
+            output::print(&aliases, opts.output);
+        },
+        SessionAction::Prune { sessions_older_than, orphaned_tmp, dry_run, yes } => {
+            let options = prune::PruneOptions {
+                sessions_older_than: sessions_older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                orphaned_tmp,
+            };
+
+            let prunable = prune::find_prunable(
+                &paths.state_dir,
+                options,
+                std::time::SystemTime::now(),
+                prune::is_running,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("unable to list the files in {:?}: {e}", paths.state_dir);
+                std::process::exit(1);
+            });
+
+            if prunable.is_empty() {
+                eprintln!("nothing to prune in {:?}", paths.state_dir);
+                return;
+            }
+
+            for file in &prunable {
+                println!("{} ({} bytes, {:?})", file.path.display(), file.size, file.artifact);
             }
+
+            let total: u64 = prunable.iter().map(|file| file.size).sum();
+
+            if dry_run {
+                eprintln!("would delete {} file(s), {total} bytes", prunable.len());
+                return;
+            }
+
+            if !yes {
+                eprint!("delete {} file(s), {total} bytes? [y/N] ", prunable.len());
+
+                let mut answer = String::new();
+                if let Err(e) = std::io::stdin().read_line(&mut answer) {
+                    eprintln!("unable to read the answer: {e}");
+                    std::process::exit(1);
+                }
+
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    eprintln!("nothing was deleted");
+                    return;
+                }
+            }
+
+            let freed = prune::prune(&prunable).unwrap_or_else(|e| {
+                eprintln!("unable to delete the files: {e}");
+                std::process::exit(1);
+            });
+            eprintln!("deleted {} file(s), {freed} bytes", prunable.len());
+        },
+        SessionAction::Dev { action: DevAction::DumpDesktopFiles { json } } => {
+            let index = find_command::DesktopFileIndex::scan(find_command::desktop_entry_locations());
+            let stdout = std::io::stdout().lock();
+
+            let written = if json {
+                index.write_json(stdout).map_err(std::io::Error::from)
+            } else {
+                index.write_testset(stdout)
+            };
+
+            if let Err(e) = written {
+                eprintln!("unable to write the desktop files: {e}");
+                std::process::exit(1);
+            }
+        },
+        SessionAction::Dev { action: DevAction::Calibrate { testsets } } => {
+            use find_command::{calibrate, testsets::expectations, testsets::paths};
+
+            let contents: Vec<String> = testsets
+                .iter()
+                .map(|path| {
+                    std::fs::read_to_string(path).unwrap_or_else(|e| {
+                        eprintln!("unable to read the testset {path:?}: {e}");
+                        std::process::exit(1);
+                    })
+                })
+                .collect();
+
+            let corpora: Vec<calibrate::Corpus> = testsets
+                .iter()
+                .zip(&contents)
+                .map(|(path, testset)| calibrate::Corpus {
+                    index: find_command::DesktopFileIndex::new(paths(testset)),
+                    expectations: expectations(testset).unwrap_or_else(|e| {
+                        eprintln!("invalid testset {path:?}: {e}");
+                        std::process::exit(1);
+                    }),
+                })
+                .collect();
+
+            let sweeps = calibrate::sweep(&corpora, &aliases, &calibrate::default_thresholds());
+
+            println!("wm-class-similarity  partial-match-confidence  precision  recall  f1");
+            for s in &sweeps {
+                println!(
+                    "{:<19.2}  {:<24.2}  {:<9.3}  {:<6.3}  {:.3}",
+                    s.min_wm_class_similarity,
+                    s.min_partial_match_confidence,
+                    s.precision(),
+                    s.recall(),
+                    s.f1()
+                );
+            }
+
+            if let Some(best) = calibrate::recommend(&sweeps) {
+                println!(
+                    "\nrecommended: --min-wm-class-similarity {} --min-partial-match-confidence {} \
+                     (defaults are {DEFAULT_MIN_WM_CLASS_SIMILARITY} and {DEFAULT_MIN_PARTIAL_MATCH_CONFIDENCE})",
+                    best.min_wm_class_similarity, best.min_partial_match_confidence
+                );
+            }
+        },
+        SessionAction::Order { window_class, priority, after, no_after } => {
+            let after = (no_after || !after.is_empty()).then_some(after);
+            let file = session_file(location.restore_file(name));
+            let n = session::edit::set_launch_order(&file, &window_class, priority, after).unwrap_or_else(|e| {
+                eprintln!("unable to change the launch order of '{window_class}' in {file:?}: {e}");
+                std::process::exit(1);
+            });
+            eprintln!("changed the launch order of {n} application(s)");
         },
     }
 }
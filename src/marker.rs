@@ -0,0 +1,96 @@
+//! The marker that a session file was already restored in the current login, so that a restore that is run again
+//! (e.g. by a restarted systemd unit) does not launch every application a second time.
+//!
+//! Markers are kept in `$XDG_RUNTIME_DIR`, which is emptied on logout, and are named after the login session
+//! (`$XDG_SESSION_ID`) and a hash of the contents of the session file, so that a changed file is restored again.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+const PREFIX: &str = "gnome-session-restore";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestoreMarker {
+    path: PathBuf,
+}
+
+impl RestoreMarker {
+    /// The marker for restoring a session file with the given contents in the login `session_id`
+    pub fn new(runtime_dir: &Path, session_id: &str, contents: &[u8]) -> Self {
+        // the hash only has to be stable within a login, which the markers do not outlive
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+
+        RestoreMarker {
+            path: runtime_dir
+                .join(PREFIX)
+                .join(format!("restored-{session_id}-{:016x}", hasher.finish())),
+        }
+    }
+
+    /// The marker for restoring `session_file` in the current login,
+    /// `None` if `XDG_RUNTIME_DIR` or `XDG_SESSION_ID` is not set
+    pub fn current(session_file: &Path) -> io::Result<Option<Self>> {
+        let (Some(runtime_dir), Ok(session_id)) = (env::var_os("XDG_RUNTIME_DIR"), env::var("XDG_SESSION_ID")) else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read(session_file)?;
+        Ok(Some(RestoreMarker::new(
+            Path::new(&runtime_dir),
+            &session_id,
+            &contents,
+        )))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the session file was already restored in this login and should not be restored again,
+    /// `force` restores it anyway
+    pub fn skips_restore(&self, force: bool) -> bool {
+        !force && self.path.exists()
+    }
+
+    /// Records that the session file was restored in this login
+    pub fn create(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        std::fs::write(&self.path, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RestoreMarker;
+
+    #[test]
+    fn restore_marker() {
+        let runtime_dir = std::env::temp_dir().join(format!("gnome-session-restore-runtime-{}", std::process::id()));
+
+        let marker = RestoreMarker::new(&runtime_dir, "3", b"{}");
+        assert!(!marker.skips_restore(false));
+
+        marker.create().unwrap();
+        assert!(marker.path().starts_with(&runtime_dir));
+        assert!(marker.skips_restore(false));
+        assert!(!marker.skips_restore(true));
+
+        // the same file in the same login
+        assert!(RestoreMarker::new(&runtime_dir, "3", b"{}").skips_restore(false));
+
+        // a changed file, or another login
+        assert!(!RestoreMarker::new(&runtime_dir, "3", b"{ }").skips_restore(false));
+        assert!(!RestoreMarker::new(&runtime_dir, "4", b"{}").skips_restore(false));
+
+        std::fs::remove_dir_all(runtime_dir).unwrap();
+    }
+}
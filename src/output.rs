@@ -0,0 +1,307 @@
+//! The output of the subcommands that only show something, as text for people or as json for scripts.
+//!
+//! Each of these subcommands builds a report, which is written in the format given with `--output`.
+//! The json is the serialization of the report, so the field names of the reports below are part of the interface.
+
+use crate::{
+    journal::{Outcome, Record},
+    session::RestoreReport,
+};
+use clap::ArgEnum;
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+#[derive(ArgEnum, Copy, Clone, PartialEq, Debug)]
+pub enum OutputFormat {
+    /// Human readable text
+    Text,
+
+    /// Pretty printed json
+    Json,
+}
+
+pub trait Report: Serialize {
+    /// Writes the report as human readable text
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Writes `report` to `w` in the given format
+pub fn write<R: Report, W: Write>(report: &R, format: OutputFormat, mut w: W) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => report.write_text(&mut w),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut w, report)?;
+            writeln!(w)
+        },
+    }
+}
+
+/// Prints `report` to stdout in the given format, exits if that fails.
+/// A closed stdout (e.g. when piped into `head`) is not an error.
+pub fn print<R: Report>(report: &R, format: OutputFormat) {
+    let mut stdout = io::stdout().lock();
+
+    match write(report, format, &mut stdout).and_then(|()| stdout.flush()) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(e) => {
+            eprintln!("unable to write the output: {e}");
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Formats a duration in its largest whole unit, e.g. `3h`
+pub fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        s if s < 60 => format!("{s}s"),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+/// A session of a profile, for `sessions list`
+#[derive(Debug, Serialize)]
+pub struct SessionEntry {
+    pub name: String,
+    pub path: PathBuf,
+
+    /// When the session was saved, in seconds since the unix epoch
+    pub modified: u64,
+}
+
+impl Report for Vec<SessionEntry> {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        let now = SystemTime::now();
+
+        for session in self {
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(session.modified);
+            let age = now.duration_since(modified).unwrap_or_default();
+
+            writeln!(w, "{}\t{} ago", session.name, format_age(age))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A window class alias, for `aliases list`
+#[derive(Debug, Serialize)]
+pub struct AliasEntry {
+    pub window_class: String,
+    pub desktop_file: String,
+}
+
+impl Report for Vec<AliasEntry> {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for alias in self {
+            writeln!(w, "{}={}", alias.window_class, alias.desktop_file)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the extension is reachable, for `probe`
+#[derive(Debug, Serialize)]
+pub struct ProbeReport {
+    pub ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The version of the running gnome-shell, if it could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell_version: Option<String>,
+}
+
+impl Report for ProbeReport {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match (&self.error, &self.shell_version) {
+            (None, Some(version)) => writeln!(w, "OK (gnome-shell {version})"),
+            (None, None) => writeln!(w, "OK"),
+            (Some(e), _) => writeln!(w, "FAIL: {e}"),
+        }
+    }
+}
+
+/// What `sync` did, one line per application
+impl Report for RestoreReport {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        for (what, names) in [
+            ("placed", &self.placed),
+            ("launched", &self.launched),
+            ("missing", &self.missing),
+            ("timed-out", &self.timed_out),
+            ("failed", &self.failed),
+        ] {
+            for name in names {
+                writeln!(w, "{what}\t{name}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The records of the journal, for `log show`: a line per record and an indented line per application
+impl Report for Vec<Record> {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        let now = SystemTime::now();
+
+        for record in self {
+            let at = SystemTime::UNIX_EPOCH + Duration::from_secs(record.timestamp);
+            let age = now.duration_since(at).unwrap_or_default();
+
+            write!(
+                w,
+                "{} ago\t{}\t{}\tgnome-session-restore {}",
+                format_age(age),
+                record.subcommand,
+                record.file.display(),
+                record.version
+            )?;
+
+            match &record.shell_version {
+                Some(shell_version) => writeln!(w, ", gnome-shell {shell_version}")?,
+                None => writeln!(w)?,
+            }
+
+            let entries: Vec<(&str, &Vec<String>)> = match &record.outcome {
+                Outcome::Saved(report) => {
+                    vec![("saved", &report.saved), ("unresolved", &report.unresolved)]
+                },
+                Outcome::Restored(report) => vec![
+                    ("placed", &report.placed),
+                    ("launched", &report.launched),
+                    ("missing", &report.missing),
+                    ("timed-out", &report.timed_out),
+                    ("failed", &report.failed),
+                ],
+                Outcome::Failed { error } => {
+                    writeln!(w, "  failed\t{error}")?;
+                    continue;
+                },
+            };
+
+            for (what, names) in entries {
+                for name in names {
+                    writeln!(w, "  {what}\t{name}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_age, write, AliasEntry, OutputFormat, ProbeReport, Report};
+    use crate::{
+        journal::{Outcome, Record},
+        session::{RestoreReport, SaveReport},
+    };
+    use std::{path::Path, time::Duration};
+
+    fn render<R: Report>(report: &R, format: OutputFormat) -> String {
+        let mut buf = Vec::new();
+        write(report, format, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn text_and_json() {
+        let aliases = vec![AliasEntry {
+            window_class: "navigator".to_owned(),
+            desktop_file: "firefox".to_owned(),
+        }];
+        assert_eq!(render(&aliases, OutputFormat::Text), "navigator=firefox\n");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&render(&aliases, OutputFormat::Json)).unwrap(),
+            serde_json::json!([{ "window_class": "navigator", "desktop_file": "firefox" }])
+        );
+
+        let failed = ProbeReport {
+            ok: false,
+            error: Some("no extension".to_owned()),
+            shell_version: None,
+        };
+        assert_eq!(render(&failed, OutputFormat::Text), "FAIL: no extension\n");
+
+        let ok = ProbeReport { ok: true, error: None, shell_version: None };
+        assert_eq!(render(&ok, OutputFormat::Json), "{\n  \"ok\": true\n}\n");
+
+        let ok = ProbeReport {
+            ok: true,
+            error: None,
+            shell_version: Some("45.2".to_owned()),
+        };
+        assert_eq!(render(&ok, OutputFormat::Text), "OK (gnome-shell 45.2)\n");
+
+        let synced = RestoreReport {
+            launched: vec!["Firefox".to_owned()],
+            placed: vec!["kitty".to_owned()],
+            missing: vec!["Firefox".to_owned()],
+            timed_out: vec!["Firefox".to_owned()],
+            failed: vec!["steam".to_owned()],
+        };
+        assert_eq!(
+            render(&synced, OutputFormat::Text),
+            "placed\tkitty\nlaunched\tFirefox\nmissing\tFirefox\ntimed-out\tFirefox\nfailed\tsteam\n"
+        );
+    }
+
+    #[test]
+    fn journal() {
+        let saved = SaveReport {
+            saved: vec!["kitty".to_owned()],
+            unresolved: vec!["steam".to_owned()],
+        };
+        let records = vec![
+            Record::new(
+                "save",
+                Path::new("/s.json"),
+                Some("45.2".to_owned()),
+                Outcome::Saved(saved),
+            ),
+            Record::new(
+                "restore",
+                Path::new("/s.json"),
+                None,
+                Outcome::Failed { error: "no shell".to_owned() },
+            ),
+        ];
+
+        // the age depends on when the test runs
+        let text = render(&records, OutputFormat::Text);
+        let lines: Vec<_> = text
+            .lines()
+            .map(|line| line.split_once(" ago").map_or(line, |(_, rest)| rest))
+            .collect();
+
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            lines,
+            [
+                format!("\tsave\t/s.json\tgnome-session-restore {version}, gnome-shell 45.2"),
+                "  saved\tkitty".to_owned(),
+                "  unresolved\tsteam".to_owned(),
+                format!("\trestore\t/s.json\tgnome-session-restore {version}"),
+                "  failed\tno shell".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(format_age(Duration::from_secs(59)), "59s");
+        assert_eq!(format_age(Duration::from_secs(3 * 60 * 60 + 5)), "3h");
+        assert_eq!(format_age(Duration::from_secs(8 * 24 * 60 * 60)), "8d");
+    }
+}
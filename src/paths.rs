@@ -0,0 +1,423 @@
+//! The locations gnome-session-restore keeps its files in.
+//!
+//! Everything is resolved once at startup, so that every file ends up in the
+//! directory given by `XDG_STATE_HOME` or below `--state-dir`.
+
+use nix::unistd::User;
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use thiserror::Error;
+
+const PREFIX: &str = "gnome-session-restore";
+
+/// The name of the session file used when no file is given
+pub const LAST_SESSION_FILE: &str = "last-session.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    /// Where state that should persist between runs is kept
+    pub state_dir: PathBuf,
+}
+
+impl Paths {
+    /// Resolves the state directory from the XDG base directories, unless it is overridden by `state_dir`
+    pub fn resolve(state_dir: Option<&Path>) -> Result<Self, xdg::BaseDirectoriesError> {
+        match state_dir {
+            Some(state_dir) => Ok(Paths::with_state_dir(state_dir)),
+            None => {
+                let dirs = xdg::BaseDirectories::with_prefix(PREFIX)?;
+
+                Ok(Paths { state_dir: dirs.get_state_home() })
+            },
+        }
+    }
+
+    /// The config file, if there is one in `$XDG_CONFIG_HOME` or `$XDG_CONFIG_DIRS`
+    pub fn config_file() -> Result<Option<PathBuf>, xdg::BaseDirectoriesError> {
+        Ok(xdg::BaseDirectories::with_prefix(PREFIX)?.find_config_file(crate::config::CONFIG_FILE))
+    }
+
+    pub fn with_state_dir(state_dir: &Path) -> Self {
+        Paths { state_dir: state_dir.to_owned() }
+    }
+
+    /// The session file that is used when no file is given explicitly
+    pub fn last_session_file(&self) -> PathBuf {
+        self.state_dir.join(LAST_SESSION_FILE)
+    }
+
+    /// Where the sessions are, given the `--file` option: a directory given with it is a profile,
+    /// any other file a single session file and without it the state directory is a profile.
+    /// Nothing is created, the state directory is only created once a session is saved to it.
+    pub fn session_location(&self, file: Option<PathBuf>) -> SessionLocation {
+        match file {
+            Some(dir) if dir.is_dir() => SessionLocation::Profile(Profile { dir, default_file: None }),
+            Some(file) => SessionLocation::File(file),
+            None => SessionLocation::Profile(Profile {
+                default_file: Some(self.last_session_file()),
+                dir: self.state_dir.clone(),
+            }),
+        }
+    }
+}
+
+/// A directory of session files, e.g. a synced folder
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub dir: PathBuf,
+
+    /// The session that is restored when no name is given, the newest one if `None`
+    default_file: Option<PathBuf>,
+}
+
+/// Where sessions are read from and written to
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionLocation {
+    /// A single session file, or `-` for stdin or stdout
+    File(PathBuf),
+    Profile(Profile),
+}
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("{0:?} is not a profile directory, `--file` has to be a directory for this")]
+    NotAProfile(PathBuf),
+
+    #[error("invalid session name {0:?}, it must not be empty or contain `/`")]
+    InvalidName(String),
+
+    #[error("the profile directory {0:?} is not writable: {1}")]
+    NotWritable(PathBuf, nix::Error),
+
+    #[error("there are no sessions in {0:?}")]
+    NoSessions(PathBuf),
+
+    #[error("there is no session {0:?}")]
+    NoSuchSession(PathBuf),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A session file in a profile
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileSession {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+impl Profile {
+    /// The file of the session with the given name, the default session file if there is none
+    fn named_file(&self, name: Option<&str>) -> Result<PathBuf, ProfileError> {
+        match name {
+            None => Ok(self
+                .default_file
+                .clone()
+                .unwrap_or_else(|| self.dir.join(LAST_SESSION_FILE))),
+            Some(name) if name.is_empty() || name.contains('/') => Err(ProfileError::InvalidName(name.to_owned())),
+            Some(name) => Ok(self.dir.join(format!("{name}.json"))),
+        }
+    }
+
+    /// The session files in the profile, newest first.
+    /// Only files that can be read as a session are listed, so that other files in a synced folder are ignored.
+    pub fn sessions(&self) -> Result<Vec<ProfileSession>, ProfileError> {
+        let mut sessions = Vec::new();
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            let path = entry.path();
+
+            if meta.is_file() && crate::session::is_session_file(&path) {
+                sessions.push(ProfileSession { path, modified: meta.modified()? });
+            }
+        }
+
+        sessions.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.path.cmp(&b.path)));
+        Ok(sessions)
+    }
+}
+
+impl SessionLocation {
+    /// The session files of the profile, newest first
+    pub fn sessions(&self) -> Result<Vec<ProfileSession>, ProfileError> {
+        match self {
+            SessionLocation::File(file) => Err(ProfileError::NotAProfile(file.clone())),
+            SessionLocation::Profile(profile) => profile.sessions(),
+        }
+    }
+
+    /// The file a session is saved to, creating the profile directory if it does not exist (yet)
+    pub fn save_file(&self, name: Option<&str>) -> Result<PathBuf, ProfileError> {
+        match self {
+            SessionLocation::File(file) if name.is_none() => Ok(file.clone()),
+            SessionLocation::File(file) => Err(ProfileError::NotAProfile(file.clone())),
+            SessionLocation::Profile(profile) => {
+                std::fs::create_dir_all(&profile.dir)?;
+
+                nix::unistd::access(&profile.dir, nix::unistd::AccessFlags::W_OK)
+                    .map_err(|e| ProfileError::NotWritable(profile.dir.clone(), e))?;
+
+                profile.named_file(name)
+            },
+        }
+    }
+
+    /// The file a session is read from, in a profile directory given with `--file` the newest session
+    /// if no name is given
+    pub fn restore_file(&self, name: Option<&str>) -> Result<PathBuf, ProfileError> {
+        match self {
+            SessionLocation::File(file) if name.is_none() => Ok(file.clone()),
+            SessionLocation::File(file) => Err(ProfileError::NotAProfile(file.clone())),
+            SessionLocation::Profile(profile) if name.is_none() && profile.default_file.is_none() => profile
+                .sessions()?
+                .into_iter()
+                .next()
+                .map(|session| session.path)
+                .ok_or_else(|| ProfileError::NoSessions(profile.dir.clone())),
+            SessionLocation::Profile(profile) => {
+                let file = profile.named_file(name)?;
+
+                // a missing default session file is reported when it is read, like before there were profiles
+                if name.is_some() && !file.exists() {
+                    return Err(ProfileError::NoSuchSession(file));
+                }
+
+                Ok(file)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ExpandError {
+    #[error("environment variable `{0}` is not set")]
+    UnsetVariable(String),
+
+    #[error("unknown user `{0}`")]
+    UnknownUser(String),
+
+    #[error("unable to determine the home directory")]
+    NoHome,
+
+    #[error("missing closing `}}` in variable reference")]
+    UnterminatedBrace,
+}
+
+/// Expands a leading `~` or `~user` and `$VAR` or `${VAR}` references in a path like a shell would.
+/// A `$` can be escaped as `\$`, paths that are not valid UTF-8 are returned as they are.
+pub fn expand(path: &OsStr) -> Result<PathBuf, ExpandError> {
+    match path.to_str() {
+        Some(path) => expand_with(path, |key| env::var(key).ok(), home_dir),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+fn expand_with<V, H>(path: &str, var: V, home: H) -> Result<PathBuf, ExpandError>
+where
+    V: Fn(&str) -> Option<String>,
+    H: Fn(Option<&str>) -> Option<PathBuf>,
+{
+    let (home_dir, rest) = match path.strip_prefix('~') {
+        Some(tilde) => {
+            let (user, rest) = tilde.split_at(tilde.find('/').unwrap_or(tilde.len()));
+            let user = Some(user).filter(|user| !user.is_empty());
+
+            let home_dir = home(user).ok_or_else(|| match user {
+                Some(user) => ExpandError::UnknownUser(user.to_owned()),
+                None => ExpandError::NoHome,
+            })?;
+
+            (Some(home_dir), rest)
+        },
+        None => (None, path),
+    };
+
+    let mut expanded = home_dir.map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut chars = rest.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => expanded.push(chars.next().unwrap()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+
+                let mut name = String::new();
+                loop {
+                    match chars.next().ok_or(ExpandError::UnterminatedBrace)? {
+                        '}' => break,
+                        c => name.push(c),
+                    }
+                }
+
+                expanded.push_str(&var(&name).ok_or(ExpandError::UnsetVariable(name))?);
+            },
+            '$' if chars.peek().is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+
+                expanded.push_str(&var(&name).ok_or(ExpandError::UnsetVariable(name))?);
+            },
+            c => expanded.push(c),
+        }
+    }
+
+    Ok(PathBuf::from(expanded))
+}
+
+/// The home directory of the given user, or the current one
+pub fn home_dir(user: Option<&str>) -> Option<PathBuf> {
+    match user {
+        None => env::var_os("HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(gio::glib::home_dir())),
+        Some(user) => User::from_name(user).ok().flatten().map(|user| user.dir),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_with, ExpandError, Paths, ProfileError, LAST_SESSION_FILE};
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    fn expand(path: &str) -> Result<PathBuf, ExpandError> {
+        let var = |key: &str| match key {
+            "HOME" => Some("/home/user".to_string()),
+            "XDG_STATE_HOME" => Some("/home/user/.local/state".to_string()),
+            _ => None,
+        };
+
+        let home = |user: Option<&str>| match user {
+            None => Some(PathBuf::from("/home/user")),
+            Some("other") => Some(PathBuf::from("/home/other")),
+            Some(_) => None,
+        };
+
+        expand_with(path, var, home)
+    }
+
+    #[test]
+    fn expand_tilde() {
+        assert_eq!(expand("~").unwrap(), PathBuf::from("/home/user"));
+        assert_eq!(
+            expand("~/sessions/work.json").unwrap(),
+            PathBuf::from("/home/user/sessions/work.json")
+        );
+        assert_eq!(
+            expand("~other/work.json").unwrap(),
+            PathBuf::from("/home/other/work.json")
+        );
+        assert_eq!(
+            expand("sessions/~/work.json").unwrap(),
+            PathBuf::from("sessions/~/work.json")
+        );
+        assert_eq!(
+            expand("~nobody/work.json"),
+            Err(ExpandError::UnknownUser("nobody".to_string()))
+        );
+    }
+
+    #[test]
+    fn expand_variables() {
+        assert_eq!(
+            expand("$HOME/work.json").unwrap(),
+            PathBuf::from("/home/user/work.json")
+        );
+        assert_eq!(
+            expand("${XDG_STATE_HOME}_old/work.json").unwrap(),
+            PathBuf::from("/home/user/.local/state_old/work.json")
+        );
+        assert_eq!(expand("~/$").unwrap(), PathBuf::from("/home/user/$"));
+        assert_eq!(expand("a$1/b").unwrap(), PathBuf::from("a$1/b"));
+        assert_eq!(expand(r"\$HOME/x").unwrap(), PathBuf::from("$HOME/x"));
+        assert_eq!(expand(r"a\b").unwrap(), PathBuf::from(r"a\b"));
+    }
+
+    #[test]
+    fn expand_errors() {
+        assert_eq!(expand("$UNSET/x"), Err(ExpandError::UnsetVariable("UNSET".to_string())));
+        assert_eq!(expand("${UNSET}"), Err(ExpandError::UnsetVariable("UNSET".to_string())));
+        assert_eq!(expand("${HOME"), Err(ExpandError::UnterminatedBrace));
+    }
+
+    #[test]
+    fn state_dir_override() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-paths-{}", std::process::id()));
+        let paths = Paths::resolve(Some(&dir)).unwrap();
+
+        let location = paths.session_location(None);
+        assert_eq!(location.restore_file(None).unwrap(), dir.join(LAST_SESSION_FILE));
+        assert!(!dir.exists());
+
+        assert_eq!(location.save_file(None).unwrap(), dir.join(LAST_SESSION_FILE));
+        assert!(dir.is_dir());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn profile_directory() {
+        const SESSION: &str = r#"{ "format_version": 4, "num_monitors": 1, "applications": [] }"#;
+
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-profile-{}", std::process::id()));
+        let paths = Paths::with_state_dir(&dir.join("state"));
+        let profile = dir.join("synced");
+        std::fs::create_dir_all(&profile).unwrap();
+
+        let location = paths.session_location(Some(profile.clone()));
+        assert!(matches!(location.restore_file(None), Err(ProfileError::NoSessions(_))));
+
+        let now = SystemTime::now();
+        for (name, age) in [("old.json", 60), ("new.json", 0), ("notes.json", 0)] {
+            let contents = if name == "notes.json" { "{}" } else { SESSION };
+            std::fs::write(profile.join(name), contents).unwrap();
+
+            let file = std::fs::File::options().write(true).open(profile.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        assert_eq!(location.save_file(None).unwrap(), profile.join(LAST_SESSION_FILE));
+        assert_eq!(location.save_file(Some("work")).unwrap(), profile.join("work.json"));
+        assert!(matches!(
+            location.save_file(Some("../x")),
+            Err(ProfileError::InvalidName(_))
+        ));
+
+        assert_eq!(location.restore_file(None).unwrap(), profile.join("new.json"));
+        assert_eq!(location.restore_file(Some("old")).unwrap(), profile.join("old.json"));
+        assert!(matches!(
+            location.restore_file(Some("work")),
+            Err(ProfileError::NoSuchSession(_))
+        ));
+
+        let sessions: Vec<_> = location.sessions().unwrap().into_iter().map(|s| s.path).collect();
+        assert_eq!(sessions, vec![profile.join("new.json"), profile.join("old.json")]);
+
+        // the state directory restores its default session file, not the newest one
+        let state = paths.session_location(None);
+        assert_eq!(
+            state.restore_file(None).unwrap(),
+            dir.join("state").join(LAST_SESSION_FILE)
+        );
+
+        let file = paths.session_location(Some(profile.join("new.json")));
+        assert_eq!(file.restore_file(None).unwrap(), profile.join("new.json"));
+        assert!(matches!(
+            file.save_file(Some("work")),
+            Err(ProfileError::NotAProfile(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
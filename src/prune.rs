@@ -0,0 +1,207 @@
+//! Cleaning up the state directory.
+//!
+//! Only files directly in the state directory that are recognized as something gnome-session-restore left there
+//! are ever pruned: session files (e.g. ones kept with `restore --rename`), except for the default session file,
+//! and the temporary files of atomic writes whose process is gone. Everything else is left alone.
+
+use crate::{paths::LAST_SESSION_FILE, session};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PruneOptions {
+    /// Prune session files that were last modified longer ago than this
+    pub sessions_older_than: Option<Duration>,
+
+    /// Prune temporary files whose writing process is not running anymore
+    pub orphaned_tmp: bool,
+}
+
+/// What a prunable file is
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Artifact {
+    Session,
+    OrphanedTmp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prunable {
+    pub path: PathBuf,
+    pub artifact: Artifact,
+    pub size: u64,
+}
+
+/// The pid in the name of a temporary file written by [`write_atomically`](session::io::write_atomically),
+/// which is named `.<file name>.<pid>.tmp`
+fn tmp_file_pid(file_name: &str) -> Option<i32> {
+    let (rest, pid) = file_name.strip_prefix('.')?.strip_suffix(".tmp")?.rsplit_once('.')?;
+
+    if rest.is_empty() || !pid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    pid.parse().ok()
+}
+
+/// Whether a process with the given pid exists
+pub fn is_running(pid: i32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// The files in `state_dir` that `options` select for pruning, ordered by path, none if `state_dir` does not exist.
+/// Symlinks and subdirectories are never considered.
+pub fn find_prunable(
+    state_dir: &Path,
+    options: PruneOptions,
+    now: SystemTime,
+    is_running: impl Fn(i32) -> bool,
+) -> io::Result<Vec<Prunable>> {
+    let mut prunable = Vec::new();
+
+    let entries = match fs::read_dir(state_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(prunable),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+
+        if !meta.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+
+        let artifact = if let Some(pid) = tmp_file_pid(file_name) {
+            (options.orphaned_tmp && !is_running(pid)).then_some(Artifact::OrphanedTmp)
+        } else if let Some(max_age) = options.sessions_older_than {
+            let age = meta
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+
+            (file_name != LAST_SESSION_FILE && age > max_age && session::is_session_file(&path))
+                .then_some(Artifact::Session)
+        } else {
+            None
+        };
+
+        if let Some(artifact) = artifact {
+            prunable.push(Prunable { path, artifact, size: meta.len() });
+        }
+    }
+
+    prunable.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(prunable)
+}
+
+/// Deletes the files, returns how many bytes were freed.
+/// Files that are already gone are skipped.
+pub fn prune(prunable: &[Prunable]) -> io::Result<u64> {
+    let mut freed = 0;
+
+    for file in prunable {
+        match fs::remove_file(&file.path) {
+            Ok(()) => freed += file.size,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_prunable, prune, tmp_file_pid, Artifact, PruneOptions};
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    const SESSION: &str = r#"{ "format_version": 4, "num_monitors": 1, "applications": [] }"#;
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn tmp_file_names() {
+        assert_eq!(tmp_file_pid(".last-session.json.1234.tmp"), Some(1234));
+        assert_eq!(tmp_file_pid(".a.b.json.7.tmp"), Some(7));
+        assert_eq!(tmp_file_pid("last-session.json.1234.tmp"), None);
+        assert_eq!(tmp_file_pid(".last-session.json.tmp"), None);
+        assert_eq!(tmp_file_pid("..1234.tmp"), None);
+        assert_eq!(tmp_file_pid(".x.-1.tmp"), None);
+    }
+
+    #[test]
+    fn prunes_only_known_artifacts() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-prune-{}", std::process::id()));
+        fs::create_dir_all(dir.join("cache")).unwrap();
+
+        for (name, contents) in [
+            ("last-session.json", SESSION),
+            ("monday.json", SESSION),
+            ("notes.json", "{}"),
+            ("notes.txt", "keep me"),
+            (".last-session.json.100.tmp", "{"),
+            (".last-session.json.200.tmp", "{"),
+            (".editor.swp.tmp", ""),
+            ("cache/.x.100.tmp", ""),
+        ] {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        std::os::unix::fs::symlink(dir.join("monday.json"), dir.join("link.json")).unwrap();
+
+        let running = |pid| pid == 200;
+        let names = |options, now| {
+            find_prunable(&dir, options, now, running)
+                .unwrap()
+                .into_iter()
+                .map(|p| (p.path.file_name().unwrap().to_string_lossy().into_owned(), p.artifact))
+                .collect::<Vec<_>>()
+        };
+
+        // nothing is selected by default
+        assert_eq!(names(PruneOptions::default(), SystemTime::now() + 30 * DAY), vec![]);
+
+        let options = PruneOptions { sessions_older_than: Some(7 * DAY), orphaned_tmp: true };
+        assert_eq!(
+            names(options, SystemTime::now()),
+            vec![(".last-session.json.100.tmp".to_owned(), Artifact::OrphanedTmp)]
+        );
+        assert_eq!(
+            names(options, SystemTime::now() + 30 * DAY),
+            vec![
+                (".last-session.json.100.tmp".to_owned(), Artifact::OrphanedTmp),
+                ("monday.json".to_owned(), Artifact::Session),
+            ]
+        );
+
+        let prunable = find_prunable(&dir, options, SystemTime::now() + 30 * DAY, running).unwrap();
+        assert_eq!(prune(&prunable).unwrap(), 1 + SESSION.len() as u64);
+        assert_eq!(prune(&prunable).unwrap(), 0);
+
+        for kept in [
+            "last-session.json",
+            "notes.json",
+            "notes.txt",
+            ".last-session.json.200.tmp",
+            ".editor.swp.tmp",
+        ] {
+            assert!(dir.join(kept).exists(), "{kept}");
+        }
+        assert!(dir.join("cache/.x.100.tmp").exists());
+        assert_eq!(find_prunable(&dir.join("missing"), options, SystemTime::now(), running).unwrap(), vec![]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
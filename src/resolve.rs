@@ -0,0 +1,229 @@
+//! Reporting what the finder makes of the currently open windows, without saving anything.
+//!
+//! This is meant for tuning the thresholds and the find order on a real session: every window is listed with the
+//! command that was found for it, how it was found and how confident the finder is, followed by how the
+//! confidences are distributed. A single made up window can be resolved the same way with `resolve`,
+//! which needs neither the shell nor its windows.
+
+use crate::{
+    dbus::MetaWindow,
+    find_command::{FindError, Found},
+    output::{self, Report},
+    session::{age, Confidence, Exec},
+};
+use clap::ArgEnum;
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    time::{Duration, SystemTime},
+};
+
+/// The number of buckets of the confidence histogram, each covers an equal part of `0.0..=1.0`
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// The widest bar of the histogram
+const MAX_BAR_WIDTH: usize = 40;
+
+/// What was found for one window
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Resolution {
+    pub window_class: String,
+    pub gtk_app_id: String,
+    pub sandboxed_app_id: String,
+    pub pid: i32,
+
+    /// How many seconds ago the user last interacted with the window, if the extension reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_for: Option<u64>,
+
+    /// The desktop file or command line that was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+
+    /// The name of the [`FindMethod`](crate::find_command::FindMethod) that found the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+
+    /// Why no command was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The [`FindError::kind`] of `error`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+}
+
+impl Resolution {
+    pub fn new(w: &MetaWindow, found: Result<Found, FindError>) -> Self {
+        let (exec, method, confidence, error) = match found {
+            Ok(found) => (
+                Some(describe_exec(&found.exec)),
+                found.method.to_possible_value().map(|v| v.get_name()),
+                Some(found.confidence),
+                None,
+            ),
+            Err(e) => (None, None, None, Some(e)),
+        };
+
+        Resolution {
+            window_class: w.window_class.clone(),
+            gtk_app_id: w.gtk_app_id.clone(),
+            sandboxed_app_id: w.sandboxed_app_id.clone(),
+            pid: w.pid,
+            idle_for: w.user_time.map(|t| age::idle_for(t, SystemTime::now()).as_secs()),
+            exec,
+            method,
+            confidence,
+            error_kind: error.as_ref().map(FindError::kind),
+            error: error.map(|e| e.to_string()),
+        }
+    }
+}
+
+/// A window that only has the given ids, to resolve without it being open
+pub fn window(window_class: &str, gtk_app_id: &str, sandboxed_app_id: &str, pid: i32) -> MetaWindow {
+    MetaWindow {
+        pid,
+        window_class: window_class.to_owned(),
+        gtk_app_id: gtk_app_id.to_owned(),
+        sandboxed_app_id: sandboxed_app_id.to_owned(),
+        ..Default::default()
+    }
+}
+
+fn describe_exec(exec: &Exec) -> String {
+    match exec {
+        Exec::DesktopFile(path) => path.display().to_string(),
+        Exec::CmdLine(cmdline) => cmdline
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" "),
+        Exec::Unresolved => String::new(),
+    }
+}
+
+/// How many of the `confidences` fall into each of the [`HISTOGRAM_BUCKETS`], a confidence of 1.0 counts
+/// towards the last bucket
+pub fn histogram(confidences: impl IntoIterator<Item = Confidence>) -> [usize; HISTOGRAM_BUCKETS] {
+    let mut buckets = [0; HISTOGRAM_BUCKETS];
+
+    for c in confidences {
+        let ix = (c.clamp(0.0, 1.0) * HISTOGRAM_BUCKETS as Confidence) as usize;
+        buckets[ix.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    buckets
+}
+
+/// What `resolve` found, one `key<TAB>value` line per field
+impl Report for Resolution {
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        match (&self.exec, self.method, self.confidence) {
+            (Some(exec), Some(method), Some(confidence)) => {
+                writeln!(w, "exec\t{exec}")?;
+                writeln!(w, "method\t{method}")?;
+                writeln!(w, "confidence\t{confidence:.2}")
+            },
+            _ => writeln!(
+                w,
+                "error\t{}: {}",
+                self.error_kind.unwrap_or("unknown"),
+                self.error.as_deref().unwrap_or("no command found")
+            ),
+        }
+    }
+}
+
+impl Report for Vec<Resolution> {
+    /// Writes a table of the windows, followed by the confidence histogram and a summary
+    fn write_text(&self, w: &mut dyn Write) -> io::Result<()> {
+        write_report(w, self)
+    }
+}
+
+fn write_report(w: &mut dyn Write, resolutions: &[Resolution]) -> io::Result<()> {
+    let class_width = resolutions
+        .iter()
+        .map(|r| r.window_class.chars().count())
+        .chain(["WINDOW CLASS".len()])
+        .max()
+        .unwrap_or_default();
+
+    let method_width = resolutions
+        .iter()
+        .filter_map(|r| r.method)
+        .map(str::len)
+        .chain(["METHOD".len()])
+        .max()
+        .unwrap_or_default();
+
+    writeln!(
+        w,
+        "{:class_width$}  {:method_width$}  CONFIDENCE  IDLE FOR  EXEC",
+        "WINDOW CLASS", "METHOD"
+    )?;
+
+    for r in resolutions {
+        let idle_for = r
+            .idle_for
+            .map_or_else(|| "-".to_owned(), |secs| output::format_age(Duration::from_secs(secs)));
+
+        match (&r.exec, r.method, r.confidence) {
+            (Some(exec), Some(method), Some(confidence)) => writeln!(
+                w,
+                "{:class_width$}  {method:method_width$}  {confidence:>10.2}  {idle_for:>8}  {exec}",
+                r.window_class
+            )?,
+            _ => writeln!(
+                w,
+                "{:class_width$}  {:method_width$}  {:>10}  {idle_for:>8}  ({})",
+                r.window_class,
+                "-",
+                "-",
+                r.error.as_deref().unwrap_or("no command found")
+            )?,
+        }
+    }
+
+    let buckets = histogram(resolutions.iter().filter_map(|r| r.confidence));
+    let max = buckets.iter().copied().max().unwrap_or_default().max(1);
+
+    writeln!(w)?;
+    writeln!(w, "confidence distribution:")?;
+
+    for (ix, &n) in buckets.iter().enumerate() {
+        let lo = ix as f32 / HISTOGRAM_BUCKETS as f32;
+        let hi = (ix + 1) as f32 / HISTOGRAM_BUCKETS as f32;
+        let bar = "#".repeat((n * MAX_BAR_WIDTH).div_ceil(max));
+
+        writeln!(w, "  {lo:.1}..{hi:.1}  {n:>4}  {bar}")?;
+    }
+
+    let resolved = resolutions.iter().filter(|r| r.confidence.is_some()).count();
+
+    writeln!(w)?;
+    writeln!(
+        w,
+        "{} windows: {resolved} resolved, {} unresolved",
+        resolutions.len(),
+        resolutions.len() - resolved
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::histogram;
+
+    #[test]
+    fn confidence_histogram() {
+        assert_eq!(histogram([]), [0; 10]);
+        assert_eq!(
+            histogram([0.0, 0.05, 0.1, 0.55, 0.99, 1.0, 1.0]),
+            [2, 1, 0, 0, 0, 1, 0, 0, 0, 3]
+        );
+    }
+}
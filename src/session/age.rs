@@ -0,0 +1,113 @@
+//! How long ago the user last interacted with a window, see `--max-age`.
+//!
+//! Newer versions of the extension report the wall-clock time of the last interaction with every window, which is
+//! saved with it. Ages in a session are counted up to its most recently used window instead of up to now, so that
+//! a session restored a week after it was saved is not left out as a whole. Windows without a user time
+//! (e.g. from older session files) are never too old.
+
+use crate::dbus::MetaWindow;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+#[error("invalid age {0:?}, expected a number followed by s, m, h, d or w, e.g. 7d")]
+pub struct AgeParseError(String);
+
+/// Parses an age like `7d` or `12h`, the units are `s`, `m`, `h`, `d` and `w`
+pub fn parse_age(s: &str) -> Result<Duration, AgeParseError> {
+    let invalid = || AgeParseError(s.to_owned());
+
+    let digits = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (n, unit) = s.split_at(digits);
+
+    let n: u64 = n.parse().map_err(|_| invalid())?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    n.checked_mul(secs).map(Duration::from_secs).ok_or_else(invalid)
+}
+
+/// How long before `now` the user time `user_time` is, nothing if it is in the future
+pub fn idle_for(user_time: u64, now: SystemTime) -> Duration {
+    now.duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(user_time))
+        .unwrap_or_default()
+}
+
+/// The time the ages of `windows` are counted up to, the user time of the most recently used of them
+pub fn reference_time<'w>(windows: impl IntoIterator<Item = &'w MetaWindow>) -> Option<SystemTime> {
+    windows
+        .into_iter()
+        .filter_map(|w| w.user_time)
+        .max()
+        .map(|t| SystemTime::UNIX_EPOCH + Duration::from_secs(t))
+}
+
+/// Whether the user last interacted with `w` longer than `max_age` before `reference`
+pub fn is_older(w: &MetaWindow, max_age: Duration, reference: SystemTime) -> bool {
+    w.user_time.is_some_and(|t| idle_for(t, reference) > max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_older, parse_age, reference_time, AgeParseError};
+    use crate::resolve;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_age("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_age("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_age("0d"), Ok(Duration::ZERO));
+
+        for invalid in [
+            "",
+            "7",
+            "d",
+            "7x",
+            "7 d",
+            " 7d",
+            "-7d",
+            "1.5h",
+            "7dd",
+            "99999999999999999999d",
+        ] {
+            assert_eq!(
+                parse_age(invalid),
+                Err(AgeParseError(invalid.to_owned())),
+                "{invalid:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ages_are_counted_up_to_the_most_recent_window() {
+        let used = |user_time: Option<u64>| {
+            let mut w = resolve::window("kitty", "", "", 1);
+            w.user_time = user_time;
+            w
+        };
+
+        let windows = [
+            used(Some(1_000_000)),
+            used(Some(1_000_000 - 60)),
+            used(Some(400_000)),
+            used(None),
+        ];
+        let reference = reference_time(&windows).unwrap();
+        let max_age = Duration::from_secs(24 * 60 * 60);
+
+        let older: Vec<_> = windows.iter().map(|w| is_older(w, max_age, reference)).collect();
+        assert_eq!(older, [false, false, true, false]);
+
+        assert_eq!(reference_time(&[used(None)]), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Differences between gnome-shell versions.
+//!
+//! The window classes of applications and the behavior of the extension can change between release series of
+//! gnome-shell, so restoring a session saved under another one is warned about. Workarounds for the behavior of
+//! specific versions belong here too, behind the checks of this module, instead of being spread over the restore.
+
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+/// The version of gnome-shell as reported by its `ShellVersion` property, e.g. `45.2`, `3.38.4` or `46.alpha`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShellVersion {
+    pub major: u32,
+
+    /// `None` for pre-releases (e.g. `46.alpha` or `46.rc`) and versions without a minor version
+    pub minor: Option<u32>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("invalid gnome-shell version {0:?}")]
+pub struct ShellVersionParseError(String);
+
+impl FromStr for ShellVersion {
+    type Err = ShellVersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('.');
+
+        let major = parts
+            .next()
+            .and_then(|major| major.parse().ok())
+            .ok_or_else(|| ShellVersionParseError(s.to_owned()))?;
+
+        // anything else in place of the minor version marks a pre-release
+        let minor = parts.next().and_then(|minor| minor.parse().ok());
+
+        Ok(ShellVersion { major, minor })
+    }
+}
+
+impl ShellVersion {
+    /// GNOME 40 switched from `3.<even minor>` release series to one per major version
+    const FIRST_MAJOR_SERIES: u32 = 40;
+
+    /// The release series, e.g. `(45, None)` for `45.2` and `(3, Some(38))` for `3.38.4`
+    fn series(self) -> (u32, Option<u32>) {
+        if self.major >= Self::FIRST_MAJOR_SERIES {
+            (self.major, None)
+        } else {
+            (self.major, self.minor)
+        }
+    }
+
+    /// Whether both versions belong to the same release series, which behave the same
+    pub fn same_series(self, other: ShellVersion) -> bool {
+        self.series() == other.series()
+    }
+}
+
+/// Formats the release series of the version, e.g. `45` or `3.38`
+impl fmt::Display for ShellVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.series() {
+            (major, Some(minor)) => write!(f, "{major}.{minor}"),
+            (major, None) => write!(f, "{major}"),
+        }
+    }
+}
+
+/// Warns if the session was saved under a different release series of gnome-shell than the running one
+pub fn warn_about_shell_version(saved: &str, current: &str) {
+    match (saved.parse::<ShellVersion>(), current.parse::<ShellVersion>()) {
+        (Ok(saved), Ok(current)) if !saved.same_series(current) => eprintln!(
+            "WARNING: the session was saved under gnome-shell {saved}, but this is gnome-shell {current}; \
+             window classes and the behavior of the extension may have changed, check the restored windows"
+        ),
+        (Err(e), _) | (_, Err(e)) => eprintln!("not comparing gnome-shell versions: {e}"),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShellVersion;
+
+    fn version(s: &str) -> ShellVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(version("45.2"), ShellVersion { major: 45, minor: Some(2) });
+        assert_eq!(version("3.38.4"), ShellVersion { major: 3, minor: Some(38) });
+        assert_eq!(version("46.alpha"), ShellVersion { major: 46, minor: None });
+        assert_eq!(version("47"), ShellVersion { major: 47, minor: None });
+
+        assert!("".parse::<ShellVersion>().is_err());
+        assert!("alpha.1".parse::<ShellVersion>().is_err());
+    }
+
+    #[test]
+    fn compare_series() {
+        assert!(version("45.2").same_series(version("45.0")));
+        assert!(version("46.alpha").same_series(version("46.1")));
+        assert!(!version("45.2").same_series(version("46.alpha")));
+
+        assert!(version("3.38.4").same_series(version("3.38.1")));
+        assert!(!version("3.38.4").same_series(version("3.36.9")));
+        assert!(!version("3.38.4").same_series(version("40.0")));
+
+        assert_eq!(version("3.38.4").to_string(), "3.38");
+        assert_eq!(version("45.2").to_string(), "45");
+    }
+}
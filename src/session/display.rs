@@ -0,0 +1,128 @@
+//! Detection of the display server mutter is running as.
+//!
+//! Mutter applies geometry differently depending on the backend: on X11 a move/resize
+//! takes effect immediately, while on Wayland the resize only happens once the client
+//! acknowledged it, which may shift the window after it was placed.
+//!
+//! The geometry of a window also means something different: on X11 it is the client area without the title bar
+//! drawn by mutter, on Wayland the frame including it. Sessions saved under one and restored under the other are
+//! therefore off by the height of the title bar.
+
+use crate::dbus::WindowGeom;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayServer {
+    X11,
+    Wayland,
+}
+
+/// What to do with the geometry of a session that was saved under a different display server
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CrossSessionType {
+    /// Do not move any windows
+    Skip,
+
+    /// Convert the geometry with [`correct_decorations`], assuming title bars of the given height
+    Correct { title_bar_height: i32 },
+
+    /// Apply the geometry as it was saved
+    Force,
+}
+
+/// Converts a geometry saved under `saved` into the one to apply under `current`,
+/// by adding or removing a title bar of `title_bar_height` pixels above the client area
+pub fn correct_decorations(
+    geom: WindowGeom,
+    saved: DisplayServer,
+    current: DisplayServer,
+    title_bar_height: i32,
+) -> WindowGeom {
+    let grow = match (saved, current) {
+        (DisplayServer::X11, DisplayServer::Wayland) => title_bar_height,
+        (DisplayServer::Wayland, DisplayServer::X11) => -title_bar_height,
+        _ => 0,
+    };
+
+    WindowGeom {
+        y: geom.y - grow,
+        height: (geom.height + grow).max(1),
+        ..geom
+    }
+}
+
+impl DisplayServer {
+    /// Detects the display server from `XDG_SESSION_TYPE`, falling back to
+    /// `WAYLAND_DISPLAY` and `DISPLAY` if that is not set or unknown
+    pub fn detect() -> Option<Self> {
+        Self::detect_from(|key| env::var(key).ok().filter(|v| !v.is_empty()))
+    }
+
+    fn detect_from<F: Fn(&str) -> Option<String>>(var: F) -> Option<Self> {
+        match var("XDG_SESSION_TYPE").as_deref() {
+            Some("x11") => Some(DisplayServer::X11),
+            Some("wayland") => Some(DisplayServer::Wayland),
+            _ if var("WAYLAND_DISPLAY").is_some() => Some(DisplayServer::Wayland),
+            _ if var("DISPLAY").is_some() => Some(DisplayServer::X11),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{correct_decorations, DisplayServer};
+    use crate::dbus::WindowGeom;
+
+    fn detect(vars: &[(&str, &str)]) -> Option<DisplayServer> {
+        DisplayServer::detect_from(|key| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string()))
+    }
+
+    #[test]
+    fn session_type_is_preferred() {
+        assert_eq!(
+            detect(&[("XDG_SESSION_TYPE", "x11"), ("WAYLAND_DISPLAY", "wayland-0")]),
+            Some(DisplayServer::X11)
+        );
+        assert_eq!(
+            detect(&[("XDG_SESSION_TYPE", "wayland"), ("DISPLAY", ":0")]),
+            Some(DisplayServer::Wayland)
+        );
+    }
+
+    #[test]
+    fn fallback_to_display_variables() {
+        // XWayland also sets DISPLAY
+        assert_eq!(
+            detect(&[
+                ("XDG_SESSION_TYPE", "tty"),
+                ("WAYLAND_DISPLAY", "wayland-0"),
+                ("DISPLAY", ":0")
+            ]),
+            Some(DisplayServer::Wayland)
+        );
+        assert_eq!(detect(&[("DISPLAY", ":0")]), Some(DisplayServer::X11));
+        assert_eq!(detect(&[]), None);
+    }
+
+    #[test]
+    fn decoration_correction() {
+        use DisplayServer::{Wayland, X11};
+
+        let geom = WindowGeom { x: 10, y: 100, width: 800, height: 600, minimized: false };
+        let rect = |g: WindowGeom| (g.x, g.y, g.width, g.height);
+
+        assert_eq!(rect(correct_decorations(geom, X11, Wayland, 37)), (10, 63, 800, 637));
+        assert_eq!(rect(correct_decorations(geom, Wayland, X11, 37)), (10, 137, 800, 563));
+        assert_eq!(rect(correct_decorations(geom, X11, X11, 37)), rect(geom));
+
+        // there and back again
+        let back = correct_decorations(correct_decorations(geom, X11, Wayland, 37), Wayland, X11, 37);
+        assert_eq!(rect(back), rect(geom));
+
+        let tiny = WindowGeom { height: 10, ..geom };
+        assert_eq!(correct_decorations(tiny, Wayland, X11, 37).height, 1);
+    }
+}
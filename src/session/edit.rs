@@ -0,0 +1,198 @@
+//! Editing of session files in place.
+//!
+//! Files are edited as plain JSON so that fields unknown to this version are preserved.
+
+use super::{io, order, Session};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EditError {
+    #[error("io error {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("serialization error {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("{0:?} is not a regular file")]
+    NotRegularFile(PathBuf),
+
+    #[error("not a session file")]
+    NotASession,
+
+    #[error("no application with window class `{0}`")]
+    NoSuchApplication(String),
+
+    #[error("{0}")]
+    Order(#[from] order::OrderError),
+}
+
+/// Disables or enables all applications with the given window class, returns how many there were
+pub fn set_disabled(path: &Path, window_class: &str, disabled: bool) -> Result<usize, EditError> {
+    edit(path, |session| set_disabled_in(session, window_class, disabled))
+}
+
+/// Changes the launch order of all applications with the given window class, returns how many there were.
+/// Fields that are `None` are left as they are.
+pub fn set_launch_order(
+    path: &Path,
+    window_class: &str,
+    priority: Option<i32>,
+    after: Option<Vec<String>>,
+) -> Result<usize, EditError> {
+    edit(path, |session| {
+        let n = set_launch_order_in(session, window_class, priority, after)?;
+        validate_launch_order(session)?;
+        Ok(n)
+    })
+}
+
+fn edit<F>(path: &Path, f: F) -> Result<usize, EditError>
+where
+    F: FnOnce(&mut Value) -> Result<usize, EditError>,
+{
+    if !io::is_regular_file(path) {
+        return Err(EditError::NotRegularFile(path.to_owned()));
+    }
+
+    let mut session: Value = serde_json::from_reader(io::open_reader(path)?)?;
+    let n = f(&mut session)?;
+
+    io::write_atomically(path, |w| Ok(serde_json::to_writer(w, &session)?))?;
+    Ok(n)
+}
+
+/// Calls `f` on all applications with the given window class, returns how many there were
+fn edit_applications<F>(session: &mut Value, window_class: &str, mut f: F) -> Result<usize, EditError>
+where
+    F: FnMut(&mut Map<String, Value>),
+{
+    let applications = session
+        .get_mut("applications")
+        .and_then(Value::as_array_mut)
+        .ok_or(EditError::NotASession)?;
+
+    let mut n = 0;
+
+    for app in applications.iter_mut().filter_map(Value::as_object_mut) {
+        if app.get("window_class").and_then(Value::as_str) == Some(window_class) {
+            f(app);
+            n += 1;
+        }
+    }
+
+    if n == 0 {
+        Err(EditError::NoSuchApplication(window_class.to_owned()))
+    } else {
+        Ok(n)
+    }
+}
+
+fn set_disabled_in(session: &mut Value, window_class: &str, disabled: bool) -> Result<usize, EditError> {
+    edit_applications(session, window_class, |app| {
+        if disabled {
+            app.insert("disabled".to_owned(), Value::Bool(true));
+        } else {
+            app.remove("disabled");
+        }
+    })
+}
+
+fn set_launch_order_in(
+    session: &mut Value,
+    window_class: &str,
+    priority: Option<i32>,
+    after: Option<Vec<String>>,
+) -> Result<usize, EditError> {
+    edit_applications(session, window_class, |app| {
+        if let Some(priority) = priority {
+            if priority == 0 {
+                app.remove("priority");
+            } else {
+                app.insert("priority".to_owned(), priority.into());
+            }
+        }
+
+        if let Some(after) = &after {
+            if after.is_empty() {
+                app.remove("after");
+            } else {
+                app.insert("after".to_owned(), after.clone().into());
+            }
+        }
+    })
+}
+
+/// Checks that the launch order dependencies of the session do not form a cycle
+fn validate_launch_order(session: &Value) -> Result<(), EditError> {
+    let session = Session::deserialize(session)?;
+    order::launch_stages(&session.applications)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_disabled_in, set_launch_order_in, validate_launch_order, EditError};
+    use crate::session::order::OrderError;
+    use serde_json::json;
+
+    #[test]
+    fn toggle_disabled() {
+        let mut session = json!({
+            "format_version": 7,
+            "num_monitors": 1,
+            "applications": [
+                { "window_class": "firefox", "future_field": 1 },
+                { "window_class": "kitty" },
+                { "window_class": "firefox" },
+            ]
+        });
+
+        assert_eq!(set_disabled_in(&mut session, "firefox", true).unwrap(), 2);
+        assert_eq!(session["applications"][0]["disabled"], true);
+        assert_eq!(session["applications"][0]["future_field"], 1);
+        assert!(session["applications"][1].get("disabled").is_none());
+        assert_eq!(session["applications"][2]["disabled"], true);
+
+        assert_eq!(set_disabled_in(&mut session, "firefox", false).unwrap(), 2);
+        assert!(session["applications"][0].get("disabled").is_none());
+
+        assert!(matches!(
+            set_disabled_in(&mut session, "code", true),
+            Err(EditError::NoSuchApplication(_))
+        ));
+        assert!(matches!(
+            set_disabled_in(&mut json!([]), "code", true),
+            Err(EditError::NotASession)
+        ));
+    }
+
+    #[test]
+    fn launch_order() {
+        let app = |class: &str| {
+            json!({
+                "window_class": class, "exec": { "CmdLine": [class] },
+                "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+                "pid": 1, "stable_seq": 1, "gtk_app_id": "", "sandboxed_app_id": ""
+            })
+        };
+        let mut session = json!({ "num_monitors": 1, "applications": [app("firefox"), app("keepassxc")] });
+
+        set_launch_order_in(&mut session, "firefox", Some(5), Some(vec!["keepassxc".to_string()])).unwrap();
+        assert_eq!(session["applications"][0]["priority"], 5);
+        assert_eq!(session["applications"][0]["after"], json!(["keepassxc"]));
+        validate_launch_order(&session).unwrap();
+
+        set_launch_order_in(&mut session, "keepassxc", None, Some(vec!["firefox".to_string()])).unwrap();
+        assert!(matches!(
+            validate_launch_order(&session),
+            Err(EditError::Order(OrderError::Cycle(_)))
+        ));
+
+        set_launch_order_in(&mut session, "firefox", Some(0), Some(vec![])).unwrap();
+        assert!(session["applications"][0].get("priority").is_none());
+        assert!(session["applications"][0].get("after").is_none());
+    }
+}
@@ -0,0 +1,392 @@
+//! Placement of windows when the monitor layout differs from the saved one.
+//!
+//! The saved coordinates refer to the saved monitor layout and may lie far outside of the current one
+//! (e.g. a docked session restored on the laptop alone), so instead the windows are fit into one work area.
+
+use crate::dbus::{Rect, WindowGeom};
+use std::{collections::HashMap, str::FromStr};
+use thiserror::Error;
+
+/// How far each window of a cascade is moved from the previous one
+const CASCADE_STEP: i32 = 32;
+
+/// What to do when the session was saved with a different number of monitors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MonitorFallback {
+    /// Do not place windows
+    Skip,
+
+    /// Fit all windows into the primary monitor's work area, keeping their relative positions
+    Primary,
+
+    /// Cascade all windows across the primary monitor's work area
+    Cascade,
+}
+
+/// Shrinks a window that is larger than `area` to fit, keeping its aspect ratio
+fn fit_size(geom: WindowGeom, area: Rect) -> (i32, i32) {
+    if geom.width <= 0 || geom.height <= 0 {
+        return (geom.width, geom.height);
+    }
+
+    let scale = f64::min(
+        1.0,
+        f64::min(
+            f64::from(area.width) / f64::from(geom.width),
+            f64::from(area.height) / f64::from(geom.height),
+        ),
+    );
+
+    (
+        (f64::from(geom.width) * scale) as i32,
+        (f64::from(geom.height) * scale) as i32,
+    )
+}
+
+/// Maps `pos` from `[from_start, from_start + from_len]` to `[to_start, to_start + to_len]`
+fn map_linear(pos: i32, from_start: i32, from_len: i32, to_start: i32, to_len: i32) -> i32 {
+    if from_len <= 0 || to_len <= 0 {
+        return to_start;
+    }
+
+    let rel = f64::from(pos - from_start) / f64::from(from_len);
+    to_start + (rel * f64::from(to_len)).round() as i32
+}
+
+/// Moves `geom` into `area` if it sticks out, shrinking it first if it is larger than `area`
+fn clamp_into(geom: &mut WindowGeom, area: Rect) {
+    let (width, height) = fit_size(*geom, area);
+
+    geom.x = geom.x.min(area.x + area.width - width).max(area.x);
+    geom.y = geom.y.min(area.y + area.height - height).max(area.y);
+    geom.width = width;
+    geom.height = height;
+}
+
+/// Fits all windows into `area`, keeping their positions relative to each other.
+///
+/// The bounding box of all windows stands in for the saved monitor layout, which is not known,
+/// and is mapped onto `area`. Windows that are larger than `area` are shrunk, windows that would
+/// stick out of `area` afterwards are moved back in.
+pub fn fit_into(geoms: &mut [WindowGeom], area: Rect) {
+    let (Some(left), Some(top)) = (geoms.iter().map(|g| g.x).min(), geoms.iter().map(|g| g.y).min()) else {
+        return;
+    };
+
+    let right = geoms.iter().map(|g| g.x + g.width).max().unwrap_or(left);
+    let bottom = geoms.iter().map(|g| g.y + g.height).max().unwrap_or(top);
+
+    for geom in geoms.iter_mut() {
+        geom.x = map_linear(geom.x, left, right - left, area.x, area.width);
+        geom.y = map_linear(geom.y, top, bottom - top, area.y, area.height);
+        clamp_into(geom, area);
+    }
+}
+
+/// Cascades all windows from the top left corner of `area`, starting over once a window would not fit anymore.
+/// Windows that are larger than `area` are shrunk.
+pub fn cascade(geoms: &mut [WindowGeom], area: Rect) {
+    let mut offset = 0;
+
+    for geom in geoms.iter_mut() {
+        let (width, height) = fit_size(*geom, area);
+
+        if offset + width > area.width || offset + height > area.height {
+            offset = 0;
+        }
+
+        geom.x = area.x + offset;
+        geom.y = area.y + offset;
+        geom.width = width;
+        geom.height = height;
+
+        offset += CASCADE_STEP;
+    }
+}
+
+/// Translates `geom` from the work area `from` into the work area `to`, keeping its position relative to the area.
+///
+/// The window is scaled by the ratio of the areas' sizes (the smaller one of width and height, to keep its
+/// aspect ratio), so that it covers as much of a monitor with a different resolution or scale as before.
+/// Windows that would still stick out of `to` are shrunk and moved back in.
+pub fn translate_rect(geom: WindowGeom, from: Rect, to: Rect) -> WindowGeom {
+    let scale = if from.width > 0 && from.height > 0 {
+        f64::min(
+            f64::from(to.width) / f64::from(from.width),
+            f64::from(to.height) / f64::from(from.height),
+        )
+    } else {
+        1.0
+    };
+
+    let mut translated = WindowGeom {
+        x: map_linear(geom.x, from.x, from.width, to.x, to.width),
+        y: map_linear(geom.y, from.y, from.height, to.y, to.height),
+        width: (f64::from(geom.width) * scale).round() as i32,
+        height: (f64::from(geom.height) * scale).round() as i32,
+        ..geom
+    };
+
+    clamp_into(&mut translated, to);
+    translated
+}
+
+/// Moves all windows onto the monitor with the work area `target`.
+///
+/// Windows whose saved monitor's work area is known are translated with [`translate_rect`],
+/// the others are cascaded across `target`.
+pub fn move_to_monitor(windows: &mut [(&mut WindowGeom, Option<Rect>)], target: Rect) {
+    let mut unknown = Vec::new();
+
+    for (geom, from) in windows.iter_mut() {
+        match from {
+            Some(from) => **geom = translate_rect(**geom, *from, target),
+            None => unknown.push(&mut **geom),
+        }
+    }
+
+    let mut cascaded: Vec<WindowGeom> = unknown.iter().map(|geom| **geom).collect();
+    cascade(&mut cascaded, target);
+
+    for (geom, cascaded) in unknown.into_iter().zip(cascaded) {
+        *geom = cascaded;
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("expected <SAVED_MONITOR>:<CURRENT_MONITOR>")]
+pub struct MonitorMappingParseError;
+
+/// Maps a saved monitor onto a current one, parsed from `<SAVED>:<CURRENT>`, both indices start at 0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MonitorMapping {
+    pub saved: u32,
+    pub current: u32,
+}
+
+impl FromStr for MonitorMapping {
+    type Err = MonitorMappingParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (saved, current) = s.split_once(':').ok_or(MonitorMappingParseError)?;
+
+        Ok(MonitorMapping {
+            saved: saved.trim().parse().map_err(|_| MonitorMappingParseError)?,
+            current: current.trim().parse().map_err(|_| MonitorMappingParseError)?,
+        })
+    }
+}
+
+/// The monitor layouts a session is moved between
+#[derive(Debug, Copy, Clone)]
+pub struct MonitorLayouts<'m> {
+    /// The work areas of the monitors when saving
+    pub saved: &'m [Rect],
+
+    /// The work areas of the monitors that are connected now
+    pub current: &'m [Rect],
+
+    /// Where windows end up that cannot be anchored to a monitor
+    pub primary: Rect,
+}
+
+/// Moves the windows from the saved monitors onto the current ones, keeping their position relative to the monitor.
+///
+/// Saved monitors are mapped as given in `mappings`, or onto the current monitor with the same index if there
+/// is one. Windows on monitors that are not mapped anywhere, and windows whose monitor is unknown,
+/// are clamped into the primary monitor.
+pub fn anchor_to_monitors(
+    windows: &mut [(&mut WindowGeom, Option<u32>)],
+    layouts: MonitorLayouts,
+    mappings: &[MonitorMapping],
+) {
+    let mappings: HashMap<u32, u32> = mappings.iter().map(|m| (m.saved, m.current)).collect();
+
+    for (geom, monitor) in windows.iter_mut() {
+        let saved_area = monitor.and_then(|m| Some((m, *layouts.saved.get(m as usize)?)));
+
+        let areas = saved_area.and_then(|(m, saved_area)| {
+            let current = mappings.get(&m).copied().unwrap_or(m);
+            Some((saved_area, *layouts.current.get(current as usize)?))
+        });
+
+        match areas {
+            Some((from, to)) => {
+                geom.x = to.x + (geom.x - from.x);
+                geom.y = to.y + (geom.y - from.y);
+                clamp_into(geom, to);
+            },
+            None => clamp_into(geom, layouts.primary),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        anchor_to_monitors, cascade, fit_into, move_to_monitor, translate_rect, MonitorLayouts, MonitorMapping,
+        CASCADE_STEP,
+    };
+    use crate::dbus::{Rect, WindowGeom};
+
+    const AREA: Rect = Rect { x: 0, y: 32, width: 1920, height: 1048 };
+
+    fn geom(x: i32, y: i32, width: i32, height: i32) -> WindowGeom {
+        WindowGeom { x, y, width, height, minimized: false }
+    }
+
+    fn rects(geoms: &[WindowGeom]) -> Vec<(i32, i32, i32, i32)> {
+        geoms.iter().map(|g| (g.x, g.y, g.width, g.height)).collect()
+    }
+
+    #[test]
+    fn fit_three_monitors_into_one() {
+        // one window per monitor of a 3x1920 layout, the first one starting left of the origin
+        let mut geoms = [
+            geom(-1920, 0, 1920, 1080),
+            geom(0, 540, 960, 540),
+            geom(1920, 0, 3840, 2160),
+        ];
+
+        fit_into(&mut geoms, AREA);
+
+        assert_eq!(
+            rects(&geoms),
+            vec![
+                // shrunk to fit, keeping the aspect ratio
+                (0, 32, 1863, 1048),
+                (480, 294, 960, 540),
+                // moved back in after shrinking
+                (57, 32, 1863, 1048),
+            ]
+        );
+
+        for g in geoms {
+            assert!(g.x >= AREA.x && g.x + g.width <= AREA.x + AREA.width);
+            assert!(g.y >= AREA.y && g.y + g.height <= AREA.y + AREA.height);
+        }
+    }
+
+    #[test]
+    fn cascade_windows() {
+        let mut geoms = [
+            geom(-100, -100, 800, 600),
+            geom(5000, 0, 4000, 1000),
+            geom(0, 0, 800, 600),
+        ];
+
+        cascade(&mut geoms, AREA);
+
+        // the second window starts over since it would not fit
+        assert_eq!(
+            rects(&geoms),
+            vec![(0, 32, 800, 600), (0, 32, 1920, 480), (32, 64, 800, 600)]
+        );
+
+        let mut geoms = [geom(0, 0, 800, 600), geom(0, 0, 800, 600)];
+        cascade(&mut geoms, AREA);
+        assert_eq!(rects(&geoms)[1], (CASCADE_STEP, 32 + CASCADE_STEP, 800, 600));
+    }
+
+    #[test]
+    fn anchor_three_monitors_onto_two() {
+        let saved = [
+            Rect { x: 0, y: 0, width: 1920, height: 1080 },
+            Rect { x: 1920, y: 0, width: 1920, height: 1080 },
+            Rect { x: 3840, y: 0, width: 2560, height: 1440 },
+        ];
+        let current = [
+            Rect { x: 0, y: 0, width: 1920, height: 1080 },
+            Rect { x: 1920, y: 0, width: 1920, height: 1080 },
+        ];
+        let layouts = MonitorLayouts { saved: &saved, current: &current, primary: current[0] };
+
+        let mut on_second = geom(2020, 100, 800, 600);
+        let mut on_third = geom(4840, 200, 2000, 1200);
+        let mut unknown = geom(-500, 100, 800, 600);
+
+        let mut windows = [
+            (&mut on_second, Some(1)),
+            (&mut on_third, Some(2)),
+            (&mut unknown, None),
+        ];
+        anchor_to_monitors(&mut windows, layouts, &["2:0".parse().unwrap()]);
+
+        assert_eq!(
+            rects(&[on_second, on_third, unknown]),
+            vec![
+                (2020, 100, 800, 600),
+                // anchored to the first monitor, then shrunk and moved back in
+                (120, 0, 1800, 1080),
+                (0, 100, 800, 600),
+            ]
+        );
+
+        // without a mapping the third monitor is unmapped and its windows are clamped into the primary monitor
+        let mut on_third = geom(4840, 200, 800, 600);
+        anchor_to_monitors(&mut [(&mut on_third, Some(2))], layouts, &[]);
+        assert_eq!(rects(&[on_third]), vec![(1120, 200, 800, 600)]);
+    }
+
+    #[test]
+    fn translate_onto_smaller_monitor() {
+        let from = Rect { x: 1920, y: 0, width: 1920, height: 1080 };
+
+        // the work area is lower because of the top bar, so the window is scaled down a little
+        let g = translate_rect(geom(2020, 100, 800, 600), from, AREA);
+        assert_eq!(rects(&[g]), vec![(100, 129, 776, 582)]);
+
+        // spanning several monitors and thus larger than the target even after scaling: shrunk and moved back in
+        let target = Rect { x: 0, y: 0, width: 1920, height: 1080 };
+        let g = translate_rect(geom(1000, 100, 4000, 1000), target, target);
+        assert_eq!(rects(&[g]), vec![(0, 100, 1920, 480)]);
+    }
+
+    #[test]
+    fn translate_between_mixed_dpi_monitors() {
+        // a window covering the right half of a 4k monitor covers the right half of a 1080p monitor
+        let hidpi = Rect { x: 0, y: 0, width: 3840, height: 2160 };
+        let lodpi = Rect { x: 3840, y: 0, width: 1920, height: 1080 };
+
+        let g = translate_rect(geom(1920, 0, 1920, 2160), hidpi, lodpi);
+        assert_eq!(rects(&[g]), vec![(4800, 0, 960, 1080)]);
+
+        // and back
+        let g = translate_rect(g, lodpi, hidpi);
+        assert_eq!(rects(&[g]), vec![(1920, 0, 1920, 2160)]);
+    }
+
+    #[test]
+    fn move_all_to_monitor() {
+        let from = Rect { x: 0, y: 0, width: 1920, height: 1080 };
+
+        let mut known = geom(960, 540, 800, 400);
+        let mut unknown = geom(-500, 100, 800, 600);
+        let mut also_unknown = geom(9000, 100, 800, 600);
+
+        move_to_monitor(
+            &mut [
+                (&mut unknown, None),
+                (&mut known, Some(from)),
+                (&mut also_unknown, None),
+            ],
+            AREA,
+        );
+
+        assert_eq!(
+            rects(&[known, unknown, also_unknown]),
+            vec![
+                (960, 556, 776, 388),
+                (0, 32, 800, 600),
+                (CASCADE_STEP, 32 + CASCADE_STEP, 800, 600)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_monitor_mapping() {
+        assert_eq!("2:0".parse(), Ok(MonitorMapping { saved: 2, current: 0 }));
+        assert!("2".parse::<MonitorMapping>().is_err());
+        assert!("a:0".parse::<MonitorMapping>().is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Which windows are left out of a save.
+//!
+//! Exclusions come in [`Layer`]s: the built-in denylist of the shell's own windows and of dialogs, the command line
+//! (`--exclude`, `--exclude-pid` and `--exclude-current`, or their values from the config file) and `--exclude-file`.
+//! A window is left out if any layer matches it. Layers are checked in the order of [`Layer`],
+//! so a window that several layers match is reported as excluded by the first of them.
+//! The same patterns select the applications of a restore, see [`restore_skip_reason`].
+
+use crate::dbus::MetaWindow;
+use regex::Regex;
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Where an exclusion comes from, in the order in which they are checked
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// The shell's own windows, see [`DEFAULT_EXCLUDED_CLASSES`], and dialogs
+    Default,
+    CommandLine,
+    ExcludeFile,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Layer::Default => "default",
+            Layer::CommandLine => "command line",
+            Layer::ExcludeFile => "exclude file",
+        })
+    }
+}
+
+/// The window classes of windows that belong to the shell itself and are never saved
+pub const DEFAULT_EXCLUDED_CLASSES: &[&str] = &["Gnome-shell"];
+
+/// A regex that has to match the whole window class, gtk app id or sandboxed app id of a window
+#[derive(Debug, Clone)]
+pub struct ExcludePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl FromStr for ExcludePattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ExcludePattern {
+            source: s.to_owned(),
+            regex: Regex::new(&format!("^(?:{s})$"))?,
+        })
+    }
+}
+
+impl ExcludePattern {
+    pub fn matches(&self, w: &MetaWindow) -> bool {
+        [&w.window_class, &w.gtk_app_id, &w.sandboxed_app_id]
+            .into_iter()
+            .any(|id| !id.is_empty() && self.regex.is_match(id))
+    }
+}
+
+/// Why a window is left out
+#[derive(Debug, Clone)]
+pub enum Rule {
+    WindowClass(&'static str),
+
+    /// Windows that are not [`WindowType::Normal`](crate::dbus::WindowType::Normal) or are left out of the taskbar, e.g. file choosers and about
+    /// boxes, which cannot be restored on their own
+    Dialog,
+
+    Pattern(ExcludePattern),
+    Pid(i32),
+
+    /// A process gnome-session-restore was started by, see `--exclude-current`
+    Ancestor(i32),
+}
+
+impl Rule {
+    fn matches(&self, w: &MetaWindow) -> bool {
+        match self {
+            Rule::WindowClass(class) => w.window_class == *class,
+            Rule::Dialog => !w.window_type.is_normal() || w.skip_taskbar,
+            Rule::Pattern(pattern) => pattern.matches(w),
+            Rule::Pid(pid) | Rule::Ancestor(pid) => w.pid == *pid,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::WindowClass(class) => write!(f, "window class {class:?}"),
+            Rule::Dialog => f.write_str("dialog"),
+            Rule::Pattern(pattern) => write!(f, "pattern {:?}", pattern.source),
+            Rule::Pid(pid) => write!(f, "pid {pid}"),
+            Rule::Ancestor(pid) => write!(f, "pid {pid} (gnome-session-restore runs in it)"),
+        }
+    }
+}
+
+/// The layer and rule that left out a window
+#[derive(Debug, Copy, Clone)]
+pub struct Exclusion<'f> {
+    pub layer: Layer,
+    pub rule: &'f Rule,
+}
+
+impl fmt::Display for Exclusion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} by the {} excludes", self.rule, self.layer)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WindowFilter {
+    /// Kept sorted by layer
+    rules: Vec<(Layer, Rule)>,
+
+    /// Print every window that is left out and why
+    pub explain: bool,
+}
+
+impl WindowFilter {
+    /// A filter with only the [`Layer::Default`] excludes
+    pub fn new() -> Self {
+        let mut filter = WindowFilter::default();
+        filter.add(
+            Layer::Default,
+            DEFAULT_EXCLUDED_CLASSES
+                .iter()
+                .copied()
+                .map(Rule::WindowClass)
+                .chain([Rule::Dialog]),
+        );
+        filter
+    }
+
+    /// Keeps dialogs, unless another exclude leaves them out
+    pub fn include_dialogs(&mut self) {
+        self.rules.retain(|(_, rule)| !matches!(rule, Rule::Dialog));
+    }
+
+    pub fn add(&mut self, layer: Layer, rules: impl IntoIterator<Item = Rule>) {
+        self.rules.extend(rules.into_iter().map(|rule| (layer, rule)));
+        self.rules.sort_by_key(|(layer, _)| *layer);
+    }
+
+    /// Drops all excludes of `layer`, e.g. for `--no-default-excludes`
+    pub fn disable(&mut self, layer: Layer) {
+        self.rules.retain(|(l, _)| *l != layer);
+    }
+
+    /// Why `w` is left out, `None` if it is kept
+    pub fn check(&self, w: &MetaWindow) -> Option<Exclusion<'_>> {
+        self.rules
+            .iter()
+            .find(|(_, rule)| rule.matches(w))
+            .map(|(layer, rule)| Exclusion { layer: *layer, rule })
+    }
+
+    /// Whether `w` is kept, printing why it is not with [`explain`](Self::explain)
+    pub fn keeps(&self, w: &MetaWindow) -> bool {
+        match self.check(w) {
+            Some(exclusion) => {
+                if self.explain {
+                    eprintln!(
+                        "excluding '{class}' (pid {pid}): {exclusion}",
+                        class = w.window_class,
+                        pid = w.pid
+                    );
+                }
+
+                false
+            },
+            None => true,
+        }
+    }
+}
+
+/// Why the application of `w` is left out of a restore by `--restore-only` (`only`, unless it is empty)
+/// and `--restore-skip` (`skip`), `None` if it is restored
+pub fn restore_skip_reason(w: &MetaWindow, only: &[ExcludePattern], skip: &[ExcludePattern]) -> Option<&'static str> {
+    if !only.is_empty() && !only.iter().any(|pattern| pattern.matches(w)) {
+        Some("not matched by --restore-only")
+    } else if skip.iter().any(|pattern| pattern.matches(w)) {
+        Some("matched by --restore-skip")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExcludeFileError {
+    #[error("unable to read exclude file {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+
+    #[error("{0:?} line {1}: {2}")]
+    Pattern(PathBuf, usize, regex::Error),
+}
+
+/// Reads the patterns of an exclude file, one per line, empty lines and lines starting with `#` are skipped
+pub fn read_exclude_file(path: &Path) -> Result<Vec<ExcludePattern>, ExcludeFileError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| ExcludeFileError::Io(path.to_owned(), e))?;
+
+    contents
+        .lines()
+        .enumerate()
+        .map(|(ix, line)| (ix + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(lineno, line)| {
+            line.parse()
+                .map_err(|e| ExcludeFileError::Pattern(path.to_owned(), lineno, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_exclude_file, restore_skip_reason, ExcludeFileError, ExcludePattern, Layer, Rule, WindowFilter};
+    use crate::dbus::{MetaWindow, WindowType};
+
+    fn pattern(s: &str) -> Rule {
+        Rule::Pattern(s.parse().unwrap())
+    }
+
+    #[test]
+    fn precedence() {
+        let mut filter = WindowFilter::new();
+        filter.add(Layer::ExcludeFile, [pattern("org\\.gnome\\..*"), pattern("Steam")]);
+        filter.add(Layer::CommandLine, [Rule::Pid(42), pattern("steam"), Rule::Ancestor(7)]);
+
+        let mut no_defaults = filter.clone();
+        no_defaults.disable(Layer::Default);
+
+        let mut no_exclude_file = filter.clone();
+        no_exclude_file.disable(Layer::ExcludeFile);
+
+        let check = |filter: &WindowFilter, w: &MetaWindow| filter.check(w).map(|exclusion| exclusion.layer);
+        let window = |pid, window_class, sandboxed_app_id: &str| MetaWindow {
+            pid,
+            sandboxed_app_id: sandboxed_app_id.to_owned(),
+            ..MetaWindow::with_class(window_class)
+        };
+
+        let (default, cli, file) = (Some(Layer::Default), Some(Layer::CommandLine), Some(Layer::ExcludeFile));
+
+        // the window, and the layer that excludes it with all layers, without the defaults and without the file
+        #[rustfmt::skip]
+        let table = [
+            (window(1, "Gnome-shell", ""),             default, None, default),
+            // the default layer comes before the command line
+            (window(42, "Gnome-shell", ""),            default, cli,  default),
+            (window(42, "kitty", ""),                  cli,     cli,  cli),
+            (window(7, "kitty", ""),                   cli,     cli,  cli),
+            // the command line comes before the exclude file
+            (window(1, "steam", "org.gnome.Steam"),    cli,     cli,  cli),
+            (window(1, "Steam", ""),                   file,    file, None),
+            (window(1, "Evince", "org.gnome.Evince"),  file,    file, None),
+            // patterns match whole ids only
+            (window(1, "steamwebhelper", ""),          None,    None, None),
+            (window(1, "kitty", "org.gnome"),          None,    None, None),
+        ];
+
+        for (w, all, without_defaults, without_file) in table {
+            assert_eq!(check(&filter, &w), all, "{w:?}");
+            assert_eq!(check(&no_defaults, &w), without_defaults, "{w:?}");
+            assert_eq!(check(&no_exclude_file, &w), without_file, "{w:?}");
+        }
+
+        let rule = |w: &MetaWindow| filter.check(w).unwrap().to_string();
+        assert_eq!(
+            rule(&window(1, "Gnome-shell", "")),
+            r#"window class "Gnome-shell" by the default excludes"#
+        );
+        assert_eq!(
+            rule(&window(7, "kitty", "")),
+            "pid 7 (gnome-session-restore runs in it) by the command line excludes"
+        );
+        assert_eq!(
+            rule(&window(1, "Steam", "")),
+            r#"pattern "Steam" by the exclude file excludes"#
+        );
+    }
+
+    #[test]
+    fn dialogs() {
+        let normal = MetaWindow::with_class("gimp");
+        let dialog = MetaWindow { window_type: WindowType::Dialog, ..MetaWindow::with_class("gimp") };
+        let skips_taskbar = MetaWindow { skip_taskbar: true, ..MetaWindow::with_class("gimp") };
+
+        let mut filter = WindowFilter::new();
+        assert!(filter.keeps(&normal));
+        assert_eq!(
+            filter.check(&dialog).unwrap().to_string(),
+            "dialog by the default excludes"
+        );
+        assert!(!filter.keeps(&skips_taskbar));
+
+        filter.include_dialogs();
+        assert!(filter.keeps(&dialog));
+        assert!(filter.keeps(&skips_taskbar));
+    }
+
+    #[test]
+    fn restore_filters() {
+        let patterns = |s: &[&str]| s.iter().map(|s| s.parse().unwrap()).collect::<Vec<ExcludePattern>>();
+        let (editors, code) = (patterns(&["code", "org\\.gnome\\.TextEditor"]), patterns(&["code"]));
+
+        let gedit =
+            MetaWindow { sandboxed_app_id: "org.gnome.TextEditor".to_owned(), ..MetaWindow::with_class("gedit") };
+        let reason =
+            |w: &MetaWindow, only: &[ExcludePattern], skip: &[ExcludePattern]| restore_skip_reason(w, only, skip);
+
+        assert_eq!(reason(&MetaWindow::with_class("code"), &[], &[]), None);
+        assert_eq!(reason(&MetaWindow::with_class("code"), &editors, &[]), None);
+        assert_eq!(reason(&gedit, &editors, &[]), None);
+        assert_eq!(
+            reason(&MetaWindow::with_class("firefox"), &editors, &[]),
+            Some("not matched by --restore-only")
+        );
+        assert_eq!(
+            reason(&MetaWindow::with_class("code"), &editors, &code),
+            Some("matched by --restore-skip")
+        );
+        assert_eq!(
+            reason(&MetaWindow::with_class("code"), &[], &code),
+            Some("matched by --restore-skip")
+        );
+        assert_eq!(reason(&MetaWindow::with_class("code-insiders"), &[], &code), None);
+    }
+
+    #[test]
+    fn exclude_file() {
+        let path = std::env::temp_dir().join(format!("gnome-session-restore-excludes-{}", std::process::id()));
+
+        std::fs::write(&path, "# games\nSteam\n\n  org\\.gnome\\..*  \n").unwrap();
+        let patterns = read_exclude_file(&path).unwrap();
+        assert_eq!(patterns.len(), 2);
+
+        std::fs::write(&path, "Steam\n(unclosed\n").unwrap();
+        assert!(matches!(
+            read_exclude_file(&path),
+            Err(ExcludeFileError::Pattern(_, 2, _))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(read_exclude_file(&path), Err(ExcludeFileError::Io(..))));
+    }
+}
@@ -0,0 +1,260 @@
+//! Versioning of the session file format.
+//!
+//! Every format version only ever adds fields to the one before it, so writing an older
+//! version is a matter of dropping everything that was introduced later.
+//! The exception are applications without a command, which older versions cannot represent at all.
+
+use super::Session;
+use serde_json::{Map, Value};
+use std::io::Write;
+use thiserror::Error;
+
+/// The oldest session file format that can still be written
+pub const OLDEST_FORMAT_VERSION: u32 = 1;
+
+/// The session file format written by default
+pub const CURRENT_FORMAT_VERSION: u32 = 16;
+
+/// The first format version that can contain applications with [`Exec::Unresolved`](super::Exec::Unresolved)
+const UNRESOLVED_EXEC_VERSION: u32 = 8;
+
+/// Where a versioned field lives in the serialized session
+#[derive(Debug, Copy, Clone)]
+enum Scope {
+    /// The top level session object
+    Session,
+
+    /// An entry of `applications`, this also includes the flattened `MetaWindow` fields
+    Application,
+}
+
+/// All fields that were added after the first format version, as `(version, scope, field)`.
+/// Optional fields, which readers of the version before simply ignore, do not start a new version,
+/// they are listed under the version that was current when they were added.
+const INTRODUCED_FIELDS: &[(u32, Scope, &str)] = &[
+    (2, Scope::Session, "format_version"),
+    (3, Scope::Application, "cwd"),
+    (3, Scope::Application, "environ"),
+    (4, Scope::Application, "group_id"),
+    (5, Scope::Application, "redacted"),
+    (6, Scope::Application, "display_name"),
+    (7, Scope::Application, "disabled"),
+    (9, Scope::Application, "priority"),
+    (9, Scope::Application, "after"),
+    (10, Scope::Session, "monitors"),
+    (10, Scope::Application, "monitor"),
+    (11, Scope::Application, "flatpak_scope"),
+    (12, Scope::Application, "extra_state"),
+    (13, Scope::Session, "display_server"),
+    (13, Scope::Application, "relative"),
+    (14, Scope::Session, "shell_version"),
+    (15, Scope::Session, "capabilities"),
+    (16, Scope::Session, "origin"),
+    (16, Scope::Application, "window_type"),
+    (16, Scope::Session, "monitor_scales"),
+    (16, Scope::Application, "user_time"),
+    (16, Scope::Application, "blocked"),
+    (16, Scope::Application, "local_geom"),
+];
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("unsupported format version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("serialization error {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+pub(super) fn legacy_format_version() -> u32 {
+    OLDEST_FORMAT_VERSION
+}
+
+pub fn is_supported(version: u32) -> bool {
+    (OLDEST_FORMAT_VERSION..=CURRENT_FORMAT_VERSION).contains(&version)
+}
+
+/// Serializes `session` in the shape of the given format version
+pub(super) fn to_writer_versioned<W: Write>(writer: W, session: &Session, version: u32) -> Result<(), FormatError> {
+    if !is_supported(version) {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+
+    if version == CURRENT_FORMAT_VERSION {
+        serde_json::to_writer(writer, session)?;
+    } else {
+        let mut value = serde_json::to_value(session)?;
+        downgrade(&mut value, version);
+        serde_json::to_writer(writer, &value)?;
+    }
+
+    Ok(())
+}
+
+/// Removes all fields from a serialized session that did not exist in `version`
+/// and stamps the session with that version, if the version already knew about `format_version`
+fn downgrade(session: &mut Value, version: u32) {
+    fn remove(obj: Option<&mut Map<String, Value>>, field: &str) {
+        if let Some(obj) = obj {
+            obj.remove(field);
+        }
+    }
+
+    if version < UNRESOLVED_EXEC_VERSION {
+        if let Some(apps) = session.get_mut("applications").and_then(Value::as_array_mut) {
+            apps.retain(|app| app["exec"] != "Unresolved");
+        }
+    }
+
+    for &(_, scope, field) in INTRODUCED_FIELDS
+        .iter()
+        .filter(|(introduced, ..)| *introduced > version)
+    {
+        match scope {
+            Scope::Session => remove(session.as_object_mut(), field),
+            Scope::Application => {
+                let apps = session
+                    .get_mut("applications")
+                    .and_then(Value::as_array_mut)
+                    .into_iter()
+                    .flatten();
+
+                for app in apps {
+                    remove(app.as_object_mut(), field);
+                }
+            },
+        }
+    }
+
+    if let Some(v) = session.get_mut("format_version") {
+        *v = version.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_writer_versioned, FormatError, CURRENT_FORMAT_VERSION};
+    use crate::{
+        dbus::{MetaWindow, WindowGeom},
+        session::{BlockedCommand, Exec, Session, SessionApplication},
+    };
+    use serde_json::Value;
+
+    fn session() -> Session {
+        Session {
+            format_version: CURRENT_FORMAT_VERSION,
+            applications: vec![SessionApplication {
+                window: MetaWindow {
+                    geom: WindowGeom { width: 100, height: 100, ..Default::default() },
+                    pid: 1,
+                    stable_seq: 1,
+                    ..MetaWindow::with_class("firefox")
+                },
+                exec: Exec::DesktopFile("/usr/share/applications/firefox.desktop".into()),
+                cwd: Some("/home/user".into()),
+                environ: None,
+                redacted: false,
+                display_name: Some("Firefox".to_string()),
+                disabled: false,
+                priority: 0,
+                after: Vec::new(),
+                extra_state: Default::default(),
+                relative: None,
+                blocked: None,
+            }],
+            num_monitors: 1,
+            monitors: None,
+            monitor_scales: None,
+            display_server: None,
+            shell_version: None,
+            capabilities: None,
+            origin: None,
+        }
+    }
+
+    fn write(version: u32) -> Result<Value, FormatError> {
+        let mut buf = Vec::new();
+        to_writer_versioned(&mut buf, &session(), version)?;
+        Ok(serde_json::from_slice(&buf).unwrap())
+    }
+
+    #[test]
+    fn write_current_version() {
+        let v = write(CURRENT_FORMAT_VERSION).unwrap();
+        assert_eq!(v["format_version"], CURRENT_FORMAT_VERSION);
+        assert_eq!(v["applications"][0]["display_name"], "Firefox");
+    }
+
+    #[test]
+    fn write_v2() {
+        let v = write(2).unwrap();
+
+        assert_eq!(v["format_version"], 2);
+        assert!(v["applications"][0].get("cwd").is_none());
+        assert!(v["applications"][0].get("display_name").is_none());
+    }
+
+    #[test]
+    fn write_v1() {
+        let v = write(1).unwrap();
+
+        assert!(v.get("format_version").is_none());
+        assert_eq!(v["num_monitors"], 1);
+        assert_eq!(v["applications"][0]["window_class"], "firefox");
+
+        let sess: Session = serde_json::from_value(v).unwrap();
+        assert_eq!(sess.format_version, 1);
+    }
+
+    #[test]
+    fn write_unresolved_exec() {
+        let mut sess = session();
+        sess.applications[0].exec = Exec::Unresolved;
+
+        let mut buf = Vec::new();
+        to_writer_versioned(&mut buf, &sess, CURRENT_FORMAT_VERSION).unwrap();
+        let v: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(v["applications"][0]["exec"], "Unresolved");
+
+        // older versions cannot represent windows without a command
+        let mut buf = Vec::new();
+        to_writer_versioned(&mut buf, &sess, 7).unwrap();
+        let v: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(v["applications"].as_array().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn round_trip_blocked_command() {
+        let mut sess = session();
+        sess.applications[0].exec = Exec::Unresolved;
+        sess.applications[0].blocked = Some(BlockedCommand {
+            reason: "proc-command-not-allowed".to_owned(),
+            binary: "steam".to_owned(),
+        });
+
+        let mut buf = Vec::new();
+        to_writer_versioned(&mut buf, &sess, CURRENT_FORMAT_VERSION).unwrap();
+        let read: Session = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(read.applications[0].exec, Exec::Unresolved);
+        assert_eq!(read.applications[0].blocked, sess.applications[0].blocked);
+
+        // the window is still written to older versions, only without why it has no command
+        let mut buf = Vec::new();
+        to_writer_versioned(&mut buf, &sess, 15).unwrap();
+        let v: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(v["applications"][0]["exec"], "Unresolved");
+        assert!(v["applications"][0].get("blocked").is_none());
+
+        let read: Session = serde_json::from_value(v).unwrap();
+        assert_eq!(read.applications[0].blocked, None);
+    }
+
+    #[test]
+    fn write_unsupported_version() {
+        assert!(matches!(write(0), Err(FormatError::UnsupportedVersion(0))));
+        assert!(matches!(
+            write(CURRENT_FORMAT_VERSION + 1),
+            Err(FormatError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+//! Inhibiting logout and suspend while a restore is in progress.
+//!
+//! gnome-session drops inhibitors when the bus name that took them vanishes,
+//! so an inhibitor is also released when the process is terminated by a signal.
+
+use crate::dbus::SessionManagerProxy;
+
+/// The app id the inhibitor is registered under
+const APP_ID: &str = "gnome-session-restore";
+
+/// `GsmInhibitorFlag`s, see the `org.gnome.SessionManager` interface
+pub const INHIBIT_LOGOUT: u32 = 1;
+pub const INHIBIT_SUSPEND: u32 = 4;
+
+pub trait Inhibitor {
+    fn inhibit(&self, app_id: &str, reason: &str, flags: u32) -> zbus::Result<u32>;
+    fn uninhibit(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+impl Inhibitor for SessionManagerProxy<'_> {
+    fn inhibit(&self, app_id: &str, reason: &str, flags: u32) -> zbus::Result<u32> {
+        // there is no toplevel window to associate the inhibitor with
+        SessionManagerProxy::inhibit(self, app_id, 0, reason, flags)
+    }
+
+    fn uninhibit(&self, cookie: u32) -> zbus::Result<()> {
+        SessionManagerProxy::uninhibit(self, cookie)
+    }
+}
+
+/// Releases the inhibitor when dropped
+#[derive(Debug)]
+pub struct InhibitGuard<'i, I: Inhibitor> {
+    inhibitor: &'i I,
+    cookie: u32,
+}
+
+impl<I: Inhibitor> Drop for InhibitGuard<'_, I> {
+    fn drop(&mut self) {
+        if let Err(e) = self.inhibitor.uninhibit(self.cookie) {
+            eprintln!("unable to release inhibitor: {e}");
+        }
+    }
+}
+
+/// Inhibits logout and suspend until the returned guard is dropped.
+/// This is best-effort, if inhibiting fails a warning is printed and `None` is returned.
+pub fn inhibit<'i, I: Inhibitor>(inhibitor: &'i I, reason: &str) -> Option<InhibitGuard<'i, I>> {
+    match inhibitor.inhibit(APP_ID, reason, INHIBIT_LOGOUT | INHIBIT_SUSPEND) {
+        Ok(cookie) => Some(InhibitGuard { inhibitor, cookie }),
+        Err(e) => {
+            eprintln!("unable to inhibit logout and suspend: {e}");
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inhibit, Inhibitor, INHIBIT_LOGOUT, INHIBIT_SUSPEND};
+    use std::cell::RefCell;
+
+    /// Mimics the inhibitor bookkeeping of gnome-session
+    #[derive(Debug, Default)]
+    struct MockSessionManager {
+        fail: bool,
+        next_cookie: RefCell<u32>,
+        inhibitors: RefCell<Vec<(u32, String, u32)>>,
+    }
+
+    impl Inhibitor for MockSessionManager {
+        fn inhibit(&self, _app_id: &str, reason: &str, flags: u32) -> zbus::Result<u32> {
+            if self.fail {
+                return Err(zbus::Error::Unsupported);
+            }
+
+            let mut cookie = self.next_cookie.borrow_mut();
+            *cookie += 1;
+
+            self.inhibitors.borrow_mut().push((*cookie, reason.to_string(), flags));
+            Ok(*cookie)
+        }
+
+        fn uninhibit(&self, cookie: u32) -> zbus::Result<()> {
+            self.inhibitors.borrow_mut().retain(|&(c, ..)| c != cookie);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn inhibits_until_dropped() {
+        let manager = MockSessionManager::default();
+
+        let guard = inhibit(&manager, "restoring session");
+        assert!(guard.is_some());
+        assert_eq!(
+            *manager.inhibitors.borrow(),
+            vec![(1, "restoring session".to_string(), INHIBIT_LOGOUT | INHIBIT_SUSPEND)]
+        );
+
+        drop(guard);
+        assert!(manager.inhibitors.borrow().is_empty());
+    }
+
+    #[test]
+    fn released_on_panic() {
+        let manager = MockSessionManager::default();
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = inhibit(&manager, "restoring session");
+            panic!("restore failed");
+        }));
+
+        assert!(res.is_err());
+        assert!(manager.inhibitors.borrow().is_empty());
+    }
+
+    #[test]
+    fn failure_is_not_fatal() {
+        let manager = MockSessionManager { fail: true, ..Default::default() };
+        assert!(inhibit(&manager, "restoring session").is_none());
+    }
+}
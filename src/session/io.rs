@@ -0,0 +1,393 @@
+use serde_json::Value;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, Read, Result, Stdout, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+/// The path that is used to request std(in|out) redirection
+pub const STD_STREAM: &str = "-";
+
+/// The format of a session read from stdin or written to stdout, where there is no file name to tell it by.
+/// Sessions are json everywhere else, other formats are converted from and to it at the stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamFormat {
+    Json,
+    Toml,
+}
+
+/// Checks whether `path` is the literal `-`, which stands for stdin or stdout
+pub fn is_std_stream(path: &Path) -> bool {
+    path == Path::new(STD_STREAM)
+}
+
+/// Checks whether `path` refers to a regular file on disk, i.e. something that
+/// can safely be renamed or removed after it was used.
+///
+/// This is not the case for `-`, `/dev/stdin`, `/dev/fd/N` (e.g. from process substitution),
+/// FIFOs, pipes or any other kind of special file. Note that `/dev/stdin` may very well resolve
+/// to a regular file if stdin was redirected from one, which is why a path that leads through `/dev`
+/// or `/proc` at any of its symlinks is never considered to be regular.
+pub fn is_regular_file(path: &Path) -> bool {
+    if is_std_stream(path) {
+        return false;
+    }
+
+    let mut link = path.to_path_buf();
+
+    // like the kernel's limit on nested symlinks
+    for _ in 0..40 {
+        let Some(link_path) = resolve_parent(&link) else {
+            return false;
+        };
+
+        if link_path.starts_with("/dev") || link_path.starts_with("/proc") {
+            return false;
+        }
+
+        match std::fs::symlink_metadata(&link_path) {
+            Ok(m) if m.file_type().is_symlink() => match std::fs::read_link(&link_path) {
+                Ok(target) => link = link_path.parent().unwrap_or(Path::new("/")).join(target),
+                Err(_) => return false,
+            },
+            Ok(m) => return m.is_file(),
+            Err(_) => return false,
+        }
+    }
+
+    false
+}
+
+/// `path` with the directories leading to it resolved, but not the last component itself
+fn resolve_parent(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?;
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    Some(parent.canonicalize().ok()?.join(file_name))
+}
+
+/// Opens `path` for reading, `-` refers to stdin
+pub fn open_reader(path: &Path) -> Result<Box<dyn Read>> {
+    if is_std_stream(path) {
+        Ok(Box::new(std::io::stdin()))
+    } else {
+        let f = File::open(path)?;
+        Ok(Box::new(BufReader::new(f)))
+    }
+}
+
+/// Opens `path` for reading like [`open_reader`], a session read from stdin in `format` is converted to json
+pub fn open_reader_as(path: &Path, format: StreamFormat) -> Result<Box<dyn Read>> {
+    if !is_std_stream(path) || format == StreamFormat::Json {
+        return open_reader(path);
+    }
+
+    let mut toml = String::new();
+    std::io::stdin().read_to_string(&mut toml)?;
+
+    Ok(Box::new(Cursor::new(toml_to_json(&toml)?)))
+}
+
+/// Opens `path` for writing like [`open_writer`], the json session written to stdout is converted to `format`.
+/// Regular files (or ones that do not exist yet) are replaced [atomically](write_atomically) once the writer is
+/// [`finish`](SessionWriter::finish)ed, so that an interrupted save never leaves a truncated session behind.
+pub fn open_writer_as(path: &Path, format: StreamFormat) -> Result<SessionWriter> {
+    if is_std_stream(path) && format == StreamFormat::Toml {
+        return Ok(SessionWriter::Toml(TomlWriter { json: Vec::new(), out: std::io::stdout() }));
+    }
+
+    if is_regular_file(path) || (!is_std_stream(path) && std::fs::symlink_metadata(path).is_err()) {
+        return Ok(SessionWriter::File(AtomicFile::create(path)?));
+    }
+
+    Ok(SessionWriter::Json(open_writer(path)?))
+}
+
+/// A session file being written, which is only complete once [`finish`](Self::finish)ed
+pub enum SessionWriter {
+    Json(Box<dyn Write>),
+    File(AtomicFile),
+    Toml(TomlWriter<Stdout>),
+}
+
+impl SessionWriter {
+    /// Writes out everything that was buffered, reporting the errors that dropping the writer would swallow
+    pub fn finish(self) -> Result<()> {
+        match self {
+            SessionWriter::Json(mut w) => w.flush(),
+            SessionWriter::File(f) => f.commit(),
+            SessionWriter::Toml(w) => w.finish(),
+        }
+    }
+}
+
+impl Write for SessionWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            SessionWriter::Json(w) => w.write(buf),
+            SessionWriter::File(f) => f.writer.write(buf),
+            SessionWriter::Toml(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            SessionWriter::Json(w) => w.flush(),
+            SessionWriter::File(f) => f.writer.flush(),
+            SessionWriter::Toml(w) => w.flush(),
+        }
+    }
+}
+
+fn toml_to_json(toml: &str) -> Result<Vec<u8>> {
+    let value: Value = toml::from_str(toml).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(serde_json::to_vec(&value)?)
+}
+
+/// Toml has no null, absent fields are read back as `None` all the same
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(obj) => {
+            *obj = std::mem::take(obj).into_iter().filter(|(_, v)| !v.is_null()).collect();
+            obj.values_mut().for_each(strip_nulls);
+        },
+        Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+        _ => {},
+    }
+}
+
+fn json_to_toml(json: &[u8]) -> Result<String> {
+    let mut value: Value = serde_json::from_slice(json)?;
+    strip_nulls(&mut value);
+
+    let invalid = |e: toml::ser::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+    toml::to_string_pretty(&toml::Value::try_from(value).map_err(invalid)?).map_err(invalid)
+}
+
+/// Collects the json written to it and writes it to `out` as toml once [`finish`](Self::finish)ed,
+/// as toml cannot be written before the whole session is known
+pub struct TomlWriter<W: Write> {
+    json: Vec<u8>,
+    out: W,
+}
+
+impl<W: Write> TomlWriter<W> {
+    pub fn finish(mut self) -> Result<()> {
+        if self.json.is_empty() {
+            return Ok(());
+        }
+
+        self.out.write_all(json_to_toml(&self.json)?.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Write for TomlWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.json.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens `path` for writing, `-` refers to stdout.
+///
+/// Regular files are created (readable only by the user, since command lines may contain secrets) or truncated,
+/// special files (e.g. `/dev/stdout`, `/dev/fd/N` or FIFOs) are only opened for writing.
+pub fn open_writer(path: &Path) -> Result<Box<dyn Write>> {
+    if is_std_stream(path) {
+        return Ok(Box::new(std::io::stdout()));
+    }
+
+    let is_special_file = std::fs::metadata(path).is_ok_and(|m| !m.is_file());
+
+    let f = if is_special_file {
+        OpenOptions::new().write(true).open(path)?
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+    };
+
+    Ok(Box::new(BufWriter::new(f)))
+}
+
+/// Replaces the file at `path` with what `write` writes, without ever leaving a partially written file behind.
+///
+/// The content is written to a temporary file next to `path` first, which is then renamed over it.
+pub fn write_atomically<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+{
+    let mut file = AtomicFile::create(path)?;
+    write(&mut file.writer)?;
+    file.commit()
+}
+
+/// The temporary file of [`write_atomically`], which replaces the file at its path once
+/// [`commit`](Self::commit)ted and is removed if it is dropped before
+pub struct AtomicFile {
+    writer: BufWriter<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file `.<file name>.<pid>.tmp` next to `path`.
+    /// If `path` is a symlink, the file it points to is replaced instead of the symlink.
+    pub fn create(path: &Path) -> Result<Self> {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+        let f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+
+        Ok(AtomicFile { writer: BufWriter::new(f), tmp_path, path, committed: false })
+    }
+
+    /// Syncs the temporary file to disk and renames it over the file at `path`
+    pub fn commit(mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        std::fs::rename(&self.tmp_path, &self.path)?;
+
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_regular_file, open_writer_as, toml_to_json, StreamFormat, TomlWriter};
+    use crate::session::{Exec, Session};
+    use serde_json::Value;
+    use std::{io::Write, os::unix::fs::symlink, path::Path};
+
+    #[test]
+    fn regular_files() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-io-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("session.json");
+        std::fs::write(&file, "{}").unwrap();
+        symlink(&file, dir.join("link")).unwrap();
+        symlink("/dev/stdin", dir.join("stdin")).unwrap();
+
+        assert!(is_regular_file(&file));
+        assert!(is_regular_file(&dir.join("link")));
+        assert!(!is_regular_file(&dir.join("stdin")));
+        assert!(!is_regular_file(&dir));
+        assert!(!is_regular_file(&dir.join("missing")));
+        assert!(!is_regular_file(Path::new("-")));
+        assert!(!is_regular_file(Path::new("/dev/stdin")));
+        assert!(!is_regular_file(Path::new("/proc/self/fd/0")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_session_files_atomically() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("session.json");
+        let link = dir.join("link");
+        std::fs::write(&file, "old").unwrap();
+        symlink(&file, &link).unwrap();
+
+        let mut writer = open_writer_as(&link, StreamFormat::Json).unwrap();
+        writer.write_all(b"new").unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "old");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2, "the temporary file is removed");
+
+        let mut writer = open_writer_as(&link, StreamFormat::Json).unwrap();
+        writer.write_all(b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "old");
+        writer.finish().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+
+        let new_file = dir.join("new.json");
+        let mut writer = open_writer_as(&new_file, StreamFormat::Json).unwrap();
+        writer.write_all(b"new").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&new_file).unwrap(), "new");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let json = r#"{
+            "format_version": 16,
+            "num_monitors": 1,
+            "display_server": null,
+            "applications": [{
+                "geom": { "x": -20, "y": 0, "width": 800, "height": 600, "minimized": false },
+                "pid": 10, "stable_seq": 1, "window_class": "kitty", "gtk_app_id": "", "sandboxed_app_id": "",
+                "group_id": null,
+                "exec": { "CmdLine": ["kitty", "--title", "it's \"me\""] },
+                "environ": { "TERM": "xterm-kitty" }
+            }, {
+                "geom": { "x": 0, "y": 0, "width": 1500, "height": 600, "minimized": true },
+                "pid": 11, "stable_seq": 2, "window_class": "steam", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": "Unresolved"
+            }]
+        }"#;
+
+        let mut out = Vec::new();
+        let mut writer = TomlWriter { json: Vec::new(), out: &mut out };
+        writer.write_all(json.as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        let toml = String::from_utf8(out).unwrap();
+        assert!(toml.contains("[[applications]]"), "{toml}");
+
+        let read: Value = serde_json::from_slice(&toml_to_json(&toml).unwrap()).unwrap();
+        let mut expected: Value = serde_json::from_str(json).unwrap();
+        expected.as_object_mut().unwrap().remove("display_server");
+        expected["applications"][0].as_object_mut().unwrap().remove("group_id");
+        assert_eq!(read, expected);
+
+        let session: Session = serde_json::from_value(read).unwrap();
+        assert_eq!(session.applications[1].exec, Exec::Unresolved);
+    }
+
+    #[test]
+    fn toml_errors_are_reported() {
+        let mut out = [0u8; 8];
+        let mut writer = TomlWriter { json: Vec::new(), out: &mut out[..] };
+        writer.write_all(br#"{ "applications": [{ "window_class": "kitty" }] }"#).unwrap();
+        assert!(writer.finish().is_err());
+
+        let mut writer = TomlWriter { json: Vec::new(), out: Vec::new() };
+        writer.write_all(b"[1, null]").unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,1050 @@
+use super::{
+    prefix_map::{self, PathPrefixMap},
+    retry_with_backoff, shell, BlockedCommand, Exec, MissingAppPolicy, RestoreError, RetryPolicy, SessionApplication,
+};
+use crate::{
+    dbus::{self, MetaWindow},
+    desktop_entry::{DesktopEntry, ExecParseError},
+    find_command::{self, index::desktop_file_id},
+};
+use gio::{
+    glib::{Pid, SpawnFlags},
+    prelude::AppLaunchContextExt,
+    AppLaunchContext,
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsString,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LaunchError {
+    #[error("empty command line")]
+    EmptyCmdLine,
+
+    #[error("{0}")]
+    Spawn(#[from] std::io::Error),
+
+    #[error("gio does not consider the desktop entry launchable (e.g. it is hidden or not an application)")]
+    NoDesktopAppInfo,
+
+    #[error("unable to read desktop entry: {0}")]
+    DesktopEntry(#[source] gio::glib::Error),
+
+    #[error("the binary {0:?} of the desktop entry is not installed")]
+    BinaryNotFound(String),
+
+    #[error("gio was unable to launch the desktop entry: {0}")]
+    Gio(#[from] gio::glib::Error),
+
+    #[error("{reason}; launching its Exec line failed as well: {fallback}")]
+    FallbackFailed {
+        reason: Box<LaunchError>,
+        fallback: Box<LaunchError>,
+    },
+
+    #[error("{0}")]
+    InvalidExec(#[from] ExecParseError),
+
+    #[error("no command was saved for the application")]
+    Unresolved,
+
+    #[error("the desktop entry has no Exec line")]
+    NoExec,
+
+    #[error("{0}")]
+    NonUtf8Argument(#[from] shell::NonUtf8Argument),
+
+    #[error("gtk-launch only finds desktop files in the application directories, which {0:?} is not in")]
+    NotInApplicationDirs(PathBuf),
+}
+
+impl LaunchError {
+    /// Whether launching again a moment later may succeed, e.g. because D-Bus activation was refused while many
+    /// applications were launched at once. Missing files and invalid desktop entries are permanent.
+    ///
+    /// Only errors that guarantee that nothing was started are transient, a timed out activation may still start
+    /// the application and launching it again would start it twice.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            LaunchError::Spawn(e) => is_transient_io_error(e),
+            LaunchError::Gio(e) => match e.kind::<gio::IOErrorEnum>() {
+                Some(gio::IOErrorEnum::Busy | gio::IOErrorEnum::WouldBlock | gio::IOErrorEnum::TooManyOpenFiles) => {
+                    true
+                },
+                // errors of D-Bus activation carry the name of the D-Bus error in their message, whatever their domain
+                _ => {
+                    let message = e.to_string();
+                    dbus::REFUSED_ERRORS.iter().any(|name| message.contains(name))
+                },
+            },
+            // it is the Exec line that is launched again, whatever made gio fail
+            LaunchError::FallbackFailed { fallback, .. } => fallback.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+/// Whether spawning a process failed because of a lack of resources that may be freed again soon
+fn is_transient_io_error(e: &io::Error) -> bool {
+    use nix::errno::Errno;
+
+    let transient = [
+        Errno::EAGAIN,
+        Errno::EBUSY,
+        Errno::EINTR,
+        Errno::EMFILE,
+        Errno::ENFILE,
+        Errno::ENOMEM,
+    ];
+
+    match e.raw_os_error() {
+        Some(errno) => transient.contains(&Errno::from_i32(errno)),
+        None => e.kind() == io::ErrorKind::Interrupted,
+    }
+}
+
+/// Additional information about how an application should be launched
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LaunchContext<'a> {
+    /// The working directory for command lines
+    pub cwd: Option<&'a Path>,
+
+    /// Environment variables for command lines,
+    /// only variables that are missing from the current environment are added
+    pub environ: Option<&'a BTreeMap<String, String>>,
+
+    /// The startup notification id to launch the application with
+    pub startup_id: Option<&'a str>,
+
+    /// Request a new instance (or at least a new window) of applications that may already be running,
+    /// only supported for desktop files
+    pub new_instance: bool,
+}
+
+/// What is known about a launched application, used to find the windows it opens
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Launched {
+    /// The pid of the launched process, not available for D-Bus activated applications
+    pub pid: Option<i32>,
+
+    /// The startup notification id the application was launched with
+    pub startup_id: Option<String>,
+
+    /// Why a desktop file could not be launched by gio, if it was launched via its Exec line instead
+    pub fallback_reason: Option<String>,
+
+    /// Why the application could not be launched at all, after retrying transient errors
+    pub error: Option<String>,
+}
+
+pub trait Launcher {
+    fn launch(&self, exec: &Exec, context: &LaunchContext) -> Result<Launched, LaunchError>;
+
+    /// Kills the process of a launch, if it is a child of the launcher that is still running.
+    /// Returns whether it was killed.
+    fn kill(&self, _launched: &Launched) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// The environment variable used to pass startup notification ids to launched applications
+const STARTUP_ID_VAR: &str = "DESKTOP_STARTUP_ID";
+
+/// Desktop actions that open a new window of an already running application
+const NEW_INSTANCE_ACTIONS: &[&str] = &["new-window", "new-empty-window", "new-instance", "NewWindow"];
+
+/// Flags that make applications without such an action open a new window, by executable name
+const NEW_INSTANCE_FLAGS: &[(&str, &str)] = &[
+    ("firefox", "--new-window"),
+    ("chromium", "--new-window"),
+    ("chromium-browser", "--new-window"),
+    ("google-chrome", "--new-window"),
+    ("google-chrome-stable", "--new-window"),
+    ("code", "--new-window"),
+    ("nautilus", "--new-window"),
+    ("gnome-terminal", "--window"),
+];
+
+/// How desktop files are launched
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum DesktopFileBackend {
+    /// Spawn the Exec line of the desktop file
+    Direct,
+
+    /// Launch via gio, falling back to spawning the Exec line if gio fails
+    #[default]
+    Gio,
+
+    /// Launch via `gtk-launch`, which only finds desktop files in the application directories
+    /// and does not report the pid of the application
+    GtkLaunch,
+
+    /// Spawn the Exec line in a transient scope of the user's systemd instance
+    SystemdRun,
+}
+
+/// Launches applications by spawning their command line directly
+/// or via the chosen backend for desktop files
+#[derive(Debug, Default)]
+pub struct SystemLauncher {
+    pub desktop_files: DesktopFileBackend,
+
+    /// Run command lines through the user's login shell, see [`via_login_shell`]
+    pub spawn_via_shell: bool,
+
+    /// The processes spawned for command lines by pid, so that they can be killed without the risk of
+    /// hitting an unrelated process that reused the pid
+    children: RefCell<HashMap<i32, Child>>,
+}
+
+impl SystemLauncher {
+    pub fn new(desktop_files: DesktopFileBackend) -> Self {
+        SystemLauncher {
+            desktop_files,
+            spawn_via_shell: false,
+            children: RefCell::default(),
+        }
+    }
+
+    fn spawn(cmdline: &[OsString], context: &LaunchContext) -> Result<Launched, LaunchError> {
+        let child = Self::spawn_child(cmdline, context)?;
+        Ok(Self::launched(&child, context))
+    }
+
+    fn launched(child: &Child, context: &LaunchContext) -> Launched {
+        Launched {
+            pid: i32::try_from(child.id()).ok(),
+            startup_id: context.startup_id.map(ToOwned::to_owned),
+            fallback_reason: None,
+            error: None,
+        }
+    }
+
+    fn spawn_child(cmdline: &[OsString], context: &LaunchContext) -> Result<Child, LaunchError> {
+        let (program, args) = cmdline.split_first().ok_or(LaunchError::EmptyCmdLine)?;
+
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+
+        if let Some(cwd) = context.cwd.filter(|cwd| cwd.is_dir()) {
+            cmd.current_dir(cwd);
+        }
+
+        // the captured variables may be stale (e.g. from a previous login)
+        if let Some(environ) = context.environ {
+            cmd.envs(environ.iter().filter(|(key, _)| std::env::var_os(key).is_none()));
+        }
+
+        if let Some(startup_id) = context.startup_id {
+            cmd.env(STARTUP_ID_VAR, startup_id);
+        }
+
+        Ok(cmd.spawn()?)
+    }
+
+    fn app_launch_context(context: &LaunchContext) -> AppLaunchContext {
+        let launch_context = AppLaunchContext::new();
+
+        if let Some(startup_id) = context.startup_id {
+            launch_context.setenv(STARTUP_ID_VAR, startup_id);
+        }
+
+        launch_context
+    }
+
+    fn launch_app_info(info: &gio::DesktopAppInfo, context: &LaunchContext) -> Result<Launched, LaunchError> {
+        let launch_context = Self::app_launch_context(context);
+
+        // the callback is not called for D-Bus activated applications
+        let mut pid = None;
+
+        info.launch_uris_as_manager(
+            &[],
+            Some(&launch_context),
+            SpawnFlags::SEARCH_PATH,
+            None,
+            Some(&mut |_: &gio::DesktopAppInfo, p: Pid| pid = Some(p.0)),
+        )?;
+
+        Ok(Launched {
+            pid,
+            startup_id: context.startup_id.map(ToOwned::to_owned),
+            fallback_reason: None,
+            error: None,
+        })
+    }
+
+    /// Launches a new instance of a desktop file via one of its [`NEW_INSTANCE_ACTIONS`] or [`NEW_INSTANCE_FLAGS`],
+    /// returns `None` if neither is available
+    fn launch_new_instance(path: &Path, context: &LaunchContext) -> Option<Result<Launched, LaunchError>> {
+        let info = gio::DesktopAppInfo::from_filename(path)?;
+
+        let action = info
+            .list_actions()
+            .into_iter()
+            .find(|action| NEW_INSTANCE_ACTIONS.contains(&action.as_str()));
+
+        if let Some(action) = action {
+            info.launch_action(&action, Some(&Self::app_launch_context(context)));
+
+            // gio does not report the pid for actions
+            return Some(Ok(Launched {
+                pid: None,
+                startup_id: context.startup_id.map(ToOwned::to_owned),
+                fallback_reason: None,
+                error: None,
+            }));
+        }
+
+        let mut argv = DesktopEntry::load(path).ok()?.argv()?.ok()?;
+
+        let program = Path::new(&argv[0]).file_name()?.to_str()?;
+        let (_, flag) = NEW_INSTANCE_FLAGS.iter().find(|(name, _)| *name == program)?;
+
+        argv.insert(1, (*flag).to_owned());
+
+        let cmdline: Vec<OsString> = argv.into_iter().map(OsString::from).collect();
+        Some(Self::spawn(&cmdline, context))
+    }
+
+    /// The Exec line of a desktop file as a command line
+    fn exec_cmdline(path: &Path) -> Result<Vec<OsString>, LaunchError> {
+        let entry = DesktopEntry::load(path).map_err(LaunchError::DesktopEntry)?;
+
+        if let Some(bin) = entry.missing_binary() {
+            return Err(LaunchError::BinaryNotFound(bin));
+        }
+
+        let argv = entry.argv().ok_or(LaunchError::NoExec)??;
+        Ok(argv.into_iter().map(OsString::from).collect())
+    }
+
+    fn launch_desktop_file(&self, path: &Path, context: &LaunchContext) -> Result<Launched, LaunchError> {
+        if context.new_instance {
+            match Self::launch_new_instance(path, context) {
+                Some(res) => return res,
+                None => eprintln!("no way to start a new instance of {path:?} is known, launching it normally"),
+            }
+        }
+
+        match self.desktop_files {
+            DesktopFileBackend::Direct => Self::spawn(&Self::exec_cmdline(path)?, context),
+            DesktopFileBackend::Gio => Self::launch_via_gio(path, context),
+            DesktopFileBackend::GtkLaunch => {
+                let desktop_id = desktop_file_id(path)
+                    .filter(|_| find_command::is_in_application_dirs(path))
+                    .ok_or_else(|| LaunchError::NotInApplicationDirs(path.to_owned()))?;
+                let launched = Self::spawn(&["gtk-launch".into(), desktop_id.into()], context)?;
+
+                // the pid is the one of gtk-launch, which exits right away
+                Ok(Launched { pid: None, ..launched })
+            },
+            DesktopFileBackend::SystemdRun => {
+                let cmdline: Vec<OsString> = ["systemd-run", "--user", "--scope", "--quiet", "--"]
+                    .into_iter()
+                    .map(OsString::from)
+                    .chain(Self::exec_cmdline(path)?)
+                    .collect();
+
+                // systemd-run executes the command itself with `--scope`, so the pid is the one of the application
+                Self::spawn(&cmdline, context)
+            },
+        }
+    }
+
+    /// Launches a desktop file via gio, falling back to spawning its Exec line directly if gio fails
+    fn launch_via_gio(path: &Path, context: &LaunchContext) -> Result<Launched, LaunchError> {
+        let reason = match gio::DesktopAppInfo::from_filename(path) {
+            Some(info) => match Self::launch_app_info(&info, context) {
+                Ok(launched) => return Ok(launched),
+                Err(e) => e,
+            },
+            None => LaunchError::NoDesktopAppInfo,
+        };
+
+        // gio does not tell why it rejected the entry, so find out here
+        let entry = DesktopEntry::load(path).map_err(LaunchError::DesktopEntry)?;
+
+        if let Some(bin) = entry.missing_binary() {
+            return Err(LaunchError::BinaryNotFound(bin));
+        }
+
+        let fallback = match entry.argv() {
+            Some(Ok(argv)) => {
+                let cmdline: Vec<OsString> = argv.into_iter().map(OsString::from).collect();
+                Self::spawn(&cmdline, context)
+            },
+            Some(Err(e)) => Err(e.into()),
+            None => return Err(reason),
+        };
+
+        match fallback {
+            Ok(launched) => Ok(Launched { fallback_reason: Some(reason.to_string()), ..launched }),
+            Err(fallback) => {
+                Err(LaunchError::FallbackFailed { reason: Box::new(reason), fallback: Box::new(fallback) })
+            },
+        }
+    }
+}
+
+impl Launcher for SystemLauncher {
+    fn launch(&self, exec: &Exec, context: &LaunchContext) -> Result<Launched, LaunchError> {
+        match exec {
+            Exec::CmdLine(cmdline) => {
+                let child = if self.spawn_via_shell {
+                    let shell = std::env::var_os("SHELL").filter(|shell| !shell.is_empty());
+                    Self::spawn_child(&via_login_shell(shell, cmdline)?, context)?
+                } else {
+                    Self::spawn_child(cmdline, context)?
+                };
+
+                let launched = Self::launched(&child, context);
+
+                if let Some(pid) = launched.pid {
+                    self.children.borrow_mut().insert(pid, child);
+                }
+
+                Ok(launched)
+            },
+            Exec::DesktopFile(path) => self.launch_desktop_file(path, context),
+            Exec::Unresolved => Err(LaunchError::Unresolved),
+        }
+    }
+
+    fn kill(&self, launched: &Launched) -> io::Result<bool> {
+        let Some(mut child) = launched.pid.and_then(|pid| self.children.borrow_mut().remove(&pid)) else {
+            return Ok(false);
+        };
+
+        if child.try_wait()?.is_some() {
+            return Ok(false);
+        }
+
+        child.kill()?;
+        child.wait()?;
+        Ok(true)
+    }
+}
+
+/// `cmdline` run by the login shell `shell` (`/bin/sh` if unknown) as `$SHELL -lc 'exec <cmdline>'`,
+/// so that the `PATH` set up in the user's profile applies. The shell is replaced by the command,
+/// which keeps the pid of the launch.
+fn via_login_shell(shell: Option<OsString>, cmdline: &[OsString]) -> Result<Vec<OsString>, LaunchError> {
+    if cmdline.is_empty() {
+        return Err(LaunchError::EmptyCmdLine);
+    }
+
+    let command = format!("exec {}", shell::quote_argv(cmdline)?);
+
+    Ok(vec![
+        shell.unwrap_or_else(|| OsString::from("/bin/sh")),
+        OsString::from("-lc"),
+        OsString::from(command),
+    ])
+}
+
+/// Applies `policy` to the applications whose saved desktop file does not exist anymore,
+/// `find` is used to find a new command for them with [`MissingAppPolicy::Resolve`]
+/// Desktop file paths are remapped first. If a remapped desktop file does not exist, the desktop file is
+/// looked up by the app ids of the window with `find_by_app_id` before `policy` applies.
+pub(super) fn handle_missing_apps<F, E, A>(
+    applications: Vec<SessionApplication>,
+    policy: MissingAppPolicy,
+    path_prefix_map: &[PathPrefixMap],
+    find: F,
+    find_by_app_id: A,
+) -> Result<Vec<SessionApplication>, RestoreError>
+where
+    F: Fn(&MetaWindow) -> Result<Exec, E>,
+    E: std::error::Error,
+    A: Fn(&MetaWindow) -> Option<Exec>,
+{
+    let mut handled = Vec::with_capacity(applications.len());
+
+    for mut app in applications {
+        if let Exec::DesktopFile(path) = &app.exec {
+            if let Some(remapped) = prefix_map::remap(path, path_prefix_map) {
+                if remapped.exists() {
+                    app.exec = Exec::DesktopFile(remapped);
+                } else if let Some(exec) = find_by_app_id(&app.window) {
+                    eprintln!("remapped desktop file '{remapped:?}' does not exist, using {exec:?} instead");
+                    app.exec = exec;
+                }
+            }
+        }
+
+        let path = match &app.exec {
+            Exec::DesktopFile(path) if !path.exists() => path,
+            _ => {
+                handled.push(app);
+                continue;
+            },
+        };
+
+        match policy {
+            MissingAppPolicy::Skip => eprintln!("skipping '{path:?}': desktop file does not exist anymore"),
+            MissingAppPolicy::Resolve => match find(&app.window) {
+                Ok(exec) => {
+                    eprintln!("desktop file '{path:?}' does not exist anymore, using {exec:?} instead");
+                    app.exec = exec;
+                    handled.push(app);
+                },
+                Err(e) => eprintln!(
+                    "skipping '{path:?}': desktop file does not exist anymore and no replacement was found: {e}"
+                ),
+            },
+            MissingAppPolicy::Error => return Err(RestoreError::MissingApp(path.clone())),
+        }
+    }
+
+    Ok(handled)
+}
+
+/// Generates the startup notification id for the `ix`th launched application,
+/// the `_TIME` suffix is the timestamp part of the startup notification spec which is unknown here
+fn startup_id(ix: usize) -> String {
+    format!("gnome-session-restore-{pid}-{ix}_TIME0", pid = std::process::id())
+}
+
+/// Launches the `ix`th application, on failure nothing is known about the launch.
+/// With `new_instance` a new instance is requested if an earlier application has the same command.
+pub(super) fn launch_application<L: Launcher>(
+    launcher: &L,
+    applications: &[SessionApplication],
+    ix: usize,
+    new_instance: bool,
+    retry: RetryPolicy,
+) -> Launched {
+    let app = &applications[ix];
+
+    if app.exec == Exec::Unresolved {
+        match &app.blocked {
+            Some(BlockedCommand { binary, .. }) => eprintln!(
+                "not launching '{name}': the command line of `{binary}` was not allowed to be saved, its window is placed if it is already open",
+                name = app.name()
+            ),
+            None => eprintln!(
+                "not launching '{name}': no command was saved, its window is placed if it is already open",
+                name = app.name()
+            ),
+        }
+        return Launched::default();
+    }
+
+    // the window is matched by pid and class instead, it never carries the id
+    let startup_notify = match &app.exec {
+        Exec::DesktopFile(path) => DesktopEntry::load(path).map_or(true, |entry| entry.startup_notify != Some(false)),
+        _ => true,
+    };
+
+    let startup_id = startup_id(ix);
+    let context = LaunchContext {
+        cwd: app.cwd.as_deref(),
+        environ: app.environ.as_ref(),
+        startup_id: startup_notify.then_some(startup_id.as_str()),
+        new_instance: new_instance && applications[..ix].iter().any(|other| other.exec == app.exec),
+    };
+
+    if app.redacted {
+        eprintln!(
+            "secrets were redacted from the command line of '{name}' when saving, it may not launch correctly",
+            name = app.name()
+        );
+    }
+
+    let name = app.name();
+    let mut attempts = 0;
+    let launch = || {
+        attempts += 1;
+        launcher.launch(&app.exec, &context)
+    };
+
+    let result = retry_with_backoff(
+        retry,
+        &format!("launch '{name}'"),
+        launch,
+        LaunchError::is_transient,
+        |_| true,
+        std::thread::sleep,
+    );
+
+    match result {
+        Ok(launched) => {
+            if let (Some(reason), Exec::DesktopFile(path)) = (&launched.fallback_reason, &app.exec) {
+                eprintln!("launched '{name}' ({path:?}) via its Exec line instead: {reason}");
+            }
+
+            if attempts > 1 {
+                eprintln!("launched '{name}' after {attempts} attempts");
+            }
+
+            launched
+        },
+        Err(e) => {
+            let gave_up = match attempts {
+                1 => String::new(),
+                n => format!(" (gave up after {n} attempts)"),
+            };
+
+            match &app.exec {
+                Exec::CmdLine(cmdline) => {
+                    eprintln!("Error spawning process '{cmdline:?}'{gave_up}: {e}")
+                },
+                Exec::DesktopFile(path) => {
+                    eprintln!("Error spawning '{name}' ({path:?}){gave_up}: {e}")
+                },
+                Exec::Unresolved => eprintln!("Error spawning '{name}'{gave_up}: {e}"),
+            }
+
+            Launched { error: Some(e.to_string()), ..Launched::default() }
+        },
+    }
+}
+
+/// Whether launching the `ix`th application would only focus the window of an earlier or `open` application,
+/// as they share a desktop file that declares `SingleMainWindow`, which is printed
+pub(super) fn focuses_single_main_window(applications: &[SessionApplication], ix: usize, open: &HashSet<usize>) -> bool {
+    let app = &applications[ix];
+
+    let Exec::DesktopFile(path) = &app.exec else {
+        return false;
+    };
+
+    let shared = applications[..ix].iter().any(|other| other.exec == app.exec)
+        || open
+            .iter()
+            .any(|&other| other != ix && applications[other].exec == app.exec);
+
+    if shared && DesktopEntry::load(path).is_ok_and(|entry| entry.single_main_window) {
+        eprintln!(
+            "not launching '{name}' again: {path:?} has a single main window, which is already open or launching",
+            name = app.name()
+        );
+        return true;
+    }
+
+    false
+}
+
+/// Launches the applications in `ixs`, returning what is known about each launch in the same order
+pub(super) fn launch_applications<L: Launcher>(
+    launcher: &L,
+    applications: &[SessionApplication],
+    ixs: Range<usize>,
+    new_instance: bool,
+    retry: RetryPolicy,
+) -> Vec<Launched> {
+    ixs.map(|ix| {
+        if focuses_single_main_window(applications, ix, &HashSet::new()) {
+            Launched::default()
+        } else {
+            launch_application(launcher, applications, ix, new_instance, retry)
+        }
+    })
+    .collect()
+}
+
+/// Launches the applications of `stage` that are not `open` already, nor would only focus the window of another
+/// application (see [`focuses_single_main_window`]), returning the launched ones.
+/// A launch is recorded in `launches` for every application of the stage, so that they stay indexed like the applications.
+pub(super) fn launch_stage<L: Launcher>(
+    launcher: &L,
+    applications: &[SessionApplication],
+    stage: Range<usize>,
+    open: &HashSet<usize>,
+    new_instance: bool,
+    retry: RetryPolicy,
+    launches: &mut Vec<Launched>,
+) -> Vec<usize> {
+    let mut launched = Vec::new();
+
+    for ix in stage {
+        if open.contains(&ix) || focuses_single_main_window(applications, ix, open) {
+            launches.push(Launched::default());
+        } else {
+            launches.push(launch_application(launcher, applications, ix, new_instance, retry));
+            launched.push(ix);
+        }
+    }
+
+    launched
+}
+
+/// Kills the processes of the `missing` applications, whose windows did not appear in time,
+/// as far as `launcher` spawned them itself
+pub(super) fn kill_windowless<L: Launcher>(
+    launcher: &L,
+    applications: &[SessionApplication],
+    launches: &[Launched],
+    missing: Vec<usize>,
+) {
+    for ix in missing {
+        let Some(launched) = launches.get(ix) else {
+            continue;
+        };
+
+        match launcher.kill(launched) {
+            Ok(true) => eprintln!(
+                "killed '{name}' (pid {pid}): its window did not appear in time",
+                name = applications[ix].name(),
+                pid = launched.pid.unwrap_or_default()
+            ),
+            Ok(false) => {},
+            Err(e) => eprintln!("unable to kill '{name}': {e}", name = applications[ix].name()),
+        }
+    }
+}
+
+/// A launcher that does not launch anything but records what it was asked to launch
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingLauncher {
+    pub launched: std::cell::RefCell<Vec<Exec>>,
+
+    /// Whether a new instance was requested, for every launch
+    pub new_instance: std::cell::RefCell<Vec<bool>>,
+
+    /// The startup notification id, for every launch
+    pub startup_id: std::cell::RefCell<Vec<Option<String>>>,
+}
+
+#[cfg(test)]
+impl Launcher for RecordingLauncher {
+    fn launch(&self, exec: &Exec, context: &LaunchContext) -> Result<Launched, LaunchError> {
+        self.launched.borrow_mut().push(exec.clone());
+        self.new_instance.borrow_mut().push(context.new_instance);
+        self.startup_id
+            .borrow_mut()
+            .push(context.startup_id.map(ToOwned::to_owned));
+        Ok(Launched::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        handle_missing_apps, launch_application, launch_applications, launch_stage, via_login_shell, Exec,
+        LaunchContext, LaunchError, Launched, Launcher, RecordingLauncher, SystemLauncher,
+    };
+    use crate::{
+        dbus::MetaWindow,
+        session::{
+            read_session,
+            tests::{NO_RETRY, SESSION},
+            MissingAppPolicy, RestoreError, RestoreReport, RetryPolicy, SessionApplication, Session,
+        },
+    };
+    use std::{
+        cell::RefCell, collections::HashSet, ffi::OsString, os::unix::ffi::OsStringExt, path::PathBuf, time::Duration,
+    };
+
+    #[test]
+    fn kills_spawned_children() {
+        let launcher = SystemLauncher::default();
+        let context = LaunchContext::default();
+
+        let running = launcher
+            .launch(&Exec::CmdLine(vec!["sleep".into(), "10".into()]), &context)
+            .unwrap();
+        assert!(launcher.kill(&running).unwrap());
+        assert!(!launcher.kill(&running).unwrap());
+
+        let exited = launcher.launch(&Exec::CmdLine(vec!["true".into()]), &context).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!launcher.kill(&exited).unwrap());
+    }
+
+    #[test]
+    fn transient_errors() {
+        let spawn = |errno: nix::errno::Errno| LaunchError::Spawn(std::io::Error::from_raw_os_error(errno as i32));
+        let gio = |code, message| LaunchError::Gio(gio::glib::Error::new(code, message));
+
+        assert!(spawn(nix::errno::Errno::EAGAIN).is_transient());
+        assert!(!spawn(nix::errno::Errno::ENOENT).is_transient());
+        assert!(!spawn(nix::errno::Errno::EACCES).is_transient());
+        assert!(!LaunchError::BinaryNotFound("firefox".to_owned()).is_transient());
+
+        assert!(gio(gio::IOErrorEnum::Busy, "busy").is_transient());
+        assert!(gio(
+            gio::IOErrorEnum::DbusError,
+            "GDBus.Error:org.freedesktop.DBus.Error.LimitsExceeded: too many pending activations"
+        )
+        .is_transient());
+        assert!(!gio(gio::IOErrorEnum::NotFound, "no such file").is_transient());
+
+        // the application may have been started anyway
+        assert!(!gio(gio::IOErrorEnum::TimedOut, "timed out").is_transient());
+        assert!(!gio(
+            gio::IOErrorEnum::DbusError,
+            "GDBus.Error:org.freedesktop.DBus.Error.NoReply: did not receive a reply"
+        )
+        .is_transient());
+
+        let fallback_failed = |fallback| LaunchError::FallbackFailed {
+            reason: Box::new(gio(gio::IOErrorEnum::Busy, "busy")),
+            fallback: Box::new(fallback),
+        };
+        assert!(fallback_failed(spawn(nix::errno::Errno::EAGAIN)).is_transient());
+        assert!(!fallback_failed(spawn(nix::errno::Errno::ENOENT)).is_transient());
+    }
+
+    #[test]
+    fn spawns_via_login_shell() {
+        let cmdline = ["printf", "%s", "it's $HOME"].map(OsString::from);
+
+        assert_eq!(
+            via_login_shell(Some("/usr/bin/zsh".into()), &cmdline).unwrap(),
+            ["/usr/bin/zsh", "-lc", r"exec 'printf' '%s' 'it'\''s $HOME'"].map(OsString::from)
+        );
+        assert_eq!(via_login_shell(None, &cmdline).unwrap()[0], "/bin/sh");
+
+        assert!(matches!(via_login_shell(None, &[]), Err(LaunchError::EmptyCmdLine)));
+        assert!(matches!(
+            via_login_shell(None, &[OsString::from_vec(b"\xff".to_vec())]),
+            Err(LaunchError::NonUtf8Argument(_))
+        ));
+
+        let launcher = SystemLauncher { spawn_via_shell: true, ..SystemLauncher::default() };
+        let running = launcher
+            .launch(
+                &Exec::CmdLine(vec!["sleep".into(), "10".into()]),
+                &LaunchContext::default(),
+            )
+            .unwrap();
+
+        // the shell execs the command, so killing the launch kills it
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(launcher.kill(&running).unwrap());
+    }
+
+    #[test]
+    fn handles_missing_apps() {
+        let apps = || {
+            let sess: Session = serde_json::from_str(
+                r#"{
+                    "num_monitors": 1,
+                    "applications": [
+                        {
+                            "geom": { "x": 0, "y": 0, "width": 800, "height": 600, "minimized": false },
+                            "pid": 10, "stable_seq": 1, "window_class": "kitty", "gtk_app_id": "", "sandboxed_app_id": "",
+                            "exec": { "CmdLine": ["kitty"] }
+                        },
+                        {
+                            "geom": { "x": 800, "y": 0, "width": 800, "height": 600, "minimized": false },
+                            "pid": 11, "stable_seq": 2, "window_class": "moved", "gtk_app_id": "", "sandboxed_app_id": "",
+                            "exec": { "DesktopFile": "/nonexistent/moved.desktop" }
+                        }
+                    ]
+                }"#,
+            )
+            .unwrap();
+
+            sess.applications
+        };
+
+        let find = |w: &MetaWindow| -> Result<Exec, std::fmt::Error> {
+            Ok(Exec::DesktopFile(PathBuf::from(format!(
+                "/usr/share/applications/{}.desktop",
+                w.window_class
+            ))))
+        };
+
+        let execs = |apps: Vec<SessionApplication>| apps.into_iter().map(|app| app.exec).collect::<Vec<_>>();
+        let no_app_id = |_: &MetaWindow| None;
+
+        let skipped = handle_missing_apps(apps(), MissingAppPolicy::Skip, &[], find, no_app_id).unwrap();
+        assert_eq!(execs(skipped), vec![Exec::CmdLine(vec!["kitty".into()])]);
+
+        let resolved = handle_missing_apps(apps(), MissingAppPolicy::Resolve, &[], find, no_app_id).unwrap();
+        assert_eq!(
+            execs(resolved),
+            vec![
+                Exec::CmdLine(vec!["kitty".into()]),
+                Exec::DesktopFile(PathBuf::from("/usr/share/applications/moved.desktop"))
+            ]
+        );
+
+        assert!(matches!(
+            handle_missing_apps(apps(), MissingAppPolicy::Error, &[], find, no_app_id),
+            Err(RestoreError::MissingApp(_))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-prefix-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("moved.desktop"), "").unwrap();
+        std::fs::write(dir.join("by-app-id.desktop"), "").unwrap();
+
+        let to_dir = [format!("/nonexistent={}", dir.display()).parse().unwrap()];
+        let remapped = handle_missing_apps(apps(), MissingAppPolicy::Error, &to_dir, find, no_app_id).unwrap();
+        assert_eq!(execs(remapped)[1], Exec::DesktopFile(dir.join("moved.desktop")));
+
+        // a remapped desktop file that does not exist either is looked up by app id
+        let elsewhere = ["/nonexistent=/elsewhere".parse().unwrap()];
+        let by_app_id = |_: &MetaWindow| Some(Exec::DesktopFile(dir.join("by-app-id.desktop")));
+        let resolved = handle_missing_apps(apps(), MissingAppPolicy::Error, &elsewhere, find, by_app_id).unwrap();
+        assert_eq!(execs(resolved)[1], Exec::DesktopFile(dir.join("by-app-id.desktop")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn launches_deduplicated_applications() {
+        let sess = read_session(SESSION.as_bytes(), true).unwrap();
+        let launcher = RecordingLauncher::default();
+
+        launch_applications(
+            &launcher,
+            &sess.applications,
+            0..sess.applications.len(),
+            false,
+            NO_RETRY,
+        );
+
+        assert_eq!(
+            launcher.launched.into_inner(),
+            vec![
+                Exec::DesktopFile(PathBuf::from("/usr/share/applications/firefox.desktop")),
+                Exec::CmdLine(vec!["kitty".into(), "--single-instance".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn launches_only_applications_without_window() {
+        let sess = read_session(SESSION.as_bytes(), false).unwrap();
+        let launcher = RecordingLauncher::default();
+        let mut launches = Vec::new();
+
+        let open = [0].into_iter().collect();
+        let launched = launch_stage(
+            &launcher,
+            &sess.applications,
+            0..3,
+            &open,
+            false,
+            NO_RETRY,
+            &mut launches,
+        );
+
+        assert_eq!(launched, vec![1, 2]);
+        assert_eq!(launcher.launched.into_inner().len(), 2);
+        // still indexed like the applications
+        assert_eq!(launches.len(), 3);
+    }
+
+    #[test]
+    fn retries_transient_launch_errors() {
+        /// Fails with the queued errors before launching
+        struct FlakyLauncher(RefCell<Vec<LaunchError>>);
+
+        impl Launcher for FlakyLauncher {
+            fn launch(&self, _: &Exec, _: &LaunchContext) -> Result<Launched, LaunchError> {
+                self.0.borrow_mut().pop().map_or(Ok(Launched::default()), Err)
+            }
+        }
+
+        let busy = || LaunchError::Spawn(std::io::Error::from_raw_os_error(nix::errno::Errno::EAGAIN as i32));
+        let retry = RetryPolicy { retries: 2, initial_backoff: Duration::ZERO };
+        let applications = read_session(SESSION.as_bytes(), false).unwrap().applications;
+
+        let launcher = FlakyLauncher(RefCell::new(vec![busy(), busy()]));
+        let launched = launch_application(&launcher, &applications, 0, false, retry);
+        assert_eq!(launched.error, None);
+
+        let launcher = FlakyLauncher(RefCell::new(vec![busy(), busy(), busy()]));
+        let given_up = launch_application(&launcher, &applications, 0, false, retry);
+        assert!(given_up.error.is_some());
+
+        // missing binaries are not retried
+        let not_found = LaunchError::Spawn(std::io::ErrorKind::NotFound.into());
+        let launcher = FlakyLauncher(RefCell::new(vec![busy(), not_found]));
+        let failed = launch_application(&launcher, &applications, 0, false, retry);
+        assert!(failed.error.is_some());
+        assert_eq!(launcher.0.into_inner().len(), 1);
+
+        let mut report = RestoreReport::default();
+        report.record_launched(&applications, &[0, 1], &[failed, launched]);
+        assert_eq!(
+            (report.launched, report.failed),
+            (vec!["firefox".to_owned()], vec!["kitty".to_owned()])
+        );
+    }
+
+    #[test]
+    fn launches_new_instances_of_repeated_applications() {
+        let sess = read_session(SESSION.as_bytes(), false).unwrap();
+        let launcher = RecordingLauncher::default();
+
+        launch_applications(
+            &launcher,
+            &sess.applications,
+            0..sess.applications.len(),
+            true,
+            NO_RETRY,
+        );
+
+        assert_eq!(launcher.launched.into_inner().len(), 3);
+        assert_eq!(launcher.new_instance.into_inner(), vec![false, false, true]);
+    }
+
+    #[test]
+    fn respects_desktop_entry_launch_hints() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-hints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let firefox = dir.join("firefox.desktop");
+        std::fs::write(
+            &firefox,
+            "[Desktop Entry]\nType=Application\nName=Firefox\nExec=true\nSingleMainWindow=true\nStartupNotify=false\n",
+        )
+        .unwrap();
+
+        let mut sess: serde_json::Value = serde_json::from_str(SESSION).unwrap();
+        for app in [1, 2] {
+            sess["applications"][app]["exec"] = serde_json::json!({ "DesktopFile": firefox });
+        }
+        let applications = read_session(sess.to_string().as_bytes(), false).unwrap().applications;
+
+        // the second firefox window would only focus the first one
+        let launcher = RecordingLauncher::default();
+        let mut launches = Vec::new();
+        let launched = launch_stage(
+            &launcher,
+            &applications,
+            0..3,
+            &HashSet::new(),
+            true,
+            NO_RETRY,
+            &mut launches,
+        );
+        assert_eq!(launched, [0, 1]);
+        assert_eq!(launches.len(), 3);
+
+        // firefox never carries a startup notification id
+        let startup_ids = launcher.startup_id.into_inner();
+        assert!(startup_ids[0].is_some());
+        assert_eq!(startup_ids[1], None);
+
+        // nor is it launched while the window of another firefox is open
+        let launcher = RecordingLauncher::default();
+        let launched = launch_stage(
+            &launcher,
+            &applications,
+            0..3,
+            &HashSet::from([1]),
+            true,
+            NO_RETRY,
+            &mut Vec::new(),
+        );
+        assert_eq!(launched, [0]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,33 +1,108 @@
-use crate::dbus::{MetaWindow, WindowCtlProxy};
-use gio::{prelude::AppInfoExt, AppLaunchContext};
-use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
+use crate::{
+    dbus::{self, MetaWindow, Rect, WindowCtlProxy, WindowGeom},
+    desktop_entry::DesktopEntry,
+    find_command::{
+        flatpak::{self, FlatpakScope},
+        methods, FindError,
+    },
+};
+use serde::{
+    ser::{Error as _, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
+    collections::{BTreeMap, HashSet},
     ffi::OsString,
     io::{Read, Write},
-    path::PathBuf,
-    process::Command,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
     time::Duration,
 };
 use thiserror::Error;
 
-pub use crate::find_command::{Capability, Confidence, FindOptions};
+pub mod age;
+pub mod compat;
+pub mod display;
+pub mod edit;
+pub mod fallback;
+pub mod filter;
+pub mod format;
+pub mod inhibit;
+pub mod io;
+pub mod launch;
+pub mod order;
+pub mod origin;
+pub mod placement;
+pub mod prefix_map;
+pub mod redact;
+pub mod relative;
+pub mod scale;
+pub mod script;
+pub mod shell;
+pub mod state_hooks;
+pub mod transform;
+pub mod wait;
+
+pub use crate::find_command::{Capabilities, Capability, Confidence, FindOptions};
+pub use display::{CrossSessionType, DisplayServer};
+pub use fallback::{MonitorFallback, MonitorMapping};
+pub use filter::{ExcludePattern, WindowFilter};
+pub use format::CURRENT_FORMAT_VERSION;
+use launch::{
+    focuses_single_main_window, handle_missing_apps, kill_windowless, launch_application, launch_applications,
+    launch_stage,
+};
+pub use launch::{DesktopFileBackend, Launched, Launcher, SystemLauncher};
+use order::sort_into_stages;
+use origin::Origin;
+use placement::{open_applications, stagger_coincident, Placer};
+pub use prefix_map::PathPrefixMap;
+pub use redact::Redactor;
+use relative::RelativeGeom;
+use state_hooks::ExtraState;
+pub use wait::{WaitRule, WaitRules};
 
 fn utf8_ser<S: Serializer>(x: &[OsString], s: S) -> Result<S::Ok, S::Error> {
     let mut seq = s.serialize_seq(Some(x.len()))?;
 
-    let itr = x.iter().map(|osstr| osstr.to_str().unwrap());
+    for osstr in x {
+        let item = osstr.to_str().ok_or_else(|| {
+            S::Error::custom(format!(
+                "command line argument {osstr:?} is not valid utf-8, save with `--lossy-cmdline` to replace it"
+            ))
+        })?;
 
-    for item in itr {
         seq.serialize_element(item)?;
     }
 
     seq.end()
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Replaces invalid utf-8 in `cmdline` with U+FFFD, so that it can be serialized, returns whether anything was replaced
+fn make_lossy(cmdline: &mut [OsString]) -> bool {
+    let mut replaced = false;
+
+    for arg in cmdline.iter_mut().filter(|arg| arg.to_str().is_none()) {
+        *arg = arg.to_string_lossy().into_owned().into();
+        replaced = true;
+    }
+
+    replaced
+}
+
+fn utf8_de<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<OsString>, D::Error> {
+    let v = Vec::<String>::deserialize(d)?;
+    Ok(v.into_iter().map(OsString::from).collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Exec {
-    CmdLine(#[serde(serialize_with = "utf8_ser")] Vec<OsString>),
+    CmdLine(#[serde(serialize_with = "utf8_ser", deserialize_with = "utf8_de")] Vec<OsString>),
     DesktopFile(PathBuf),
+
+    /// No command was found when saving, the window is only placed if it is already open
+    Unresolved,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -35,17 +110,187 @@ struct SessionApplication {
     #[serde(flatten)]
     window: MetaWindow,
     exec: Exec,
+
+    /// The working directory of the process, only captured with [`Capability::ReadProcCwd`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cwd: Option<PathBuf>,
+
+    /// The environment of the process, only captured with [`Capability::ReadProcEnviron`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environ: Option<BTreeMap<String, String>>,
+
+    /// Whether secrets were redacted from the command line, which may keep it from launching correctly
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    redacted: bool,
+
+    /// The name of the application from its desktop file, not available for command lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+
+    /// Disabled applications are kept in the session, but not restored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    disabled: bool,
+
+    /// Applications with a higher priority are launched before the others of their stage, see [`order`]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: i32,
+
+    /// The window classes or app ids of applications that have to be launched before this one, see [`order`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    after: Vec<String>,
+
+    /// Window attributes beyond the geometry, see [`state_hooks`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    extra_state: ExtraState,
+
+    /// The geometry as fractions of the monitor's work area, only saved on request, see [`relative`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relative: Option<RelativeGeom>,
+
+    /// Why the command line that was found for the window could not be saved, for applications that were saved with
+    /// [`Exec::Unresolved`] because the policy does not allow using command lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blocked: Option<BlockedCommand>,
+}
+
+/// A command line that was found for a window, but not allowed to be saved, see `--procfs-use-command-policy`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockedCommand {
+    /// The [`FindError::kind`] of why no command was saved, e.g. `proc-command-not-allowed`
+    pub reason: String,
+
+    /// The file name of the binary of the command line, its arguments are never saved
+    pub binary: String,
+}
+
+fn is_zero(x: &i32) -> bool {
+    *x == 0
+}
+
+impl SessionApplication {
+    /// A human readable name for the application
+    fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.window.window_class)
+    }
+}
+
+fn display_name(exec: &Exec) -> Option<String> {
+    match exec {
+        Exec::DesktopFile(path) => DesktopEntry::load(path).ok()?.name,
+        Exec::CmdLine(_) | Exec::Unresolved => None,
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Session {
+    #[serde(default = "format::legacy_format_version")]
+    format_version: u32,
     applications: Vec<SessionApplication>,
     num_monitors: u32,
+
+    /// The work areas of the monitors, only reported by newer versions of the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monitors: Option<Vec<Rect>>,
+
+    /// The scale factors of the monitors, indexed like `monitors`, only reported by newer versions of the extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monitor_scales: Option<Vec<f64>>,
+
+    /// The display server the session was saved under, if it was known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display_server: Option<DisplayServer>,
+
+    /// The version of gnome-shell the session was saved under, if it was known, see [`compat`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shell_version: Option<String>,
+
+    /// The user and host the session was saved by, if they were known, see [`origin`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<Origin>,
+
+    /// What the session was allowed to capture when it was saved, to audit e.g. that it holds no environments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    capabilities: Option<Capabilities>,
+}
+
+impl Session {
+    /// Whether the geometry of the windows means anything, sessions saved without monitors
+    /// (e.g. from a headless shell) cannot be compared to the current monitors or placed
+    fn has_geometry(&self) -> bool {
+        self.num_monitors > 0
+    }
 }
 
+/// Removes all but one application per window class, disabled applications are never merged with enabled ones
 fn dedup_applications(sess: &mut Vec<SessionApplication>) {
-    sess.sort_by(|app1, app2| app1.window.window_class.cmp(&app2.window.window_class));
-    sess.dedup_by(|app1, app2| app1.window.window_class == app2.window.window_class);
+    sess.sort_by(|app1, app2| {
+        (&app1.window.window_class, app1.disabled).cmp(&(&app2.window.window_class, app2.disabled))
+    });
+    sess.dedup_by(|app1, app2| app1.window.window_class == app2.window.window_class && app1.disabled == app2.disabled);
+}
+
+/// Finds the groups (e.g. of tabbed windows) that consist of more than one window
+fn window_groups<'w, I>(windows: I) -> BTreeMap<u64, Vec<&'w MetaWindow>>
+where
+    I: IntoIterator<Item = &'w MetaWindow>,
+{
+    let mut groups: BTreeMap<u64, Vec<&MetaWindow>> = BTreeMap::new();
+
+    for w in windows {
+        if let Some(group_id) = w.group_id {
+            groups.entry(group_id).or_default().push(w);
+        }
+    }
+
+    groups.retain(|_, windows| windows.len() > 1);
+    groups
+}
+
+fn warn_about_window_groups(applications: &[SessionApplication]) {
+    for (group_id, windows) in window_groups(applications.iter().map(|app| &app.window)) {
+        let classes: Vec<_> = windows.iter().map(|w| w.window_class.as_str()).collect();
+
+        eprintln!(
+            "windows {classes:?} are grouped (group {group_id}); grouping and tabs are not restored, \
+             the group is restored as a single window"
+        );
+    }
+}
+
+/// Finds the desktop files that the windows of more than one window class were resolved to,
+/// along with the window classes and the confidence of their matches
+fn shared_desktop_files<'a, I>(found: I) -> BTreeMap<&'a Path, Vec<(&'a str, Confidence)>>
+where
+    I: IntoIterator<Item = (&'a SessionApplication, Confidence)>,
+{
+    let mut shared: BTreeMap<&Path, Vec<(&str, Confidence)>> = BTreeMap::new();
+
+    for (app, confidence) in found {
+        if let Exec::DesktopFile(path) = &app.exec {
+            let classes = shared.entry(path).or_default();
+
+            if !classes.iter().any(|(class, _)| *class == app.window.window_class) {
+                classes.push((&app.window.window_class, confidence));
+            }
+        }
+    }
+
+    shared.retain(|_, classes| classes.len() > 1);
+    shared
+}
+
+fn warn_about_shared_desktop_files(applications: &[SessionApplication], confidences: &[Confidence]) {
+    for (path, classes) in shared_desktop_files(applications.iter().zip(confidences.iter().copied())) {
+        let classes: Vec<_> = classes
+            .iter()
+            .map(|(class, confidence)| format!("'{class}' ({confidence:.2})"))
+            .collect();
+
+        eprintln!(
+            "the windows of {classes} were all matched to {path:?}, some of them are likely mismatched",
+            classes = classes.join(", ")
+        );
+    }
 }
 
 #[derive(Debug, Error)]
@@ -55,82 +300,1718 @@ pub enum SaveError {
 
     #[error("serialization error {0}")]
     Serialization(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Format(#[from] format::FormatError),
+
+    #[error("io error {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Transform(#[from] transform::TransformError),
+
+    #[error(
+        "no applications were found, not replacing the saved session with an empty one (use `--allow-empty` to do so)"
+    )]
+    WouldEmptySession,
+
+    #[error("no applications were found, not saving an empty session (use `--allow-empty` to do so)")]
+    NoApplications,
+
+    #[error("the shell reports no monitors (e.g. because it runs headless), not saving a session without geometry (use `--allow-empty` to do so)")]
+    NoMonitors,
+}
+
+/// A window for which no command was found, with everything it can be identified by
+#[derive(Debug, Serialize)]
+pub struct UnresolvedWindow {
+    pub window_class: String,
+    pub gtk_app_id: String,
+    pub sandboxed_app_id: String,
+    pub title: String,
+    pub pid: i32,
+
+    /// Why no command was found
+    pub reason: String,
+
+    /// An `--alias` for the window, to be completed with the name of its desktop file
+    pub alias: String,
+}
+
+impl UnresolvedWindow {
+    fn new(window: &MetaWindow, reason: String) -> Self {
+        UnresolvedWindow {
+            window_class: window.window_class.clone(),
+            gtk_app_id: window.gtk_app_id.clone(),
+            sandboxed_app_id: window.sandboxed_app_id.clone(),
+            title: window.title.clone(),
+            pid: window.pid,
+            reason,
+            alias: format!("{}=", window.window_class),
+        }
+    }
 }
 
-pub type RestoreError = serde_json::Error;
+#[derive(Debug, Copy, Clone)]
+pub struct SaveOptions<'r> {
+    /// The session file format version to write
+    pub format_version: u32,
+
+    /// What may be looked at to find commands and capture the state of processes, recorded in the session
+    pub capabilities: Capabilities,
+
+    /// Used to redact secrets from command lines, if any
+    pub redactor: Option<&'r Redactor>,
+
+    /// Record windows for which no command was found with [`Exec::Unresolved`] instead of dropping them
+    pub include_unmatched: bool,
+
+    /// Which windows are not saved
+    pub filter: &'r WindowFilter,
+
+    /// Replace invalid utf-8 in command lines instead of failing to save, which may keep them from launching
+    pub lossy_cmdline: bool,
+
+    /// Whether the session that is replaced contains applications, in which case an empty window list is
+    /// retried and, unless [`SaveOptions::allow_empty`] is given, never saved
+    pub replaces_non_empty: bool,
+
+    /// Save an empty session even if it replaces one with applications
+    pub allow_empty: bool,
+
+    /// How to retry listing the windows when the shell is briefly unavailable
+    pub retry: RetryPolicy,
+
+    /// The display server the session is saved under, recorded to detect restores under a different one
+    pub display_server: Option<DisplayServer>,
+
+    /// The version of gnome-shell the session is saved under, recorded to warn about restores under a different one
+    pub shell_version: Option<&'r str>,
+
+    /// Also save the theme variant (dark or light) of windows that report one, it is not restored
+    pub capture_theme_variant: bool,
+
+    /// Also save the geometry of windows relative to their monitor's work area, see [`relative`]
+    pub relative_geometry: bool,
+
+    /// A program to pipe the session through before it is written, see [`transform`]
+    pub transform_cmd: Option<&'r Path>,
+}
 
-pub fn save<W: Write, F, E>(conn: &WindowCtlProxy, writer: W, find: F) -> Result<(), SaveError>
+/// How often to try again, waiting `initial_backoff` before the first retry and twice as long before every next one
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub initial_backoff: Duration,
+}
+
+/// Calls `attempt` until it returns a result `done` accepts or an error that is not `transient`,
+/// at most `policy.retries` more times, and returns its last result. `what` is printed with every retry.
+fn retry_with_backoff<T, E, A, D, S>(
+    policy: RetryPolicy,
+    what: &str,
+    mut attempt: A,
+    transient: impl Fn(&E) -> bool,
+    done: D,
+    mut sleep: S,
+) -> Result<T, E>
 where
-    F: Fn(&MetaWindow) -> Result<Exec, E>,
-    E: std::error::Error,
+    A: FnMut() -> Result<T, E>,
+    D: Fn(&T) -> bool,
+    S: FnMut(Duration),
+    E: std::fmt::Display,
+{
+    let mut backoff = policy.initial_backoff;
+
+    for _ in 0..policy.retries {
+        match attempt() {
+            Ok(x) if !done(&x) => {
+                eprintln!("nothing was returned when trying to {what}, retrying in {backoff:?}")
+            },
+            Err(e) if transient(&e) => eprintln!("unable to {what}, retrying in {backoff:?}: {e}"),
+            res => return res,
+        }
+
+        sleep(backoff);
+        backoff = backoff.saturating_mul(2);
+    }
+
+    attempt()
+}
+
+/// Refuses to save a session without applications, which would only be restored to nothing, unless allowed
+fn check_not_emptied(applications: &[SessionApplication], options: &SaveOptions) -> Result<(), SaveError> {
+    match applications.is_empty() && !options.allow_empty {
+        true if options.replaces_non_empty => Err(SaveError::WouldEmptySession),
+        true => Err(SaveError::NoApplications),
+        false => Ok(()),
+    }
+}
+
+/// Refuses to save a session without monitors (e.g. from a headless shell), whose geometry means nothing, unless allowed
+fn check_has_monitors(num_monitors: u32, options: &SaveOptions) -> Result<(), SaveError> {
+    if num_monitors == 0 && !options.allow_empty {
+        Err(SaveError::NoMonitors)
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` is a session file that can be read
+pub fn is_session_file(path: &Path) -> bool {
+    io::open_reader(path)
+        .ok()
+        .is_some_and(|rdr| serde_json::from_reader::<_, Session>(rdr).is_ok())
+}
+
+/// Whether the session file at `path` contains any applications, `false` if it cannot be read
+pub fn has_applications(path: &Path) -> bool {
+    #[derive(Deserialize)]
+    struct Applications {
+        applications: Vec<SessionApplication>,
+    }
+
+    io::open_reader(path)
+        .ok()
+        .and_then(|rdr| serde_json::from_reader::<_, Applications>(rdr).ok())
+        .is_some_and(|sess| !sess.applications.is_empty())
+}
+
+/// How to wait for launched applications to open their windows before placing them
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SettleStrategy {
+    /// Launch everything, sleep for the given duration and place all windows that are open by then
+    Fixed(Duration),
+
+    /// Launch everything and place windows as they appear
+    Poll,
+
+    /// Launch applications one by one, waiting for the windows of each before launching the next
+    PerApp,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RestoreOptions<'r> {
+    pub settle_strategy: SettleStrategy,
+
+    /// How long to keep trying to find the windows of launched applications,
+    /// with [`SettleStrategy::PerApp`] this applies to each application separately
+    pub wait_timeout: Duration,
+
+    /// How long to wait for the window of any single application at most, counted from when it is first looked for.
+    /// Applications whose window did not appear by then are given up on and reported as timed out,
+    /// so that one of them does not hold up a whole stage until [`RestoreOptions::wait_timeout`].
+    pub timeout_per_app: Option<Duration>,
+
+    /// Whose geometry semantics to assume when placing windows
+    pub display_server: DisplayServer,
+
+    /// The version of the running gnome-shell, if it is known
+    pub shell_version: Option<&'r str>,
+
+    /// What to do if the session was saved under a different display server
+    pub cross_session_type: CrossSessionType,
+
+    pub on_missing_app: MissingAppPolicy,
+
+    /// Launch every saved window instead of one per window class,
+    /// requesting new instances for applications that were already launched
+    pub new_instance: bool,
+
+    /// How to retry launching an application that failed with a transient error, see [`LaunchError::is_transient`](launch::LaunchError::is_transient)
+    pub launch_retry: RetryPolicy,
+
+    /// Which windows to accept as the windows of applications that show other windows first
+    pub wait_rules: &'r WaitRules,
+
+    /// What to do if the number of monitors differs from the saved one
+    pub monitor_fallback: MonitorFallback,
+
+    /// If given and the number of monitors differs from the saved one, windows are moved from the saved monitors
+    /// onto the current ones instead of applying [`RestoreOptions::monitor_fallback`]
+    pub monitor_map: &'r [MonitorMapping],
+
+    /// Move all windows onto the current monitor with this index (counted from 0), whether or not the number of
+    /// monitors changed, instead of [`RestoreOptions::monitor_map`] and [`RestoreOptions::monitor_fallback`]
+    pub target_monitor: Option<u32>,
+
+    /// Scale windows by the ratio of the current to the saved scale factor of their monitor where they differ,
+    /// see [`scale`]
+    pub dpi_aware: bool,
+
+    /// Windows whose edges are all at most this many pixels away from the saved ones are not moved,
+    /// so that applications that position themselves are not fought over a few pixels
+    pub geometry_tolerance: u32,
+
+    /// The window classes of applications that position their windows themselves, which are never moved
+    pub self_placing: &'r HashSet<String>,
+
+    /// The window classes of applications whose windows are never moved or resized, e.g. shaped desktop widgets
+    /// that restoring a raw geometry would clip
+    pub skip_geometry: &'r HashSet<String>,
+
+    /// Leave out applications whose window the user had not interacted with for longer than this when the session
+    /// was saved, see [`age`]
+    pub max_age: Option<Duration>,
+
+    /// Only restore the applications whose window class, gtk app id or sandboxed app id matches any of these,
+    /// all of them if it is empty
+    pub restore_only: &'r [ExcludePattern],
+
+    /// Never restore the applications whose window class, gtk app id or sandboxed app id matches any of these
+    pub restore_skip: &'r [ExcludePattern],
+
+    /// Minimize windows that were saved minimized as soon as they appear, so that they do not keep the focus
+    pub no_activate_minimized: bool,
+
+    /// Move windows that were saved with the same geometry as earlier ones this many pixels further right and down,
+    /// so that they do not hide each other, 0 to place them as they were saved
+    pub stagger: u32,
+
+    pub mode: RestoreMode,
+
+    /// Kill the processes of launched command lines whose windows did not appear within the wait timeout
+    pub kill_on_timeout: bool,
+
+    /// Prefixes of saved desktop file paths to replace, so that sessions can be moved between machines
+    /// that install applications in different places
+    pub path_prefix_map: &'r [PathPrefixMap],
+
+    /// Fail the restore on the first D-Bus error instead of printing it and going on with the other windows
+    pub strict_dbus: bool,
+
+    /// A program to pipe the session through before it is restored, see [`transform`]
+    pub transform_cmd: Option<&'r Path>,
+
+    /// Refuse to restore sessions saved by another user or on another host instead of warning about them
+    pub strict_host: bool,
+
+    /// Rewrite the home directory the session was saved with to the current one in everything that is launched
+    pub rewrite_home: bool,
+
+    /// How long to keep watching the placed windows after the restore, moving them back to their saved position
+    /// when they moved away by more than [`RestoreOptions::geometry_tolerance`] (e.g. because the application
+    /// restores its own last position a few seconds after launch), zero to not watch them
+    pub reassert_geometry: Duration,
+
+    /// The window classes of applications whose windows are not watched with [`RestoreOptions::reassert_geometry`]
+    pub no_reassert: &'r HashSet<String>,
+
+    /// Ends watching the windows with [`RestoreOptions::reassert_geometry`] early once it is set,
+    /// e.g. by a signal handler
+    pub cancel: Option<&'r AtomicBool>,
+}
+
+/// Which parts of a restore to do
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RestoreMode {
+    /// Launch the applications and move their windows to the saved positions
+    Full,
+
+    /// Only launch the applications, without waiting for their windows
+    OnlyLaunch,
+
+    /// Only move the currently open windows to their saved positions, without launching anything
+    OnlyGeometry,
+
+    /// Move the windows that are already open to their saved positions and launch only the other applications
+    Sync,
+}
+
+/// What a save did
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SaveReport {
+    /// Applications that were saved, by name
+    pub saved: Vec<String>,
+
+    /// The window classes of the windows for which no command was found, whether or not they were saved
+    pub unresolved: Vec<String>,
+}
+
+/// What a restore did, by application name
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Applications that were launched
+    pub launched: Vec<String>,
+
+    /// Applications whose window was found and moved to its saved position, or already was there
+    pub placed: Vec<String>,
+
+    /// Applications whose window was not found
+    pub missing: Vec<String>,
+
+    /// The applications of `missing` that were given up on after [`RestoreOptions::timeout_per_app`]
+    #[serde(default)]
+    pub timed_out: Vec<String>,
+
+    /// Applications that could not be launched, even after retrying transient errors
+    #[serde(default)]
+    pub failed: Vec<String>,
+}
+
+impl RestoreReport {
+    /// Records the `launched` applications as launched or failed, by their entry in `launches`
+    fn record_launched(&mut self, applications: &[SessionApplication], launched: &[usize], launches: &[Launched]) {
+        for &ix in launched {
+            let app = &applications[ix];
+
+            match &launches[ix].error {
+                _ if app.exec == Exec::Unresolved => {},
+                Some(_) => self.failed.push(app.name().to_owned()),
+                None => self.launched.push(app.name().to_owned()),
+            }
+        }
+    }
+
+    /// Records the applications in `tried` whose window was looked for, `missing` are the ones whose window
+    /// was not found, which is also printed
+    fn record_placed(&mut self, applications: &[SessionApplication], tried: &[usize], missing: &[usize]) {
+        for &ix in tried {
+            let name = applications[ix].name().to_owned();
+
+            if missing.contains(&ix) {
+                eprintln!("unable to find a window for '{name}'");
+                self.missing.push(name);
+            } else {
+                self.placed.push(name);
+            }
+        }
+    }
+
+    /// Prints the applications that could not be launched, each launch error was printed already
+    fn print_failed(&self) {
+        if !self.failed.is_empty() {
+            eprintln!("unable to launch: {}", self.failed.join(", "));
+        }
+    }
+}
+
+/// Applications known to position their windows themselves after they appear
+pub const SELF_PLACING_CLASSES: &[&str] = &["Gimp", "Gimp-2.10", "gimp"];
+
+/// What to do with applications whose saved desktop file does not exist anymore
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MissingAppPolicy {
+    /// Do not launch the application
+    Skip,
+
+    /// Find a new command for the saved window, like when saving
+    Resolve,
+
+    /// Fail the restore
+    Error,
+}
+
+#[derive(Debug, Error)]
+pub enum RestoreError {
+    #[error("deserialization error {0}")]
+    Deserialization(#[from] serde_json::Error),
+
+    #[error("desktop file {0:?} does not exist anymore")]
+    MissingApp(PathBuf),
+
+    #[error("{0}")]
+    Order(#[from] order::OrderError),
+
+    #[error("dbus error {0}")]
+    DBus(#[from] zbus::Error),
+
+    #[error("{0}")]
+    Transform(#[from] transform::TransformError),
+
+    #[error("the session was saved by {saved}, not by {current} (restore it anyway without --strict-host)")]
+    ForeignOrigin { saved: String, current: String },
+}
+
+/// The pids of the parent, grandparent and so on of the process with the given pid, up to the one whose
+/// parent cannot be read (e.g. init). The window of the terminal a process was started from belongs to one of them.
+pub fn process_ancestors(pid: i32) -> Vec<i32> {
+    let mut ancestors = Vec::new();
+    let mut pid = pid;
+
+    while let Ok(ppid) = methods::try_read_proc_ppid(pid) {
+        // pid 0 is the parent of init and kernel threads, it has no entry in /proc
+        if ppid <= 0 || ancestors.contains(&ppid) {
+            break;
+        }
+
+        ancestors.push(ppid);
+        pid = ppid;
+    }
+
+    ancestors
+}
+
+/// The command line of the process `pid` that was not allowed to be used, if that is why finding a command failed
+/// with `e`. Without [`Capability::ProcFsSearch`] it is unknown whether the process even has one.
+fn blocked_command(e: &FindError, pid: i32, capabilities: Capabilities) -> Option<BlockedCommand> {
+    if !matches!(e, FindError::NotAllowedToUseProcCmdNoOtherOptionFound)
+        || !capabilities.contains(Capability::ProcFsSearch)
+    {
+        return None;
+    }
+
+    let cmdline = methods::try_find_command_in_proc(pid).ok()?;
+    let binary = Path::new(cmdline.first()?).file_name()?;
+
+    Some(BlockedCommand {
+        reason: e.kind().to_owned(),
+        binary: binary.to_string_lossy().into_owned(),
+    })
+}
+
+fn capture_cwd(pid: i32, capabilities: Capabilities) -> Option<PathBuf> {
+    if !capabilities.contains(Capability::ReadProcCwd) {
+        return None;
+    }
+
+    match methods::try_read_proc_cwd(pid) {
+        Ok(cwd) if cwd.to_str().is_some() => Some(cwd),
+        Ok(cwd) => {
+            eprintln!("ignoring cwd {cwd:?} of pid {pid}: not valid utf-8");
+            None
+        },
+        Err(e) => {
+            eprintln!("unable to read cwd of pid {pid}: {e}");
+            None
+        },
+    }
+}
+
+fn capture_environ(pid: i32, capabilities: Capabilities) -> Option<BTreeMap<String, String>> {
+    if !capabilities.contains(Capability::ReadProcEnviron) {
+        return None;
+    }
+
+    match methods::try_read_proc_environ(pid) {
+        Ok(vars) => Some(
+            vars.into_iter()
+                .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+                .collect(),
+        ),
+        Err(e) => {
+            eprintln!("unable to read environment of pid {pid}: {e}");
+            None
+        },
+    }
+}
+
+/// The flatpak installation of a sandboxed application, read from /proc like its command line
+fn capture_flatpak_scope(pid: i32, capabilities: Capabilities) -> Option<FlatpakScope> {
+    if !capabilities.contains(Capability::ProcFsSearch) {
+        return None;
+    }
+
+    flatpak::try_read_flatpak_scope(pid)
+        .map_err(|e| eprintln!("unable to read the flatpak installation of pid {pid}: {e}"))
+        .ok()
+}
+
+/// Runs `find` for all windows on as many threads as there are CPUs, as every window is resolved on its own.
+/// The results are in the order of `windows`.
+fn find_commands<F, R>(windows: &[MetaWindow], find: &F) -> Vec<R>
+where
+    F: Fn(&MetaWindow) -> R + Sync,
+    R: Send,
+{
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = windows.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = windows
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(find).collect::<Vec<_>>()))
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Lists the windows that would be saved, i.e. all but those left out by `filter`,
+/// and finds the command of each with `find`, keeping the order of the windows.
+/// Listing is retried according to `retry`, and also while no windows are listed if `expect_windows` is set.
+pub fn resolve_windows<F, R>(
+    conn: &WindowCtlProxy,
+    capabilities: Capabilities,
+    filter: &WindowFilter,
+    retry: RetryPolicy,
+    expect_windows: bool,
+    find: &F,
+) -> zbus::Result<Vec<(MetaWindow, R)>>
+where
+    F: Fn(&MetaWindow) -> R + Sync,
+    R: Send,
 {
+    let res = retry_with_backoff(
+        retry,
+        "list windows",
+        || conn.list_windows(),
+        dbus::is_transient,
+        |records| !records.is_empty() || !expect_windows,
+        std::thread::sleep,
+    )?;
+
+    let mut windows: Vec<_> = res
+        .into_iter()
+        .enumerate()
+        .filter_map(|(ix, record)| {
+            record
+                .map_err(|e| eprintln!("skipping window record #{ix}: unable to decode: {e}"))
+                .ok()
+        })
+        .filter(|w| filter.keeps(w))
+        .collect();
+
+    for w in windows.iter_mut().filter(|w| !w.sandboxed_app_id.is_empty()) {
+        w.flatpak_scope = capture_flatpak_scope(w.pid, capabilities);
+    }
+
+    let found = find_commands(&windows, find);
+    Ok(windows.into_iter().zip(found).collect())
+}
+
+/// Saves the current session, `open_writer` is only called once the session is ready to be written,
+/// so that nothing is truncated if saving fails
+pub fn save<O, U: Write, F>(
+    conn: &WindowCtlProxy,
+    open_writer: O,
+    unresolved_out: Option<U>,
+    options: SaveOptions<'_>,
+    find: F,
+) -> Result<SaveReport, SaveError>
+where
+    O: FnOnce() -> std::io::Result<io::SessionWriter>,
+    F: Fn(&MetaWindow) -> Result<(Exec, Confidence), FindError> + Sync,
+{
+    if !format::is_supported(options.format_version) {
+        return Err(format::FormatError::UnsupportedVersion(options.format_version).into());
+    }
+
     let num_monitors = conn.get_num_monitors()?;
+    check_has_monitors(num_monitors, &options)?;
+
+    let monitors = match conn.get_monitor_work_areas() {
+        Ok(monitors) => Some(monitors),
+        Err(e) if dbus::is_unknown_method(&e) => None,
+        Err(e) => {
+            eprintln!("unable to get the work areas of the monitors: {e}");
+            None
+        },
+    };
 
-    let res = conn.list_windows()?;
+    let monitor_scales = match conn.get_monitor_scales() {
+        Ok(scales) => Some(scales),
+        Err(e) if dbus::is_unknown_method(&e) => None,
+        Err(e) => {
+            eprintln!("unable to get the scale factors of the monitors: {e}");
+            None
+        },
+    };
 
-    let v: Vec<_> = res
+    // the shell may briefly list no windows while it is busy, which must not replace a good session
+    let windows = resolve_windows(
+        conn,
+        options.capabilities,
+        options.filter,
+        options.retry,
+        options.replaces_non_empty,
+        &find,
+    )?;
+
+    // the errors are reported here in the order of the windows, not as they happen on the threads
+    let mut unresolved = Vec::new();
+
+    let (mut v, confidences): (Vec<_>, Vec<_>) = windows
         .into_iter()
-        .filter(|w| w.window_class != "Gnome-shell")
-        .filter_map(|w| {
+        .filter_map(|(w, found)| {
             let wm_class = w.window_class.clone();
             let gtk_app_id = w.gtk_app_id.clone();
             let sandboxed_app_id = w.sandboxed_app_id.clone();
             let pid = w.pid;
 
-            find(&w)
-                .map(|exec| SessionApplication { window: w, exec })
-                .map_err(|e| eprintln!("unable to find command for {{ wm_class: {:?}, gtk_app_id: {:?}, sandboxed_app_id: {:?}, pid: {:?} }}: {e}", wm_class, gtk_app_id, sandboxed_app_id, pid))
-                .ok()
+            let mut blocked = None;
+
+            let (mut exec, confidence) = match found {
+                Ok(found) => found,
+                Err(e) => {
+                    eprintln!("unable to find command for {{ wm_class: {:?}, gtk_app_id: {:?}, sandboxed_app_id: {:?}, pid: {:?} }}: {e}", wm_class, gtk_app_id, sandboxed_app_id, pid);
+                    unresolved.push(UnresolvedWindow::new(&w, e.to_string()));
+
+                    // a window whose command is known but not allowed to be used is kept, to be looked at later
+                    blocked = blocked_command(&e, pid, options.capabilities);
+
+                    match &blocked {
+                        Some(BlockedCommand { binary, .. }) => eprintln!(
+                            "recording the window of '{wm_class}' without a command, the command line of `{binary}` is not allowed to be used"
+                        ),
+                        None if options.include_unmatched => {
+                            eprintln!("recording the window of '{wm_class}' without a command")
+                        },
+                        None => return None,
+                    }
+
+                    (Exec::Unresolved, 0.0)
+                },
+            };
+
+            if let (Exec::CmdLine(cmdline), true) = (&mut exec, options.lossy_cmdline) {
+                if make_lossy(cmdline) {
+                    eprintln!("replaced invalid utf-8 in the command line of '{wm_class}', it may not launch correctly");
+                }
+            }
+
+            let redacted = match (&mut exec, options.redactor) {
+                (Exec::CmdLine(cmdline), Some(redactor)) => redactor.redact(cmdline),
+                _ => false,
+            };
+
+            let mut extra_state = state_hooks::capture(&w);
+            if options.capture_theme_variant {
+                if let Some(variant) = state_hooks::theme_variant(&w) {
+                    extra_state.insert(state_hooks::THEME_VARIANT.to_owned(), variant);
+                }
+            }
+
+            let app = SessionApplication {
+                cwd: capture_cwd(pid, options.capabilities),
+                environ: capture_environ(pid, options.capabilities),
+                display_name: display_name(&exec),
+                disabled: false,
+                priority: 0,
+                after: Vec::new(),
+                extra_state,
+                relative: None,
+                blocked,
+                window: w,
+                exec,
+                redacted,
+            };
+
+            Some((app, confidence))
         })
-        .collect();
+        .unzip();
+
+    check_not_emptied(&v, &options)?;
+
+    if options.relative_geometry {
+        match &monitors {
+            Some(monitors) => add_relative_geometry(&mut v, monitors),
+            None => eprintln!("the extension does not report the monitors, saving only absolute geometry"),
+        }
+    }
+
+    warn_about_window_groups(&v);
+    warn_about_shared_desktop_files(&v, &confidences);
+
+    let session = Session {
+        format_version: CURRENT_FORMAT_VERSION,
+        applications: v,
+        num_monitors,
+        monitors,
+        monitor_scales,
+        display_server: options.display_server,
+        shell_version: options.shell_version.map(str::to_owned),
+        capabilities: Some(options.capabilities),
+        origin: Origin::current(),
+    };
+
+    let session = match options.transform_cmd {
+        Some(cmd) => transform::transform(&session, cmd)?,
+        None => session,
+    };
+
+    let mut writer = open_writer()?;
+    format::to_writer_versioned(&mut writer, &session, options.format_version)?;
+    writer.finish()?;
+
+    if let Some(mut out) = unresolved_out {
+        serde_json::to_writer_pretty(&mut out, &unresolved)?;
+        writeln!(out).map_err(serde_json::Error::io)?;
+        out.flush().map_err(serde_json::Error::io)?;
+    }
+
+    Ok(SaveReport {
+        saved: session.applications.iter().map(|app| app.name().to_owned()).collect(),
+        unresolved: unresolved.into_iter().map(|w| w.window_class).collect(),
+    })
+}
+
+/// Saves the geometry of the windows whose monitor is known relative to its work area
+fn add_relative_geometry(applications: &mut [SessionApplication], monitors: &[Rect]) {
+    for app in applications {
+        let area = app.window.monitor.and_then(|m| monitors.get(m as usize));
+
+        // the extension knows best where the window is on its monitor, if it tells
+        app.relative = area.and_then(|&area| match app.window.local_geom {
+            Some(local) => RelativeGeom::from_local(local, area),
+            None => RelativeGeom::new(app.window.geom, area),
+        });
 
-    let session = Session { applications: v, num_monitors };
+        if app.relative.is_none() {
+            eprintln!(
+                "the monitor of '{name}' is unknown, saving only its absolute geometry",
+                name = app.name()
+            );
+        }
+    }
+}
+
+/// Converts the relative geometry of the applications that have one back into pixels of the current monitors.
+/// Windows are put on the current monitor the saved one is mapped to, or on `target` if given,
+/// and on the primary monitor if that does not exist.
+fn resolve_relative_geometry(
+    conn: &WindowCtlProxy,
+    applications: &mut [SessionApplication],
+    mappings: &[MonitorMapping],
+    target: Option<u32>,
+) -> zbus::Result<()> {
+    let current = conn.get_monitor_work_areas()?;
+    let primary = conn.get_primary_work_area()?;
 
-    serde_json::to_writer(writer, &session)?;
+    for app in applications {
+        let Some(relative) = app.relative else {
+            continue;
+        };
+
+        let monitor = target.or_else(|| {
+            let saved = app.window.monitor?;
+            Some(mappings.iter().find(|m| m.saved == saved).map_or(saved, |m| m.current))
+        });
+        let area = monitor
+            .and_then(|m| current.get(m as usize))
+            .copied()
+            .unwrap_or(primary);
+
+        app.window.geom = relative.resolve(area, app.window.geom);
+    }
 
     Ok(())
 }
 
-pub fn restore<R: Read>(conn: &WindowCtlProxy, rdr: R) -> Result<(), RestoreError> {
-    let deduped_sess = {
-        let mut sess: Session = serde_json::from_reader(rdr)?;
+/// Scales the windows of monitors whose scale factor changed since the session was saved, see `--dpi-aware`,
+/// returns how many windows were scaled. Windows with a relative geometry already fit their monitor.
+fn apply_monitor_scales(
+    conn: &WindowCtlProxy,
+    applications: &mut [SessionApplication],
+    saved_scales: &[f64],
+    mappings: &[MonitorMapping],
+    target: Option<u32>,
+) -> zbus::Result<usize> {
+    let current_scales = conn.get_monitor_scales()?;
+    let current = conn.get_monitor_work_areas()?;
+    let primary = conn.get_primary_work_area()?;
+    let mut scaled = 0;
+
+    for app in applications.iter_mut().filter(|app| app.relative.is_none()) {
+        let Some(saved) = app.window.monitor else {
+            continue;
+        };
+        let monitor = target.unwrap_or_else(|| mappings.iter().find(|m| m.saved == saved).map_or(saved, |m| m.current));
+
+        let (Some(&from), Some(&to)) = (saved_scales.get(saved as usize), current_scales.get(monitor as usize)) else {
+            continue;
+        };
+
+        if let Some(ratio) = scale::ratio(from, to) {
+            let area = current.get(monitor as usize).copied().unwrap_or(primary);
+            app.window.geom = scale::scale_within(app.window.geom, area, ratio);
+            scaled += 1;
+        }
+    }
+
+    Ok(scaled)
+}
+
+/// Adjusts the geometry of a session saved under the display server `saved` according to `policy`,
+/// returns whether windows should be placed
+fn apply_cross_session_type(
+    applications: &mut [SessionApplication],
+    saved: DisplayServer,
+    current: DisplayServer,
+    policy: CrossSessionType,
+) -> bool {
+    match policy {
+        CrossSessionType::Skip => {
+            eprintln!("not moving windows, use `--cross-session-type correct` or `force` to move them anyway");
+            false
+        },
+        CrossSessionType::Correct { title_bar_height } => {
+            for app in applications {
+                app.window.geom = display::correct_decorations(app.window.geom, saved, current, title_bar_height);
+            }
+            true
+        },
+        CrossSessionType::Force => true,
+    }
+}
+
+fn list_decodable_windows(conn: &WindowCtlProxy) -> zbus::Result<Vec<MetaWindow>> {
+    Ok(conn.list_windows()?.into_iter().filter_map(Result::ok).collect())
+}
+
+fn read_session<R: Read>(rdr: R, dedup: bool) -> Result<Session, RestoreError> {
+    read_transformed_session(rdr, dedup, None)
+}
+
+/// Reads a session like [`read_session`], piping it through `transform_cmd` first if given
+fn read_transformed_session<R: Read>(
+    rdr: R,
+    dedup: bool,
+    transform_cmd: Option<&Path>,
+) -> Result<Session, RestoreError> {
+    let mut sess: Session = serde_json::from_reader(rdr)?;
+
+    if let Some(cmd) = transform_cmd {
+        sess = transform::transform(&sess, cmd)?;
+    }
+
+    if sess.format_version > CURRENT_FORMAT_VERSION {
+        eprintln!(
+            "session file has format version {}, but only versions up to {CURRENT_FORMAT_VERSION} are known; some information may be ignored",
+            sess.format_version
+        );
+    }
+
+    warn_about_window_groups(&sess.applications);
+
+    if dedup {
         dedup_applications(&mut sess.applications);
-        sess
+    }
+
+    Ok(sess)
+}
+
+/// Adjusts the saved geometry of the applications to the current monitors according to `fallback`,
+/// returns whether the windows should be placed at all
+fn apply_monitor_fallback(
+    conn: &WindowCtlProxy,
+    applications: &mut [SessionApplication],
+    fallback: MonitorFallback,
+) -> bool {
+    if fallback == MonitorFallback::Skip {
+        eprintln!("not placing windows");
+        return false;
+    }
+
+    let area = match conn.get_primary_work_area() {
+        Ok(area) => area,
+        Err(e) => {
+            eprintln!("unable to get the work area of the primary monitor, not placing windows: {e}");
+            return false;
+        },
+    };
+
+    let mut geoms: Vec<WindowGeom> = applications.iter().map(|app| app.window.geom).collect();
+
+    match fallback {
+        MonitorFallback::Primary => fallback::fit_into(&mut geoms, area),
+        MonitorFallback::Cascade => fallback::cascade(&mut geoms, area),
+        MonitorFallback::Skip => unreachable!(),
+    }
+
+    for (app, geom) in applications.iter_mut().zip(geoms) {
+        app.window.geom = geom;
+    }
+
+    true
+}
+
+/// Moves the windows of the applications from the saved monitors onto the current ones, see [`fallback::anchor_to_monitors`]
+fn anchor_to_monitors(
+    conn: &WindowCtlProxy,
+    applications: &mut [SessionApplication],
+    saved: Option<&[Rect]>,
+    mappings: &[MonitorMapping],
+) -> Result<(), String> {
+    let saved = saved.ok_or("the session does not contain the monitor layout")?;
+    let current = conn.get_monitor_work_areas().map_err(|e| e.to_string())?;
+    let primary = conn.get_primary_work_area().map_err(|e| e.to_string())?;
+
+    let layouts = fallback::MonitorLayouts { saved, current: &current, primary };
+
+    let mut windows: Vec<_> = applications
+        .iter_mut()
+        .map(|app| (&mut app.window.geom, app.window.monitor))
+        .collect();
+
+    fallback::anchor_to_monitors(&mut windows, layouts, mappings);
+    Ok(())
+}
+
+/// Moves the windows of the applications onto the current monitor `target`, see [`fallback::move_to_monitor`]
+fn move_to_target_monitor(
+    conn: &WindowCtlProxy,
+    applications: &mut [SessionApplication],
+    saved: Option<&[Rect]>,
+    target: u32,
+) -> Result<(), String> {
+    let current = conn.get_monitor_work_areas().map_err(|e| e.to_string())?;
+    let area = *current
+        .get(target as usize)
+        .ok_or_else(|| format!("there are only {} monitor(s)", current.len()))?;
+
+    let mut windows: Vec<_> = applications
+        .iter_mut()
+        .map(|app| {
+            let from = saved
+                .zip(app.window.monitor)
+                .and_then(|(saved, m)| saved.get(m as usize).copied());
+            (&mut app.window.geom, from)
+        })
+        .collect();
+
+    fallback::move_to_monitor(&mut windows, area);
+    Ok(())
+}
+
+/// The highest `stable_seq` of the currently open windows,
+/// everything newer than this must have been opened afterwards
+fn newest_stable_seq(conn: &WindowCtlProxy, strict_dbus: bool) -> zbus::Result<Option<u32>> {
+    match list_decodable_windows(conn) {
+        Ok(windows) => Ok(Some(windows.iter().map(|w| w.stable_seq).max().unwrap_or(0))),
+        Err(e) if strict_dbus => Err(e),
+        Err(e) => {
+            eprintln!("unable to list windows before launching: {e}");
+            Ok(None)
+        },
+    }
+}
+
+/// Warns about sessions that were saved by another user or on another host, or refuses them with
+/// [`RestoreOptions::strict_host`], and rewrites the saved home directory with [`RestoreOptions::rewrite_home`]
+fn check_origin(
+    saved: Option<&Origin>,
+    current: Option<&Origin>,
+    applications: &mut [SessionApplication],
+    options: &RestoreOptions,
+) -> Result<(), RestoreError> {
+    let (saved, current) = match (saved, current) {
+        (Some(saved), Some(current)) => (saved, current),
+        (None, _) if options.rewrite_home => {
+            eprintln!("not rewriting the home directory: the session does not record who saved it");
+            return Ok(());
+        },
+        (Some(_), None) if options.strict_host || options.rewrite_home => {
+            eprintln!("unable to determine the current user and host, not comparing them to the session's");
+            return Ok(());
+        },
+        _ => return Ok(()),
     };
 
-    for app in &deduped_sess.applications {
-        match &app.exec {
-            Exec::CmdLine(cmdline) => {
-                let res = Command::new(&cmdline[0]).args(&cmdline[1..]).spawn();
+    let other_home = saved.home != current.home;
+
+    if saved.is_foreign(current) {
+        if options.strict_host {
+            return Err(RestoreError::ForeignOrigin { saved: saved.to_string(), current: current.to_string() });
+        }
 
-                if let Err(e) = res {
-                    eprintln!("Error spawning process '{cmdline:?}': {e:?}");
+        let hint = if other_home && !options.rewrite_home {
+            ", see --rewrite-home"
+        } else {
+            ""
+        };
+        eprintln!(
+            "WARNING: the session was saved by {saved}, not by {current}; it may launch files of that machine{hint}"
+        );
+    }
+
+    if options.rewrite_home && other_home {
+        let rewritten = applications
+            .iter_mut()
+            .filter(|app| !app.disabled)
+            .map(|app| origin::rewrite_home(app, &saved.home, &current.home))
+            .filter(|&rewritten| rewritten)
+            .count();
+
+        eprintln!(
+            "rewrote {from:?} to {to:?} in {rewritten} applications",
+            from = saved.home,
+            to = current.home
+        );
+    }
+
+    Ok(())
+}
+
+pub fn restore<R: Read, L: Launcher, F, E>(
+    conn: &WindowCtlProxy,
+    rdr: R,
+    launcher: &L,
+    options: RestoreOptions<'_>,
+    find: F,
+) -> Result<RestoreReport, RestoreError>
+where
+    F: Fn(&MetaWindow) -> Result<Exec, E>,
+    E: std::error::Error,
+{
+    // every window needs its own launch to recreate multi window layouts
+    let mut deduped_sess = read_transformed_session(rdr, !options.new_instance, options.transform_cmd)?;
+
+    let has_geometry = deduped_sess.has_geometry();
+
+    if let (Some(saved), Some(current)) = (&deduped_sess.shell_version, options.shell_version) {
+        compat::warn_about_shell_version(saved, current);
+    }
+
+    check_origin(
+        deduped_sess.origin.as_ref(),
+        Origin::current().as_ref(),
+        &mut deduped_sess.applications,
+        &options,
+    )?;
+
+    let reference = age::reference_time(deduped_sess.applications.iter().map(|app| &app.window));
+    let (disabled, enabled): (Vec<_>, Vec<_>) = deduped_sess.applications.into_iter().partition(|app| app.disabled);
+
+    for app in disabled {
+        eprintln!("skipping '{name}': disabled", name = app.name());
+    }
+
+    let (stale, enabled): (Vec<_>, Vec<_>) = match (options.max_age, reference) {
+        (Some(max_age), Some(reference)) => enabled
+            .into_iter()
+            .partition(|app| age::is_older(&app.window, max_age, reference)),
+        _ => (Vec::new(), enabled),
+    };
+
+    for app in stale {
+        eprintln!(
+            "skipping '{name}': not used for longer than --max-age",
+            name = app.name()
+        );
+    }
+
+    // before anything is launched or placed, so that left out windows are not moved either
+    let enabled: Vec<_> = enabled
+        .into_iter()
+        .filter(
+            |app| match filter::restore_skip_reason(&app.window, options.restore_only, options.restore_skip) {
+                Some(reason) => {
+                    eprintln!("skipping '{name}': {reason}", name = app.name());
+                    false
+                },
+                None => true,
+            },
+        )
+        .collect();
+
+    // nothing is launched, so missing desktop files do not matter
+    let enabled = match options.mode {
+        RestoreMode::OnlyGeometry => enabled,
+        _ => handle_missing_apps(
+            enabled,
+            options.on_missing_app,
+            options.path_prefix_map,
+            find,
+            |w: &MetaWindow| crate::find_command::find_command_by_app_id(w).ok(),
+        )?,
+    };
+
+    for app in &enabled {
+        if let Some(variant) = app.extra_state.get(state_hooks::THEME_VARIANT) {
+            eprintln!(
+                "'{}' was saved with the {variant} theme variant, which cannot be restored",
+                app.window.window_class
+            );
+        }
+    }
+
+    let (mut applications, stages) = sort_into_stages(enabled)?;
+
+    if !has_geometry && options.mode != RestoreMode::OnlyLaunch {
+        eprintln!("the session was saved without monitors (e.g. from a headless shell), not placing windows");
+    }
+
+    let place = options.mode != RestoreMode::OnlyLaunch
+        && has_geometry
+        && match (options.target_monitor, conn.get_num_monitors()) {
+            (Some(target), _) => {
+                match move_to_target_monitor(conn, &mut applications, deduped_sess.monitors.as_deref(), target) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        eprintln!("unable to move windows to monitor {target}, not placing windows: {e}");
+                        false
+                    },
                 }
             },
-            Exec::DesktopFile(path) => match gio::DesktopAppInfo::from_filename(path) {
-                Some(x) => {
-                    if let Err(e) = x.launch_uris::<AppLaunchContext>(&[], None) {
-                        eprintln!("Error spawning process '{path:?}': {e:?}");
+            (None, Ok(n)) if n == deduped_sess.num_monitors => true,
+            (None, Ok(n)) => {
+                eprintln!(
+                    "the session was saved with {saved} monitor(s), but {n} are connected",
+                    saved = deduped_sess.num_monitors
+                );
+                if options.monitor_map.is_empty() {
+                    apply_monitor_fallback(conn, &mut applications, options.monitor_fallback)
+                } else {
+                    match anchor_to_monitors(
+                        conn,
+                        &mut applications,
+                        deduped_sess.monitors.as_deref(),
+                        options.monitor_map,
+                    ) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            eprintln!("unable to map monitors: {e}, falling back to --fallback");
+                            apply_monitor_fallback(conn, &mut applications, options.monitor_fallback)
+                        },
                     }
-                },
-                None => eprintln!("Error spawning process '{path:?}': could not get desktop app info"),
+                }
+            },
+            (None, Err(e)) if options.strict_dbus => return Err(e.into()),
+            (None, Err(e)) => {
+                eprintln!("unable to get the number of monitors, not placing windows: {e}");
+                false
+            },
+        };
+
+    if place && applications.iter().any(|app| app.relative.is_some()) {
+        match resolve_relative_geometry(conn, &mut applications, options.monitor_map, options.target_monitor) {
+            Ok(()) => {},
+            Err(e) if options.strict_dbus => return Err(e.into()),
+            Err(e) => eprintln!("unable to get the work areas of the monitors, using the absolute geometry: {e}"),
+        }
+    }
+
+    if place && options.dpi_aware {
+        match &deduped_sess.monitor_scales {
+            Some(saved_scales) => {
+                match apply_monitor_scales(
+                    conn,
+                    &mut applications,
+                    saved_scales,
+                    options.monitor_map,
+                    options.target_monitor,
+                ) {
+                    Ok(0) => {},
+                    Ok(scaled) => eprintln!("scaled {scaled} windows to the scale factors of their monitors"),
+                    Err(e) if options.strict_dbus => return Err(e.into()),
+                    Err(e) => eprintln!("unable to get the scale factors of the monitors, not scaling windows: {e}"),
+                }
+            },
+            None => eprintln!("the session was saved without the scale factors of the monitors, not scaling windows"),
+        }
+    }
+
+    let place = place
+        && match deduped_sess.display_server {
+            Some(saved) if saved != options.display_server => {
+                eprintln!(
+                    "the session was saved under {saved:?}, but this is {current:?}",
+                    current = options.display_server
+                );
+                apply_cross_session_type(
+                    &mut applications,
+                    saved,
+                    options.display_server,
+                    options.cross_session_type,
+                )
             },
+            _ => true,
+        };
+
+    if place && options.stagger > 0 {
+        stagger_coincident(applications.iter_mut().map(|app| &mut app.window.geom), options.stagger);
+    }
+
+    let applications = &applications;
+    let all: Vec<usize> = (0..applications.len()).collect();
+    let mut report = RestoreReport::default();
+
+    if options.mode == RestoreMode::OnlyGeometry {
+        if place {
+            let mut placer = Placer::new(conn, applications, options);
+            let missing = placer.place(all.clone(), &[], None, Duration::ZERO)?;
+            report.record_placed(applications, &all, &missing);
+            placer.reassert()?;
+        }
+
+        return Ok(report);
+    }
+
+    if !place {
+        // without placing windows there is nothing to wait for between stages
+        if options.mode == RestoreMode::Sync {
+            let open = open_applications(conn, applications, options.strict_dbus)?;
+            let mut launches = Vec::with_capacity(applications.len());
+            let launched = launch_stage(
+                launcher,
+                applications,
+                0..applications.len(),
+                &open,
+                options.new_instance,
+                options.launch_retry,
+                &mut launches,
+            );
+            report.record_launched(applications, &launched, &launches);
+        } else {
+            let launches = launch_applications(
+                launcher,
+                applications,
+                0..applications.len(),
+                options.new_instance,
+                options.launch_retry,
+            );
+            report.record_launched(applications, &all, &launches);
         }
+
+        report.print_failed();
+        return Ok(report);
     }
 
-    std::thread::sleep(Duration::from_secs(1));
+    let mut placer = Placer::new(conn, applications, options);
+    let mut launches = Vec::with_capacity(applications.len());
+
+    // the windows that are already open are only moved, the applications without one are launched as usual
+    let open: HashSet<usize> = if options.mode == RestoreMode::Sync {
+        let missing = placer.place(all.clone(), &[], None, Duration::ZERO)?;
+        let open: Vec<usize> = all.iter().copied().filter(|ix| !missing.contains(ix)).collect();
 
-    let cur_num_monitors = conn.get_num_monitors();
+        report.record_placed(applications, &open, &[]);
+        open.into_iter().collect()
+    } else {
+        HashSet::new()
+    };
+
+    let kill_windowless = |launches: &[Launched], missing: Vec<usize>| {
+        if options.kill_on_timeout {
+            kill_windowless(launcher, applications, launches, missing);
+        }
+    };
 
-    if matches!(cur_num_monitors, Ok(n) if n == deduped_sess.num_monitors) {
-        for app in &deduped_sess.applications {
-            if !app.window.window_class.is_empty() {
-                if let Err(e) = conn.set_window_geom_by_class(&app.window.window_class, app.window.geom) {
-                    eprintln!("Error moving window '{class}': {e:?}", class = app.window.window_class,);
+    for stage in stages {
+        match options.settle_strategy {
+            SettleStrategy::Fixed(settle_time) => {
+                let launched_after = newest_stable_seq(conn, options.strict_dbus)?;
+                let launched = launch_stage(
+                    launcher,
+                    applications,
+                    stage,
+                    &open,
+                    options.new_instance,
+                    options.launch_retry,
+                    &mut launches,
+                );
+                report.record_launched(applications, &launched, &launches);
+
+                if launched.is_empty() {
+                    continue;
+                }
+
+                std::thread::sleep(settle_time);
+                let missing = placer.place(launched.clone(), &launches, launched_after, Duration::ZERO)?;
+                report.record_placed(applications, &launched, &missing);
+                kill_windowless(&launches, missing);
+            },
+            SettleStrategy::Poll => {
+                let launched_after = newest_stable_seq(conn, options.strict_dbus)?;
+                let launched = launch_stage(
+                    launcher,
+                    applications,
+                    stage,
+                    &open,
+                    options.new_instance,
+                    options.launch_retry,
+                    &mut launches,
+                );
+                report.record_launched(applications, &launched, &launches);
+
+                let missing = placer.place(launched.clone(), &launches, launched_after, options.wait_timeout)?;
+                report.record_placed(applications, &launched, &missing);
+                kill_windowless(&launches, missing);
+            },
+            SettleStrategy::PerApp => {
+                for ix in stage {
+                    if open.contains(&ix) || focuses_single_main_window(applications, ix, &open) {
+                        launches.push(Launched::default());
+                        continue;
+                    }
+
+                    let launched_after = newest_stable_seq(conn, options.strict_dbus)?;
+                    launches.push(launch_application(
+                        launcher,
+                        applications,
+                        ix,
+                        options.new_instance,
+                        options.launch_retry,
+                    ));
+                    report.record_launched(applications, &[ix], &launches);
+
+                    let missing = placer.place(vec![ix], &launches, launched_after, options.wait_timeout)?;
+                    report.record_placed(applications, &[ix], &missing);
+                    kill_windowless(&launches, missing);
                 }
+            },
+        }
+    }
+
+    placer.reassert()?;
+
+    report.timed_out = placer
+        .timed_out
+        .iter()
+        .map(|&ix| applications[ix].name().to_owned())
+        .collect();
+
+    if !report.timed_out.is_empty() {
+        eprintln!("gave up waiting for the windows of: {}", report.timed_out.join(", "));
+    }
+
+    report.print_failed();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_cross_session_type, check_has_monitors, check_not_emptied, has_applications, retry_with_backoff,
+        Capabilities, CrossSessionType, DisplayServer, RetryPolicy, SaveError, SaveOptions, WindowFilter,
+        CURRENT_FORMAT_VERSION,
+    };
+    use super::{
+        blocked_command, find_commands, make_lossy, process_ancestors, read_session, shared_desktop_files,
+        window_groups, Exec,
+    };
+    use crate::{
+        dbus::MetaWindow,
+        find_command::{Capability, FindError},
+    };
+    use std::{cell::Cell, ffi::OsString, os::unix::ffi::OsStringExt, path::PathBuf, time::Duration};
+
+    pub(super) const NO_RETRY: RetryPolicy = RetryPolicy { retries: 0, initial_backoff: Duration::ZERO };
+
+    pub(super) const SESSION: &str = r#"{
+        "format_version": 4,
+        "num_monitors": 1,
+        "applications": [
+            {
+                "geom": { "x": 0, "y": 0, "width": 800, "height": 600, "minimized": false },
+                "pid": 10, "stable_seq": 1, "window_class": "kitty", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": { "CmdLine": ["kitty", "--single-instance"] },
+                "cwd": "/home/user"
+            },
+            {
+                "geom": { "x": 800, "y": 0, "width": 800, "height": 600, "minimized": false },
+                "pid": 11, "stable_seq": 2, "window_class": "firefox", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": { "DesktopFile": "/usr/share/applications/firefox.desktop" },
+                "group_id": 7
+            },
+            {
+                "geom": { "x": 0, "y": 600, "width": 800, "height": 600, "minimized": true },
+                "pid": 12, "stable_seq": 3, "window_class": "firefox", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": { "DesktopFile": "/usr/share/applications/firefox.desktop" },
+                "group_id": 7
             }
+        ]
+    }"#;
+
+    #[test]
+    fn reads_mixed_relative_and_absolute_geometry() {
+        let mut sess: serde_json::Value = serde_json::from_str(SESSION).unwrap();
+        sess["applications"][0]["relative"] = serde_json::json!({ "x": 0.0, "y": 0.0, "width": 0.5, "height": 0.5 });
+
+        let applications = read_session(sess.to_string().as_bytes(), false).unwrap().applications;
+        let relative: Vec<_> = applications.iter().map(|app| app.relative.is_some()).collect();
+
+        assert_eq!(relative, vec![true, false, false]);
+    }
+
+    #[test]
+    fn finds_window_groups() {
+        let sess: super::Session = serde_json::from_str(SESSION).unwrap();
+        let groups = window_groups(sess.applications.iter().map(|app| &app.window));
+
+        let stable_seqs: Vec<_> = groups
+            .iter()
+            .map(|(&group_id, windows)| (group_id, windows.iter().map(|w| w.stable_seq).collect::<Vec<_>>()))
+            .collect();
+
+        assert_eq!(stable_seqs, vec![(7, vec![2, 3])]);
+    }
+
+    #[test]
+    fn finds_commands_in_order() {
+        let windows: Vec<_> = (0..20)
+            .flat_map(|_| read_session(SESSION.as_bytes(), false).unwrap().applications)
+            .map(|app| app.window)
+            .collect();
+
+        let found = find_commands(&windows, &|w: &MetaWindow| {
+            // finish out of order
+            std::thread::sleep(std::time::Duration::from_millis(u64::from(w.stable_seq % 3)));
+            w.stable_seq
+        });
+
+        assert_eq!(found, windows.iter().map(|w| w.stable_seq).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn finds_process_ancestors() {
+        let ancestors = process_ancestors(std::process::id() as i32);
+
+        assert_eq!(ancestors.first(), Some(&(std::os::unix::process::parent_id() as i32)));
+        assert!(!ancestors.contains(&(std::process::id() as i32)));
+    }
+
+    #[test]
+    fn finds_shared_desktop_files() {
+        let mut sess: super::Session = serde_json::from_str(SESSION).unwrap();
+        let firefox = PathBuf::from("/usr/share/applications/firefox.desktop");
+
+        // the two windows of firefox share their desktop file, which is fine
+        let found = sess.applications.iter().zip([1.0, 0.9, 0.9]);
+        assert!(shared_desktop_files(found).is_empty());
+
+        sess.applications[0].exec = Exec::DesktopFile(firefox.clone());
+        let found = sess.applications.iter().zip([0.85, 0.9, 0.9]);
+
+        assert_eq!(
+            shared_desktop_files(found).into_iter().collect::<Vec<_>>(),
+            vec![(firefox.as_path(), vec![("kitty", 0.85), ("firefox", 0.9)])]
+        );
+    }
+
+    #[test]
+    fn dedup_keeps_disabled_applications() {
+        let mut session: serde_json::Value = serde_json::from_str(SESSION).unwrap();
+        session["applications"][2]["disabled"] = true.into();
+
+        let sess = read_session(session.to_string().as_bytes(), true).unwrap();
+        let apps: Vec<_> = sess
+            .applications
+            .iter()
+            .map(|app| (app.window.window_class.as_str(), app.disabled))
+            .collect();
+
+        assert_eq!(apps, vec![("firefox", false), ("firefox", true), ("kitty", false)]);
+    }
+
+    #[test]
+    fn keeps_commands_blocked_by_policy() {
+        let pid = std::process::id() as i32;
+        let search: Capabilities = [Capability::ProcFsSearch].into_iter().collect();
+        let not_allowed = FindError::NotAllowedToUseProcCmdNoOtherOptionFound;
+
+        let blocked = blocked_command(&not_allowed, pid, search).unwrap();
+        assert_eq!(blocked.reason, "proc-command-not-allowed");
+        assert!(!blocked.binary.contains('/'), "{blocked:?}");
+
+        // nothing is known to be blocked if no command line was found or it could not be read
+        assert_eq!(blocked_command(&FindError::NoSuitableEntryFound, pid, search), None);
+        assert_eq!(blocked_command(&not_allowed, pid, Capabilities::none()), None);
+    }
+
+    #[test]
+    fn invalid_utf8_cmdline() {
+        let mut exec = Exec::CmdLine(vec!["vim".into(), OsString::from_vec(b"caf\xe9.txt".to_vec())]);
+
+        let e = serde_json::to_string(&exec).unwrap_err();
+        assert!(e.to_string().contains("--lossy-cmdline"), "{e}");
+
+        let Exec::CmdLine(cmdline) = &mut exec else {
+            unreachable!()
+        };
+        assert!(make_lossy(cmdline));
+        assert!(!make_lossy(cmdline));
+
+        assert_eq!(
+            serde_json::to_string(&exec).unwrap(),
+            "{\"CmdLine\":[\"vim\",\"caf\u{fffd}.txt\"]}"
+        );
+    }
+
+    #[test]
+    fn geometry_survives_save_and_restore() {
+        let saved = read_session(SESSION.as_bytes(), false).unwrap();
+        let rects = |sess: &super::Session| {
+            sess.applications
+                .iter()
+                .map(|app| {
+                    (
+                        app.window.geom.x,
+                        app.window.geom.y,
+                        app.window.geom.width,
+                        app.window.geom.height,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut buf = Vec::new();
+        super::format::to_writer_versioned(&mut buf, &saved, CURRENT_FORMAT_VERSION).unwrap();
+        let restored = read_session(buf.as_slice(), false).unwrap();
+
+        let mut again = Vec::new();
+        super::format::to_writer_versioned(&mut again, &restored, CURRENT_FORMAT_VERSION).unwrap();
+
+        assert_eq!(rects(&restored), rects(&saved));
+        assert_eq!(buf, again);
+
+        // restoring under the same display server does not touch the geometry either
+        let mut applications = restored.applications;
+        let correct = CrossSessionType::Correct { title_bar_height: 37 };
+        apply_cross_session_type(&mut applications, DisplayServer::X11, DisplayServer::X11, correct);
+        assert_eq!(rects(&super::Session { applications, ..restored }), rects(&saved));
+    }
+
+    #[test]
+    fn cross_session_type() {
+        let apply = |policy| {
+            let mut applications = read_session(SESSION.as_bytes(), false).unwrap().applications;
+            let place = apply_cross_session_type(&mut applications, DisplayServer::X11, DisplayServer::Wayland, policy);
+            (place, applications[0].window.geom.y, applications[0].window.geom.height)
+        };
+
+        let saved = read_session(SESSION.as_bytes(), false).unwrap().applications[0]
+            .window
+            .geom;
+
+        assert_eq!(apply(CrossSessionType::Skip), (false, saved.y, saved.height));
+        assert_eq!(apply(CrossSessionType::Force), (true, saved.y, saved.height));
+        assert_eq!(
+            apply(CrossSessionType::Correct { title_bar_height: 30 }),
+            (true, saved.y - 30, saved.height + 30)
+        );
+    }
+
+    fn save_options(filter: &WindowFilter) -> SaveOptions<'_> {
+        SaveOptions {
+            format_version: CURRENT_FORMAT_VERSION,
+            capabilities: Capabilities::none(),
+            redactor: None,
+            include_unmatched: false,
+            filter,
+            lossy_cmdline: false,
+            replaces_non_empty: false,
+            allow_empty: false,
+            retry: RetryPolicy { retries: 3, initial_backoff: Duration::from_millis(10) },
+            display_server: None,
+            shell_version: None,
+            capture_theme_variant: false,
+            relative_geometry: false,
+            transform_cmd: None,
         }
     }
 
-    Ok(())
+    #[test]
+    fn never_replaces_a_session_with_an_empty_one() {
+        let filter = WindowFilter::new();
+        let applications = read_session(SESSION.as_bytes(), false).unwrap().applications;
+
+        let options = SaveOptions { replaces_non_empty: true, ..save_options(&filter) };
+        assert!(matches!(
+            check_not_emptied(&[], &options),
+            Err(SaveError::WouldEmptySession)
+        ));
+        assert!(check_not_emptied(&applications, &options).is_ok());
+
+        let options = SaveOptions {
+            replaces_non_empty: true,
+            allow_empty: true,
+            ..save_options(&filter)
+        };
+        assert!(check_not_emptied(&[], &options).is_ok());
+
+        // nothing to lose, but nothing to restore either
+        assert!(matches!(
+            check_not_emptied(&[], &save_options(&filter)),
+            Err(SaveError::NoApplications)
+        ));
+    }
+
+    #[test]
+    fn headless_sessions() {
+        let filter = WindowFilter::new();
+
+        assert!(matches!(
+            check_has_monitors(0, &save_options(&filter)),
+            Err(SaveError::NoMonitors)
+        ));
+        assert!(check_has_monitors(1, &save_options(&filter)).is_ok());
+
+        let options = SaveOptions { allow_empty: true, ..save_options(&filter) };
+        assert!(check_has_monitors(0, &options).is_ok());
+
+        // a session saved headless before this was refused has no geometry to compare or place
+        let mut sess: serde_json::Value = serde_json::from_str(SESSION).unwrap();
+        assert!(read_session(sess.to_string().as_bytes(), false).unwrap().has_geometry());
+
+        sess["num_monitors"] = 0.into();
+        assert!(!read_session(sess.to_string().as_bytes(), false).unwrap().has_geometry());
+    }
+
+    #[test]
+    fn finds_non_empty_session_files() {
+        let dir = std::env::temp_dir().join(format!("gnome-session-restore-non-empty-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let non_empty = dir.join("non-empty.json");
+        std::fs::write(&non_empty, SESSION).unwrap();
+        let empty = dir.join("empty.json");
+        std::fs::write(
+            &empty,
+            r#"{ "format_version": 4, "num_monitors": 1, "applications": [] }"#,
+        )
+        .unwrap();
+        let garbage = dir.join("garbage.json");
+        std::fs::write(&garbage, "{").unwrap();
+
+        assert!(has_applications(&non_empty));
+        assert!(!has_applications(&empty));
+        assert!(!has_applications(&garbage));
+        assert!(!has_applications(&dir.join("missing.json")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retries_with_backoff() {
+        let policy = RetryPolicy { retries: 3, initial_backoff: Duration::from_millis(100) };
+        let attempts = Cell::new(0);
+        let sleeps = Cell::new(0);
+        let mut slept = Vec::new();
+
+        // empty twice, then windows
+        let res: Result<Vec<u32>, &str> = retry_with_backoff(
+            policy,
+            "list windows",
+            || {
+                attempts.set(attempts.get() + 1);
+                Ok(if attempts.get() < 3 { vec![] } else { vec![1] })
+            },
+            |_| true,
+            |windows| !windows.is_empty(),
+            |d| slept.push(d),
+        );
+        assert_eq!(res, Ok(vec![1]));
+        assert_eq!(slept, [Duration::from_millis(100), Duration::from_millis(200)]);
+
+        // gives up after the retries, returning the last result
+        let busy = || -> Result<Vec<u32>, &str> { Err("busy") };
+        let res = retry_with_backoff(
+            policy,
+            "list windows",
+            busy,
+            |_| true,
+            |_| true,
+            |_| sleeps.set(sleeps.get() + 1),
+        );
+        assert_eq!((res, sleeps.get()), (Err("busy"), 3));
+
+        // errors that will not go away are not retried
+        sleeps.set(0);
+        let unknown = || -> Result<Vec<u32>, &str> { Err("unknown method") };
+        let res = retry_with_backoff(
+            policy,
+            "list windows",
+            unknown,
+            |_| false,
+            |_| true,
+            |_| sleeps.set(sleeps.get() + 1),
+        );
+        assert_eq!((res, sleeps.get()), (Err("unknown method"), 0));
+    }
 }
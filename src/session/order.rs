@@ -0,0 +1,152 @@
+//! The order in which applications are launched.
+//!
+//! Applications can be launched after others (e.g. a browser after the password manager its extensions need)
+//! via `after`, and before others of the same stage via `priority`. Applications are launched in stages,
+//! where every stage only depends on the ones before it, so the windows of a stage can be waited for
+//! before launching the next one.
+
+use super::{RestoreError, SessionApplication};
+use crate::dbus::MetaWindow;
+use std::ops::Range;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum OrderError {
+    #[error("the launch order of {0:?} depends on itself (via `after`)")]
+    Cycle(Vec<String>),
+}
+
+/// Whether `window` is identified by `name`, which may be a window class or an app id
+fn is_named(window: &MetaWindow, name: &str) -> bool {
+    [&window.window_class, &window.gtk_app_id, &window.sandboxed_app_id]
+        .into_iter()
+        .any(|id| !id.is_empty() && id == name)
+}
+
+/// Splits the applications into the stages they are launched in, as indices into `applications`.
+///
+/// An application is placed into the first stage after all applications it is to be launched `after`,
+/// within a stage applications with a higher `priority` come first. Names in `after` that do not match
+/// any of the applications are ignored.
+pub(super) fn launch_stages(applications: &[SessionApplication]) -> Result<Vec<Vec<usize>>, OrderError> {
+    let mut dependencies: Vec<Vec<usize>> = Vec::with_capacity(applications.len());
+
+    for app in applications {
+        let mut deps = Vec::new();
+
+        for name in &app.after {
+            let len = deps.len();
+
+            deps.extend(
+                applications
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, other)| !std::ptr::eq(app, *other) && is_named(&other.window, name))
+                    .map(|(ix, _)| ix),
+            );
+
+            if deps.len() == len {
+                eprintln!(
+                    "ignoring that '{app}' is to be launched after '{name}': there is no such application",
+                    app = app.name()
+                );
+            }
+        }
+
+        dependencies.push(deps);
+    }
+
+    let mut stage_of: Vec<Option<usize>> = vec![None; applications.len()];
+    let mut stages = Vec::new();
+
+    loop {
+        let mut stage: Vec<usize> = (0..applications.len())
+            .filter(|&ix| stage_of[ix].is_none())
+            .filter(|&ix| dependencies[ix].iter().all(|&dep| stage_of[dep].is_some()))
+            .collect();
+
+        if stage.is_empty() {
+            break;
+        }
+
+        for &ix in &stage {
+            stage_of[ix] = Some(stages.len());
+        }
+
+        // the sort is stable, so the saved order is kept for equal priorities
+        stage.sort_by_key(|&ix| std::cmp::Reverse(applications[ix].priority));
+        stages.push(stage);
+    }
+
+    let cyclic: Vec<String> = (0..applications.len())
+        .filter(|&ix| stage_of[ix].is_none())
+        .map(|ix| applications[ix].name().to_owned())
+        .collect();
+
+    if cyclic.is_empty() {
+        Ok(stages)
+    } else {
+        Err(OrderError::Cycle(cyclic))
+    }
+}
+
+/// Sorts the applications into launch order, returning the ranges of the stages they are launched in
+pub(super) fn sort_into_stages(
+    applications: Vec<SessionApplication>,
+) -> Result<(Vec<SessionApplication>, Vec<Range<usize>>), RestoreError> {
+    let stages = launch_stages(&applications)?;
+
+    let mut applications: Vec<_> = applications.into_iter().map(Some).collect();
+    let mut sorted = Vec::with_capacity(applications.len());
+    let mut ranges = Vec::with_capacity(stages.len());
+
+    for stage in stages {
+        let start = sorted.len();
+        sorted.extend(stage.into_iter().filter_map(|ix| applications[ix].take()));
+        ranges.push(start..sorted.len());
+    }
+
+    Ok((sorted, ranges))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{launch_stages, OrderError};
+    use crate::session::read_session;
+
+    const SESSION: &str = r#"{
+        "format_version": 9,
+        "num_monitors": 1,
+        "applications": [
+            { "window_class": "firefox", "exec": { "CmdLine": ["firefox"] }, "after": ["org.keepassxc.KeePassXC"],
+              "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+              "pid": 1, "stable_seq": 1, "gtk_app_id": "", "sandboxed_app_id": "" },
+            { "window_class": "kitty", "exec": { "CmdLine": ["kitty"] },
+              "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+              "pid": 2, "stable_seq": 2, "gtk_app_id": "", "sandboxed_app_id": "" },
+            { "window_class": "KeePassXC", "exec": { "CmdLine": ["keepassxc"] }, "after": ["unknown"],
+              "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+              "pid": 3, "stable_seq": 3, "gtk_app_id": "", "sandboxed_app_id": "org.keepassxc.KeePassXC" },
+            { "window_class": "thunderbird", "exec": { "CmdLine": ["thunderbird"] }, "priority": 10,
+              "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+              "pid": 4, "stable_seq": 4, "gtk_app_id": "", "sandboxed_app_id": "" }
+        ]
+    }"#;
+
+    #[test]
+    fn stages() {
+        let sess = read_session(SESSION.as_bytes(), false).unwrap();
+        assert_eq!(launch_stages(&sess.applications).unwrap(), vec![vec![3, 1, 2], vec![0]]);
+    }
+
+    #[test]
+    fn cycles() {
+        let mut sess = read_session(SESSION.as_bytes(), false).unwrap();
+        sess.applications[2].after = vec!["firefox".to_string()];
+
+        assert_eq!(
+            launch_stages(&sess.applications),
+            Err(OrderError::Cycle(vec!["firefox".to_string(), "KeePassXC".to_string()]))
+        );
+    }
+}
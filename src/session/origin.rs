@@ -0,0 +1,209 @@
+//! Which user on which host a session was saved by.
+//!
+//! Sessions in a synced state directory may come from another machine or user, whose command lines and desktop
+//! files point into a different home directory. Restoring them is warned about (or refused with `--strict-host`),
+//! and the saved home directory can be rewritten to the current one in everything that is launched.
+
+use super::{Exec, SessionApplication};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// Prefixes of arguments after which a path may start, e.g. `file:///home/user/notes.txt`
+const PATH_PREFIXES: &[&[u8]] = &[b"file://"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Origin {
+    pub host: String,
+    pub user: String,
+    pub home: PathBuf,
+}
+
+impl Origin {
+    /// The user and host gnome-session-restore runs as, `None` if any of them is unknown
+    pub fn current() -> Option<Self> {
+        let mut buf = [0u8; 256];
+        let host = nix::unistd::gethostname(&mut buf).ok()?.to_string_lossy().into_owned();
+        let user = nix::unistd::User::from_uid(nix::unistd::getuid()).ok()??.name;
+
+        Some(Origin { host, user, home: crate::paths::home_dir(None)? })
+    }
+
+    /// Whether the session was saved by another user or on another host
+    pub fn is_foreign(&self, current: &Origin) -> bool {
+        (&self.host, &self.user) != (&current.host, &current.user)
+    }
+}
+
+/// Formats the origin as `user@host`
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.user, self.host)
+    }
+}
+
+/// `path` with the prefix `from` replaced by `to`, if it starts with `from` as a whole path component
+fn rewrite_path(path: &[u8], from: &[u8], to: &[u8]) -> Option<Vec<u8>> {
+    let rest = path.strip_prefix(from)?;
+
+    (rest.is_empty() || rest[0] == b'/').then(|| [to, rest].concat())
+}
+
+/// The argument with the home directory `from` replaced by `to`, `None` if it does not contain a path into `from`.
+/// Only arguments that are a path (`/home/user/x`), the value of an option (`--dir=/home/user/x`)
+/// or a file URI (`file:///home/user/x`) are rewritten, never paths in the middle of other text.
+fn rewrite_arg(arg: &OsStr, from: &Path, to: &Path) -> Option<OsString> {
+    let (arg, from, to) = (arg.as_bytes(), from.as_os_str().as_bytes(), to.as_os_str().as_bytes());
+
+    let option_value = arg
+        .starts_with(b"-")
+        .then(|| arg.iter().position(|&b| b == b'='))
+        .flatten()
+        .map(|eq| eq + 1);
+
+    let prefix_len = PATH_PREFIXES
+        .iter()
+        .find(|prefix| arg.starts_with(prefix))
+        .map(|prefix| prefix.len())
+        .or(option_value)
+        .unwrap_or(0);
+
+    let (prefix, path) = arg.split_at(prefix_len);
+    let rewritten = rewrite_path(path, from, to)?;
+
+    Some(OsString::from_vec([prefix, &rewritten].concat()))
+}
+
+/// Rewrites the home directory `from` to `to` in the command line, desktop file and working directory of `app`,
+/// returns whether anything was rewritten
+pub(super) fn rewrite_home(app: &mut SessionApplication, from: &Path, to: &Path) -> bool {
+    // `/home/user/` and `/home/user` are the same prefix
+    let (from, to) = (from.components().as_path(), to.components().as_path());
+
+    let rewrite = |path: &mut PathBuf| {
+        let new = rewrite_path(
+            path.as_os_str().as_bytes(),
+            from.as_os_str().as_bytes(),
+            to.as_os_str().as_bytes(),
+        );
+        new.map(|new| *path = PathBuf::from(OsString::from_vec(new))).is_some()
+    };
+
+    let mut rewritten = match &mut app.cwd {
+        Some(cwd) => rewrite(cwd),
+        None => false,
+    };
+
+    match &mut app.exec {
+        Exec::DesktopFile(path) => rewritten |= rewrite(path),
+        Exec::CmdLine(args) => {
+            for arg in args {
+                if let Some(new) = rewrite_arg(arg, from, to) {
+                    *arg = new;
+                    rewritten = true;
+                }
+            }
+        },
+        Exec::Unresolved => {},
+    }
+
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_arg, rewrite_home, Origin};
+    use crate::session::{Exec, SessionApplication};
+    use std::{
+        ffi::{OsStr, OsString},
+        os::unix::ffi::{OsStrExt, OsStringExt},
+        path::{Path, PathBuf},
+    };
+
+    fn rewrite(arg: &str) -> Option<String> {
+        rewrite_arg(OsStr::new(arg), Path::new("/home/alex"), Path::new("/home/sam"))
+            .map(|arg| arg.into_string().unwrap())
+    }
+
+    #[test]
+    fn rewrites_path_shaped_arguments() {
+        assert_eq!(rewrite("/home/alex"), Some("/home/sam".to_owned()));
+        assert_eq!(rewrite("/home/alex/"), Some("/home/sam/".to_owned()));
+        assert_eq!(
+            rewrite("/home/alex/.local/bin/tool"),
+            Some("/home/sam/.local/bin/tool".to_owned())
+        );
+        assert_eq!(rewrite("--dir=/home/alex/src"), Some("--dir=/home/sam/src".to_owned()));
+        assert_eq!(rewrite("-C=/home/alex"), Some("-C=/home/sam".to_owned()));
+        assert_eq!(
+            rewrite("file:///home/alex/notes.txt"),
+            Some("file:///home/sam/notes.txt".to_owned())
+        );
+
+        // only whole path components
+        assert_eq!(rewrite("/home/alexandra/notes.txt"), None);
+        assert_eq!(rewrite("/home/alex.bak"), None);
+
+        // paths that do not start the argument or the value of an option
+        assert_eq!(rewrite("home/alex/x"), None);
+        assert_eq!(rewrite("see /home/alex/x"), None);
+        assert_eq!(rewrite("https://example.org/home/alex/x"), None);
+        assert_eq!(rewrite("key=/home/alex/x"), None);
+        assert_eq!(rewrite("--title=copy of /home/alex"), None);
+        assert_eq!(rewrite("--"), None);
+        assert_eq!(rewrite(""), None);
+
+        // arguments that are not valid utf-8
+        let arg = OsString::from_vec(b"/home/alex/\xff.txt".to_vec());
+        assert_eq!(
+            rewrite_arg(&arg, Path::new("/home/alex"), Path::new("/home/sam"))
+                .unwrap()
+                .as_bytes(),
+            b"/home/sam/\xff.txt"
+        );
+    }
+
+    #[test]
+    fn rewrites_applications() {
+        let mut app: SessionApplication = serde_json::from_str(
+            r#"{
+                "geom": { "x": 0, "y": 0, "width": 800, "height": 600, "minimized": false },
+                "pid": 10, "stable_seq": 1, "window_class": "code", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": { "CmdLine": ["/home/alex/.local/bin/code", "--title", "/home/alex", "/home/alexandra"] },
+                "cwd": "/home/alex/src"
+            }"#,
+        )
+        .unwrap();
+
+        assert!(rewrite_home(&mut app, Path::new("/home/alex/"), Path::new("/home/sam")));
+        assert_eq!(
+            app.exec,
+            Exec::CmdLine(
+                ["/home/sam/.local/bin/code", "--title", "/home/sam", "/home/alexandra"]
+                    .map(OsString::from)
+                    .to_vec()
+            )
+        );
+        assert_eq!(app.cwd, Some(PathBuf::from("/home/sam/src")));
+
+        assert!(!rewrite_home(&mut app, Path::new("/home/alex"), Path::new("/home/sam")));
+    }
+
+    #[test]
+    fn foreign_origins() {
+        let origin = |host: &str, user: &str| Origin {
+            host: host.to_owned(),
+            user: user.to_owned(),
+            home: PathBuf::from(format!("/home/{user}")),
+        };
+
+        assert!(!origin("desktop", "alex").is_foreign(&origin("desktop", "alex")));
+        assert!(origin("laptop", "alex").is_foreign(&origin("desktop", "alex")));
+        assert!(origin("desktop", "sam").is_foreign(&origin("desktop", "alex")));
+        assert_eq!(origin("desktop", "alex").to_string(), "alex@desktop");
+    }
+}
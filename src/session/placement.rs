@@ -0,0 +1,1002 @@
+//! Matching of saved windows to the windows that are currently open, and moving them to their saved positions.
+//!
+//! Some applications (e.g. Spotify or browsers) change their WM_CLASS after startup,
+//! so a freshly launched instance does not necessarily report the class that was saved.
+//! Because of this matching falls back through a chain of increasingly loose methods,
+//! see [`MatchMethod`]. The matched windows are then moved by the [`Placer`].
+
+use super::{
+    launch::Launched,
+    list_decodable_windows, state_hooks,
+    wait::{detect_shell_restart, poll, shell_owner, SHELL_RESTART_TIMEOUT},
+    DisplayServer, RestoreOptions, SessionApplication,
+};
+use crate::dbus::{self, MetaWindow, WindowCtlProxy, WindowGeom};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
+};
+
+/// The methods used to match a saved window to an open one, in the order they are tried
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchMethod {
+    /// The window carries the startup notification id or the pid the saved window was launched with
+    Launch,
+
+    /// The window classes are equal
+    ExactClass,
+
+    /// The window classes are equal when ignoring case
+    CaseInsensitiveClass,
+
+    /// The gtk app ids or the sandboxed app ids are equal
+    AppId,
+
+    /// The window is the newest unclaimed window that appeared after the applications were launched.
+    /// This is only considered once no better match can be expected anymore.
+    NewestUnclaimed,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Match<'w> {
+    /// The index of the saved window
+    pub saved: usize,
+    pub window: &'w MetaWindow,
+    pub method: MatchMethod,
+}
+
+fn matches_by(method: MatchMethod, saved: &MetaWindow, launched: Option<&Launched>, window: &MetaWindow) -> bool {
+    fn non_empty_eq(a: &str, b: &str) -> bool {
+        !a.is_empty() && a == b
+    }
+
+    match method {
+        MatchMethod::Launch => launched.is_some_and(|launched| {
+            launched
+                .startup_id
+                .as_deref()
+                .is_some_and(|id| non_empty_eq(id, &window.startup_id))
+                || launched.pid == Some(window.pid)
+        }),
+        MatchMethod::ExactClass => non_empty_eq(&saved.window_class, &window.window_class),
+        MatchMethod::CaseInsensitiveClass => {
+            !saved.window_class.is_empty() && saved.window_class.eq_ignore_ascii_case(&window.window_class)
+        },
+        MatchMethod::AppId => {
+            non_empty_eq(&saved.gtk_app_id, &window.gtk_app_id)
+                || non_empty_eq(&saved.sandboxed_app_id, &window.sandboxed_app_id)
+        },
+        MatchMethod::NewestUnclaimed => false,
+    }
+}
+
+/// Matches saved windows to currently open windows.
+///
+/// - `saved` are the saved windows, given as `(index, window)` so that callers can pass a subset
+/// - `open` is a snapshot of the currently open windows
+/// - `launches` are the launches of the saved windows, indexed like `saved`; missing entries mean nothing is known
+/// - `claimed` are the `stable_seq`s of windows that were already matched previously and are not available anymore
+/// - `launched_after` is the highest `stable_seq` that existed before launching,
+///   if given [`MatchMethod::NewestUnclaimed`] is used as the last resort
+/// - `accepts` decides whether an open window may be matched to a saved window at all (given its index),
+///   see [`WaitRules`](super::wait::WaitRules)
+///
+/// Every method of the chain is applied to all saved windows before the next one is tried,
+/// so a looser method can never steal a window that a stricter one would match to a different saved window.
+/// Within a method, saved windows are matched in order to the oldest available open window.
+pub fn match_windows<'s, 'w, I, A>(
+    saved: I,
+    open: &'w [MetaWindow],
+    launches: &[Launched],
+    claimed: &HashSet<u32>,
+    launched_after: Option<u32>,
+    accepts: A,
+) -> Vec<Match<'w>>
+where
+    I: IntoIterator<Item = (usize, &'s MetaWindow)>,
+    A: Fn(usize, &MetaWindow) -> bool,
+{
+    let mut open: Vec<&MetaWindow> = open.iter().filter(|w| !claimed.contains(&w.stable_seq)).collect();
+    open.sort_by_key(|w| w.stable_seq);
+
+    let mut unmatched: Vec<(usize, &MetaWindow)> = saved.into_iter().collect();
+    let mut matches = Vec::new();
+
+    for method in [
+        MatchMethod::Launch,
+        MatchMethod::ExactClass,
+        MatchMethod::CaseInsensitiveClass,
+        MatchMethod::AppId,
+    ] {
+        unmatched.retain(|&(saved_ix, saved_window)| {
+            let launched = launches.get(saved_ix);
+
+            match open
+                .iter()
+                .position(|w| matches_by(method, saved_window, launched, w) && accepts(saved_ix, w))
+            {
+                Some(pos) => {
+                    matches.push(Match { saved: saved_ix, window: open.remove(pos), method });
+                    false
+                },
+                None => true,
+            }
+        });
+    }
+
+    if let Some(launched_after) = launched_after {
+        let mut newest: Vec<&MetaWindow> = open.into_iter().filter(|w| w.stable_seq > launched_after).collect();
+
+        for (saved_ix, _) in unmatched {
+            if let Some(pos) = newest.iter().rposition(|w| accepts(saved_ix, w)) {
+                matches.push(Match {
+                    saved: saved_ix,
+                    window: newest.remove(pos),
+                    method: MatchMethod::NewestUnclaimed,
+                });
+            }
+        }
+    }
+
+    matches
+}
+
+/// The applications that already have an open window, matched like [`Placer::place`] does but without moving anything
+pub(super) fn open_applications(
+    conn: &WindowCtlProxy,
+    applications: &[SessionApplication],
+    strict_dbus: bool,
+) -> zbus::Result<HashSet<usize>> {
+    let open = match list_decodable_windows(conn) {
+        Ok(open) => open,
+        Err(e) if strict_dbus => return Err(e),
+        Err(e) => {
+            eprintln!("unable to list windows, launching all applications: {e}");
+            return Ok(HashSet::new());
+        },
+    };
+
+    let saved = applications.iter().enumerate().map(|(ix, app)| (ix, &app.window));
+
+    Ok(
+        match_windows(saved, &open, &[], &HashSet::new(), None, |_, _| true)
+            .into_iter()
+            .map(|m| m.saved)
+            .collect(),
+    )
+}
+
+/// Moves the windows of applications to their saved positions,
+/// remembering which windows were already placed across calls to [`Placer::place`]
+pub(super) struct Placer<'a> {
+    conn: &'a WindowCtlProxy<'a>,
+    applications: &'a [SessionApplication],
+
+    /// The `stable_seq`s of the windows that were already placed
+    claimed: HashSet<u32>,
+
+    /// Older extensions can only move windows by class
+    by_stable_seq: bool,
+
+    /// Older extensions can only move one window per call
+    batch: bool,
+
+    options: RestoreOptions<'a>,
+
+    /// When each window was first seen, by `stable_seq`
+    first_seen: HashMap<u32, Instant>,
+
+    /// Older extensions cannot set the state of windows
+    set_state: bool,
+
+    /// The [`state_hooks`] the extension failed to restore, which are not tried again
+    failed_hooks: HashSet<&'static str>,
+
+    /// The windows that are at their saved position, by `stable_seq` with the index of their application
+    placed: Vec<(u32, usize)>,
+
+    /// The unique bus name of the running gnome-shell, to notice when it restarts
+    shell_owner: Option<String>,
+
+    /// When the window of each application was first waited for, by the index of the application
+    waiting_since: HashMap<usize, Instant>,
+
+    /// The applications that were given up on after [`RestoreOptions::timeout_per_app`]
+    pub(super) timed_out: Vec<usize>,
+}
+
+impl<'a> Placer<'a> {
+    pub(super) fn new(conn: &'a WindowCtlProxy<'a>, applications: &'a [SessionApplication], options: RestoreOptions<'a>) -> Self {
+        Placer {
+            conn,
+            applications,
+            claimed: HashSet::new(),
+            by_stable_seq: true,
+            batch: true,
+            options,
+            first_seen: HashMap::new(),
+            set_state: true,
+            failed_hooks: HashSet::new(),
+            placed: Vec::new(),
+            shell_owner: shell_owner(conn),
+            waiting_since: HashMap::new(),
+            timed_out: Vec::new(),
+        }
+    }
+
+    /// Moves the windows of the `pending` applications to their saved positions, retrying until `wait_timeout`
+    /// has passed to give windows that appear late or change their class after startup a chance to settle.
+    /// Applications are given up on early once they were waited for longer than [`RestoreOptions::timeout_per_app`].
+    /// If gnome-shell restarted in the meantime, it is waited for first. The proxy sends to the shell's well-known
+    /// name and reaches the new shell as it is, but the windows the old one knew are forgotten.
+    /// Returns the applications for which no window was found, or the first D-Bus error with
+    /// [`RestoreOptions::strict_dbus`].
+    pub(super) fn place(
+        &mut self,
+        mut pending: Vec<usize>,
+        launches: &[Launched],
+        mut launched_after: Option<u32>,
+        wait_timeout: Duration,
+    ) -> zbus::Result<Vec<usize>> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+        // windows that should be minimized have focus until they are, so they are looked for more often
+        const MINIMIZED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+        let conn = self.conn;
+
+        if detect_shell_restart(&mut self.shell_owner, SHELL_RESTART_TIMEOUT, || shell_owner(conn)) {
+            // the windows of the new shell have new stable_seqs, the old ones mean nothing anymore
+            self.claimed.clear();
+            self.first_seen.clear();
+            self.placed.clear();
+            launched_after = None;
+        }
+
+        // looking for already open windows is not waiting for them
+        if !wait_timeout.is_zero() {
+            let now = Instant::now();
+
+            for &ix in &pending {
+                self.waiting_since.entry(ix).or_insert(now);
+            }
+        }
+
+        let mut given_up = Vec::new();
+
+        poll(wait_timeout, None, |timed_out| {
+            match list_decodable_windows(self.conn) {
+                Ok(open) => {
+                    let now = Instant::now();
+
+                    for w in &open {
+                        self.first_seen.entry(w.stable_seq).or_insert(now);
+                    }
+
+                    // the last resort is only used once no better match can be expected anymore
+                    let launched_after = launched_after.filter(|_| timed_out);
+                    let saved = pending.iter().map(|&ix| (ix, &self.applications[ix].window));
+                    let accepts = |ix: usize, w: &MetaWindow| {
+                        let open_for = now.saturating_duration_since(self.first_seen[&w.stable_seq]);
+                        self.options.wait_rules.accepts(
+                            &self.applications[ix].window.window_class,
+                            w,
+                            open_for,
+                            timed_out,
+                        )
+                    };
+                    let mut moves = Vec::new();
+
+                    for m in match_windows(saved, &open, launches, &self.claimed, launched_after, accepts) {
+                        let app = &self.applications[m.saved];
+
+                        match self.keep_position(app, m.window) {
+                            // minimizing is never skipped, the window would keep the focus
+                            Some(reason) if !(self.options.no_activate_minimized && app.window.geom.minimized) => {
+                                eprintln!("not moving '{name}': {reason}", name = app.name());
+
+                                let class = &app.window.window_class;
+
+                                if !self.options.self_placing.contains(class)
+                                    && !self.options.skip_geometry.contains(class)
+                                {
+                                    self.placed.push((m.window.stable_seq, m.saved));
+                                }
+
+                                self.claimed.insert(m.window.stable_seq);
+                                pending.retain(|&ix| ix != m.saved);
+                                self.restore_state(app, m.window)?;
+                            },
+                            _ => moves.push(m),
+                        }
+                    }
+
+                    if self.options.no_activate_minimized {
+                        // minimize first to give the focus back as soon as possible
+                        moves.sort_by_key(|m| !self.applications[m.saved].window.geom.minimized);
+                    }
+
+                    let geoms: Vec<_> = moves
+                        .iter()
+                        .map(|m| (m.window, self.applications[m.saved].window.geom))
+                        .collect();
+                    let mut placed = Vec::new();
+
+                    // moving a window that is already minimized is ignored by many compositors
+                    let frames = frames_before_minimizing(&geoms);
+
+                    for ((w, _), res) in frames.iter().zip(self.set_window_geoms(&frames)?) {
+                        match res {
+                            Ok(_) => {},
+                            Err(e) if self.options.strict_dbus => return Err(e),
+                            Err(e) => eprintln!("Error moving window '{class}': {e:?}", class = w.window_class),
+                        }
+                    }
+
+                    for (m, res) in moves.iter().zip(self.set_window_geoms(&geoms)?) {
+                        let app = &self.applications[m.saved];
+
+                        match res {
+                            Ok(_) => {
+                                placed.push((m.window, app.window.geom));
+                                self.placed.push((m.window.stable_seq, m.saved));
+                                self.claimed.insert(m.window.stable_seq);
+                                pending.retain(|&ix| ix != m.saved);
+                                self.restore_state(app, m.window)?;
+
+                                if m.method != MatchMethod::ExactClass {
+                                    eprintln!(
+                                        "placed '{saved}' on window '{class}' via {method:?}",
+                                        saved = app.name(),
+                                        class = m.window.window_class,
+                                        method = m.method
+                                    );
+                                }
+                            },
+                            Err(e) if self.options.strict_dbus => return Err(e),
+                            Err(e) => eprintln!("Error moving window '{class}': {e:?}", class = m.window.window_class),
+                        }
+                    }
+
+                    if self.options.display_server == DisplayServer::Wayland && !placed.is_empty() {
+                        self.reapply_unsettled(&placed)?;
+                    }
+                },
+                Err(e) if self.options.strict_dbus => return Err(e),
+                Err(e) => eprintln!("unable to list windows: {e}"),
+            }
+
+            if let Some(limit) = self.options.timeout_per_app {
+                let now = Instant::now();
+                let expired = |ix: &usize| {
+                    self.waiting_since
+                        .get(ix)
+                        .is_some_and(|&since| now.saturating_duration_since(since) >= limit)
+                };
+
+                for &ix in pending.iter().filter(|ix| expired(ix)) {
+                    eprintln!(
+                        "giving up on '{name}': its window did not appear within {limit:?}",
+                        name = self.applications[ix].name()
+                    );
+                    given_up.push(ix);
+                }
+
+                pending.retain(|ix| !given_up.contains(ix));
+            }
+
+            if pending.is_empty() {
+                return Ok(None);
+            }
+
+            if self.options.no_activate_minimized
+                && pending.iter().any(|&ix| self.applications[ix].window.geom.minimized)
+            {
+                Ok(Some(MINIMIZED_POLL_INTERVAL))
+            } else {
+                Ok(Some(POLL_INTERVAL))
+            }
+        })?;
+
+        self.timed_out.extend_from_slice(&given_up);
+        pending.extend(given_up);
+        Ok(pending)
+    }
+
+    /// Moves the placed windows back to their saved positions for [`RestoreOptions::reassert_geometry`],
+    /// except for minimized ones and those of [`RestoreOptions::no_reassert`]
+    pub(super) fn reassert(&mut self) -> zbus::Result<()> {
+        let placed: Vec<_> = self
+            .placed
+            .iter()
+            .map(|&(stable_seq, ix)| (stable_seq, &self.applications[ix].window))
+            .filter(|(_, saved)| !saved.geom.minimized && !self.options.no_reassert.contains(&saved.window_class))
+            .map(|(stable_seq, saved)| (stable_seq, saved.geom))
+            .collect();
+
+        if self.options.reassert_geometry.is_zero() || placed.is_empty() {
+            return Ok(());
+        }
+
+        let (conn, options) = (self.conn, self.options);
+
+        let list = || match list_decodable_windows(conn) {
+            Err(e) if !options.strict_dbus => {
+                eprintln!("unable to list windows: {e}");
+                Ok(Vec::new())
+            },
+            res => res,
+        };
+
+        let set = |moves: &[(&MetaWindow, WindowGeom)]| {
+            for ((w, _), res) in moves.iter().zip(self.set_window_geoms(moves)?) {
+                match res {
+                    Ok(_) => {},
+                    Err(e) if options.strict_dbus => return Err(e),
+                    Err(e) => eprintln!("Error moving window '{class}': {e:?}", class = w.window_class),
+                }
+            }
+
+            Ok(())
+        };
+
+        reassert_geometry(
+            &placed,
+            options.reassert_geometry,
+            options.geometry_tolerance,
+            options.cancel,
+            list,
+            set,
+        )
+        .map(|_| ())
+    }
+
+    /// On Wayland a resize only takes effect once the client acknowledged it, which can move the window
+    /// away from where it was placed, so the geometry is applied once more to windows where it did not stick
+    fn reapply_unsettled(&mut self, placed: &[(&MetaWindow, WindowGeom)]) -> zbus::Result<()> {
+        const CONFIGURE_DELAY: Duration = Duration::from_millis(200);
+
+        std::thread::sleep(CONFIGURE_DELAY);
+
+        let open = match list_decodable_windows(self.conn) {
+            Ok(open) => open,
+            Err(e) if self.options.strict_dbus => return Err(e),
+            Err(e) => {
+                eprintln!("unable to list windows: {e}");
+                return Ok(());
+            },
+        };
+
+        let unsettled: Vec<_> = placed
+            .iter()
+            .filter(|(_, geom)| !geom.minimized)
+            .filter_map(|&(window, geom)| {
+                open.iter()
+                    .find(|w| w.stable_seq == window.stable_seq)
+                    .filter(|w| !same_rect(w.geom, geom))
+                    .map(|w| (w, geom))
+            })
+            .collect();
+
+        for ((w, _), res) in unsettled.iter().zip(self.set_window_geoms(&unsettled)?) {
+            match res {
+                Ok(_) => {},
+                Err(e) if self.options.strict_dbus => return Err(e),
+                Err(e) => eprintln!("Error moving window '{class}': {e:?}", class = w.window_class),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores the [`state_hooks`] state saved for `app` on its window
+    fn restore_state(&mut self, app: &SessionApplication, window: &MetaWindow) -> zbus::Result<()> {
+        if !self.set_state || !self.by_stable_seq {
+            return Ok(());
+        }
+
+        for (key, value) in state_hooks::restore(&app.extra_state) {
+            if self.failed_hooks.contains(key) {
+                continue;
+            }
+
+            match self.conn.set_window_state_by_stable_seq(window.stable_seq, key, &value) {
+                Ok(_) => {},
+                Err(e) if dbus::is_unknown_method(&e) => {
+                    eprintln!("the extension cannot set the state of windows (e.g. `{key}`), update it to restore it");
+                    self.set_state = false;
+                    return Ok(());
+                },
+                Err(e) if self.options.strict_dbus => return Err(e),
+                Err(e) => {
+                    eprintln!(
+                        "unable to restore `{key}` of '{name}': {e}, not trying again",
+                        name = app.name()
+                    );
+                    self.failed_hooks.insert(key);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Why the window of `app` should stay where it is, if it should
+    fn keep_position(&self, app: &SessionApplication, window: &MetaWindow) -> Option<&'static str> {
+        if self.options.skip_geometry.contains(&app.window.window_class) {
+            Some("its geometry is skipped")
+        } else if self.options.self_placing.contains(&app.window.window_class) {
+            Some("it positions itself")
+        } else if within_tolerance(window.geom, app.window.geom, self.options.geometry_tolerance) {
+            Some("it is already in place")
+        } else {
+            None
+        }
+    }
+
+    /// Moves all windows in one call if the extension supports it, one by one otherwise.
+    /// Only fails if moving them at once fails with [`RestoreOptions::strict_dbus`].
+    fn set_window_geoms(&mut self, moves: &[(&MetaWindow, WindowGeom)]) -> zbus::Result<Vec<zbus::Result<bool>>> {
+        if self.batch && self.by_stable_seq && !moves.is_empty() {
+            let batch: Vec<_> = moves.iter().map(|(w, geom)| (w.stable_seq, *geom)).collect();
+
+            match self.conn.set_window_geoms_by_stable_seq(&batch) {
+                Ok(results) if results.len() == moves.len() => return Ok(results.into_iter().map(Ok).collect()),
+                Ok(results) => eprintln!(
+                    "moving {n} windows at once returned {m} results, moving them one by one",
+                    n = moves.len(),
+                    m = results.len()
+                ),
+                Err(e) if dbus::is_unknown_method(&e) => self.batch = false,
+                Err(e) if self.options.strict_dbus => return Err(e),
+                Err(e) => eprintln!("unable to move windows at once: {e}, moving them one by one"),
+            }
+        }
+
+        Ok(moves
+            .iter()
+            .map(|&(window, geom)| self.set_window_geom(window, geom))
+            .collect())
+    }
+
+    fn set_window_geom(&mut self, window: &MetaWindow, geom: WindowGeom) -> zbus::Result<bool> {
+        if self.by_stable_seq {
+            match self.conn.set_window_geom_by_stable_seq(window.stable_seq, geom) {
+                Err(e) if dbus::is_unknown_method(&e) => self.by_stable_seq = false,
+                res => return res,
+            }
+        }
+
+        // the extension would move whichever window without a class it finds first
+        if window.window_class.is_empty() {
+            eprintln!("not moving a window without a class, update the extension to move it");
+            return Ok(false);
+        }
+
+        self.conn.set_window_geom_by_class(&window.window_class, geom)
+    }
+}
+
+/// Moves every window that has the same geometry as earlier ones `step` pixels right and down per earlier window,
+/// minimized windows are left alone as they do not hide anything
+pub(super) fn stagger_coincident<'g>(geoms: impl Iterator<Item = &'g mut WindowGeom>, step: u32) {
+    let mut seen: HashMap<(i32, i32, i32, i32), i32> = HashMap::new();
+
+    for geom in geoms.filter(|geom| !geom.minimized) {
+        let earlier = seen.entry((geom.x, geom.y, geom.width, geom.height)).or_default();
+        let offset = earlier.saturating_mul(i32::try_from(step).unwrap_or(i32::MAX));
+
+        geom.x = geom.x.saturating_add(offset);
+        geom.y = geom.y.saturating_add(offset);
+        *earlier += 1;
+    }
+}
+
+fn same_rect(a: WindowGeom, b: WindowGeom) -> bool {
+    (a.x, a.y, a.width, a.height) == (b.x, b.y, b.width, b.height)
+}
+
+/// The moves that set the saved rect of windows that are to be minimized while they are not minimized yet,
+/// so that they have their saved size once they are unminimized. They are applied before `moves`,
+/// as the rect of a window that is already minimized is ignored by many compositors.
+fn frames_before_minimizing<'w>(moves: &[(&'w MetaWindow, WindowGeom)]) -> Vec<(&'w MetaWindow, WindowGeom)> {
+    moves
+        .iter()
+        .filter(|(window, geom)| geom.minimized && !window.geom.minimized)
+        .map(|&(window, geom)| (window, WindowGeom { minimized: false, ..geom }))
+        .collect()
+}
+
+/// Whether every edge of `live` is at most `tolerance` pixels away from the corresponding edge of `saved`,
+/// windows that are minimized but should not be (or the other way around) are never within tolerance
+fn within_tolerance(live: WindowGeom, saved: WindowGeom, tolerance: u32) -> bool {
+    let edges = |g: WindowGeom| [g.x, g.y, g.x + g.width, g.y + g.height];
+
+    live.minimized == saved.minimized
+        && edges(live)
+            .into_iter()
+            .zip(edges(saved))
+            .all(|(a, b)| a.abs_diff(b) <= tolerance)
+}
+
+/// Watches the `placed` windows (by `stable_seq`, with their saved geometry) for `duration` and moves the ones whose
+/// geometry is not within `tolerance` of the saved one back, see [`RestoreOptions::reassert_geometry`].
+/// `list` lists the open windows, `set` moves windows. Returns how often windows were moved back.
+fn reassert_geometry<L, S>(
+    placed: &[(u32, WindowGeom)],
+    duration: Duration,
+    tolerance: u32,
+    cancel: Option<&AtomicBool>,
+    mut list: L,
+    mut set: S,
+) -> zbus::Result<usize>
+where
+    L: FnMut() -> zbus::Result<Vec<MetaWindow>>,
+    S: FnMut(&[(&MetaWindow, WindowGeom)]) -> zbus::Result<()>,
+{
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    let mut reasserted = 0;
+
+    poll(duration, cancel, |_| {
+        let open = list()?;
+
+        let moved: Vec<_> = placed
+            .iter()
+            .filter_map(|&(stable_seq, geom)| {
+                open.iter()
+                    .find(|w| w.stable_seq == stable_seq)
+                    .filter(|w| !within_tolerance(w.geom, geom, tolerance))
+                    .map(|w| (w, geom))
+            })
+            .collect();
+
+        for (w, _) in &moved {
+            eprintln!("moving '{class}' back to its saved position", class = w.window_class);
+        }
+
+        if !moved.is_empty() {
+            set(&moved)?;
+            reasserted += moved.len();
+        }
+
+        Ok(Some(POLL_INTERVAL))
+    })?;
+
+    Ok(reasserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        frames_before_minimizing, match_windows, reassert_geometry, stagger_coincident, within_tolerance, Launched,
+        Match, MatchMethod,
+    };
+    use crate::dbus::{MetaWindow, WindowGeom};
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashSet,
+        sync::atomic::AtomicBool,
+        time::Duration,
+    };
+
+    fn summarize(matches: Vec<Match>) -> Vec<(usize, u32, MatchMethod)> {
+        let mut v: Vec<_> = matches
+            .into_iter()
+            .map(|m| (m.saved, m.window.stable_seq, m.method))
+            .collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn exact_class_is_preferred() {
+        let saved = [MetaWindow { stable_seq: 1, ..MetaWindow::with_class("Spotify") }];
+        let open = [
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("spotify") },
+            MetaWindow { stable_seq: 11, ..MetaWindow::with_class("Spotify") },
+        ];
+
+        let m = match_windows(saved.iter().enumerate(), &open, &[], &HashSet::new(), None, |_, _| true);
+        assert_eq!(summarize(m), vec![(0, 11, MatchMethod::ExactClass)]);
+    }
+
+    #[test]
+    fn case_insensitive_class() {
+        let saved = [MetaWindow { stable_seq: 1, ..MetaWindow::with_class("Spotify") }];
+        let open = [MetaWindow { stable_seq: 10, ..MetaWindow::with_class("spotify") }];
+
+        let m = match_windows(saved.iter().enumerate(), &open, &[], &HashSet::new(), None, |_, _| true);
+        assert_eq!(summarize(m), vec![(0, 10, MatchMethod::CaseInsensitiveClass)]);
+    }
+
+    #[test]
+    fn app_ids() {
+        let saved = [
+            MetaWindow {
+                stable_seq: 1,
+                sandboxed_app_id: "org.mozilla.firefox".to_owned(),
+                ..MetaWindow::with_class("firefox")
+            },
+            MetaWindow {
+                stable_seq: 2,
+                gtk_app_id: "org.gnome.Terminal".to_owned(),
+                ..MetaWindow::with_class("gnome-terminal-server")
+            },
+        ];
+        let open = [
+            MetaWindow {
+                stable_seq: 10,
+                sandboxed_app_id: "org.mozilla.firefox".to_owned(),
+                ..MetaWindow::with_class("Navigator")
+            },
+            MetaWindow { stable_seq: 11, gtk_app_id: "org.gnome.Terminal".to_owned(), ..Default::default() },
+        ];
+
+        let m = match_windows(saved.iter().enumerate(), &open, &[], &HashSet::new(), None, |_, _| true);
+        assert_eq!(
+            summarize(m),
+            vec![(0, 10, MatchMethod::AppId), (1, 11, MatchMethod::AppId)]
+        );
+    }
+
+    #[test]
+    fn looser_methods_do_not_steal_windows() {
+        // the first saved window would match the second open window case insensitively,
+        // but that one is an exact match for the second saved window
+        let saved = [
+            MetaWindow { stable_seq: 1, ..MetaWindow::with_class("Code") },
+            MetaWindow { stable_seq: 2, ..MetaWindow::with_class("code") },
+        ];
+        let open = [
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("code") },
+            MetaWindow { stable_seq: 11, ..MetaWindow::with_class("CODE") },
+        ];
+
+        let m = match_windows(saved.iter().enumerate(), &open, &[], &HashSet::new(), None, |_, _| true);
+        assert_eq!(
+            summarize(m),
+            vec![
+                (0, 11, MatchMethod::CaseInsensitiveClass),
+                (1, 10, MatchMethod::ExactClass)
+            ]
+        );
+    }
+
+    #[test]
+    fn claimed_windows_are_skipped() {
+        let saved = [MetaWindow { stable_seq: 1, ..MetaWindow::with_class("kitty") }];
+        let open = [
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 11, ..MetaWindow::with_class("kitty") },
+        ];
+
+        let m = match_windows(
+            saved.iter().enumerate(),
+            &open,
+            &[],
+            &HashSet::from([10]),
+            None,
+            |_, _| true,
+        );
+        assert_eq!(summarize(m), vec![(0, 11, MatchMethod::ExactClass)]);
+    }
+
+    #[test]
+    fn newest_unclaimed_window() {
+        let saved = [
+            MetaWindow { stable_seq: 1, ..MetaWindow::with_class("Spotify") },
+            MetaWindow { stable_seq: 2, ..MetaWindow::with_class("firefox") },
+        ];
+        let open = [
+            MetaWindow { stable_seq: 5, ..Default::default() },
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("firefox") },
+            MetaWindow { stable_seq: 11, ..Default::default() },
+            MetaWindow { stable_seq: 12, ..Default::default() },
+        ];
+
+        let m = match_windows(saved.iter().enumerate(), &open, &[], &HashSet::new(), None, |_, _| true);
+        assert_eq!(summarize(m), vec![(1, 10, MatchMethod::ExactClass)]);
+
+        let m = match_windows(
+            saved.iter().enumerate(),
+            &open,
+            &[],
+            &HashSet::new(),
+            Some(9),
+            |_, _| true,
+        );
+        assert_eq!(
+            summarize(m),
+            vec![(0, 12, MatchMethod::NewestUnclaimed), (1, 10, MatchMethod::ExactClass)]
+        );
+
+        // windows that existed before launching are never considered
+        let open = [MetaWindow { stable_seq: 5, ..Default::default() }];
+        let m = match_windows(
+            saved.iter().enumerate(),
+            &open,
+            &[],
+            &HashSet::new(),
+            Some(9),
+            |_, _| true,
+        );
+        assert_eq!(summarize(m), vec![]);
+    }
+
+    #[test]
+    fn launch_identity_is_preferred() {
+        let saved = [
+            MetaWindow { stable_seq: 1, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 2, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 3, ..MetaWindow::with_class("kitty") },
+        ];
+
+        let mut open = [
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 11, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 12, ..MetaWindow::with_class("kitty") },
+        ];
+        open[1].startup_id = "gnome-session-restore-1-0_TIME0".to_string();
+        open[2].pid = 1234;
+
+        let launches = [
+            Launched {
+                startup_id: Some("gnome-session-restore-1-0_TIME0".to_string()),
+                ..Launched::default()
+            },
+            Launched::default(),
+            Launched { pid: Some(1234), ..Launched::default() },
+        ];
+
+        let m = match_windows(
+            saved.iter().enumerate(),
+            &open,
+            &launches,
+            &HashSet::new(),
+            None,
+            |_, _| true,
+        );
+        assert_eq!(
+            summarize(m),
+            vec![
+                (0, 11, MatchMethod::Launch),
+                (1, 10, MatchMethod::ExactClass),
+                (2, 12, MatchMethod::Launch)
+            ]
+        );
+    }
+
+    #[test]
+    fn saved_subset() {
+        let saved = [
+            MetaWindow { stable_seq: 1, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 2, ..MetaWindow::with_class("firefox") },
+        ];
+        let open = [
+            MetaWindow { stable_seq: 10, ..MetaWindow::with_class("kitty") },
+            MetaWindow { stable_seq: 11, ..MetaWindow::with_class("firefox") },
+        ];
+
+        let m = match_windows(
+            saved.iter().enumerate().skip(1),
+            &open,
+            &[],
+            &HashSet::new(),
+            None,
+            |_, _| true,
+        );
+        assert_eq!(summarize(m), vec![(1, 11, MatchMethod::ExactClass)]);
+    }
+
+    #[test]
+    fn geometry_tolerance() {
+        let geom = |x, y, width, height, minimized| WindowGeom { x, y, width, height, minimized };
+        let saved = geom(100, 100, 800, 600, false);
+
+        assert!(within_tolerance(saved, saved, 0));
+        assert!(within_tolerance(geom(103, 98, 800, 600, false), saved, 3));
+        // the right edge is 5 pixels away
+        assert!(!within_tolerance(geom(103, 98, 802, 600, false), saved, 3));
+        assert!(!within_tolerance(geom(100, 100, 800, 600, true), saved, 100));
+    }
+
+    #[test]
+    fn reasserts_geometry() {
+        let geom = |x, y| WindowGeom { x, y, width: 800, height: 600, minimized: false };
+        let window = |stable_seq, window_class, geom| MetaWindow {
+            geom,
+            pid: 1,
+            stable_seq,
+            ..MetaWindow::with_class(window_class)
+        };
+
+        // a shell in which spotify moves its window to where it was last time once after it was placed,
+        // and kitty nudges its window by a few pixels
+        let shell = RefCell::new(vec![(1, "spotify", geom(100, 100)), (2, "kitty", geom(900, 100))]);
+        let lists = Cell::new(0);
+        let list = || {
+            lists.set(lists.get() + 1);
+            let mut shell = shell.borrow_mut();
+
+            if lists.get() == 2 {
+                shell[0].2 = geom(0, 0);
+                shell[1].2 = geom(902, 100);
+            }
+
+            Ok(shell
+                .iter()
+                .map(|&(seq, class, geom)| window(seq, class, geom))
+                .collect())
+        };
+        let set = |moves: &[(&MetaWindow, WindowGeom)]| {
+            for (w, geom) in moves {
+                shell
+                    .borrow_mut()
+                    .iter_mut()
+                    .find(|(seq, ..)| *seq == w.stable_seq)
+                    .unwrap()
+                    .2 = *geom;
+            }
+            Ok(())
+        };
+
+        let placed = [(1, geom(100, 100)), (2, geom(900, 100)), (3, geom(0, 0))];
+        let reasserted = reassert_geometry(&placed, Duration::from_millis(700), 3, None, list, set).unwrap();
+
+        assert_eq!(reasserted, 1);
+        assert!(lists.get() >= 3);
+        assert_eq!(shell.borrow()[0].2.x, 100);
+        assert_eq!(shell.borrow()[1].2.x, 902);
+
+        // cancelled watches end after one look at the windows
+        let cancel = AtomicBool::new(true);
+        let lists = Cell::new(0);
+        let list = || {
+            lists.set(lists.get() + 1);
+            Ok(Vec::new())
+        };
+
+        reassert_geometry(&placed, Duration::from_secs(60), 0, Some(&cancel), list, |_| Ok(())).unwrap();
+        assert_eq!(lists.get(), 1);
+    }
+
+    #[test]
+    fn sizes_windows_before_minimizing() {
+        let geom = |x, minimized| WindowGeom { x, y: 0, width: 800, height: 600, minimized };
+        let window = |minimized| MetaWindow { geom: geom(0, minimized), ..MetaWindow::with_class("kitty") };
+        let (open, already_minimized) = (window(false), window(true));
+
+        let moves = [
+            (&open, geom(100, true)),
+            (&open, geom(200, false)),
+            (&already_minimized, geom(300, true)),
+        ];
+
+        let frames: Vec<_> = frames_before_minimizing(&moves)
+            .into_iter()
+            .map(|(_, g)| (g.x, g.minimized))
+            .collect();
+        assert_eq!(frames, vec![(100, false)]);
+    }
+
+    #[test]
+    fn staggers_coincident_windows() {
+        let geom = |x, y, minimized| WindowGeom { x, y, width: 800, height: 600, minimized };
+        let mut geoms = [
+            geom(0, 0, false),
+            geom(0, 0, false),
+            geom(100, 0, false),
+            geom(0, 0, true),
+            geom(0, 0, false),
+        ];
+
+        stagger_coincident(geoms.iter_mut(), 30);
+
+        assert_eq!(
+            geoms.iter().map(|g| (g.x, g.y)).collect::<Vec<_>>(),
+            vec![(0, 0), (30, 30), (100, 0), (0, 0), (60, 60)]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Moving sessions between machines that install applications under different prefixes,
+//! e.g. flatpaks in `/var/lib/flatpak` on one and in `~/.local/share/flatpak` on the other.
+
+use crate::paths::{self, ExpandError};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Replaces the prefix `from` of saved desktop file paths with `to`, parsed from `FROM=TO`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathPrefixMap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PathPrefixMapParseError {
+    #[error("expected FROM=TO, e.g. `/var/lib/flatpak=~/.local/share/flatpak`")]
+    Syntax,
+
+    #[error(transparent)]
+    Expand(#[from] ExpandError),
+}
+
+impl FromStr for PathPrefixMap {
+    type Err = PathPrefixMapParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(PathPrefixMap {
+                from: paths::expand(OsStr::new(from))?,
+                to: paths::expand(OsStr::new(to))?,
+            }),
+            _ => Err(PathPrefixMapParseError::Syntax),
+        }
+    }
+}
+
+/// The path with the prefix of the first map that matches replaced, `None` if none matches.
+/// Prefixes match whole path components only.
+pub fn remap(path: &Path, maps: &[PathPrefixMap]) -> Option<PathBuf> {
+    maps.iter()
+        .find_map(|map| Some(map.to.join(path.strip_prefix(&map.from).ok()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remap, PathPrefixMap, PathPrefixMapParseError};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn remap_prefixes() {
+        let maps: Vec<PathPrefixMap> = ["/var/lib/flatpak=/home/user/.local/share/flatpak", "/usr=/opt"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        assert_eq!(
+            remap(
+                Path::new("/var/lib/flatpak/exports/share/applications/org.gimp.GIMP.desktop"),
+                &maps
+            ),
+            Some(PathBuf::from(
+                "/home/user/.local/share/flatpak/exports/share/applications/org.gimp.GIMP.desktop"
+            ))
+        );
+        assert_eq!(
+            remap(Path::new("/usr/share/applications/firefox.desktop"), &maps),
+            Some(PathBuf::from("/opt/share/applications/firefox.desktop"))
+        );
+        assert_eq!(remap(Path::new("/usr2/share/applications/x.desktop"), &maps), None);
+
+        assert_eq!("/usr".parse::<PathPrefixMap>(), Err(PathPrefixMapParseError::Syntax));
+        assert_eq!("=/usr".parse::<PathPrefixMap>(), Err(PathPrefixMapParseError::Syntax));
+    }
+}
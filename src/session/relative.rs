@@ -0,0 +1,119 @@
+//! Window geometry relative to the work area of the window's monitor.
+//!
+//! Sessions that move between machines with different resolutions (e.g. a 1440p desktop and a 1080p laptop)
+//! keep the share of the monitor a window covers instead of its pixels. The pixels are saved as well,
+//! so sessions with relative geometry still restore exactly on the machine they were saved on.
+
+use crate::dbus::{Rect, WindowGeom};
+use serde::{Deserialize, Serialize};
+
+/// Windows are never restored smaller than this, so that rounding tiny fractions does not make them disappear
+const MIN_SIZE: i32 = 32;
+
+/// A window rect as fractions of the work area of its monitor, `x` and `y` from the top left corner of the work area
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelativeGeom {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl RelativeGeom {
+    /// The fractions of `area` that `geom` covers, `None` if `area` is empty
+    pub fn new(geom: WindowGeom, area: Rect) -> Option<Self> {
+        let local = Rect {
+            x: geom.x - area.x,
+            y: geom.y - area.y,
+            width: geom.width,
+            height: geom.height,
+        };
+
+        Self::from_local(local, area)
+    }
+
+    /// The fractions of `area` that `local` covers, which is already relative to the top left corner of `area`
+    /// (see [`MetaWindow::local_geom`](crate::dbus::MetaWindow::local_geom)), `None` if `area` is empty
+    pub fn from_local(local: Rect, area: Rect) -> Option<Self> {
+        if area.width <= 0 || area.height <= 0 {
+            return None;
+        }
+
+        let (width, height) = (f64::from(area.width), f64::from(area.height));
+
+        Some(RelativeGeom {
+            x: f64::from(local.x) / width,
+            y: f64::from(local.y) / height,
+            width: f64::from(local.width) / width,
+            height: f64::from(local.height) / height,
+        })
+    }
+
+    /// The rect in `area` these fractions describe, everything but the rect is taken from `geom`
+    pub fn resolve(self, area: Rect, geom: WindowGeom) -> WindowGeom {
+        let scale = |fraction: f64, len: i32| (fraction * f64::from(len)).round() as i32;
+
+        WindowGeom {
+            x: area.x + scale(self.x, area.width),
+            y: area.y + scale(self.y, area.height),
+            width: scale(self.width, area.width).max(MIN_SIZE),
+            height: scale(self.height, area.height).max(MIN_SIZE),
+            ..geom
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RelativeGeom, MIN_SIZE};
+    use crate::dbus::{Rect, WindowGeom};
+
+    fn geom(x: i32, y: i32, width: i32, height: i32) -> WindowGeom {
+        WindowGeom { x, y, width, height, minimized: false }
+    }
+
+    fn rect(g: WindowGeom) -> (i32, i32, i32, i32, bool) {
+        (g.x, g.y, g.width, g.height, g.minimized)
+    }
+
+    #[test]
+    fn between_resolutions() {
+        let desktop = Rect { x: 2560, y: 32, width: 2560, height: 1408 };
+        let laptop = Rect { x: 0, y: 32, width: 1920, height: 1048 };
+
+        // the right half of the desktop's second monitor
+        let relative = RelativeGeom::new(geom(3840, 32, 1280, 1408), desktop).unwrap();
+        assert_eq!(relative, RelativeGeom { x: 0.5, y: 0.0, width: 0.5, height: 1.0 });
+
+        let restored = relative.resolve(laptop, geom(0, 0, 0, 0));
+        assert_eq!(rect(restored), (960, 32, 960, 1048, false));
+
+        // and back without losing a pixel
+        let relative = RelativeGeom::new(restored, laptop).unwrap();
+        assert_eq!(rect(relative.resolve(desktop, restored)), (3840, 32, 1280, 1408, false));
+
+        // as reported relative to the work area by the extension
+        let local = Rect { x: 1280, y: 0, width: 1280, height: 1408 };
+        assert_eq!(
+            RelativeGeom::from_local(local, desktop),
+            RelativeGeom::new(geom(3840, 32, 1280, 1408), desktop)
+        );
+    }
+
+    #[test]
+    fn keeps_windows_visible() {
+        let area = Rect { x: 0, y: 0, width: 1920, height: 1080 };
+        let tiny = RelativeGeom { x: 0.25, y: 0.25, width: 0.001, height: 0.0 };
+
+        let minimized = WindowGeom { minimized: true, ..geom(0, 0, 0, 0) };
+        assert_eq!(
+            rect(tiny.resolve(area, minimized)),
+            (480, 270, MIN_SIZE, MIN_SIZE, true)
+        );
+
+        assert_eq!(
+            RelativeGeom::new(geom(0, 0, 10, 10), Rect { x: 0, y: 0, width: 0, height: 10 }),
+            None
+        );
+    }
+}
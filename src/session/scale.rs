@@ -0,0 +1,55 @@
+//! Scaling of windows saved on monitors with a different scale factor than the current ones, see `--dpi-aware`.
+//!
+//! A session saved on a standard display and restored on a HiDPI one (or the other way round) would otherwise
+//! place tiny or huge windows. Every window is scaled by the ratio of the current to the saved scale factor of its
+//! monitor, around the origin of the monitor's work area.
+
+use crate::dbus::{Rect, WindowGeom};
+
+/// The factor to scale windows from a monitor with the scale `saved` to one with the scale `current` by,
+/// `None` if they are the same or either of them is not a valid scale factor
+pub fn ratio(saved: f64, current: f64) -> Option<f64> {
+    let valid = |scale: f64| scale.is_finite() && scale > 0.0;
+
+    (valid(saved) && valid(current) && (saved - current).abs() > f64::EPSILON).then(|| current / saved)
+}
+
+/// Scales `geom` by `ratio`, keeping its origin relative to the origin of the work area `area`
+pub fn scale_within(geom: WindowGeom, area: Rect, ratio: f64) -> WindowGeom {
+    let scale = |value: i32| (f64::from(value) * ratio).round() as i32;
+
+    WindowGeom {
+        x: area.x + scale(geom.x - area.x),
+        y: area.y + scale(geom.y - area.y),
+        width: scale(geom.width),
+        height: scale(geom.height),
+        ..geom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ratio, scale_within};
+    use crate::dbus::{Rect, WindowGeom};
+
+    #[test]
+    fn scales_between_monitors() {
+        assert_eq!(ratio(1.0, 2.0), Some(2.0));
+        assert_eq!(ratio(2.0, 1.0), Some(0.5));
+        assert_eq!(ratio(1.25, 1.25), None);
+        assert_eq!(ratio(0.0, 2.0), None);
+        assert_eq!(ratio(1.0, f64::NAN), None);
+
+        let area = Rect { x: 1920, y: 32, width: 3840, height: 2128 };
+        let geom = WindowGeom { x: 2020, y: 132, width: 800, height: 601, minimized: true };
+
+        assert_eq!(
+            scale_within(geom, area, 2.0),
+            WindowGeom { x: 2120, y: 232, width: 1600, height: 1202, minimized: true }
+        );
+        assert_eq!(
+            scale_within(geom, area, 0.5),
+            WindowGeom { x: 1970, y: 82, width: 400, height: 301, minimized: true }
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Export of a session as a standalone shell script.
+//!
+//! The script launches the applications like a restore would, without needing gnome-session-restore,
+//! and doubles as an audit of what a restore would do. Windows can optionally be moved to their saved
+//! positions by calling the extension via `gdbus` once the applications had time to start.
+
+use super::{
+    read_session,
+    shell::{quote as shell_quote, quote_argv},
+    sort_into_stages, Exec, RestoreError, SessionApplication,
+};
+use crate::{desktop_entry::DesktopEntry, find_command::index::desktop_file_id};
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("{0}")]
+    Session(#[from] RestoreError),
+
+    #[error("io error {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Quotes `s` as a GVariant string literal, as `gdbus call` expects its arguments in the GVariant text format
+fn gvariant_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// The command that launches `app`, or why there is none
+fn launch_command(app: &SessionApplication) -> Result<String, String> {
+    let command = match &app.exec {
+        Exec::CmdLine(cmdline) if cmdline.is_empty() => return Err("empty command line".to_owned()),
+        Exec::CmdLine(cmdline) => quote_argv(cmdline).map_err(|e| e.to_string())?,
+        // gtk-launch only finds desktop files in the application directories
+        Exec::DesktopFile(path) if path.ancestors().any(|dir| dir.ends_with("applications")) => {
+            let desktop_id = desktop_file_id(path).unwrap_or_default();
+            return Ok(format!("gtk-launch {}", shell_quote(&desktop_id)));
+        },
+        Exec::DesktopFile(path) => {
+            let entry = DesktopEntry::load(path).map_err(|e| format!("unable to read {path:?}: {e}"))?;
+
+            match entry.argv() {
+                Some(Ok(argv)) if argv.is_empty() => return Err("empty command line".to_owned()),
+                Some(Ok(argv)) => quote_argv(&argv).map_err(|e| e.to_string())?,
+                Some(Err(e)) => return Err(format!("invalid Exec line in {path:?}: {e}")),
+                None => return Err(format!("{path:?} has no Exec line")),
+            }
+        },
+        Exec::Unresolved => return Err("no command was saved".to_owned()),
+    };
+
+    Ok(match (&app.exec, &app.cwd) {
+        (Exec::CmdLine(_), Some(cwd)) => {
+            format!("(cd {} && exec {command})", shell_quote(&cwd.to_string_lossy()))
+        },
+        _ => command,
+    })
+}
+
+/// Writes a bash script that launches the applications of the session read from `rdr` in their launch order.
+/// If `geometry` is given, the script waits for that long and then moves the windows to their saved positions.
+///
+/// Saved environments are not exported, as they are only partially applied by a restore and may contain secrets.
+pub fn export_script<R: Read, W: Write>(rdr: R, mut writer: W, geometry: Option<Duration>) -> Result<(), ScriptError> {
+    let sess = read_session(rdr, true)?;
+    let (disabled, enabled): (Vec<_>, Vec<_>) = sess.applications.into_iter().partition(|app| app.disabled);
+    let (applications, _) = sort_into_stages(enabled)?;
+
+    let mut script = String::from("#!/bin/bash\n# Generated by gnome-session-restore\n");
+
+    for app in &applications {
+        let _ = writeln!(script, "\n# {}", app.name());
+
+        match launch_command(app) {
+            Ok(command) => {
+                let _ = writeln!(script, "{command} &");
+            },
+            Err(reason) => {
+                let _ = writeln!(script, "# skipped: {reason}");
+            },
+        }
+    }
+
+    for app in &disabled {
+        let _ = writeln!(script, "\n# {}\n# skipped: disabled", app.name());
+    }
+
+    if let Some(delay) = geometry {
+        let _ = writeln!(script, "\nsleep {}", delay.as_secs_f64());
+
+        for app in applications.iter().filter(|app| app.exec != Exec::Unresolved) {
+            let geom = app.window.geom;
+
+            // like a restore, the size is set before minimizing, see `frames_before_minimizing`
+            let steps: &[bool] = if geom.minimized { &[false, true] } else { &[false] };
+
+            for minimized in steps {
+                let _ = writeln!(
+                    script,
+                    "gdbus call --session --dest org.gnome.Shell --object-path /com/github/clueliss/WindowCtl \
+                     --method com.github.clueliss.WindowCtl.SetWindowGeomByClass {} '({}, {}, {}, {}, {})' >/dev/null",
+                    shell_quote(&gvariant_quote(&app.window.window_class)),
+                    geom.x,
+                    geom.y,
+                    geom.width,
+                    geom.height,
+                    minimized,
+                );
+            }
+        }
+    }
+
+    writer.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::export_script;
+    use std::time::Duration;
+
+    const SESSION: &str = r#"{
+        "format_version": 9,
+        "num_monitors": 1,
+        "applications": [
+            { "window_class": "firefox", "exec": { "DesktopFile": "/usr/share/applications/firefox.desktop" },
+              "after": ["kitty"], "display_name": "Firefox",
+              "geom": { "x": 0, "y": 0, "width": 800, "height": 600, "minimized": false },
+              "pid": 1, "stable_seq": 1, "gtk_app_id": "", "sandboxed_app_id": "" },
+            { "window_class": "kitty", "exec": { "CmdLine": ["kitty", "--title", "it's me"] }, "cwd": "/home/user",
+              "geom": { "x": 800, "y": 0, "width": 800, "height": 600, "minimized": true },
+              "pid": 2, "stable_seq": 2, "gtk_app_id": "", "sandboxed_app_id": "" },
+            { "window_class": "steam", "exec": "Unresolved",
+              "geom": { "x": 0, "y": 0, "width": 0, "height": 0, "minimized": false },
+              "pid": 3, "stable_seq": 3, "gtk_app_id": "", "sandboxed_app_id": "" }
+        ]
+    }"#;
+
+    #[test]
+    fn export() {
+        let mut buf = Vec::new();
+        export_script(SESSION.as_bytes(), &mut buf, Some(Duration::from_secs(5))).unwrap();
+        let script = String::from_utf8(buf).unwrap();
+
+        let kitty = script
+            .find(r"(cd '/home/user' && exec 'kitty' '--title' 'it'\''s me') &")
+            .unwrap();
+        let firefox = script.find("gtk-launch 'firefox' &").unwrap();
+        assert!(kitty < firefox, "{script}");
+
+        assert!(script.contains("# steam\n# skipped: no command was saved"));
+        assert!(script.contains("\nsleep 5\n"));
+
+        let frame = script
+            .find(r#"SetWindowGeomByClass ''\''kitty'\''' '(800, 0, 800, 600, false)'"#)
+            .unwrap();
+        let minimize = script
+            .find(r#"SetWindowGeomByClass ''\''kitty'\''' '(800, 0, 800, 600, true)'"#)
+            .unwrap();
+        assert!(frame < minimize, "{script}");
+        assert!(!script.contains("'steam'"));
+    }
+}
@@ -0,0 +1,114 @@
+//! Quoting of command lines for the shell, shared by the exported scripts and `--spawn-via-shell`.
+
+use std::ffi::{OsStr, OsString};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+#[error("argument {0:?} is not valid utf-8 and cannot be passed through the shell")]
+pub struct NonUtf8Argument(pub OsString);
+
+/// Quotes `s` for the shell, so that it is passed on as a single argument as it is
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes every argument of `argv` and joins them with spaces, so that the shell runs exactly `argv`
+pub fn quote_argv<S: AsRef<OsStr>>(argv: &[S]) -> Result<String, NonUtf8Argument> {
+    let args = argv
+        .iter()
+        .map(|arg| {
+            let arg = arg.as_ref();
+            arg.to_str().map(quote).ok_or_else(|| NonUtf8Argument(arg.to_owned()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(args.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, quote_argv, NonUtf8Argument};
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt, process::Command};
+
+    /// Strings the shell treats specially in one way or another
+    const TRICKY: &[&str] = &[
+        "",
+        " ",
+        "plain",
+        "a b",
+        "  leading and trailing  ",
+        "tab\there",
+        "new\nline",
+        "it's",
+        "'",
+        "''",
+        "'\\''",
+        r#""double""#,
+        r"back\slash",
+        r"\",
+        "$HOME",
+        "${HOME:-x}",
+        "$(echo injected)",
+        "`echo injected`",
+        "*",
+        "?",
+        "[a-z]",
+        "~",
+        "~root",
+        "-n",
+        "--",
+        "a;b",
+        "a && b",
+        "a | b",
+        "a > b",
+        "#comment",
+        "!!",
+        "{a,b}",
+        "x=y",
+        "ünïcödé ✓",
+    ];
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(
+            quote_argv(&["kitty", "--title", "it's me"]).unwrap(),
+            r"'kitty' '--title' 'it'\''s me'"
+        );
+        assert_eq!(quote_argv::<&str>(&[]).unwrap(), "");
+
+        let invalid = OsString::from_vec(b"caf\xe9".to_vec());
+        assert_eq!(
+            quote_argv(&[OsString::from("cat"), invalid.clone()]),
+            Err(NonUtf8Argument(invalid))
+        );
+    }
+
+    #[test]
+    fn shell_reads_back_every_argument() {
+        // printf repeats its format for every argument, which separates them by NUL
+        let script = format!("printf '%s\\0' {}", quote_argv(TRICKY).unwrap());
+
+        for shell in ["sh", "bash"] {
+            let Ok(output) = Command::new(shell)
+                .arg("-c")
+                .arg(&script)
+                .env("HOME", "/home/user")
+                .output()
+            else {
+                continue;
+            };
+
+            let args: Vec<_> = output
+                .stdout
+                .split(|&b| b == 0)
+                .map(|arg| String::from_utf8_lossy(arg))
+                .collect();
+
+            // the last NUL ends an empty piece
+            assert_eq!(args[..args.len() - 1], *TRICKY, "{shell}");
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Small window attributes beyond the geometry (e.g. always on top or on all workspaces).
+//!
+//! Every attribute is captured and restored by its own hook, and saved under the hook's key in the
+//! `extra_state` of an application, so that new attributes do not need new session file fields.
+//! Keys that no hook knows (e.g. from a newer version) are kept in the session file, but not restored.
+//!
+//! Hooks only capture what the extension reports in [`MetaWindow::state`], which older versions do not,
+//! and are restored via [`set_window_state_by_stable_seq`](crate::dbus::WindowCtlProxy::set_window_state_by_stable_seq),
+//! which older versions do not know about.
+//!
+//! The theme variant (dark or light) some applications choose per window is not a hook, since it can only be
+//! read but not set from outside of the application. It is only saved on request, to tell what cannot be restored.
+
+use crate::dbus::MetaWindow;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The state of an application's window that is saved by the hooks, by [`StateHook::key`]
+pub type ExtraState = BTreeMap<String, Value>;
+
+pub trait StateHook: Sync {
+    /// The key of the attribute, both in the window records of the extension and in [`ExtraState`]
+    fn key(&self) -> &'static str;
+
+    /// The value to save for the window, `None` if the attribute is not reported or has its default value
+    fn capture(&self, window: &MetaWindow) -> Option<Value>;
+
+    /// The value to send to the extension to restore the saved `value`, `None` if it is not valid
+    fn restore(&self, value: &Value) -> Option<zvariant::Value<'static>>;
+}
+
+/// An attribute that is either set or not, only saved if it is set
+struct Flag(&'static str);
+
+impl StateHook for Flag {
+    fn key(&self) -> &'static str {
+        self.0
+    }
+
+    fn capture(&self, window: &MetaWindow) -> Option<Value> {
+        window.state.get(self.0).filter(|v| v.as_bool() == Some(true)).cloned()
+    }
+
+    fn restore(&self, value: &Value) -> Option<zvariant::Value<'static>> {
+        value.as_bool().map(zvariant::Value::from)
+    }
+}
+
+/// The opacity of the window from 0 to 255, only saved if the window is not opaque
+struct Opacity;
+
+impl Opacity {
+    fn valid(value: &Value) -> Option<u32> {
+        value.as_u64().filter(|&o| o <= 255).map(|o| o as u32)
+    }
+}
+
+impl StateHook for Opacity {
+    fn key(&self) -> &'static str {
+        "opacity"
+    }
+
+    fn capture(&self, window: &MetaWindow) -> Option<Value> {
+        window
+            .state
+            .get("opacity")
+            .filter(|v| Self::valid(v).is_some_and(|o| o < 255))
+            .cloned()
+    }
+
+    fn restore(&self, value: &Value) -> Option<zvariant::Value<'static>> {
+        Self::valid(value).map(zvariant::Value::from)
+    }
+}
+
+/// Which half of the monitor the window is tiled to, or whether it is maximized
+struct Tile;
+
+impl Tile {
+    const MODES: &'static [&'static str] = &["left", "right", "maximized"];
+
+    fn valid(value: &Value) -> Option<&str> {
+        value.as_str().filter(|mode| Self::MODES.contains(mode))
+    }
+}
+
+impl StateHook for Tile {
+    fn key(&self) -> &'static str {
+        "tile"
+    }
+
+    fn capture(&self, window: &MetaWindow) -> Option<Value> {
+        window.state.get("tile").filter(|v| Self::valid(v).is_some()).cloned()
+    }
+
+    fn restore(&self, value: &Value) -> Option<zvariant::Value<'static>> {
+        Self::valid(value).map(|mode| zvariant::Value::from(mode.to_owned()))
+    }
+}
+
+/// The monitor a fullscreen window covers, windows that are not fullscreen are reported as -1
+struct FullscreenMonitor;
+
+impl FullscreenMonitor {
+    fn valid(value: &Value) -> Option<i32> {
+        value.as_i64().and_then(|m| i32::try_from(m).ok()).filter(|&m| m >= 0)
+    }
+}
+
+impl StateHook for FullscreenMonitor {
+    fn key(&self) -> &'static str {
+        "fullscreen_monitor"
+    }
+
+    fn capture(&self, window: &MetaWindow) -> Option<Value> {
+        window
+            .state
+            .get("fullscreen_monitor")
+            .filter(|v| Self::valid(v).is_some())
+            .cloned()
+    }
+
+    fn restore(&self, value: &Value) -> Option<zvariant::Value<'static>> {
+        Self::valid(value).map(zvariant::Value::from)
+    }
+}
+
+/// The key of the theme variant in the window records of the extension and in [`ExtraState`]
+pub const THEME_VARIANT: &str = "theme_variant";
+
+/// The theme variant the window asked for, `None` if it is not reported or is not `dark` or `light`
+pub fn theme_variant(window: &MetaWindow) -> Option<Value> {
+    window
+        .state
+        .get(THEME_VARIANT)
+        .filter(|v| matches!(v.as_str(), Some("dark" | "light")))
+        .cloned()
+}
+
+/// All hooks, in the order they are restored
+pub static HOOKS: &[&dyn StateHook] = &[&Flag("above"), &Flag("sticky"), &Opacity, &Tile, &FullscreenMonitor];
+
+/// The state of the window captured by all hooks
+pub fn capture(window: &MetaWindow) -> ExtraState {
+    HOOKS
+        .iter()
+        .filter_map(|hook| Some((hook.key().to_owned(), hook.capture(window)?)))
+        .collect()
+}
+
+/// The values to send to the extension to restore the saved state, in the order of [`HOOKS`].
+/// Invalid values are reported and skipped, unknown keys are skipped silently.
+pub fn restore(state: &ExtraState) -> Vec<(&'static str, zvariant::Value<'static>)> {
+    HOOKS
+        .iter()
+        .filter_map(|hook| {
+            let value = state.get(hook.key())?;
+
+            match hook.restore(value) {
+                Some(v) => Some((hook.key(), v)),
+                None => {
+                    eprintln!("ignoring invalid saved `{}`: {value}", hook.key());
+                    None
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capture, restore, theme_variant, Flag, FullscreenMonitor, Opacity, StateHook, Tile, THEME_VARIANT};
+    use crate::dbus::MetaWindow;
+    use serde_json::{json, Value};
+
+    fn window(state: Value) -> MetaWindow {
+        MetaWindow { state: serde_json::from_value(state).unwrap(), ..MetaWindow::with_class("kitty") }
+    }
+
+    #[test]
+    fn flag() {
+        let above = Flag("above");
+
+        assert_eq!(above.capture(&window(json!({ "above": true }))), Some(json!(true)));
+        assert_eq!(above.capture(&window(json!({ "above": false }))), None);
+        assert_eq!(above.capture(&window(json!({}))), None);
+
+        assert_eq!(above.restore(&json!(true)), Some(zvariant::Value::from(true)));
+        assert_eq!(above.restore(&json!("yes")), None);
+    }
+
+    #[test]
+    fn opacity() {
+        assert_eq!(Opacity.capture(&window(json!({ "opacity": 200 }))), Some(json!(200)));
+        assert_eq!(Opacity.capture(&window(json!({ "opacity": 255 }))), None);
+        assert_eq!(Opacity.capture(&window(json!({ "opacity": 300 }))), None);
+
+        assert_eq!(Opacity.restore(&json!(128)), Some(zvariant::Value::from(128u32)));
+        assert_eq!(Opacity.restore(&json!(-1)), None);
+    }
+
+    #[test]
+    fn tile() {
+        assert_eq!(Tile.capture(&window(json!({ "tile": "left" }))), Some(json!("left")));
+        assert_eq!(Tile.capture(&window(json!({ "tile": "none" }))), None);
+
+        assert_eq!(
+            Tile.restore(&json!("maximized")),
+            Some(zvariant::Value::from("maximized".to_owned()))
+        );
+        assert_eq!(Tile.restore(&json!("diagonal")), None);
+    }
+
+    #[test]
+    fn fullscreen_monitor() {
+        assert_eq!(
+            FullscreenMonitor.capture(&window(json!({ "fullscreen_monitor": 1 }))),
+            Some(json!(1))
+        );
+        assert_eq!(
+            FullscreenMonitor.capture(&window(json!({ "fullscreen_monitor": -1 }))),
+            None
+        );
+
+        assert_eq!(FullscreenMonitor.restore(&json!(0)), Some(zvariant::Value::from(0i32)));
+    }
+
+    #[test]
+    fn theme_variant_is_not_restored() {
+        let w = window(json!({ "theme_variant": "dark" }));
+        assert_eq!(theme_variant(&w), Some(json!("dark")));
+        assert_eq!(theme_variant(&window(json!({ "theme_variant": "" }))), None);
+
+        // only saved on request, never by the hooks
+        assert!(capture(&w).is_empty());
+
+        let state = [(THEME_VARIANT.to_owned(), json!("dark"))].into_iter().collect();
+        assert!(restore(&state).is_empty());
+    }
+
+    #[test]
+    fn capture_and_restore_all() {
+        let w = window(json!({ "above": true, "sticky": false, "opacity": 255, "tile": "right" }));
+        let mut state = capture(&w);
+
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            json!({ "above": true, "tile": "right" })
+        );
+
+        state.insert("from_the_future".to_owned(), json!(1));
+        state.insert("opacity".to_owned(), json!("opaque"));
+
+        let keys: Vec<_> = restore(&state).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec!["above", "tile"]);
+    }
+}
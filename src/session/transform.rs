@@ -0,0 +1,111 @@
+//! Transformation of sessions by an external program, e.g. to drop applications or rewrite their commands.
+//!
+//! The program gets the session as json on stdin and prints the transformed session as json on stdout.
+//! Its stderr is passed through, so it can report what it did.
+
+use super::{format, Session};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+    #[error("unable to run transform command {0:?}: {1}")]
+    Spawn(PathBuf, io::Error),
+
+    #[error("io error {0}")]
+    Io(#[from] io::Error),
+
+    #[error("transform command {0:?} failed: {1}")]
+    Failed(PathBuf, ExitStatus),
+
+    #[error("transform command returned an invalid session: {0}")]
+    InvalidSession(#[from] serde_json::Error),
+
+    #[error("transform command returned unsupported format version {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// Pipes `session` through `cmd` and returns the session it printed
+pub(super) fn transform(session: &Session, cmd: &Path) -> Result<Session, TransformError> {
+    let input = serde_json::to_vec(session).map_err(io::Error::from)?;
+
+    let mut child = Command::new(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| TransformError::Spawn(cmd.to_owned(), e))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+
+    // written on its own thread, as the program may print before it read everything
+    let (written, output) = std::thread::scope(|s| {
+        let writer = s.spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output();
+        (writer.join().expect("writing stdin does not panic"), output)
+    });
+
+    let output = output?;
+
+    if !output.status.success() {
+        return Err(TransformError::Failed(cmd.to_owned(), output.status));
+    }
+
+    // a program that does not need the session may exit without reading it
+    match written {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {},
+    }
+
+    let transformed: Session = serde_json::from_slice(&output.stdout)?;
+
+    if !format::is_supported(transformed.format_version) {
+        return Err(TransformError::UnsupportedVersion(transformed.format_version));
+    }
+
+    Ok(transformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transform, TransformError};
+    use crate::session::Session;
+    use std::path::Path;
+
+    const SESSION: &str = r#"{
+        "format_version": 4,
+        "num_monitors": 1,
+        "applications": [
+            {
+                "geom": { "x": 0, "y": 0, "width": 800, "height": 600, "minimized": false },
+                "pid": 10, "stable_seq": 1, "window_class": "kitty", "gtk_app_id": "", "sandboxed_app_id": "",
+                "exec": { "CmdLine": ["kitty"] }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn pipes_through_program() {
+        let sess: Session = serde_json::from_str(SESSION).unwrap();
+
+        let same = transform(&sess, Path::new("cat")).unwrap();
+        assert_eq!(same.format_version, 4);
+        assert_eq!(same.applications[0].window.window_class, "kitty");
+
+        assert!(matches!(
+            transform(&sess, Path::new("false")),
+            Err(TransformError::Failed(..))
+        ));
+        assert!(matches!(
+            transform(&sess, Path::new("true")),
+            Err(TransformError::InvalidSession(_))
+        ));
+        assert!(matches!(
+            transform(&sess, Path::new("/nonexistent/transform")),
+            Err(TransformError::Spawn(..))
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+//! Rules for recognizing the real window of applications that show other windows first.
+//!
+//! Some applications (e.g. Steam or Discord) open a splash screen or an updater before their main window.
+//! Without a rule that window would be placed instead of the real one, which then stays where it appears.
+//!
+//! Windows are waited for with [`poll`], a restart of gnome-shell in the meantime is noticed
+//! with [`detect_shell_restart`].
+
+use crate::dbus::{MetaWindow, WindowCtlProxy};
+use regex::Regex;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WaitRuleParseError {
+    #[error("expected <WINDOW_CLASS>:<CONDITION>=<VALUE>")]
+    Syntax,
+
+    #[error("unknown condition `{0}`, expected one of title, min-size or settle-ms")]
+    UnknownCondition(String),
+
+    #[error("invalid title regex: {0}")]
+    Title(String),
+
+    #[error("invalid size `{0}`, expected <WIDTH>x<HEIGHT>")]
+    Size(String),
+
+    #[error("invalid number of milliseconds `{0}`")]
+    Settle(String),
+}
+
+/// What a window has to fulfill to be accepted as the window of a saved window
+#[derive(Debug, Clone)]
+pub enum WaitCondition {
+    /// The title of the window matches
+    Title(Regex),
+
+    /// The window is at least this large
+    MinSize { width: i32, height: i32 },
+
+    /// The window has been open for at least this long, this gives splash screens the chance to close.
+    /// Once waiting for windows timed out this is not required anymore.
+    Settle(Duration),
+}
+
+impl WaitCondition {
+    fn accepts(&self, window: &MetaWindow, open_for: Duration, timed_out: bool) -> bool {
+        match self {
+            WaitCondition::Title(re) => re.is_match(&window.title),
+            WaitCondition::MinSize { width, height } => window.geom.width >= *width && window.geom.height >= *height,
+            WaitCondition::Settle(settle) => timed_out || open_for >= *settle,
+        }
+    }
+}
+
+/// A condition for the windows of a window class, parsed from `<WINDOW_CLASS>:<CONDITION>=<VALUE>`
+/// where the condition is one of `title=<REGEX>`, `min-size=<WIDTH>x<HEIGHT>` or `settle-ms=<MILLISECONDS>`
+#[derive(Debug, Clone)]
+pub struct WaitRule {
+    pub window_class: String,
+    pub condition: WaitCondition,
+}
+
+impl FromStr for WaitRule {
+    type Err = WaitRuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (window_class, condition) = s.split_once(':').ok_or(WaitRuleParseError::Syntax)?;
+        let (key, value) = condition.split_once('=').ok_or(WaitRuleParseError::Syntax)?;
+
+        if window_class.is_empty() {
+            return Err(WaitRuleParseError::Syntax);
+        }
+
+        let condition = match key {
+            "title" => WaitCondition::Title(Regex::new(value).map_err(|e| WaitRuleParseError::Title(e.to_string()))?),
+            "min-size" => {
+                let size = value
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+
+                match size {
+                    Some((width, height)) => WaitCondition::MinSize { width, height },
+                    None => return Err(WaitRuleParseError::Size(value.to_owned())),
+                }
+            },
+            "settle-ms" => WaitCondition::Settle(Duration::from_millis(
+                value
+                    .parse()
+                    .map_err(|_| WaitRuleParseError::Settle(value.to_owned()))?,
+            )),
+            _ => return Err(WaitRuleParseError::UnknownCondition(key.to_owned())),
+        };
+
+        Ok(WaitRule { window_class: window_class.to_owned(), condition })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WaitRules {
+    rules: Vec<WaitRule>,
+}
+
+impl WaitRules {
+    pub fn new(rules: Vec<WaitRule>) -> Self {
+        WaitRules { rules }
+    }
+
+    /// Checks whether `window`, which has been open for `open_for`, may be the window of a saved window of
+    /// `window_class`, i.e. whether it fulfills all conditions for that class
+    pub fn accepts(&self, window_class: &str, window: &MetaWindow, open_for: Duration, timed_out: bool) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| rule.window_class == window_class)
+            .all(|rule| rule.condition.accepts(window, open_for, timed_out))
+    }
+}
+
+/// How long to wait for gnome-shell to be back on the bus after it restarted
+pub(super) const SHELL_RESTART_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The unique bus name of the process that owns the shell's name, which changes whenever gnome-shell restarts
+pub(super) fn shell_owner(conn: &WindowCtlProxy) -> Option<String> {
+    zbus::fdo::DBusProxy::new(conn.connection())
+        .ok()?
+        .get_name_owner(conn.destination())
+        .ok()
+}
+
+/// Checks whether the owner of the shell's name is not `known` anymore, i.e. gnome-shell restarted (e.g. with
+/// Alt+F2 `r` on X11), and if so waits up to `timeout` for it to be back on the bus. `known` is updated to the
+/// current owner, `owner` looks it up. Returns whether the shell restarted.
+pub(super) fn detect_shell_restart(
+    known: &mut Option<String>,
+    timeout: Duration,
+    mut owner: impl FnMut() -> Option<String>,
+) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    let mut current = owner();
+
+    if known.is_none() || *known == current {
+        *known = current;
+        return false;
+    }
+
+    eprintln!("gnome-shell restarted, waiting for it to be back before placing windows");
+
+    let _ = poll(timeout, None, |_| {
+        if current.is_none() {
+            current = owner();
+        }
+
+        Ok(current.is_none().then_some(POLL_INTERVAL))
+    });
+
+    if current.is_none() {
+        eprintln!("gnome-shell is not back after {timeout:?}");
+    }
+
+    *known = current;
+    true
+}
+
+/// Calls `step` until it returns `None` or `timeout` has passed, sleeping for the interval it returns in between
+/// (but not beyond the timeout). `step` is told whether the timeout has passed, i.e. whether this is its last call.
+/// Once `cancel` is set no further calls are made.
+pub(super) fn poll(
+    timeout: Duration,
+    cancel: Option<&AtomicBool>,
+    mut step: impl FnMut(bool) -> zbus::Result<Option<Duration>>,
+) -> zbus::Result<()> {
+    let deadline = Instant::now() + timeout;
+    let cancelled = || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+
+    loop {
+        let timed_out = Instant::now() >= deadline;
+
+        match step(timed_out)? {
+            Some(interval) if !timed_out && !cancelled() => {
+                std::thread::sleep(interval.min(deadline.saturating_duration_since(Instant::now())));
+            },
+            _ => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_shell_restart, WaitRule, WaitRuleParseError, WaitRules};
+    use crate::{
+        dbus::{MetaWindow, WindowGeom},
+        session::placement::{match_windows, MatchMethod},
+    };
+    use std::{cell::RefCell, collections::HashSet, time::Duration};
+
+    fn rules(rules: &[&str]) -> WaitRules {
+        WaitRules::new(rules.iter().map(|r| r.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parse() {
+        assert!("Steam:title=^Steam$".parse::<WaitRule>().is_ok());
+        assert!("Steam:min-size=800x600".parse::<WaitRule>().is_ok());
+        assert!("Steam:settle-ms=500".parse::<WaitRule>().is_ok());
+
+        assert_eq!("Steam".parse::<WaitRule>().unwrap_err(), WaitRuleParseError::Syntax);
+        assert_eq!(
+            "Steam:size=1".parse::<WaitRule>().unwrap_err(),
+            WaitRuleParseError::UnknownCondition("size".into())
+        );
+        assert_eq!(
+            "Steam:min-size=800".parse::<WaitRule>().unwrap_err(),
+            WaitRuleParseError::Size("800".into())
+        );
+        assert!(matches!(
+            "Steam:title=(".parse::<WaitRule>(),
+            Err(WaitRuleParseError::Title(_))
+        ));
+    }
+
+    #[test]
+    fn splash_screen_then_main_window() {
+        let window = |stable_seq, title: &str, width, height| MetaWindow {
+            stable_seq,
+            title: title.to_owned(),
+            geom: WindowGeom { width, height, ..Default::default() },
+            ..MetaWindow::with_class("Steam")
+        };
+
+        let saved = [window(1, "Steam", 1200, 800)];
+        let rules = rules(&["Steam:title=^Steam$", "Steam:min-size=400x300"]);
+        let accepts =
+            |saved_ix: usize, w: &MetaWindow| rules.accepts(&saved[saved_ix].window_class, w, Duration::ZERO, false);
+
+        // first only the updater is open
+        let snapshots = [
+            vec![window(10, "Steam - Updating", 300, 100)],
+            vec![window(10, "Steam - Updating", 300, 100), window(11, "Steam", 1200, 800)],
+            vec![window(11, "Steam", 1200, 800)],
+        ];
+
+        let placed: Vec<Option<u32>> = snapshots
+            .iter()
+            .map(|open| {
+                match_windows(saved.iter().enumerate(), open, &[], &HashSet::new(), Some(0), accepts)
+                    .first()
+                    .map(|m| {
+                        assert_eq!(m.method, MatchMethod::ExactClass);
+                        m.window.stable_seq
+                    })
+            })
+            .collect();
+
+        assert_eq!(placed, vec![None, Some(11), Some(11)]);
+    }
+
+    #[test]
+    fn settle_time() {
+        let rules = rules(&["Steam:settle-ms=500"]);
+        let w = MetaWindow { stable_seq: 10, title: "Steam".to_owned(), ..MetaWindow::with_class("Steam") };
+
+        assert!(!rules.accepts("Steam", &w, Duration::from_millis(100), false));
+        assert!(rules.accepts("Steam", &w, Duration::from_millis(500), false));
+        assert!(rules.accepts("Steam", &w, Duration::from_millis(100), true));
+
+        // other classes are not affected
+        assert!(rules.accepts("firefox", &w, Duration::ZERO, false));
+    }
+
+    #[test]
+    fn detects_shell_restarts() {
+        let owners = RefCell::new(vec![Some(":1.10"), Some(":1.10"), None, None, Some(":1.42")].into_iter());
+        let owner = || owners.borrow_mut().next().flatten().map(str::to_owned);
+
+        let mut known = None;
+        assert!(!detect_shell_restart(&mut known, Duration::from_secs(5), owner));
+        assert_eq!(known.as_deref(), Some(":1.10"));
+        assert!(!detect_shell_restart(&mut known, Duration::from_secs(5), owner));
+
+        // gone, then back under a new name
+        assert!(detect_shell_restart(&mut known, Duration::from_secs(5), owner));
+        assert_eq!(known.as_deref(), Some(":1.42"));
+
+        // not back in time
+        let mut known = Some(":1.42".to_owned());
+        assert!(detect_shell_restart(&mut known, Duration::ZERO, || None));
+        assert_eq!(known, None);
+        assert!(!detect_shell_restart(&mut known, Duration::ZERO, || Some(
+            ":1.50".to_owned()
+        )));
+    }
+}
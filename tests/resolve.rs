@@ -0,0 +1,66 @@
+//! `resolve` against the desktop files of the fedora testset, which does not need the shell or D-Bus.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+fn resolve(args: &[&str]) -> Output {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("resolve");
+    let testset = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/testsets/fedora.list");
+
+    Command::new(env!("CARGO_BIN_EXE_gnome-session-restore"))
+        .env("XDG_CONFIG_HOME", &dir)
+        .arg("--state-dir")
+        .arg(&dir)
+        .args(args)
+        .arg("--testset")
+        .arg(testset)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn resolves_by_app_id() {
+    let output = resolve(&["resolve", "--wm-class", "firefox", "--sandboxed-app-id", "org.mozilla.firefox"]);
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        stdout(&output),
+        "exec\t/var/lib/flatpak/exports/share/applications/org.mozilla.firefox.desktop\n\
+         method\tsandboxed-app-id\n\
+         confidence\t1.00\n"
+    );
+}
+
+#[test]
+fn resolves_by_window_class_as_json() {
+    let output = resolve(&["--output", "json", "resolve", "--wm-class", "gnome-terminal-server", "--gtk-app-id", "org.gnome.Terminal"]);
+    assert!(output.status.success(), "{output:?}");
+
+    let resolution: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // the testset was recorded with a doubled slash in this path
+    assert_eq!(
+        Path::new(resolution["exec"].as_str().unwrap()),
+        Path::new("/usr/share/applications/org.gnome.Terminal.desktop")
+    );
+    assert_eq!(resolution["method"], "gtk-app-id");
+    assert_eq!(resolution["window_class"], "gnome-terminal-server");
+}
+
+#[test]
+fn fails_with_the_error_kind() {
+    let output = resolve(&["resolve", "--wm-class", "no-such-application-at-all"]);
+
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert!(stdout(&output).starts_with("error\tproc-command-not-allowed: "), "{output:?}");
+
+    let output = resolve(&["--output", "json", "resolve", "--wm-class", "no-such-application-at-all"]);
+    let resolution: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(resolution["error_kind"], "proc-command-not-allowed");
+    assert!(resolution.get("exec").is_none());
+}